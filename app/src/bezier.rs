@@ -0,0 +1,410 @@
+//! Bicubic Bézier patches and the Utah teapot.
+//!
+//! Patches are tessellated into a grid of `divisions` × `divisions` quads
+//! when the scene is built, with exact normals and the patch parameters as
+//! texture coordinates, and go down the triangle mesh path.
+
+use ::std::default::Default;
+use ::std::iter::{Extend, Iterator};
+use ::std::vec::Vec;
+
+use ::math::{Point3, Vec3};
+
+use crate::mesh::TriangleMesh;
+
+/// The cubic Bernstein polynomials at `t` and their derivatives.
+fn bernstein(t: f32) -> ([f32; 4], [f32; 4]) {
+    let s = 1.0 - t;
+    (
+        [s * s * s, 3.0 * t * s * s, 3.0 * t * t * s, t * t * t],
+        [
+            -3.0 * s * s,
+            3.0 * s * (s - 2.0 * t),
+            3.0 * t * (2.0 * s - t),
+            3.0 * t * t,
+        ],
+    )
+}
+
+/// The point at `(u, v)` of a patch and its derivatives along u and v.
+/// Control points are in rows of constant v, along u within a row.
+fn evaluate(cp: &[Point3; 16], u: f32, v: f32) -> (Point3, Vec3, Vec3) {
+    let (bu, du) = bernstein(u);
+    let (bv, dv) = bernstein(v);
+    let mut p = Vec3::default();
+    let mut dp_du = Vec3::default();
+    let mut dp_dv = Vec3::default();
+    for i in 0..4 {
+        for j in 0..4 {
+            let c = cp[4 * i + j] - Point3::default();
+            p += bv[i] * bu[j] * c;
+            dp_du += bv[i] * du[j] * c;
+            dp_dv += dv[i] * bu[j] * c;
+        }
+    }
+    (Point3::default() + p, dp_du, dp_dv)
+}
+
+/// The normal at `(u, v)`, pointing along dp/du × dp/dv. Where a row of
+/// control points collapses into a pole the derivatives are parallel, so
+/// the normal is taken from ever closer to the patch center until it is
+/// defined.
+fn normal(cp: &[Point3; 16], u: f32, v: f32) -> Vec3 {
+    for &step in &[0.0, 1e-4, 1e-3, 1e-2] {
+        let (_, du, dv) =
+            evaluate(cp, u + step * (0.5 - u), v + step * (0.5 - v));
+        let n = du.cross(&dv);
+        let scale = du.length().max(dv.length());
+        if n.length() > 1e-6 * scale * scale {
+            return n.unit();
+        }
+    }
+    Vec3::new(0.0, 0.0, 1.0)
+}
+
+/// Tessellates `patches` of 16 indices into `points` each, in rows of
+/// constant v. The front of a patch is on the side of dp/du × dp/dv.
+pub fn tessellate(
+    points: &[Point3], patches: &[[usize; 16]], divisions: usize,
+    material: usize,
+) -> TriangleMesh {
+    let n = divisions + 1;
+    let mut positions = Vec::with_capacity(patches.len() * n * n);
+    let mut normals = Vec::with_capacity(patches.len() * n * n);
+    let mut uvs = Vec::with_capacity(patches.len() * n * n);
+    let mut indices = Vec::with_capacity(patches.len() * divisions * 6);
+    for patch in patches {
+        let mut cp = [Point3::default(); 16];
+        for (c, &i) in cp.iter_mut().zip(patch.iter()) {
+            *c = points[i];
+        }
+        let base = positions.len();
+        for i in 0..n {
+            let v = i as f32 / divisions as f32;
+            for j in 0..n {
+                let u = j as f32 / divisions as f32;
+                positions.push(evaluate(&cp, u, v).0);
+                normals.push(normal(&cp, u, v));
+                uvs.push((u, v));
+            }
+        }
+        for i in 0..divisions {
+            for j in 0..divisions {
+                let a = base + i * n + j;
+                let (b, c, d) = (a + 1, a + n + 1, a + n);
+                indices.extend_from_slice(&[a, b, c, a, c, d]);
+            }
+        }
+    }
+    TriangleMesh::new(positions, indices, normals, uvs, material)
+}
+
+/// The control points of one quarter or one half of the teapot, from the
+/// original data set by Martin Newell.
+const TEAPOT_POINTS: [[f32; 3]; 127] = [
+    [0.2, 0.0, 2.7],
+    [0.2, -0.112, 2.7],
+    [0.112, -0.2, 2.7],
+    [0.0, -0.2, 2.7],
+    [1.3375, 0.0, 2.53125],
+    [1.3375, -0.749, 2.53125],
+    [0.749, -1.3375, 2.53125],
+    [0.0, -1.3375, 2.53125],
+    [1.4375, 0.0, 2.53125],
+    [1.4375, -0.805, 2.53125],
+    [0.805, -1.4375, 2.53125],
+    [0.0, -1.4375, 2.53125],
+    [1.5, 0.0, 2.4],
+    [1.5, -0.84, 2.4],
+    [0.84, -1.5, 2.4],
+    [0.0, -1.5, 2.4],
+    [1.75, 0.0, 1.875],
+    [1.75, -0.98, 1.875],
+    [0.98, -1.75, 1.875],
+    [0.0, -1.75, 1.875],
+    [2.0, 0.0, 1.35],
+    [2.0, -1.12, 1.35],
+    [1.12, -2.0, 1.35],
+    [0.0, -2.0, 1.35],
+    [2.0, 0.0, 0.9],
+    [2.0, -1.12, 0.9],
+    [1.12, -2.0, 0.9],
+    [0.0, -2.0, 0.9],
+    [-2.0, 0.0, 0.9],
+    [2.0, 0.0, 0.45],
+    [2.0, -1.12, 0.45],
+    [1.12, -2.0, 0.45],
+    [0.0, -2.0, 0.45],
+    [1.5, 0.0, 0.225],
+    [1.5, -0.84, 0.225],
+    [0.84, -1.5, 0.225],
+    [0.0, -1.5, 0.225],
+    [1.5, 0.0, 0.15],
+    [1.5, -0.84, 0.15],
+    [0.84, -1.5, 0.15],
+    [0.0, -1.5, 0.15],
+    [-1.6, 0.0, 2.025],
+    [-1.6, -0.3, 2.025],
+    [-1.5, -0.3, 2.25],
+    [-1.5, 0.0, 2.25],
+    [-2.3, 0.0, 2.025],
+    [-2.3, -0.3, 2.025],
+    [-2.5, -0.3, 2.25],
+    [-2.5, 0.0, 2.25],
+    [-2.7, 0.0, 2.025],
+    [-2.7, -0.3, 2.025],
+    [-3.0, -0.3, 2.25],
+    [-3.0, 0.0, 2.25],
+    [-2.7, 0.0, 1.8],
+    [-2.7, -0.3, 1.8],
+    [-3.0, -0.3, 1.8],
+    [-3.0, 0.0, 1.8],
+    [-2.7, 0.0, 1.575],
+    [-2.7, -0.3, 1.575],
+    [-3.0, -0.3, 1.35],
+    [-3.0, 0.0, 1.35],
+    [-2.5, 0.0, 1.125],
+    [-2.5, -0.3, 1.125],
+    [-2.65, -0.3, 0.9375],
+    [-2.65, 0.0, 0.9375],
+    [-2.0, -0.3, 0.9],
+    [-1.9, -0.3, 0.6],
+    [-1.9, 0.0, 0.6],
+    [1.7, 0.0, 1.425],
+    [1.7, -0.66, 1.425],
+    [1.7, -0.66, 0.6],
+    [1.7, 0.0, 0.6],
+    [2.6, 0.0, 1.425],
+    [2.6, -0.66, 1.425],
+    [3.1, -0.66, 0.825],
+    [3.1, 0.0, 0.825],
+    [2.3, 0.0, 2.1],
+    [2.3, -0.25, 2.1],
+    [2.4, -0.25, 2.025],
+    [2.4, 0.0, 2.025],
+    [2.7, 0.0, 2.4],
+    [2.7, -0.25, 2.4],
+    [3.3, -0.25, 2.4],
+    [3.3, 0.0, 2.4],
+    [2.8, 0.0, 2.475],
+    [2.8, -0.25, 2.475],
+    [3.525, -0.25, 2.49375],
+    [3.525, 0.0, 2.49375],
+    [2.9, 0.0, 2.475],
+    [2.9, -0.15, 2.475],
+    [3.45, -0.15, 2.5125],
+    [3.45, 0.0, 2.5125],
+    [2.8, 0.0, 2.4],
+    [2.8, -0.15, 2.4],
+    [3.2, -0.15, 2.4],
+    [3.2, 0.0, 2.4],
+    [0.0, 0.0, 3.15],
+    [0.8, 0.0, 3.15],
+    [0.8, -0.45, 3.15],
+    [0.45, -0.8, 3.15],
+    [0.0, -0.8, 3.15],
+    [0.0, 0.0, 2.85],
+    [1.4, 0.0, 2.4],
+    [1.4, -0.784, 2.4],
+    [0.784, -1.4, 2.4],
+    [0.0, -1.4, 2.4],
+    [0.4, 0.0, 2.55],
+    [0.4, -0.224, 2.55],
+    [0.224, -0.4, 2.55],
+    [0.0, -0.4, 2.55],
+    [1.3, 0.0, 2.55],
+    [1.3, -0.728, 2.55],
+    [0.728, -1.3, 2.55],
+    [0.0, -1.3, 2.55],
+    [1.3, 0.0, 2.4],
+    [1.3, -0.728, 2.4],
+    [0.728, -1.3, 2.4],
+    [0.0, -1.3, 2.4],
+    [0.0, 0.0, 0.0],
+    [1.425, -0.798, 0.0],
+    [1.5, 0.0, 0.075],
+    [1.425, 0.0, 0.0],
+    [0.798, -1.425, 0.0],
+    [0.0, -1.5, 0.075],
+    [0.0, -1.425, 0.0],
+    [1.5, -0.84, 0.075],
+    [0.84, -1.5, 0.075],
+];
+
+/// Rim, body, lid and bottom are mirrored into all four quadrants, handle
+/// and spout only across the xz plane.
+const TEAPOT_QUARTERS: usize = 6;
+
+const TEAPOT_PATCHES: [[usize; 16]; 10] = [
+    // Rim.
+    [102, 103, 104, 105, 4, 5, 6, 7, 8, 9, 10, 11, 12, 13, 14, 15],
+    // Body.
+    [
+        12, 13, 14, 15, 16, 17, 18, 19, 20, 21, 22, 23, 24, 25, 26, 27,
+    ],
+    [
+        24, 25, 26, 27, 29, 30, 31, 32, 33, 34, 35, 36, 37, 38, 39, 40,
+    ],
+    // Lid.
+    [
+        96, 96, 96, 96, 97, 98, 99, 100, 101, 101, 101, 101, 0, 1, 2, 3,
+    ],
+    [
+        0, 1, 2, 3, 106, 107, 108, 109, 110, 111, 112, 113, 114, 115, 116, 117,
+    ],
+    // Bottom.
+    [
+        118, 118, 118, 118, 124, 122, 119, 121, 123, 126, 125, 120, 40, 39, 38,
+        37,
+    ],
+    // Handle.
+    [
+        41, 42, 43, 44, 45, 46, 47, 48, 49, 50, 51, 52, 53, 54, 55, 56,
+    ],
+    [
+        53, 54, 55, 56, 57, 58, 59, 60, 61, 62, 63, 64, 28, 65, 66, 67,
+    ],
+    // Spout.
+    [
+        68, 69, 70, 71, 72, 73, 74, 75, 76, 77, 78, 79, 80, 81, 82, 83,
+    ],
+    [
+        80, 81, 82, 83, 84, 85, 86, 87, 88, 89, 90, 91, 92, 93, 94, 95,
+    ],
+];
+
+/// The control points and the 32 patches of the Utah teapot: 3.15 tall,
+/// standing on the z = 0 plane with its spout towards +x.
+pub fn teapot() -> (Vec<Point3>, Vec<[usize; 16]>) {
+    let mut points = Vec::new();
+    let mut patches = Vec::new();
+    // Mirroring flips the side a patch faces, unless its rows are reversed
+    // as well.
+    for &(sx, sy) in &[(1.0, 1.0), (1.0, -1.0), (-1.0, 1.0), (-1.0, -1.0)] {
+        let base = points.len();
+        points.extend(
+            TEAPOT_POINTS
+                .iter()
+                .map(|p| Point3::new(sx * p[0], sy * p[1], p[2])),
+        );
+        let mirrored = sx * sy < 0.0;
+        let count = if sx < 0.0 {
+            TEAPOT_QUARTERS
+        } else {
+            TEAPOT_PATCHES.len()
+        };
+        for patch in &TEAPOT_PATCHES[..count] {
+            let mut indices = [0; 16];
+            for (k, index) in indices.iter_mut().enumerate() {
+                let (row, column) = (k / 4, k % 4);
+                let column = if mirrored { 3 - column } else { column };
+                *index = base + patch[4 * row + column];
+            }
+            patches.push(indices);
+        }
+    }
+    (points, patches)
+}
+
+#[cfg(test)]
+pub mod tests {
+    use super::*;
+    use ::std::sync::Arc;
+    use ::std::{assert, assert_eq};
+
+    use crate::pbrt::{HitRecord, Hitable, Ray};
+
+    /// A flat patch over [0, 3] x [0, 3] in the xy plane with one control
+    /// point lifted.
+    fn bump() -> (Vec<Point3>, [usize; 16]) {
+        let mut points: Vec<Point3> = (0..16)
+            .map(|k| Point3::new((k % 4) as f32, (k / 4) as f32, 0.0))
+            .collect();
+        points[5] = Point3::new(1.0, 1.0, 1.0);
+        let mut patch = [0; 16];
+        for (k, index) in patch.iter_mut().enumerate() {
+            *index = k;
+        }
+        (points, patch)
+    }
+
+    #[test]
+    fn test_evaluate() {
+        let (points, patch) = bump();
+        let mut cp = [Point3::default(); 16];
+        for k in 0..16 {
+            cp[k] = points[patch[k]];
+        }
+        // The corners interpolate, the lifted point pulls the inside up by
+        // its Bernstein weights.
+        assert_eq!(evaluate(&cp, 0.0, 0.0).0, Point3::new(0.0, 0.0, 0.0));
+        assert_eq!(evaluate(&cp, 1.0, 1.0).0, Point3::new(3.0, 3.0, 0.0));
+        let (p, du, dv) = evaluate(&cp, 1.0 / 3.0, 1.0 / 3.0);
+        let weight = 3.0 * (1.0f32 / 3.0) * (4.0 / 9.0);
+        assert!((p - Point3::new(1.0, 1.0, weight * weight)).length() < 1e-6);
+        // The derivatives of the linear x and y parameterization.
+        assert!((du.x() - 3.0).abs() < 1e-5 && du.y().abs() < 1e-6);
+        assert!((dv.y() - 3.0).abs() < 1e-5 && dv.x().abs() < 1e-6);
+        // Facing +z, tilted away from the bump beyond it.
+        let n = normal(&cp, 0.5, 0.5);
+        assert!(n.z() > 0.0 && n.x() > 0.0 && n.y() > 0.0);
+
+        // A patch with a pole at v = 0 still has a normal there.
+        let mut cone = cp;
+        for c in &mut cone[..4] {
+            *c = Point3::new(1.5, 0.0, 1.0);
+        }
+        let n = normal(&cone, 0.5, 0.0);
+        assert!((n - normal(&cone, 0.5, 0.01)).length() < 0.1);
+    }
+
+    #[test]
+    fn test_tessellate() {
+        let (points, patch) = bump();
+        let mesh = tessellate(&points, &[patch], 4, 2);
+        assert_eq!(mesh.positions.len(), 25);
+        assert_eq!(mesh.num_triangles(), 32);
+        assert_eq!(mesh.uvs[24], (1.0, 1.0));
+        let tris = TriangleMesh::triangles(&Arc::new(mesh));
+        let r = Ray::new(
+            Point3::new(2.9, 2.9, 5.0),
+            Vec3::new(0.0, 0.0, -1.0),
+            0.0,
+            0,
+        );
+        let mut rec = HitRecord::default();
+        assert!(tris.hit(&r, 0.001, f32::MAX, &mut rec));
+        assert_eq!(rec.material, 2);
+        assert!(rec.normal.z() > 0.9);
+        assert!(rec.u > 0.9 && rec.v > 0.9);
+    }
+
+    #[test]
+    fn test_teapot() {
+        let (points, patches) = teapot();
+        assert_eq!(patches.len(), 32);
+        let mesh = tessellate(&points, &patches, 6, 0);
+        let bb = mesh
+            .positions
+            .iter()
+            .fold((points[0], points[0]), |(lo, hi), &p| {
+                (lo.min(p), hi.max(p))
+            });
+        assert!((bb.0.z()).abs() < 1e-6 && (bb.1.z() - 3.15).abs() < 1e-6);
+        assert!((bb.0.y() + 2.0).abs() < 1e-6 && (bb.1.y() - 2.0).abs() < 1e-6);
+        // Spout and handle.
+        assert!(bb.1.x() > 3.2 && bb.0.x() < -2.8);
+
+        // The body faces outwards in all four quadrants.
+        let n = (6 + 1) * (6 + 1);
+        for &k in &[1, 2, 11, 12, 21, 22, 27, 28] {
+            for (p, normal) in mesh.positions[n * k..n * (k + 1)]
+                .iter()
+                .zip(&mesh.normals[n * k..n * (k + 1)])
+            {
+                let radial = Vec3::new(p.x(), p.y(), 0.0);
+                assert!(normal.dot(radial) > 0.0, "patch {}", k);
+            }
+        }
+    }
+}
@@ -0,0 +1,270 @@
+//! Constructive solid geometry: unions, intersections and differences of
+//! closed shapes.
+//!
+//! A CSG node asks both operands for the spans along a ray that are inside
+//! them with `Hitable::hit_all` and combines them into the spans inside the
+//! result. Every boundary of the result keeps the material and texture
+//! coordinates of the operand it came from. Operands must enclose a volume,
+//! like spheres, boxes, closed tori and other CSG nodes, placed with any
+//! transform.
+
+use ::std::clone::Clone;
+use ::std::cmp::{Ordering, PartialOrd};
+use ::std::iter::Iterator;
+use ::std::option::Option::{self, None, Some};
+use ::std::sync::Arc;
+use ::std::vec::Vec;
+
+use crate::pbrt::{HitRecord, Hitable, Ray, Span, AABB};
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum CsgOp {
+    Union,
+    Intersection,
+    /// The left operand with the right one cut out of it.
+    Difference,
+}
+
+impl CsgOp {
+    /// Whether a point inside the left operand or not and inside the right
+    /// operand or not is inside the result.
+    fn inside(self, left: bool, right: bool) -> bool {
+        match self {
+            CsgOp::Union => left || right,
+            CsgOp::Intersection => left && right,
+            CsgOp::Difference => left && !right,
+        }
+    }
+}
+
+/// Combines the spans `left` and `right` of the operands along the same
+/// line with `op`. Surfaces of the right operand of a difference face into
+/// the result, so their normals are flipped.
+fn combine(op: CsgOp, left: &[Span], right: &[Span], spans: &mut Vec<Span>) {
+    // The boundaries in order along the line: where, whether the line
+    // enters there, which operand and the record.
+    let mut events: Vec<(f32, bool, usize, &HitRecord)> = Vec::new();
+    for (side, operand) in [left, right].iter().enumerate() {
+        for span in operand.iter() {
+            events.push((span.enter.t, true, side, &span.enter));
+            events.push((span.exit.t, false, side, &span.exit));
+        }
+    }
+    events.sort_by(|a, b| a.0.partial_cmp(&b.0).unwrap_or(Ordering::Equal));
+
+    let mut inside = [false, false];
+    let mut enter: Option<HitRecord> = None;
+    for (_, entering, side, rec) in events {
+        let was = op.inside(inside[0], inside[1]);
+        inside[side] = entering;
+        let now = op.inside(inside[0], inside[1]);
+        if was == now {
+            continue;
+        }
+        let mut rec = rec.clone();
+        if op == CsgOp::Difference && side == 1 {
+            rec.normal = -rec.normal;
+        }
+        match enter.take() {
+            Some(enter) => spans.push(Span { enter, exit: rec }),
+            None => enter = Some(rec),
+        }
+    }
+}
+
+/// Two closed shapes combined with a boolean operation.
+#[derive(Debug)]
+pub struct Csg {
+    op: CsgOp,
+    left: Arc<dyn Hitable>,
+    right: Arc<dyn Hitable>,
+}
+
+impl Csg {
+    pub fn new(
+        op: CsgOp, left: Arc<dyn Hitable>, right: Arc<dyn Hitable>,
+    ) -> Self {
+        Csg { op, left, right }
+    }
+}
+
+impl Hitable for Csg {
+    fn hit(
+        &self, r: &Ray, t_min: f32, t_max: f32, rec: &mut HitRecord,
+    ) -> bool {
+        let mut spans = Vec::new();
+        if !self.hit_all(r, &mut spans) {
+            return false;
+        }
+        for span in &spans {
+            for boundary in &[&span.enter, &span.exit] {
+                if boundary.t >= t_max {
+                    return false;
+                }
+                if boundary.t > t_min {
+                    *rec = (*boundary).clone();
+                    return true;
+                }
+            }
+        }
+        false
+    }
+
+    fn hit_all(&self, r: &Ray, spans: &mut Vec<Span>) -> bool {
+        let mut left = Vec::new();
+        if !self.left.hit_all(r, &mut left) {
+            return false;
+        }
+        if left.is_empty() && self.op != CsgOp::Union {
+            return true;
+        }
+        let mut right = Vec::new();
+        if !self.right.hit_all(r, &mut right) {
+            return false;
+        }
+        combine(self.op, &left, &right, spans);
+        true
+    }
+
+    /// A union is inside the bounds of either operand, an intersection
+    /// inside both and a difference inside the left one.
+    fn bounding_box(&self, t0: f32, t1: f32) -> Option<AABB> {
+        let left = self.left.bounding_box(t0, t1)?;
+        if self.op == CsgOp::Difference {
+            return Some(left);
+        }
+        let right = self.right.bounding_box(t0, t1)?;
+        match self.op {
+            CsgOp::Intersection => {
+                let min = left.min().max(right.min());
+                // Disjoint operands leave an empty box at a corner.
+                let max = left.max().min(right.max()).max(min);
+                Some(AABB::new(min, max))
+            }
+            _ => Some(AABB::surround(left, right)),
+        }
+    }
+}
+
+#[cfg(test)]
+pub mod tests {
+    use super::*;
+    use ::std::default::Default;
+    use ::std::{assert, assert_eq};
+
+    use ::math::{Point3, Vec3};
+
+    use crate::shapes::{Cuboid, Disk, Sphere};
+
+    fn ray_x(x: f32) -> Ray {
+        Ray::new(Point3::new(x, 0.0, 0.0), Vec3::new(1.0, 0.0, 0.0), 0.0, 0)
+    }
+
+    fn spans(hitable: &dyn Hitable, r: &Ray) -> Vec<(f32, f32)> {
+        let mut spans = Vec::new();
+        assert!(hitable.hit_all(r, &mut spans));
+        spans.iter().map(|s| (s.enter.t, s.exit.t)).collect()
+    }
+
+    fn close(a: &[(f32, f32)], b: &[(f32, f32)]) -> bool {
+        a.len() == b.len()
+            && a.iter().zip(b).all(|(a, b)| {
+                (a.0 - b.0).abs() < 1e-4 && (a.1 - b.1).abs() < 1e-4
+            })
+    }
+
+    #[test]
+    fn test_csg_spans() {
+        // Spheres along x at -1 and 1 that overlap between -0.5 and 0.5.
+        let a: Arc<dyn Hitable> =
+            Arc::new(Sphere::new(Point3::new(-1.0, 0.0, 0.0), 1.5, 1));
+        let b: Arc<dyn Hitable> =
+            Arc::new(Sphere::new(Point3::new(1.0, 0.0, 0.0), 1.5, 2));
+        let r = ray_x(-10.0);
+        let union = Csg::new(CsgOp::Union, a.clone(), b.clone());
+        assert!(close(&spans(&union, &r), &[(7.5, 12.5)]));
+        let both = Csg::new(CsgOp::Intersection, a.clone(), b.clone());
+        assert!(close(&spans(&both, &r), &[(9.5, 10.5)]));
+        let lens = Csg::new(CsgOp::Difference, a.clone(), b.clone());
+        assert!(close(&spans(&lens, &r), &[(7.5, 9.5)]));
+
+        // A box with a hole drilled through it gives two spans, and a line
+        // that starts inside reports the boundary behind it as well.
+        let block: Arc<dyn Hitable> = Arc::new(Cuboid::new(
+            Point3::new(-2.0, -1.0, -1.0),
+            Point3::new(2.0, 1.0, 1.0),
+            3,
+        ));
+        let hole: Arc<dyn Hitable> =
+            Arc::new(Sphere::new(Point3::default(), 0.5, 4));
+        let drilled = Csg::new(CsgOp::Difference, block, hole);
+        assert!(close(&spans(&drilled, &r), &[(8.0, 9.5), (10.5, 12.0)]));
+        assert!(close(
+            &spans(&drilled, &ray_x(-1.0)),
+            &[(-1.0, 0.5), (1.5, 3.0)]
+        ));
+
+        // Only closed shapes take part.
+        let open = Csg::new(
+            CsgOp::Union,
+            a,
+            Arc::new(Disk::new(
+                Point3::default(),
+                Vec3::new(1.0, 0.0, 0.0),
+                1.0,
+                5,
+            )),
+        );
+        assert!(!open.hit_all(&r, &mut Vec::new()));
+        let mut rec = HitRecord::default();
+        assert!(!open.hit(&r, 0.001, f32::MAX, &mut rec));
+    }
+
+    #[test]
+    fn test_csg_hit() {
+        let a: Arc<dyn Hitable> =
+            Arc::new(Sphere::new(Point3::new(-1.0, 0.0, 0.0), 1.5, 1));
+        let b: Arc<dyn Hitable> =
+            Arc::new(Sphere::new(Point3::new(1.0, 0.0, 0.0), 1.5, 2));
+        let lens = Csg::new(CsgOp::Difference, a, b);
+        let mut rec = HitRecord::default();
+        let r = ray_x(-10.0);
+        assert!(lens.hit(&r, 0.001, f32::MAX, &mut rec));
+        assert_eq!(rec.material, 1);
+        assert!((rec.normal - Vec3::new(-1.0, 0.0, 0.0)).length() < 1e-5);
+        // The cut surface belongs to the right sphere and faces out of the
+        // result, against the ray.
+        assert!(lens.hit(&r, 8.0, f32::MAX, &mut rec));
+        assert_eq!(rec.material, 2);
+        assert!((rec.t - 9.5).abs() < 1e-4);
+        assert!(rec.normal.x() > 0.0);
+        assert!(!lens.hit(&r, 8.0, 9.0, &mut rec));
+        assert!(!lens.hit(&r, 9.6, f32::MAX, &mut rec));
+
+        let bb = lens.bounding_box(0.0, 0.0).unwrap();
+        assert_eq!(bb.min(), Point3::new(-2.5, -1.5, -1.5));
+        assert_eq!(bb.max(), Point3::new(0.5, 1.5, 1.5));
+    }
+
+    #[test]
+    fn test_csg_bounds() {
+        let a: Arc<dyn Hitable> = Arc::new(Cuboid::new(
+            Point3::new(0.0, 0.0, 0.0),
+            Point3::new(2.0, 2.0, 2.0),
+            1,
+        ));
+        let b: Arc<dyn Hitable> = Arc::new(Cuboid::new(
+            Point3::new(1.0, -1.0, 1.0),
+            Point3::new(3.0, 1.0, 3.0),
+            1,
+        ));
+        let union = Csg::new(CsgOp::Union, a.clone(), b.clone());
+        let bb = union.bounding_box(0.0, 0.0).unwrap();
+        assert_eq!(bb.min(), Point3::new(0.0, -1.0, 0.0));
+        assert_eq!(bb.max(), Point3::new(3.0, 2.0, 3.0));
+        let both = Csg::new(CsgOp::Intersection, a, b);
+        let bb = both.bounding_box(0.0, 0.0).unwrap();
+        assert_eq!(bb.min(), Point3::new(1.0, 0.0, 1.0));
+        assert_eq!(bb.max(), Point3::new(2.0, 1.0, 2.0));
+    }
+}
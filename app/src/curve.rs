@@ -0,0 +1,542 @@
+//! Thin curves for hair and fur.
+//!
+//! A curve is a cubic Bézier with a width that tapers linearly from one end
+//! to the other, intersected by recursive subdivision like pbrt's curve
+//! shape (Nakamaru and Ohno, "Ray Tracing for Curves Primitive", 2002).
+//! Flat curves are ribbons that always face the ray, cylinder curves are
+//! flat as well but shade like a tube, and ribbon curves turn with a normal
+//! given at either end. Every curve is split into segments with their own
+//! tight bounds, so the BVH can cull most of a long strand.
+
+use ::std::clone::Clone;
+use ::std::cmp::Ord;
+use ::std::convert::From;
+use ::std::default::Default;
+use ::std::option::Option::{self, None, Some};
+use ::std::sync::Arc;
+
+use ::math::{Point3, Vec3};
+
+use crate::pbrt::AABB;
+use crate::pbrt::{orthonormal_basis, HitRecord, Hitable, HitableList, Ray};
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum CurveKind {
+    /// A ribbon facing the ray.
+    Flat,
+    /// A ribbon facing the ray with the normals of a tube.
+    Cylinder,
+    /// A ribbon that turns between the normals given at its ends.
+    Ribbon,
+}
+
+/// The most a curve is subdivided when searching for a hit.
+const MAX_DEPTH: i32 = 10;
+
+fn lerp(t: f32, a: Vec3, b: Vec3) -> Vec3 {
+    a + t * (b - a)
+}
+
+/// The polar form of the cubic with control points `cp`.
+fn blossom(cp: &[Vec3; 4], u0: f32, u1: f32, u2: f32) -> Vec3 {
+    let a = [
+        lerp(u0, cp[0], cp[1]),
+        lerp(u0, cp[1], cp[2]),
+        lerp(u0, cp[2], cp[3]),
+    ];
+    let b = [lerp(u1, a[0], a[1]), lerp(u1, a[1], a[2])];
+    lerp(u2, b[0], b[1])
+}
+
+/// The control points of both halves of a cubic, sharing the middle one.
+fn split(cp: &[Vec3; 4]) -> [Vec3; 7] {
+    [
+        cp[0],
+        (cp[0] + cp[1]) / 2.0,
+        (cp[0] + 2.0 * cp[1] + cp[2]) / 4.0,
+        (cp[0] + 3.0 * cp[1] + 3.0 * cp[2] + cp[3]) / 8.0,
+        (cp[1] + 2.0 * cp[2] + cp[3]) / 4.0,
+        (cp[2] + cp[3]) / 2.0,
+        cp[3],
+    ]
+}
+
+/// The point at `u` and the derivative there.
+fn evaluate(cp: &[Vec3; 4], u: f32) -> (Vec3, Vec3) {
+    let a = [
+        lerp(u, cp[0], cp[1]),
+        lerp(u, cp[1], cp[2]),
+        lerp(u, cp[2], cp[3]),
+    ];
+    let b = [lerp(u, a[0], a[1]), lerp(u, a[1], a[2])];
+    let d = b[1] - b[0];
+    let derivative = if d.dot(d) > 0.0 {
+        3.0 * d
+    } else {
+        // Coincident control points at an end.
+        cp[3] - cp[0]
+    };
+    (lerp(u, b[0], b[1]), derivative)
+}
+
+/// The corners of the box around the points `cp`.
+fn bounds(cp: &[Vec3]) -> (Point3, Point3) {
+    let origin = Point3::default();
+    let (mut lo, mut hi) = (origin + cp[0], origin + cp[0]);
+    for &p in &cp[1..] {
+        lo = lo.min(origin + p);
+        hi = hi.max(origin + p);
+    }
+    (lo, hi)
+}
+
+/// Rotates `v` by `radians` around the unit `axis`.
+fn rotate(v: Vec3, axis: Vec3, radians: f32) -> Vec3 {
+    let (sin, cos) = radians.sin_cos();
+    cos * v + sin * axis.cross(&v) + (1.0 - cos) * axis.dot(v) * axis
+}
+
+#[derive(Debug)]
+pub struct Curve {
+    points: [Vec3; 4],
+    widths: (f32, f32),
+    kind: CurveKind,
+    /// The normals at the ends of ribbons.
+    normals: (Vec3, Vec3),
+    material: usize,
+}
+
+impl Curve {
+    /// A curve with the Bézier control `points` that is `widths.0` wide at
+    /// the start and `widths.1` at the end. Only ribbons use `normals`.
+    pub fn new(
+        points: [Point3; 4], widths: (f32, f32), kind: CurveKind,
+        normals: (Vec3, Vec3), material: usize,
+    ) -> Self {
+        let normals = match kind {
+            CurveKind::Ribbon => (normals.0.unit(), normals.1.unit()),
+            _ => normals,
+        };
+        Curve {
+            points: [
+                Vec3::from(points[0]),
+                Vec3::from(points[1]),
+                Vec3::from(points[2]),
+                Vec3::from(points[3]),
+            ],
+            widths,
+            kind,
+            normals,
+            material,
+        }
+    }
+
+    /// A strand of cubic Bézier curves that share their ends, from 3n + 1
+    /// control `points`. The width tapers along the whole strand, and
+    /// ribbons need n + 1 `normals` at the ends of the curves. Each curve
+    /// is split into 2^`split_depth` segments.
+    pub fn strand(
+        points: &[Point3], widths: (f32, f32), kind: CurveKind,
+        normals: &[Vec3], split_depth: u32, material: usize,
+    ) -> HitableList {
+        let n = (points.len() - 1) / 3;
+        let width =
+            |i: usize| widths.0 + (widths.1 - widths.0) * i as f32 / n as f32;
+        let mut hitables = HitableList::default();
+        for i in 0..n {
+            let mut cp = [Point3::default(); 4];
+            cp.copy_from_slice(&points[3 * i..3 * i + 4]);
+            let normals = match kind {
+                CurveKind::Ribbon => (normals[i], normals[i + 1]),
+                _ => (Vec3::default(), Vec3::default()),
+            };
+            let curve = Arc::new(Curve::new(
+                cp,
+                (width(i), width(i + 1)),
+                kind,
+                normals,
+                material,
+            ));
+            let segments = Curve::segments(&curve, split_depth);
+            hitables.list.extend_from_slice(&segments.list);
+        }
+        hitables
+    }
+
+    /// Splits the curve into 2^`split_depth` segments for the BVH.
+    pub fn segments(curve: &Arc<Curve>, split_depth: u32) -> HitableList {
+        let count = 1 << split_depth.min(16);
+        let mut hitables = HitableList::default();
+        for i in 0..count {
+            hitables.list.push(Arc::new(CurveSegment {
+                curve: curve.clone(),
+                u0: i as f32 / count as f32,
+                u1: (i + 1) as f32 / count as f32,
+            }));
+        }
+        hitables
+    }
+
+    fn width(&self, u: f32) -> f32 {
+        self.widths.0 + u * (self.widths.1 - self.widths.0)
+    }
+}
+
+/// The part of a curve between `u0` and `u1`.
+#[derive(Debug)]
+pub struct CurveSegment {
+    curve: Arc<Curve>,
+    u0: f32,
+    u1: f32,
+}
+
+/// The closest hit found so far, in the coordinates of the ray.
+struct Candidate {
+    z: f32,
+    u: f32,
+    v: f32,
+    width: f32,
+    normal: Option<Vec3>,
+}
+
+/// Ray coordinates: the ray starts at the origin and runs along +z, x
+/// follows the curve as far as possible.
+struct Search<'a> {
+    curve: &'a Curve,
+    frame: (Vec3, Vec3, Vec3),
+    /// The ray direction in world space, unit length.
+    direction: Vec3,
+    z_min: f32,
+    z_max: f32,
+    best: Option<Candidate>,
+}
+
+impl<'a> Search<'a> {
+    fn ray_space(&self, v: Vec3) -> Vec3 {
+        let (x, y, z) = self.frame;
+        Vec3::new(v.dot(x), v.dot(y), v.dot(z))
+    }
+
+    fn world_space(&self, v: Vec3) -> Vec3 {
+        let (x, y, z) = self.frame;
+        v.x() * x + v.y() * y + v.z() * z
+    }
+
+    /// Whether the box around `cp` widened by `width` can hold a hit.
+    fn overlaps(&self, cp: &[Vec3], width: f32) -> bool {
+        let half = 0.5 * width;
+        let (lo, hi) = bounds(cp);
+        lo.x() - half <= 0.0
+            && hi.x() + half >= 0.0
+            && lo.y() - half <= 0.0
+            && hi.y() + half >= 0.0
+            && lo.z() - half <= self.z_max
+            && hi.z() + half >= self.z_min
+    }
+
+    fn recurse(&mut self, cp: &[Vec3; 4], u0: f32, u1: f32, depth: i32) {
+        if depth > 0 {
+            let halves = split(cp);
+            let u = [u0, 0.5 * (u0 + u1), u1];
+            for half in 0..2 {
+                let mut part = [Vec3::default(); 4];
+                part.copy_from_slice(&halves[3 * half..3 * half + 4]);
+                let width = self
+                    .curve
+                    .width(u[half])
+                    .max(self.curve.width(u[half + 1]));
+                if self.overlaps(&part, width) {
+                    self.recurse(&part, u[half], u[half + 1], depth - 1);
+                }
+            }
+            return;
+        }
+
+        // The segment is close enough to a line. The ray has to pass
+        // between the perpendiculars to the tangents at both ends.
+        let edge = (cp[1].y() - cp[0].y()) * -cp[0].y()
+            + cp[0].x() * (cp[0].x() - cp[1].x());
+        if edge < 0.0 {
+            return;
+        }
+        let edge = (cp[2].y() - cp[3].y()) * -cp[3].y()
+            + cp[3].x() * (cp[3].x() - cp[2].x());
+        if edge < 0.0 {
+            return;
+        }
+        // The closest point to the ray on the line through the ends.
+        let (dx, dy) = (cp[3].x() - cp[0].x(), cp[3].y() - cp[0].y());
+        let denom = dx * dx + dy * dy;
+        if denom == 0.0 {
+            return;
+        }
+        let w = (-cp[0].x() * dx - cp[0].y() * dy) / denom;
+        let u = (u0 + w * (u1 - u0)).clamp(u0, u1);
+        let mut width = self.curve.width(u);
+        let mut normal = None;
+        if self.curve.kind == CurveKind::Ribbon {
+            let (n0, n1) = self.curve.normals;
+            // Seen edge on, a ribbon is narrower.
+            let n = slerp(u, n0, n1);
+            width *= n.dot(self.direction).abs();
+            normal = Some(n);
+        }
+        let (pc, dpcdw) = evaluate(cp, w.clamp(0.0, 1.0));
+        let distance2 = pc.x() * pc.x() + pc.y() * pc.y();
+        if distance2 > 0.25 * width * width
+            || pc.z() <= self.z_min
+            || pc.z() >= self.z_max
+        {
+            return;
+        }
+        // v runs across the curve, from the right of the tangent to the
+        // left.
+        let distance = distance2.sqrt();
+        let side = dpcdw.x() * -pc.y() + pc.x() * dpcdw.y();
+        let v = if side > 0.0 {
+            0.5 + distance / width
+        } else {
+            0.5 - distance / width
+        };
+        self.z_max = pc.z();
+        self.best = Some(Candidate {
+            z: pc.z(),
+            u,
+            v,
+            width,
+            normal,
+        });
+    }
+}
+
+/// Interpolates between the unit vectors `a` and `b` along the great
+/// circle.
+fn slerp(t: f32, a: Vec3, b: Vec3) -> Vec3 {
+    let cos = a.dot(b).clamp(-1.0, 1.0);
+    let angle = cos.acos();
+    if angle < 1e-4 {
+        return lerp(t, a, b).unit();
+    }
+    let sin = angle.sin();
+    ((1.0 - t) * angle).sin() / sin * a + (t * angle).sin() / sin * b
+}
+
+impl CurveSegment {
+    fn control_points(&self) -> [Vec3; 4] {
+        let (cp, u0, u1) = (&self.curve.points, self.u0, self.u1);
+        [
+            blossom(cp, u0, u0, u0),
+            blossom(cp, u0, u0, u1),
+            blossom(cp, u0, u1, u1),
+            blossom(cp, u1, u1, u1),
+        ]
+    }
+}
+
+impl Hitable for CurveSegment {
+    fn hit(
+        &self, r: &Ray, t_min: f32, t_max: f32, rec: &mut HitRecord,
+    ) -> bool {
+        let curve = &*self.curve;
+        let cp = self.control_points();
+        let length = r.direction.length();
+        let dz = r.direction / length;
+        let chord = cp[3] - cp[0];
+        let across = chord - chord.dot(dz) * dz;
+        let dx = if across.dot(across) > 0.0 {
+            across.unit()
+        } else {
+            orthonormal_basis(dz).0
+        };
+        let mut search = Search {
+            curve,
+            frame: (dx, dz.cross(&dx), dz),
+            direction: dz,
+            z_min: t_min * length,
+            z_max: if t_max < f32::MAX {
+                t_max * length
+            } else {
+                f32::MAX
+            },
+            best: None,
+        };
+        let origin = Vec3::from(r.origin);
+        let cp = [
+            search.ray_space(cp[0] - origin),
+            search.ray_space(cp[1] - origin),
+            search.ray_space(cp[2] - origin),
+            search.ray_space(cp[3] - origin),
+        ];
+        let max_width = curve.width(self.u0).max(curve.width(self.u1));
+        if !search.overlaps(&cp, max_width) {
+            return false;
+        }
+
+        // Subdivide until the pieces are flat to within a twentieth of the
+        // width.
+        let mut flatness = 0.0f32;
+        for i in 0..2 {
+            let d = cp[i] - 2.0 * cp[i + 1] + cp[i + 2];
+            flatness = flatness.max(d.x().abs()).max(d.y().abs());
+            flatness = flatness.max(d.z().abs());
+        }
+        let eps = 0.05 * curve.widths.0.max(curve.widths.1);
+        let depth = (::std::f32::consts::SQRT_2 * 6.0 * flatness / (8.0 * eps))
+            .log2()
+            / 2.0;
+        let depth = if depth.is_nan() {
+            0
+        } else {
+            depth.clamp(0.0, MAX_DEPTH as f32) as i32
+        };
+        search.recurse(&cp, self.u0, self.u1, depth);
+        let hit = match search.best.take() {
+            Some(hit) => hit,
+            None => return false,
+        };
+
+        let (_, dpdu) = evaluate(&curve.points, hit.u);
+        let dpdv = match hit.normal {
+            Some(n) => n.cross(&dpdu).unit() * hit.width,
+            None => {
+                // Across the curve in the plane facing the ray.
+                let d = search.ray_space(dpdu);
+                let mut dpdv = Vec3::new(-d.y(), d.x(), 0.0).unit() * hit.width;
+                if curve.kind == CurveKind::Cylinder {
+                    // Turn the normal around the tangent as on a tube,
+                    // from -90 degrees at one edge to 90 at the other.
+                    let theta = (hit.v - 0.5) * ::std::f32::consts::PI;
+                    dpdv = rotate(dpdv, d.unit(), -theta);
+                }
+                search.world_space(dpdv)
+            }
+        };
+        let t = hit.z / length;
+        rec.t = t;
+        rec.p = r.point_at_param(t);
+        rec.normal = dpdu.cross(&dpdv).unit();
+        rec.material = curve.material;
+        rec.u = hit.u;
+        rec.v = hit.v;
+        rec.dpdu = dpdu;
+        rec.dpdv = dpdv;
+        true
+    }
+
+    fn bounding_box(&self, _: f32, _: f32) -> Option<AABB> {
+        let cp = self.control_points();
+        let half =
+            0.5 * self.curve.width(self.u0).max(self.curve.width(self.u1));
+        let (lo, hi) = bounds(&cp);
+        let pad = Vec3::new(half, half, half);
+        Some(AABB::new(lo - pad, hi + pad))
+    }
+
+    fn material(&self) -> Option<usize> {
+        Some(self.curve.material)
+    }
+}
+
+#[cfg(test)]
+pub mod tests {
+    use super::*;
+    use ::std::assert;
+
+    /// A strand along x from 0 to 3 that bulges up in y.
+    fn strand(kind: CurveKind, normal: Vec3) -> Arc<Curve> {
+        Arc::new(Curve::new(
+            [
+                Point3::new(0.0, 0.0, 0.0),
+                Point3::new(1.0, 0.5, 0.0),
+                Point3::new(2.0, 0.5, 0.0),
+                Point3::new(3.0, 0.0, 0.0),
+            ],
+            (0.2, 0.1),
+            kind,
+            (normal, normal),
+            4,
+        ))
+    }
+
+    fn down(x: f32, y: f32) -> Ray {
+        Ray::new(Point3::new(x, y, 5.0), Vec3::new(0.0, 0.0, -1.0), 0.0, 0)
+    }
+
+    #[test]
+    fn test_bezier() {
+        let cp = [
+            Vec3::new(0.0, 0.0, 0.0),
+            Vec3::new(1.0, 2.0, 0.0),
+            Vec3::new(2.0, 2.0, 0.0),
+            Vec3::new(3.0, 0.0, 0.0),
+        ];
+        let (p, d) = evaluate(&cp, 0.5);
+        assert!((p - Vec3::new(1.5, 1.5, 0.0)).length() < 1e-6);
+        assert!((d - Vec3::new(3.0, 0.0, 0.0)).length() < 1e-6);
+        // The halves and the blossom describe the same curve.
+        let halves = split(&cp);
+        let mut right = [Vec3::default(); 4];
+        right.copy_from_slice(&halves[3..]);
+        assert!(
+            (evaluate(&right, 0.5).0 - evaluate(&cp, 0.75).0).length() < 1e-6
+        );
+        assert!((blossom(&cp, 0.5, 0.5, 1.0) - halves[4]).length() < 1e-6);
+        assert!((blossom(&cp, 0.5, 0.5, 0.5) - p).length() < 1e-6);
+    }
+
+    #[test]
+    fn test_curve_hit() {
+        let segments =
+            Curve::segments(&strand(CurveKind::Flat, Vec3::default()), 2);
+        assert!(segments.list.len() == 4);
+        let mut rec = HitRecord::default();
+        // The middle of the strand is at y = 0.375 and 0.15 wide.
+        assert!(segments.hit(&down(1.5, 0.4), 0.001, f32::MAX, &mut rec));
+        assert!((rec.t - 5.0).abs() < 1e-4);
+        assert!((rec.u - 0.5).abs() < 0.01);
+        assert!(((rec.v - 0.5).abs() - 0.025 / 0.15).abs() < 0.02);
+        assert!(rec.material == 4);
+        assert!(rec.normal.z().abs() > 0.999);
+        assert!((rec.dpdu.unit() - Vec3::new(1.0, 0.0, 0.0)).length() < 1e-3);
+        assert!(!segments.hit(&down(1.5, 0.46), 0.001, f32::MAX, &mut rec));
+        assert!(!segments.hit(&down(1.5, 0.4), 0.001, 4.0, &mut rec));
+        // Beyond the ends.
+        assert!(!segments.hit(&down(-0.05, 0.0), 0.001, f32::MAX, &mut rec));
+        assert!(segments.hit(&down(2.95, 0.0), 0.001, f32::MAX, &mut rec));
+
+        // Along the strand the ray sees its full width too.
+        let r = Ray::new(
+            Point3::new(1.5, 0.4, 5.0),
+            Vec3::new(0.0, 0.0, -1.0),
+            0.0,
+            0,
+        );
+        let cylinder =
+            Curve::segments(&strand(CurveKind::Cylinder, Vec3::default()), 2);
+        assert!(cylinder.hit(&r, 0.001, f32::MAX, &mut rec));
+        // Off center a tube's normal leans outwards.
+        assert!(rec.normal.y().abs() > 0.2 && rec.normal.z().abs() < 0.98);
+
+        // Ribbons seen edge on vanish.
+        let z = Vec3::new(0.0, 0.0, 1.0);
+        let y = Vec3::new(0.0, 1.0, 0.0);
+        let facing = Curve::segments(&strand(CurveKind::Ribbon, z), 2);
+        assert!(facing.hit(&r, 0.001, f32::MAX, &mut rec));
+        let edge_on = Curve::segments(&strand(CurveKind::Ribbon, y), 2);
+        assert!(!edge_on.hit(&r, 0.001, f32::MAX, &mut rec));
+    }
+
+    #[test]
+    fn test_curve_bounds() {
+        let curve = strand(CurveKind::Flat, Vec3::default());
+        let segments = Curve::segments(&curve, 1);
+        let first = segments.list[0].bounding_box(0.0, 0.0).unwrap();
+        // The first half starts at x = 0 with width 0.2 and ends at the
+        // top of the bulge.
+        assert!((first.min().x() + 0.1).abs() < 1e-6);
+        assert!((first.max().x() - 1.5 - 0.1).abs() < 0.2);
+        assert!(first.max().y() < 0.375 + 0.1 + 0.13);
+        let all = segments.bounding_box(0.0, 0.0).unwrap();
+        assert!((all.max().x() - 3.0 - 0.075).abs() < 1e-6);
+    }
+}
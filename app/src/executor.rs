@@ -1,15 +1,35 @@
+//! A fixed-size thread pool.
+//!
+//! Tasks are queued on a channel and picked up by whichever worker is free.
+//! A panicking task is caught on the worker, which then carries on with the
+//! next task. Dropping the pool runs all queued tasks and joins the workers.
+//!
+//! `ThreadPool::spawn` returns a `TaskHandle` to wait for a task's result.
+//! `ThreadPool::scope` runs tasks that borrow from the caller's stack; it
+//! returns only after all of them have finished.
+
 use ::std::{
+    any::Any,
+    assert,
     boxed::Box,
     clone::Clone,
+    fmt::{self, Display, Formatter},
     iter::Iterator,
-    marker::Send,
+    marker::{PhantomData, Send},
+    mem,
     ops::{Drop, FnOnce},
+    option::Option::{self, None, Some},
+    panic::{self, AssertUnwindSafe},
+    result::Result::{self, Err, Ok},
+    string::{String, ToString},
     sync::{
+        atomic::{AtomicBool, AtomicUsize, Ordering},
         mpsc::{channel, Receiver, Sender},
-        {Arc, Mutex},
+        {Arc, Condvar, Mutex, PoisonError},
     },
     thread::{self, JoinHandle},
     vec::Vec,
+    write,
 };
 
 pub struct Task {
@@ -26,35 +46,363 @@ pub trait Executor {
     fn execute(&mut self, task: Task);
 }
 
+/// Why a task did not produce a result.
+#[derive(Debug, Clone, PartialEq)]
+pub enum TaskError {
+    /// The task panicked with this message.
+    Panicked(String),
+    /// The task was cancelled or dropped before it started.
+    Cancelled,
+}
+
+impl Display for TaskError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        match self {
+            TaskError::Panicked(message) => {
+                write!(f, "task panicked: {}", message)
+            }
+            TaskError::Cancelled => write!(f, "task cancelled"),
+        }
+    }
+}
+
+fn panic_message(payload: &(dyn Any + Send)) -> String {
+    if let Some(s) = payload.downcast_ref::<&str>() {
+        s.to_string()
+    } else if let Some(s) = payload.downcast_ref::<String>() {
+        s.clone()
+    } else {
+        "unknown panic payload".to_string()
+    }
+}
+
+/// Waits for the result of a spawned task.
+pub struct TaskHandle<T> {
+    result: Receiver<Result<T, TaskError>>,
+    cancelled: Arc<AtomicBool>,
+}
+
+impl<T> TaskHandle<T> {
+    /// Keeps the task from starting if it is still queued. A running task is
+    /// not interrupted.
+    pub fn cancel(&self) {
+        self.cancelled.store(true, Ordering::SeqCst);
+    }
+
+    /// Blocks until the task has finished.
+    pub fn join(self) -> Result<T, TaskError> {
+        // A task dropped without running also closes the channel.
+        self.result.recv().unwrap_or(Err(TaskError::Cancelled))
+    }
+}
+
+/// Wraps `f` so that it reports its result, or its panic, to the returned
+/// handle.
+fn package<'a, T, F>(
+    f: F, scope: Option<Arc<ScopeState>>,
+) -> (Box<dyn FnOnce() + Send + 'a>, TaskHandle<T>)
+where
+    F: FnOnce() -> T + Send + 'a,
+    T: Send + 'a,
+{
+    let (sender, receiver) = channel();
+    let cancelled = Arc::new(AtomicBool::new(false));
+    let flag = cancelled.clone();
+    let job = move || {
+        // Dropped last, after the result has been sent.
+        let pending = scope.map(Pending);
+        let result = if flag.load(Ordering::SeqCst) {
+            Err(TaskError::Cancelled)
+        } else {
+            panic::catch_unwind(AssertUnwindSafe(f))
+                .map_err(|p| TaskError::Panicked(panic_message(&*p)))
+        };
+        if let (Some(pending), Err(TaskError::Panicked(_))) =
+            (&pending, &result)
+        {
+            pending.0.panics.fetch_add(1, Ordering::SeqCst);
+        }
+        // Nobody may be waiting for the result.
+        let _ = sender.send(result);
+    };
+    let handle = TaskHandle {
+        result: receiver,
+        cancelled,
+    };
+    (Box::new(job), handle)
+}
+
 pub struct ThreadPool {
     threads: Vec<JoinHandle<()>>,
-    sender: Sender<Task>,
+    sender: Option<Sender<Task>>,
 }
 
 impl ThreadPool {
     pub fn new(size: usize) -> Self {
+        assert!(size > 0, "thread pool needs at least one thread");
         let (sender, receiver): (Sender<Task>, Receiver<Task>) = channel();
         let receiver = Arc::new(Mutex::new(receiver));
         let mut threads = Vec::with_capacity(size);
         for _ in 0..size {
             let receiver = Arc::clone(&receiver);
-            threads.push(thread::spawn(move || loop {
-                let task = receiver.lock().unwrap().recv().unwrap();
-                (task.f)();
+            threads.push(thread::spawn(move || {
+                // recv() fails once the pool has dropped its sender.
+                while let Ok(task) = {
+                    let receiver =
+                        receiver.lock().unwrap_or_else(PoisonError::into_inner);
+                    receiver.recv()
+                } {
+                    // Panics are reported through the task's handle, if it
+                    // has one. Either way the worker lives on.
+                    let _ = panic::catch_unwind(AssertUnwindSafe(task.f));
+                }
             }));
         }
-        ThreadPool { threads, sender }
+        ThreadPool {
+            threads,
+            sender: Some(sender),
+        }
+    }
+
+    fn submit(&self, f: Box<dyn FnOnce() + Send + 'static>) {
+        // Workers never exit while the pool holds the sender.
+        self.sender.as_ref().unwrap().send(Task { f }).unwrap()
+    }
+
+    /// Queues `f` and returns a handle to its result.
+    // Rendering only uses scoped tasks so far.
+    #[allow(dead_code)]
+    pub fn spawn<T, F>(&self, f: F) -> TaskHandle<T>
+    where
+        F: FnOnce() -> T + Send + 'static,
+        T: Send + 'static,
+    {
+        let (job, handle) = package(f, None);
+        self.submit(job);
+        handle
+    }
+
+    /// Runs `f` with a `Scope` whose tasks may borrow anything that outlives
+    /// the call. Waits for all of them before returning, and panics if one
+    /// of them panicked and its handle was not joined.
+    ///
+    /// Calling this from a task of the same pool can deadlock when all
+    /// workers end up waiting.
+    pub fn scope<'env, F, R>(&'env self, f: F) -> R
+    where
+        F: for<'scope> FnOnce(&'scope Scope<'scope, 'env>) -> R,
+    {
+        let scope = Scope {
+            pool: self,
+            state: Arc::new(ScopeState {
+                pending: Mutex::new(0),
+                done: Condvar::new(),
+                panics: AtomicUsize::new(0),
+            }),
+            scope: PhantomData,
+            env: PhantomData,
+        };
+        let result = panic::catch_unwind(AssertUnwindSafe(|| f(&scope)));
+        scope.state.wait();
+        match result {
+            Err(payload) => panic::resume_unwind(payload),
+            Ok(_) if scope.state.panics.load(Ordering::SeqCst) > 0 => {
+                ::std::panic!("a scoped task panicked")
+            }
+            Ok(r) => r,
+        }
     }
 }
 
 impl Executor for ThreadPool {
     fn execute(&mut self, task: Task) {
-        self.sender.send(task).unwrap()
+        self.submit(task.f)
     }
 }
 
 impl Drop for ThreadPool {
     fn drop(&mut self) {
+        // Closing the channel lets the workers finish the queued tasks and
+        // then leave their loops.
+        self.sender.take();
         self.threads.drain(..).for_each(|t| t.join().unwrap());
     }
 }
+
+struct ScopeState {
+    pending: Mutex<usize>,
+    done: Condvar,
+    /// Panicked tasks whose handles have not been joined.
+    panics: AtomicUsize,
+}
+
+impl ScopeState {
+    fn wait(&self) {
+        let mut pending =
+            self.pending.lock().unwrap_or_else(PoisonError::into_inner);
+        while *pending > 0 {
+            pending = self
+                .done
+                .wait(pending)
+                .unwrap_or_else(PoisonError::into_inner);
+        }
+    }
+}
+
+/// Counts a scoped task as finished when dropped, whether it ran or not.
+struct Pending(Arc<ScopeState>);
+
+impl Drop for Pending {
+    fn drop(&mut self) {
+        let mut pending = self
+            .0
+            .pending
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner);
+        *pending -= 1;
+        if *pending == 0 {
+            self.0.done.notify_all();
+        }
+    }
+}
+
+/// Spawns tasks that borrow from the environment of `ThreadPool::scope`.
+pub struct Scope<'scope, 'env: 'scope> {
+    pool: &'scope ThreadPool,
+    state: Arc<ScopeState>,
+    scope: PhantomData<&'scope mut &'scope ()>,
+    env: PhantomData<&'env mut &'env ()>,
+}
+
+impl<'scope, 'env> Scope<'scope, 'env> {
+    pub fn spawn<T, F>(&'scope self, f: F) -> ScopedTaskHandle<'scope, T>
+    where
+        F: FnOnce() -> T + Send + 'scope,
+        T: Send + 'scope,
+    {
+        *self
+            .state
+            .pending
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner) += 1;
+        let (job, handle) = package(f, Some(self.state.clone()));
+        // Safe because `ThreadPool::scope` does not return before the job
+        // has run or been dropped, so nothing it borrows can go away first.
+        let job = unsafe {
+            mem::transmute::<
+                Box<dyn FnOnce() + Send + 'scope>,
+                Box<dyn FnOnce() + Send + 'static>,
+            >(job)
+        };
+        self.pool.submit(job);
+        ScopedTaskHandle {
+            handle,
+            state: self.state.clone(),
+            scope: PhantomData,
+        }
+    }
+}
+
+/// A `TaskHandle` that cannot outlive its scope.
+pub struct ScopedTaskHandle<'scope, T> {
+    handle: TaskHandle<T>,
+    state: Arc<ScopeState>,
+    scope: PhantomData<&'scope ()>,
+}
+
+impl<'scope, T> ScopedTaskHandle<'scope, T> {
+    pub fn cancel(&self) {
+        self.handle.cancel()
+    }
+
+    /// Blocks until the task has finished. A panic returned here is no
+    /// longer raised again by `ThreadPool::scope`.
+    pub fn join(self) -> Result<T, TaskError> {
+        let result = self.handle.join();
+        if let Err(TaskError::Panicked(_)) = result {
+            self.state.panics.fetch_sub(1, Ordering::SeqCst);
+        }
+        result
+    }
+}
+
+#[cfg(test)]
+pub mod tests {
+    use super::*;
+    use ::std::iter::IntoIterator;
+    use ::std::sync::mpsc::sync_channel;
+    use ::std::{assert, assert_eq, panic, vec};
+
+    #[test]
+    fn test_spawn_and_join() {
+        let pool = ThreadPool::new(3);
+        let handles: Vec<_> =
+            (0..10).map(|i| pool.spawn(move || i * i)).collect();
+        let results: Vec<_> =
+            handles.into_iter().map(|h| h.join().unwrap()).collect();
+        assert_eq!(results, vec![0, 1, 4, 9, 16, 25, 36, 49, 64, 81]);
+    }
+
+    #[test]
+    fn test_panic_is_returned() {
+        let pool = ThreadPool::new(1);
+        let failed = pool.spawn(|| -> i32 { panic!("boom") });
+        assert_eq!(failed.join(), Err(TaskError::Panicked("boom".to_string())));
+        // The worker survived.
+        assert_eq!(pool.spawn(|| 1).join(), Ok(1));
+    }
+
+    #[test]
+    fn test_drop_runs_queued_tasks() {
+        let counter = Arc::new(AtomicUsize::new(0));
+        let mut pool = ThreadPool::new(2);
+        for _ in 0..20 {
+            let counter = counter.clone();
+            pool.execute(Task::new(move || {
+                counter.fetch_add(1, Ordering::SeqCst);
+            }));
+        }
+        ::std::mem::drop(pool);
+        assert_eq!(counter.load(Ordering::SeqCst), 20);
+    }
+
+    #[test]
+    fn test_cancel() {
+        let pool = ThreadPool::new(1);
+        let (release, blocked) = sync_channel::<()>(0);
+        let first = pool.spawn(move || blocked.recv().unwrap());
+        let second = pool.spawn(|| 2);
+        second.cancel();
+        release.send(()).unwrap();
+        assert_eq!(first.join(), Ok(()));
+        assert_eq!(second.join(), Err(TaskError::Cancelled));
+    }
+
+    #[test]
+    fn test_scope_borrows() {
+        let pool = ThreadPool::new(4);
+        let data = [1, 2, 3, 4, 5, 6, 7, 8];
+        let sum = pool.scope(|s| {
+            let handles: Vec<_> = data
+                .chunks(3)
+                .map(|chunk| s.spawn(move || chunk.iter().sum::<i32>()))
+                .collect();
+            handles.into_iter().map(|h| h.join().unwrap()).sum::<i32>()
+        });
+        assert_eq!(sum, 36);
+    }
+
+    #[test]
+    fn test_scope_unjoined_panic() {
+        let pool = ThreadPool::new(2);
+        let result = panic::catch_unwind(AssertUnwindSafe(|| {
+            pool.scope(|s| {
+                s.spawn(|| panic!("boom"));
+            })
+        }));
+        assert!(result.is_err());
+        // A joined panic is only reported through the handle.
+        let message = pool.scope(|s| s.spawn(|| panic!("boom")).join());
+        assert_eq!(message, Err(TaskError::Panicked("boom".to_string())));
+    }
+}
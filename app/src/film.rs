@@ -0,0 +1,439 @@
+//! Accumulates radiance samples into pixels.
+//!
+//! Samples are given in raster space: x to the right, y down, and pixel
+//! (i, j) covering [i, i + 1) x [j, j + 1). Each sample is weighted into
+//! every pixel whose center is within the reconstruction filter's radius.
+//! Splats, as used by light tracing, are added to the one pixel they land in
+//! without a weight.
+//!
+//! Parallel renderers record into one `FilmTile` per image tile and merge
+//! the tiles in a fixed order, which keeps the result independent of
+//! scheduling.
+
+use ::std::boxed::Box;
+use ::std::clone::Clone;
+use ::std::cmp;
+use ::std::default::Default;
+use ::std::f32::consts::PI;
+use ::std::fmt::Debug;
+use ::std::iter::Iterator;
+use ::std::marker::{Send, Sync};
+use ::std::vec::Vec;
+use ::std::{assert, vec};
+
+use ::math::Vec3;
+
+use crate::image::Image;
+use crate::render::Tile;
+
+/// A separable reconstruction filter, zero outside `[-radius, radius]`. The
+/// weight at offset (x, y) is `evaluate_1d(x) * evaluate_1d(y)`.
+pub trait Filter: Debug + Send + Sync {
+    fn radius(&self) -> f32;
+
+    fn evaluate_1d(&self, x: f32) -> f32;
+}
+
+#[derive(Debug, Clone)]
+pub struct BoxFilter {
+    pub radius: f32,
+}
+
+impl Filter for BoxFilter {
+    fn radius(&self) -> f32 {
+        self.radius
+    }
+
+    fn evaluate_1d(&self, x: f32) -> f32 {
+        if x.abs() <= self.radius {
+            1.0
+        } else {
+            0.0
+        }
+    }
+}
+
+/// A triangle falling off linearly to zero at the radius.
+#[derive(Debug, Clone)]
+pub struct TentFilter {
+    pub radius: f32,
+}
+
+impl Filter for TentFilter {
+    fn radius(&self) -> f32 {
+        self.radius
+    }
+
+    fn evaluate_1d(&self, x: f32) -> f32 {
+        (self.radius - x.abs()).max(0.0)
+    }
+}
+
+/// `exp(-alpha x^2)`, shifted down so that it reaches zero at the radius.
+#[derive(Debug, Clone)]
+pub struct GaussianFilter {
+    pub radius: f32,
+    pub alpha: f32,
+}
+
+impl Filter for GaussianFilter {
+    fn radius(&self) -> f32 {
+        self.radius
+    }
+
+    fn evaluate_1d(&self, x: f32) -> f32 {
+        let edge = (-self.alpha * self.radius * self.radius).exp();
+        ((-self.alpha * x * x).exp() - edge).max(0.0)
+    }
+}
+
+/// Mitchell and Netravali, "Reconstruction Filters in Computer Graphics",
+/// 1988. `b = c = 1/3` is the recommended compromise between blurring and
+/// ringing.
+#[derive(Debug, Clone)]
+pub struct MitchellFilter {
+    pub radius: f32,
+    pub b: f32,
+    pub c: f32,
+}
+
+impl Filter for MitchellFilter {
+    fn radius(&self) -> f32 {
+        self.radius
+    }
+
+    fn evaluate_1d(&self, x: f32) -> f32 {
+        // The cubic is defined on [-2, 2].
+        let x = (2.0 * x / self.radius).abs();
+        let (b, c) = (self.b, self.c);
+        let v = if x > 2.0 {
+            0.0
+        } else if x > 1.0 {
+            (-b - 6.0 * c) * x * x * x
+                + (6.0 * b + 30.0 * c) * x * x
+                + (-12.0 * b - 48.0 * c) * x
+                + (8.0 * b + 24.0 * c)
+        } else {
+            (12.0 - 9.0 * b - 6.0 * c) * x * x * x
+                + (-18.0 + 12.0 * b + 6.0 * c) * x * x
+                + (6.0 - 2.0 * b)
+        };
+        v / 6.0
+    }
+}
+
+/// A windowed sinc. `tau` is the number of sinc lobes within the radius.
+#[derive(Debug, Clone)]
+pub struct LanczosFilter {
+    pub radius: f32,
+    pub tau: f32,
+}
+
+fn sinc(x: f32) -> f32 {
+    let x = x.abs();
+    if x < 1e-5 {
+        1.0
+    } else {
+        (PI * x).sin() / (PI * x)
+    }
+}
+
+impl Filter for LanczosFilter {
+    fn radius(&self) -> f32 {
+        self.radius
+    }
+
+    fn evaluate_1d(&self, x: f32) -> f32 {
+        let x = x.abs();
+        if x > self.radius {
+            return 0.0;
+        }
+        let x = x / self.radius;
+        sinc(x * self.tau) * sinc(x)
+    }
+}
+
+/// A filter by name and parameters, as stored in scene files.
+#[derive(Debug, Clone, PartialEq)]
+pub enum FilterDesc {
+    Box { radius: f32 },
+    Tent { radius: f32 },
+    Gaussian { radius: f32, alpha: f32 },
+    Mitchell { radius: f32, b: f32, c: f32 },
+    Lanczos { radius: f32, tau: f32 },
+}
+
+impl Default for FilterDesc {
+    /// One sample per pixel footprint, which is what plain averaging does.
+    fn default() -> Self {
+        FilterDesc::Box { radius: 0.5 }
+    }
+}
+
+impl FilterDesc {
+    pub fn build(&self) -> Box<dyn Filter> {
+        match *self {
+            FilterDesc::Box { radius } => Box::new(BoxFilter { radius }),
+            FilterDesc::Tent { radius } => Box::new(TentFilter { radius }),
+            FilterDesc::Gaussian { radius, alpha } => {
+                Box::new(GaussianFilter { radius, alpha })
+            }
+            FilterDesc::Mitchell { radius, b, c } => {
+                Box::new(MitchellFilter { radius, b, c })
+            }
+            FilterDesc::Lanczos { radius, tau } => {
+                Box::new(LanczosFilter { radius, tau })
+            }
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, Default)]
+struct FilmPixel {
+    /// Sum of weight * radiance.
+    sum: Vec3,
+    weight: f32,
+}
+
+/// Pixels `x0..x1` by `y0..y1` of a film.
+#[derive(Debug)]
+pub struct FilmTile {
+    x0: usize,
+    y0: usize,
+    x1: usize,
+    y1: usize,
+    pixels: Vec<FilmPixel>,
+    splats: Vec<(f32, f32, Vec3)>,
+}
+
+impl FilmTile {
+    /// Adds a filtered sample. Only the pixels inside the tile are touched,
+    /// so a tile must cover the filter's footprint around its samples.
+    pub fn add_sample(
+        &mut self, filter: &dyn Filter, x: f32, y: f32, radiance: Vec3,
+    ) {
+        // Skip NaN and infinite samples rather than spoil whole pixels.
+        if !(radiance.x() + radiance.y() + radiance.z()).is_finite() {
+            return;
+        }
+        let r = filter.radius();
+        let px0 = cmp::max((x - 0.5 - r).ceil() as isize, self.x0 as isize);
+        let py0 = cmp::max((y - 0.5 - r).ceil() as isize, self.y0 as isize);
+        let px1 =
+            cmp::min((x - 0.5 + r).floor() as isize + 1, self.x1 as isize);
+        let py1 =
+            cmp::min((y - 0.5 + r).floor() as isize + 1, self.y1 as isize);
+        let width = self.x1 - self.x0;
+        for py in py0..py1 {
+            let wy = filter.evaluate_1d(py as f32 + 0.5 - y);
+            for px in px0..px1 {
+                let w = filter.evaluate_1d(px as f32 + 0.5 - x) * wy;
+                let i =
+                    (py as usize - self.y0) * width + (px as usize - self.x0);
+                let pixel = &mut self.pixels[i];
+                pixel.sum += w * radiance;
+                pixel.weight += w;
+            }
+        }
+    }
+
+    /// Adds `radiance` to the pixel at `(x, y)`, which may lie anywhere on
+    /// the film.
+    // No integrator traces light paths yet.
+    #[allow(dead_code)]
+    pub fn add_splat(&mut self, x: f32, y: f32, radiance: Vec3) {
+        if (radiance.x() + radiance.y() + radiance.z()).is_finite() {
+            self.splats.push((x, y, radiance));
+        }
+    }
+}
+
+#[derive(Debug)]
+pub struct Film {
+    pub width: usize,
+    pub height: usize,
+    /// The radius of the filter that samples are added with.
+    radius: f32,
+    pixels: Vec<FilmPixel>,
+    splats: Vec<Vec3>,
+}
+
+impl Film {
+    /// An empty film for samples added with `filter`.
+    pub fn new(width: usize, height: usize, filter: &dyn Filter) -> Self {
+        Film {
+            width,
+            height,
+            radius: filter.radius(),
+            pixels: vec![FilmPixel::default(); width * height],
+            splats: vec![Vec3::default(); width * height],
+        }
+    }
+
+    /// An empty tile for the samples of `tile`, grown by the filter radius.
+    pub fn tile(&self, tile: Tile) -> FilmTile {
+        let r = (self.radius - 0.5).max(0.0).ceil() as usize;
+        let x0 = tile.x0.saturating_sub(r);
+        let y0 = tile.y0.saturating_sub(r);
+        let x1 = cmp::min(tile.x1 + r, self.width);
+        let y1 = cmp::min(tile.y1 + r, self.height);
+        FilmTile {
+            x0,
+            y0,
+            x1,
+            y1,
+            pixels: vec![FilmPixel::default(); (x1 - x0) * (y1 - y0)],
+            splats: Vec::new(),
+        }
+    }
+
+    /// Adds a tile's contributions to the film.
+    pub fn merge(&mut self, tile: FilmTile) {
+        let width = tile.x1 - tile.x0;
+        for y in tile.y0..tile.y1 {
+            for x in tile.x0..tile.x1 {
+                let from = tile.pixels[(y - tile.y0) * width + (x - tile.x0)];
+                let to = &mut self.pixels[y * self.width + x];
+                to.sum += from.sum;
+                to.weight += from.weight;
+            }
+        }
+        for (x, y, radiance) in tile.splats {
+            self.add_splat(x, y, radiance);
+        }
+    }
+
+    pub fn add_splat(&mut self, x: f32, y: f32, radiance: Vec3) {
+        if x < 0.0 || y < 0.0 {
+            return;
+        }
+        let (px, py) = (x as usize, y as usize);
+        if px < self.width && py < self.height {
+            self.splats[py * self.width + px] += radiance;
+        }
+    }
+
+    /// Resolves the film. Splats are scaled by `splat_scale`, typically one
+    /// over the number of light paths per pixel.
+    pub fn image(&self, splat_scale: f32) -> Image {
+        assert!(splat_scale.is_finite());
+        let mut image = Image::new(self.width, self.height);
+        for (i, (p, splat)) in self.pixels.iter().zip(&self.splats).enumerate()
+        {
+            let mut color = *splat * splat_scale;
+            // Negative lobes can cancel the weight out entirely.
+            if p.weight != 0.0 {
+                color += p.sum / p.weight;
+            }
+            image.pixels[i] = color;
+        }
+        image
+    }
+}
+
+#[cfg(test)]
+pub mod tests {
+    use super::*;
+    use ::std::assert_eq;
+
+    fn filters() -> Vec<FilterDesc> {
+        vec![
+            FilterDesc::Box { radius: 0.5 },
+            FilterDesc::Tent { radius: 1.0 },
+            FilterDesc::Gaussian {
+                radius: 1.5,
+                alpha: 2.0,
+            },
+            FilterDesc::Mitchell {
+                radius: 2.0,
+                b: 1.0 / 3.0,
+                c: 1.0 / 3.0,
+            },
+            FilterDesc::Lanczos {
+                radius: 3.0,
+                tau: 3.0,
+            },
+        ]
+    }
+
+    #[test]
+    fn test_filters() {
+        for desc in filters() {
+            let filter = desc.build();
+            let r = filter.radius();
+            assert!(filter.evaluate_1d(0.0) > 0.0, "{:?}", desc);
+            assert_eq!(filter.evaluate_1d(r + 0.01), 0.0, "{:?}", desc);
+            assert_eq!(
+                filter.evaluate_1d(0.3),
+                filter.evaluate_1d(-0.3),
+                "{:?}",
+                desc
+            );
+        }
+        // Mitchell's cubic passes through 8/9 (with b = c = 1/3) at zero.
+        let mitchell = filters()[3].build();
+        assert!((mitchell.evaluate_1d(0.0) - 8.0 / 9.0).abs() < 1e-6);
+    }
+
+    #[test]
+    fn test_constant_radiance() {
+        // Any normalized filter reproduces a constant signal.
+        for desc in filters() {
+            let filter = desc.build();
+            let mut film = Film::new(8, 6, &*filter);
+            let mut tile = film.tile(Tile {
+                x0: 0,
+                y0: 0,
+                x1: 8,
+                y1: 6,
+            });
+            for y in 0..6 * 4 {
+                for x in 0..8 * 4 {
+                    let (fx, fy) =
+                        (x as f32 * 0.25 + 0.1, y as f32 * 0.25 + 0.1);
+                    tile.add_sample(&*filter, fx, fy, Vec3::new(0.5, 1.0, 2.0));
+                }
+            }
+            film.merge(tile);
+            let image = film.image(0.0);
+            for p in &image.pixels {
+                assert!((p.y() - 1.0).abs() < 1e-4, "{:?}: {:?}", desc, p);
+            }
+        }
+    }
+
+    #[test]
+    fn test_tiles_and_splats() {
+        let filter = FilterDesc::Gaussian {
+            radius: 1.5,
+            alpha: 2.0,
+        }
+        .build();
+        let mut whole = Film::new(4, 4, &*filter);
+        let mut tiled = Film::new(4, 4, &*filter);
+        let all = Tile {
+            x0: 0,
+            y0: 0,
+            x1: 4,
+            y1: 4,
+        };
+        let left = Tile { x1: 2, ..all };
+        let mut tile = tiled.tile(left);
+        assert_eq!((tile.x0, tile.x1, tile.y1), (0, 3, 4));
+        tile.add_sample(&*filter, 1.9, 2.2, Vec3::new(1.0, 1.0, 1.0));
+        // A splat outside the tile still lands on the film.
+        tile.add_splat(3.5, 0.5, Vec3::new(2.0, 0.0, 0.0));
+        tiled.merge(tile);
+        let mut tile = whole.tile(all);
+        tile.add_sample(&*filter, 1.9, 2.2, Vec3::new(1.0, 1.0, 1.0));
+        whole.merge(tile);
+        whole.add_splat(3.5, 0.5, Vec3::new(2.0, 0.0, 0.0));
+
+        let (a, b) = (whole.image(0.5), tiled.image(0.5));
+        for (a, b) in a.pixels.iter().zip(&b.pixels) {
+            assert_eq!(a.x().to_bits(), b.x().to_bits());
+        }
+        // Out of the filter's reach, so only the splat.
+        assert_eq!(a.pixel(3, 0).x(), 1.0);
+    }
+}
@@ -0,0 +1,515 @@
+//! Scattering from hair fibers after pbrt's hair BSDF (d'Eon et al., "An
+//! Energy-Conserving Hair Reflectance Model", 2011, and Chiang et al., "A
+//! Practical and Controllable Hair and Fur Model for Production Path
+//! Tracing", 2016).
+//!
+//! A fiber is a rough dielectric cylinder. Light reflects off the surface
+//! (R), passes through the fiber (TT) or reflects once inside it (TRT), and
+//! all longer paths are lumped into one more lobe. The pigment in the
+//! fiber absorbs along the way. The fiber runs along `dpdu` of the hit and
+//! `v` goes across it, as for curves.
+
+use ::std::clone::Clone;
+use ::std::default::Default;
+use ::std::f32::consts::LN_2;
+use ::std::iter::Iterator;
+use ::std::option::Option::{self, None, Some};
+
+use ::math::Vec3;
+
+use crate::material::fresnel_dielectric;
+use crate::pbrt::{
+    orthonormal_basis, BsdfSample, HitRecord, Material, Ray, RNG,
+};
+
+const PI: f32 = ::std::f32::consts::PI;
+
+/// The number of lobes that are modelled separately, the rest is the
+/// last one.
+const P_MAX: usize = 3;
+
+/// How a fiber absorbs light.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum HairAbsorption {
+    /// The absorption coefficient, relative to the fiber diameter.
+    SigmaA(Vec3),
+    /// The color the hair should look like after many bounces.
+    Color(Vec3),
+    /// The concentrations of the two melanin pigments. Eumelanin makes
+    /// hair brown to black, pheomelanin red.
+    Melanin { eumelanin: f32, pheomelanin: f32 },
+}
+
+impl HairAbsorption {
+    /// The absorption coefficient for fibers with the azimuthal roughness
+    /// `beta_n`.
+    pub fn sigma_a(&self, beta_n: f32) -> Vec3 {
+        match *self {
+            HairAbsorption::SigmaA(sigma_a) => sigma_a,
+            HairAbsorption::Color(c) => {
+                // Chiang et al., equation 9.
+                let b = beta_n;
+                let d = 5.969 - 0.215 * b + 2.532 * b.powi(2)
+                    - 10.73 * b.powi(3)
+                    + 5.574 * b.powi(4)
+                    + 0.245 * b.powi(5);
+                let f = |c: f32| (c.max(1e-4).ln() / d).powi(2);
+                Vec3::new(f(c.x()), f(c.y()), f(c.z()))
+            }
+            HairAbsorption::Melanin {
+                eumelanin,
+                pheomelanin,
+            } => {
+                eumelanin * Vec3::new(0.419, 0.697, 1.37)
+                    + pheomelanin * Vec3::new(0.187, 0.4, 1.05)
+            }
+        }
+    }
+}
+
+fn luminance(c: Vec3) -> f32 {
+    0.2126 * c.x() + 0.7152 * c.y() + 0.0722 * c.z()
+}
+
+fn safe_sqrt(x: f32) -> f32 {
+    x.max(0.0).sqrt()
+}
+
+fn safe_asin(x: f32) -> f32 {
+    x.clamp(-1.0, 1.0).asin()
+}
+
+/// The modified Bessel function of the first kind of order 0.
+fn i0(x: f32) -> f32 {
+    let mut sum = 0.0;
+    let mut x2i = 1.0;
+    let mut factorial = 1.0;
+    let mut four_i = 1.0;
+    for i in 0..10 {
+        if i > 1 {
+            factorial *= i as f32;
+        }
+        sum += x2i / (four_i * factorial * factorial);
+        x2i *= x * x;
+        four_i *= 4.0;
+    }
+    sum
+}
+
+fn log_i0(x: f32) -> f32 {
+    if x > 12.0 {
+        x + 0.5 * (-(2.0 * PI).ln() + (1.0 / x).ln() + 1.0 / (8.0 * x))
+    } else {
+        i0(x).ln()
+    }
+}
+
+/// The longitudinal scattering function with the variance `v`.
+fn mp(cos_i: f32, cos_o: f32, sin_i: f32, sin_o: f32, v: f32) -> f32 {
+    let a = cos_i * cos_o / v;
+    let b = sin_i * sin_o / v;
+    if v <= 0.1 {
+        // In logarithms to stay finite for smooth fibers.
+        (log_i0(a) - b - 1.0 / v + LN_2 + (1.0 / (2.0 * v)).ln()).exp()
+    } else {
+        (-b).exp() * i0(a) / ((1.0 / v).sinh() * 2.0 * v)
+    }
+}
+
+/// The attenuation of each lobe, from Fresnel reflection at the surface
+/// and the transmittance `t` of one pass through the fiber.
+fn ap(cos_o: f32, eta: f32, h: f32, t: Vec3) -> [Vec3; P_MAX + 1] {
+    let cos_gamma_o = safe_sqrt(1.0 - h * h);
+    let f = fresnel_dielectric(cos_o * cos_gamma_o, eta);
+    let mut ap = [Vec3::new(f, f, f); P_MAX + 1];
+    ap[1] = (1.0 - f) * (1.0 - f) * t;
+    for p in 2..P_MAX {
+        ap[p] = ap[p - 1] * t * f;
+    }
+    let rest = ap[P_MAX - 1] * f * t;
+    let one = |x: f32| 1.0 - x * f;
+    ap[P_MAX] = Vec3::new(
+        rest.x() / one(t.x()),
+        rest.y() / one(t.y()),
+        rest.z() / one(t.z()),
+    );
+    ap
+}
+
+/// The azimuthal angle by which lobe `p` leaves the fiber.
+fn phi(p: usize, gamma_o: f32, gamma_t: f32) -> f32 {
+    let p = p as f32;
+    2.0 * p * gamma_t - 2.0 * gamma_o + p * PI
+}
+
+fn logistic(x: f32, s: f32) -> f32 {
+    let e = (-x.abs() / s).exp();
+    e / (s * (1.0 + e) * (1.0 + e))
+}
+
+fn logistic_cdf(x: f32, s: f32) -> f32 {
+    1.0 / (1.0 + (-x / s).exp())
+}
+
+/// The logistic distribution with scale `s` restricted to [-π, π].
+fn trimmed_logistic(x: f32, s: f32) -> f32 {
+    logistic(x, s) / (logistic_cdf(PI, s) - logistic_cdf(-PI, s))
+}
+
+fn sample_trimmed_logistic(u: f32, s: f32) -> f32 {
+    let k = logistic_cdf(PI, s) - logistic_cdf(-PI, s);
+    let x = -s * (1.0 / (u * k + logistic_cdf(-PI, s)) - 1.0).ln();
+    x.clamp(-PI, PI)
+}
+
+/// The azimuthal scattering function of lobe `p`.
+fn np(phi_: f32, p: usize, s: f32, gamma_o: f32, gamma_t: f32) -> f32 {
+    let mut dphi = phi_ - phi(p, gamma_o, gamma_t);
+    while dphi > PI {
+        dphi -= 2.0 * PI;
+    }
+    while dphi < -PI {
+        dphi += 2.0 * PI;
+    }
+    trimmed_logistic(dphi, s)
+}
+
+#[derive(Debug)]
+pub struct Hair {
+    sigma_a: Vec3,
+    eta: f32,
+    /// The longitudinal variance of each lobe.
+    v: [f32; P_MAX + 1],
+    /// The azimuthal logistic scale.
+    s: f32,
+    /// The sines and cosines of 2, 4 and 8 times the scale tilt.
+    sin_2k_alpha: [f32; 3],
+    cos_2k_alpha: [f32; 3],
+}
+
+/// Directions in the frame of a fiber: x along it, z along the normal.
+struct Local {
+    sin_theta: f32,
+    cos_theta: f32,
+    phi: f32,
+}
+
+impl Local {
+    fn new(w: Vec3) -> Self {
+        let sin_theta = w.x().clamp(-1.0, 1.0);
+        Local {
+            sin_theta,
+            cos_theta: safe_sqrt(1.0 - sin_theta * sin_theta),
+            phi: w.z().atan2(w.y()),
+        }
+    }
+}
+
+/// What the lobes of a hit have in common.
+struct Fiber {
+    gamma_o: f32,
+    gamma_t: f32,
+    /// The attenuation of each lobe.
+    ap: [Vec3; P_MAX + 1],
+}
+
+impl Hair {
+    /// A fiber with the refractive index `eta`, the longitudinal and
+    /// azimuthal roughness `beta_m` and `beta_n` in [0, 1] and scales that
+    /// tilt the fiber surface by `alpha` degrees.
+    pub fn new(
+        sigma_a: Vec3, eta: f32, beta_m: f32, beta_n: f32, alpha: f32,
+    ) -> Self {
+        let beta_m = beta_m.clamp(1e-3, 1.0);
+        let beta_n = beta_n.clamp(1e-3, 1.0);
+        let v0 =
+            (0.726 * beta_m + 0.812 * beta_m.powi(2) + 3.7 * beta_m.powi(20))
+                .powi(2);
+        let s = (PI / 8.0).sqrt()
+            * (0.265 * beta_n
+                + 1.194 * beta_n.powi(2)
+                + 5.372 * beta_n.powi(22));
+        let mut sin_2k_alpha = [alpha.to_radians().sin(); 3];
+        let mut cos_2k_alpha = [safe_sqrt(1.0 - sin_2k_alpha[0].powi(2)); 3];
+        for i in 1..3 {
+            let (sin, cos) = (sin_2k_alpha[i - 1], cos_2k_alpha[i - 1]);
+            sin_2k_alpha[i] = 2.0 * cos * sin;
+            cos_2k_alpha[i] = cos * cos - sin * sin;
+        }
+        Hair {
+            sigma_a,
+            eta,
+            v: [v0, 0.25 * v0, 4.0 * v0, 4.0 * v0],
+            s,
+            sin_2k_alpha,
+            cos_2k_alpha,
+        }
+    }
+
+    /// The frame of the fiber at `rec`: along it, across it and the
+    /// normal.
+    fn frame(rec: &HitRecord) -> (Vec3, Vec3, Vec3) {
+        let n = rec.normal.unit();
+        let along = rec.dpdu - rec.dpdu.dot(n) * n;
+        let ss = if along.dot(along) > 1e-12 {
+            along.unit()
+        } else {
+            orthonormal_basis(n).0
+        };
+        (ss, n.cross(&ss), n)
+    }
+
+    fn to_local(frame: &(Vec3, Vec3, Vec3), w: Vec3) -> Vec3 {
+        Vec3::new(w.dot(frame.0), w.dot(frame.1), w.dot(frame.2))
+    }
+
+    fn fiber(&self, rec: &HitRecord, o: &Local) -> Fiber {
+        let h = (2.0 * rec.v - 1.0).clamp(-1.0, 1.0);
+        let eta = self.eta;
+        // Refraction into the fiber, projected onto the normal plane with
+        // Bravais' index.
+        let sin_theta_t = o.sin_theta / eta;
+        let cos_theta_t = safe_sqrt(1.0 - sin_theta_t * sin_theta_t);
+        let etap = safe_sqrt(eta * eta - o.sin_theta * o.sin_theta)
+            / o.cos_theta.max(1e-6);
+        let sin_gamma_t = h / etap;
+        let cos_gamma_t = safe_sqrt(1.0 - sin_gamma_t * sin_gamma_t);
+        let d = 2.0 * cos_gamma_t / cos_theta_t.max(1e-6);
+        let t = Vec3::new(
+            (-self.sigma_a.x() * d).exp(),
+            (-self.sigma_a.y() * d).exp(),
+            (-self.sigma_a.z() * d).exp(),
+        );
+        Fiber {
+            gamma_o: safe_asin(h),
+            gamma_t: safe_asin(sin_gamma_t),
+            ap: ap(o.cos_theta, eta, h, t),
+        }
+    }
+
+    /// The outgoing elevation seen by lobe `p`, shifted by the tilt of the
+    /// scales.
+    fn tilt(&self, p: usize, o: &Local) -> (f32, f32) {
+        let (sin, cos) = (o.sin_theta, o.cos_theta);
+        let (s, c) = (&self.sin_2k_alpha, &self.cos_2k_alpha);
+        let (sin_op, cos_op) = match p {
+            0 => (sin * c[1] - cos * s[1], cos * c[1] + sin * s[1]),
+            1 => (sin * c[0] + cos * s[0], cos * c[0] - sin * s[0]),
+            2 => (sin * c[2] + cos * s[2], cos * c[2] - sin * s[2]),
+            _ => (sin, cos),
+        };
+        (sin_op, cos_op.abs())
+    }
+
+    /// The probability of sampling each lobe.
+    fn lobe_pdf(fiber: &Fiber) -> [f32; P_MAX + 1] {
+        let sum: f32 = fiber.ap.iter().map(|&a| luminance(a)).sum();
+        let mut pdf = [1.0 / (P_MAX + 1) as f32; P_MAX + 1];
+        if sum > 0.0 {
+            for (pdf, &a) in pdf.iter_mut().zip(&fiber.ap) {
+                *pdf = luminance(a) / sum;
+            }
+        }
+        pdf
+    }
+
+    fn pdf_local(&self, fiber: &Fiber, o: &Local, i: &Local) -> f32 {
+        let lobe_pdf = Hair::lobe_pdf(fiber);
+        let dphi = i.phi - o.phi;
+        let mut pdf = 0.0;
+        for (p, &lobe) in lobe_pdf.iter().enumerate().take(P_MAX) {
+            let (sin_op, cos_op) = self.tilt(p, o);
+            pdf += mp(i.cos_theta, cos_op, i.sin_theta, sin_op, self.v[p])
+                * lobe
+                * np(dphi, p, self.s, fiber.gamma_o, fiber.gamma_t);
+        }
+        let v = self.v[P_MAX];
+        pdf + mp(i.cos_theta, o.cos_theta, i.sin_theta, o.sin_theta, v)
+            * lobe_pdf[P_MAX]
+            / (2.0 * PI)
+    }
+
+    fn eval_local(&self, fiber: &Fiber, o: &Local, i: &Local) -> Vec3 {
+        let dphi = i.phi - o.phi;
+        let mut f = Vec3::default();
+        for p in 0..P_MAX {
+            let (sin_op, cos_op) = self.tilt(p, o);
+            let m = mp(i.cos_theta, cos_op, i.sin_theta, sin_op, self.v[p]);
+            let n = np(dphi, p, self.s, fiber.gamma_o, fiber.gamma_t);
+            f += m * n * fiber.ap[p];
+        }
+        let v = self.v[P_MAX];
+        let m = mp(i.cos_theta, o.cos_theta, i.sin_theta, o.sin_theta, v);
+        // Our BSDFs include the cosine, which this one divides out.
+        f + m / (2.0 * PI) * fiber.ap[P_MAX]
+    }
+}
+
+impl Material for Hair {
+    fn sample(
+        &self, rng: &mut RNG, ray: &Ray, rec: &HitRecord,
+    ) -> Option<BsdfSample> {
+        let frame = Hair::frame(rec);
+        let o = Local::new(Hair::to_local(&frame, -ray.direction.unit()));
+        let fiber = self.fiber(rec, &o);
+        let lobe_pdf = Hair::lobe_pdf(&fiber);
+
+        // Pick a lobe, then an elevation and an azimuth from it.
+        let mut u = rng.rand();
+        let mut p = 0;
+        while p < P_MAX && u >= lobe_pdf[p] {
+            u -= lobe_pdf[p];
+            p += 1;
+        }
+        let (sin_op, cos_op) = self.tilt(p, &o);
+        let v = self.v[p];
+        let u1 = rng.rand().max(1e-5);
+        let cos_theta = 1.0 + v * (u1 + (1.0 - u1) * (-2.0 / v).exp()).ln();
+        let sin_theta = safe_sqrt(1.0 - cos_theta * cos_theta);
+        let cos_phi = (2.0 * PI * rng.rand()).cos();
+        let sin_theta_i = (-cos_theta * sin_op + sin_theta * cos_phi * cos_op)
+            .clamp(-1.0, 1.0);
+        let cos_theta_i = safe_sqrt(1.0 - sin_theta_i * sin_theta_i);
+        let u2 = rng.rand();
+        let dphi = if p < P_MAX {
+            phi(p, fiber.gamma_o, fiber.gamma_t)
+                + sample_trimmed_logistic(u2, self.s)
+        } else {
+            2.0 * PI * u2
+        };
+        let phi_i = o.phi + dphi;
+        let i = Local {
+            sin_theta: sin_theta_i,
+            cos_theta: cos_theta_i,
+            phi: phi_i,
+        };
+        let (ss, ts, n) = frame;
+        let direction = sin_theta_i * ss
+            + cos_theta_i * phi_i.cos() * ts
+            + cos_theta_i * phi_i.sin() * n;
+        let pdf = self.pdf_local(&fiber, &o, &i);
+        if pdf <= 0.0 || !pdf.is_finite() {
+            return None;
+        }
+        Some(BsdfSample {
+            direction,
+            f: self.eval_local(&fiber, &o, &i),
+            pdf,
+            delta: false,
+        })
+    }
+
+    fn eval(&self, ray: &Ray, rec: &HitRecord, direction: Vec3) -> Vec3 {
+        let frame = Hair::frame(rec);
+        let o = Local::new(Hair::to_local(&frame, -ray.direction.unit()));
+        let i = Local::new(Hair::to_local(&frame, direction.unit()));
+        let fiber = self.fiber(rec, &o);
+        self.eval_local(&fiber, &o, &i)
+    }
+
+    fn pdf(&self, ray: &Ray, rec: &HitRecord, direction: Vec3) -> f32 {
+        let frame = Hair::frame(rec);
+        let o = Local::new(Hair::to_local(&frame, -ray.direction.unit()));
+        let i = Local::new(Hair::to_local(&frame, direction.unit()));
+        let fiber = self.fiber(rec, &o);
+        self.pdf_local(&fiber, &o, &i)
+    }
+}
+
+#[cfg(test)]
+pub mod tests {
+    use super::*;
+    use ::std::assert;
+
+    use ::math::Point3;
+
+    /// A fiber along x seen from `wo`, hit at `v` across it.
+    fn hit(wo: Vec3, v: f32) -> (Ray, HitRecord) {
+        let ray = Ray::new(Point3::default() + wo, -wo, 0.0, 1);
+        let rec = HitRecord {
+            normal: Vec3::new(0.0, 0.0, 1.0),
+            dpdu: Vec3::new(2.0, 0.0, 0.0),
+            v,
+            ..HitRecord::default()
+        };
+        (ray, rec)
+    }
+
+    #[test]
+    fn test_white_furnace() {
+        // Without absorption a fiber scatters all light, whichever way it
+        // is seen.
+        let mut rng = RNG::default();
+        let wos = [
+            Vec3::new(0.0, 0.0, 1.0),
+            Vec3::new(0.5, 0.3, 0.8).unit(),
+            Vec3::new(-0.7, 0.1, 0.3).unit(),
+        ];
+        for &beta_m in &[0.3, 0.6, 0.9] {
+            let hair = Hair::new(Vec3::default(), 1.55, beta_m, 0.5, 0.0);
+            for (k, &wo) in wos.iter().enumerate() {
+                let (ray, rec) = hit(wo, 0.2 + 0.3 * k as f32);
+                let n = 100000;
+                let mut sum = 0.0;
+                for _ in 0..n {
+                    // Uniform directions on the sphere.
+                    let wi = rng.random_unit_vector();
+                    sum += hair.eval(&ray, &rec, wi).y() * 4.0 * PI;
+                }
+                assert!((sum / n as f32 - 1.0).abs() < 0.05);
+            }
+        }
+    }
+
+    #[test]
+    fn test_hair_sampling() {
+        let mut rng = RNG::default();
+        let sigma_a = HairAbsorption::Melanin {
+            eumelanin: 0.8,
+            pheomelanin: 0.2,
+        }
+        .sigma_a(0.3);
+        let hair = Hair::new(sigma_a, 1.55, 0.3, 0.3, 2.0);
+        let (ray, rec) = hit(Vec3::new(0.3, -0.2, 0.9).unit(), 0.7);
+        let n = 20000;
+        let mut sum = Vec3::default();
+        for _ in 0..n {
+            let s = hair.sample(&mut rng, &ray, &rec).unwrap();
+            assert!((s.direction.length() - 1.0).abs() < 1e-4);
+            let pdf = hair.pdf(&ray, &rec, s.direction);
+            assert!((pdf - s.pdf).abs() <= 1e-3 * s.pdf);
+            let f = hair.eval(&ray, &rec, s.direction);
+            assert!((f - s.f).length() <= 1e-3 * s.f.length() + 1e-6);
+            sum += s.f / s.pdf;
+        }
+        // Pigment absorbs blue more than red.
+        let albedo = sum / n as f32;
+        assert!(albedo.x() > albedo.z() && albedo.x() < 1.0);
+
+        // The density integrates to one.
+        let n = 100000;
+        let mut total = 0.0;
+        for _ in 0..n {
+            let wi = rng.random_unit_vector();
+            total += hair.pdf(&ray, &rec, wi) * 4.0 * PI;
+        }
+        assert!((total / n as f32 - 1.0).abs() < 0.05);
+    }
+
+    #[test]
+    fn test_absorption() {
+        // Matching a color recovers it roughly after many bounces, and
+        // darker colors absorb more.
+        let dark = HairAbsorption::Color(Vec3::new(0.1, 0.1, 0.1));
+        let light = HairAbsorption::Color(Vec3::new(0.8, 0.8, 0.8));
+        assert!(dark.sigma_a(0.3).x() > light.sigma_a(0.3).x());
+        let white = HairAbsorption::Color(Vec3::new(1.0, 1.0, 1.0));
+        assert!(white.sigma_a(0.3).length() < 1e-6);
+        let blond = HairAbsorption::Melanin {
+            eumelanin: 0.3,
+            pheomelanin: 0.0,
+        };
+        let s = blond.sigma_a(0.3);
+        assert!(s.x() < s.y() && s.y() < s.z());
+    }
+}
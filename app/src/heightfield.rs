@@ -0,0 +1,394 @@
+//! Terrain from a grid of heights.
+//!
+//! The grid spans x from 0 to `size.x` and z from 0 to `size.z` with the
+//! heights along y, scaled by `size.y`. Each cell between four grid points
+//! is two triangles with normals interpolated from the grid, but nothing
+//! is tessellated: rays walk an implicit quadtree of the cells that keeps
+//! the lowest and the highest point under each node, front to back, and
+//! skip every node they pass above or below (Tevs et al., "Maximum Mipmaps
+//! for Fast, Accurate, and Scalable Dynamic Height Field Rendering", 2008).
+
+use ::std::cmp::{self, PartialOrd};
+use ::std::iter::Iterator;
+use ::std::option::Option::{self, None, Some};
+use ::std::vec::Vec;
+use ::std::{assert, vec};
+
+use ::math::{Point3, Vec3};
+
+use crate::image::Image;
+use crate::pbrt::{HitRecord, Hitable, Ray, AABB};
+
+const BBOX_PADDING: f32 = 0.0001;
+
+/// The lowest and highest height under each node of one level of the
+/// quadtree.
+#[derive(Debug)]
+struct Level {
+    nx: usize,
+    nz: usize,
+    ranges: Vec<(f32, f32)>,
+}
+
+/// A node of the quadtree: its level and its position in the level.
+type Node = (usize, usize, usize);
+
+#[derive(Debug)]
+pub struct Heightfield {
+    /// Grid points along x and z.
+    nx: usize,
+    nz: usize,
+    /// Scaled heights, rows along x.
+    heights: Vec<f32>,
+    normals: Vec<Vec3>,
+    size: Vec3,
+    /// From one node per cell to a single root.
+    levels: Vec<Level>,
+    material: usize,
+}
+
+impl Heightfield {
+    /// A heightfield of `nx` × `nz` grid points, at least 2 × 2, with the
+    /// rows of `heights` along x.
+    pub fn new(
+        nx: usize, nz: usize, heights: &[f32], size: Vec3, material: usize,
+    ) -> Self {
+        assert!(nx >= 2 && nz >= 2 && heights.len() == nx * nz);
+        let heights: Vec<f32> = heights.iter().map(|&h| h * size.y()).collect();
+        let (dx, dz) = (size.x() / (nx - 1) as f32, size.z() / (nz - 1) as f32);
+        let h = |i: usize, j: usize| heights[j * nx + i];
+        let mut normals = Vec::with_capacity(nx * nz);
+        for j in 0..nz {
+            for i in 0..nx {
+                // Central differences, one-sided at the border.
+                let (i0, i1) = (i.saturating_sub(1), cmp::min(i + 1, nx - 1));
+                let (j0, j1) = (j.saturating_sub(1), cmp::min(j + 1, nz - 1));
+                let dhdx = (h(i1, j) - h(i0, j)) / ((i1 - i0) as f32 * dx);
+                let dhdz = (h(i, j1) - h(i, j0)) / ((j1 - j0) as f32 * dz);
+                normals.push(Vec3::new(-dhdx, 1.0, -dhdz).unit());
+            }
+        }
+
+        let (cx, cz) = (nx - 1, nz - 1);
+        let mut ranges = Vec::with_capacity(cx * cz);
+        for j in 0..cz {
+            for i in 0..cx {
+                let corners =
+                    [h(i, j), h(i + 1, j), h(i, j + 1), h(i + 1, j + 1)];
+                let lo = corners.iter().fold(f32::MAX, |a, &b| a.min(b));
+                let hi = corners.iter().fold(f32::MIN, |a, &b| a.max(b));
+                ranges.push((lo, hi));
+            }
+        }
+        let mut levels = vec![Level {
+            nx: cx,
+            nz: cz,
+            ranges,
+        }];
+        while levels[levels.len() - 1].ranges.len() > 1 {
+            let below = &levels[levels.len() - 1];
+            let (nx, nz) = (below.nx.div_ceil(2), below.nz.div_ceil(2));
+            let mut ranges = Vec::with_capacity(nx * nz);
+            for j in 0..nz {
+                for i in 0..nx {
+                    let mut range = (f32::MAX, f32::MIN);
+                    for (ci, cj) in children(i, j, below) {
+                        let (lo, hi) = below.ranges[cj * below.nx + ci];
+                        range = (range.0.min(lo), range.1.max(hi));
+                    }
+                    ranges.push(range);
+                }
+            }
+            levels.push(Level { nx, nz, ranges });
+        }
+
+        Heightfield {
+            nx,
+            nz,
+            heights,
+            normals,
+            size,
+            levels,
+            material,
+        }
+    }
+
+    /// A heightfield with the first channel of `image` as heights. Its
+    /// rows run along x, from z = 0 at the top.
+    pub fn from_image(image: &Image, size: Vec3, material: usize) -> Self {
+        let heights: Vec<f32> = image.pixels.iter().map(|p| p.x()).collect();
+        Heightfield::new(image.width, image.height, &heights, size, material)
+    }
+
+    fn point(&self, i: usize, j: usize) -> Point3 {
+        Point3::new(
+            self.size.x() * i as f32 / (self.nx - 1) as f32,
+            self.heights[j * self.nx + i],
+            self.size.z() * j as f32 / (self.nz - 1) as f32,
+        )
+    }
+
+    /// The range of t in [`t_min`, `t_max`] where `r` is over `node`, at
+    /// `x` and `z` in its level, widened a little so that rays along the
+    /// edge between two nodes find both.
+    fn clip(
+        &self, r: &Ray, (level, x, z): Node, t_min: f32, t_max: f32,
+    ) -> Option<(f32, f32)> {
+        let cells = 1 << level;
+        let (dx, dz) = (
+            self.size.x() / (self.nx - 1) as f32,
+            self.size.z() / (self.nz - 1) as f32,
+        );
+        let x1 = cmp::min((x + 1) * cells, self.nx - 1);
+        let z1 = cmp::min((z + 1) * cells, self.nz - 1);
+        let slabs = [
+            (
+                r.origin.x(),
+                r.direction.x(),
+                (x * cells) as f32 * dx,
+                x1 as f32 * dx,
+            ),
+            (
+                r.origin.z(),
+                r.direction.z(),
+                (z * cells) as f32 * dz,
+                z1 as f32 * dz,
+            ),
+        ];
+        let (mut t0, mut t1) = (t_min, t_max);
+        for &(o, d, lo, hi) in &slabs {
+            let eps = 1e-5 * (hi - lo).max(1.0);
+            let (lo, hi) = (lo - eps, hi + eps);
+            if d == 0.0 {
+                if o < lo || o > hi {
+                    return None;
+                }
+                continue;
+            }
+            let (a, b) = ((lo - o) / d, (hi - o) / d);
+            t0 = t0.max(a.min(b));
+            t1 = t1.min(a.max(b));
+        }
+        if t0 <= t1 {
+            Some((t0, t1))
+        } else {
+            None
+        }
+    }
+
+    /// Finds the closest hit under `node` before `t_max`, which shrinks to
+    /// every hit found.
+    fn visit(
+        &self, r: &Ray, node: Node, t_min: f32, t_max: &mut f32,
+        rec: &mut HitRecord,
+    ) -> bool {
+        let (level, x, z) = node;
+        let (t0, t1) = match self.clip(r, node, t_min, *t_max) {
+            Some(span) => span,
+            None => return false,
+        };
+        let nodes = &self.levels[level];
+        let (lo, hi) = nodes.ranges[z * nodes.nx + x];
+        let (y0, y1) = (r.point_at_param(t0).y(), r.point_at_param(t1).y());
+        let eps = 1e-4 * (hi - lo).max(1.0);
+        if y0.min(y1) > hi + eps || y0.max(y1) < lo - eps {
+            return false;
+        }
+        if level == 0 {
+            return self.hit_cell(r, x, z, t_min, t_max, rec);
+        }
+
+        // Visit the children in the order the ray enters them.
+        let below = &self.levels[level - 1];
+        let mut order: Vec<(f32, usize, usize)> = children(x, z, below)
+            .filter_map(|(cx, cz)| {
+                self.clip(r, (level - 1, cx, cz), t_min, *t_max)
+                    .map(|(t, _)| (t, cx, cz))
+            })
+            .collect();
+        order.sort_by(|a, b| a.0.partial_cmp(&b.0).unwrap());
+        let mut hit = false;
+        for (t, cx, cz) in order {
+            if t > *t_max {
+                break;
+            }
+            hit |= self.visit(r, (level - 1, cx, cz), t_min, t_max, rec);
+        }
+        hit
+    }
+
+    fn hit_cell(
+        &self, r: &Ray, x: usize, z: usize, t_min: f32, t_max: &mut f32,
+        rec: &mut HitRecord,
+    ) -> bool {
+        let corners = [(x, z), (x + 1, z), (x + 1, z + 1), (x, z + 1)];
+        let mut hit = false;
+        for triangle in &[[0, 1, 2], [0, 2, 3]] {
+            let v = triangle.map(|k| corners[k]);
+            let p = v.map(|(i, j)| self.point(i, j));
+            // Möller-Trumbore.
+            let e1 = p[1] - p[0];
+            let e2 = p[2] - p[0];
+            let pvec = r.direction.cross(&e2);
+            let det = e1.dot(pvec);
+            if det.abs() < 1e-12 {
+                continue;
+            }
+            let inv_det = 1.0 / det;
+            let tvec = r.origin - p[0];
+            let b1 = tvec.dot(pvec) * inv_det;
+            if !(0.0..=1.0).contains(&b1) {
+                continue;
+            }
+            let qvec = tvec.cross(&e1);
+            let b2 = r.direction.dot(qvec) * inv_det;
+            if b2 < 0.0 || b1 + b2 > 1.0 {
+                continue;
+            }
+            let t = e2.dot(qvec) * inv_det;
+            if t >= *t_max || t <= t_min {
+                continue;
+            }
+            let b0 = 1.0 - b1 - b2;
+            let n = v.map(|(i, j)| self.normals[j * self.nx + i]);
+            let normal = (b0 * n[0] + b1 * n[1] + b2 * n[2]).unit();
+            let p = r.point_at_param(t);
+            rec.t = t;
+            rec.p = p;
+            rec.normal = normal;
+            rec.material = self.material;
+            rec.u = p.x() / self.size.x();
+            rec.v = p.z() / self.size.z();
+            let along = Vec3::new(self.size.x(), 0.0, 0.0);
+            rec.dpdu = along - along.dot(normal) * normal;
+            let across = Vec3::new(0.0, 0.0, self.size.z());
+            rec.dpdv = across - across.dot(normal) * normal;
+            *t_max = t;
+            hit = true;
+        }
+        hit
+    }
+}
+
+/// The nodes of `below` under node `x`, `z` of the level above it.
+fn children(
+    x: usize, z: usize, below: &Level,
+) -> impl Iterator<Item = (usize, usize)> {
+    static OFFSETS: [(usize, usize); 4] = [(0, 0), (1, 0), (0, 1), (1, 1)];
+    let (nx, nz) = (below.nx, below.nz);
+    OFFSETS
+        .iter()
+        .map(move |&(a, b)| (2 * x + a, 2 * z + b))
+        .filter(move |&(cx, cz)| cx < nx && cz < nz)
+}
+
+impl Hitable for Heightfield {
+    fn hit(
+        &self, r: &Ray, t_min: f32, t_max: f32, rec: &mut HitRecord,
+    ) -> bool {
+        let mut t_max = t_max;
+        let root = self.levels.len() - 1;
+        self.visit(r, (root, 0, 0), t_min, &mut t_max, rec)
+    }
+
+    fn bounding_box(&self, _: f32, _: f32) -> Option<AABB> {
+        let (lo, hi) = self.levels[self.levels.len() - 1].ranges[0];
+        Some(AABB::new(
+            Point3::new(-BBOX_PADDING, lo - BBOX_PADDING, -BBOX_PADDING),
+            Point3::new(
+                self.size.x() + BBOX_PADDING,
+                hi + BBOX_PADDING,
+                self.size.z() + BBOX_PADDING,
+            ),
+        ))
+    }
+
+    fn material(&self) -> Option<usize> {
+        Some(self.material)
+    }
+}
+
+#[cfg(test)]
+pub mod tests {
+    use super::*;
+    use ::std::default::Default;
+    use ::std::ops::Fn;
+
+    use crate::pbrt::RNG;
+
+    fn down(x: f32, z: f32) -> Ray {
+        Ray::new(Point3::new(x, 10.0, z), Vec3::new(0.0, -1.0, 0.0), 0.0, 0)
+    }
+
+    /// A bump in a grid that is not a power of two wide.
+    fn bump() -> (Heightfield, impl Fn(f32, f32) -> f32) {
+        let (nx, nz) = (37, 21);
+        let f =
+            |x: f32, z: f32| (-((x - 2.0).powi(2) + (z - 1.0).powi(2))).exp();
+        let mut heights = Vec::new();
+        for j in 0..nz {
+            for i in 0..nx {
+                let (x, z) = (4.0 * i as f32 / 36.0, 2.0 * j as f32 / 20.0);
+                heights.push(f(x, z));
+            }
+        }
+        let size = Vec3::new(4.0, 1.5, 2.0);
+        let field = Heightfield::new(nx, nz, &heights, size, 3);
+        (field, move |x, z| 1.5 * f(x, z))
+    }
+
+    #[test]
+    fn test_heightfield_hit() {
+        let (field, f) = bump();
+        assert!(field.levels.len() == 7);
+        let mut rec = HitRecord::default();
+        assert!(field.hit(&down(2.0, 1.0), 0.001, f32::MAX, &mut rec));
+        assert!((rec.p.y() - 1.5).abs() < 1e-3);
+        assert!(rec.normal.y() > 0.999);
+        assert!(rec.material == 3);
+        assert!((rec.u - 0.5).abs() < 1e-6 && (rec.v - 0.5).abs() < 1e-6);
+        // The normal leans away from the top of the bump.
+        assert!(field.hit(&down(2.5, 1.0), 0.001, f32::MAX, &mut rec));
+        assert!(rec.normal.x() > 0.3);
+        assert!(!field.hit(&down(4.1, 1.0), 0.001, f32::MAX, &mut rec));
+
+        // Grazing rays from all sides find the same hits as testing every
+        // cell.
+        let mut rng = RNG::default();
+        let mut hits = 0;
+        for _ in 0..500 {
+            let (x, z) = (4.0 * rng.rand(), 2.0 * rng.rand());
+            let target = Point3::new(x, f(x, z), z);
+            let d = Vec3::new(rng.rand() - 0.5, -0.3, rng.rand() - 0.5);
+            let r = Ray::new(target - 10.0 * d, d, 0.0, 0);
+            let mut closest = f32::MAX;
+            for z in 0..20 {
+                for x in 0..36 {
+                    let mut t = closest;
+                    let mut rec = HitRecord::default();
+                    field.hit_cell(&r, x, z, 0.001, &mut t, &mut rec);
+                    closest = t;
+                }
+            }
+            let mut rec = HitRecord::default();
+            if field.hit(&r, 0.001, f32::MAX, &mut rec) {
+                hits += 1;
+                assert!((rec.t - closest).abs() < 1e-4);
+                assert!((rec.p.y() - f(rec.p.x(), rec.p.z())).abs() < 0.02);
+            } else {
+                assert!(closest == f32::MAX);
+            }
+        }
+        assert!(hits > 400);
+    }
+
+    #[test]
+    fn test_heightfield_bounds() {
+        let (field, _) = bump();
+        let bbox = field.bounding_box(0.0, 0.0).unwrap();
+        assert!(bbox.max().y() > 1.49 && bbox.max().y() < 1.501);
+        assert!(bbox.min().y() < 0.02);
+        assert!((bbox.max().x() - 4.0).abs() < 1e-3);
+        assert!((bbox.max().z() - 2.0).abs() < 1e-3);
+    }
+}
@@ -0,0 +1,689 @@
+//! Image buffers and writers.
+//!
+//! The output format is picked from the file extension:
+//!
+//! * `.png`: 8 or 16 bits per channel, gamma 2
+//! * `.ppm`: binary `P6`, 8 bits per channel, gamma 2
+//! * `.pfm`: 32-bit float, linear
+//! * `.hdr`: Radiance RGBE, linear
+//! * `.exr`: OpenEXR, half or float, uncompressed, ZIP or PIZ, linear
+//!
+//! The low dynamic range formats clamp; the others keep the radiance as
+//! rendered.
+//!
+//! For textures and heightfields, PNG, PFM and Radiance HDR images can be
+//! read back, as well as baseline JPEGs (`.jpg` or `.jpeg`).
+
+use ::std::clone::Clone;
+use ::std::cmp;
+use ::std::default::Default;
+use ::std::fmt::{self, Display, Formatter};
+use ::std::fs::File;
+use ::std::io::{self, BufReader, BufWriter, Read, Write};
+use ::std::iter::{Extend, Iterator};
+use ::std::option::Option::{self, None, Some};
+use ::std::path::{Path, PathBuf};
+use ::std::result::Result::{self, Err, Ok};
+use ::std::string::{String, ToString};
+use ::std::vec::Vec;
+use ::std::{format, vec, write, writeln};
+
+use ::exr::prelude::{self as exr, f16, WritableImage};
+use ::math::Vec3;
+
+use crate::jpeg;
+
+#[derive(Debug)]
+pub enum ImageError {
+    UnknownFormat(PathBuf),
+    Io { path: PathBuf, error: io::Error },
+    Encode { path: PathBuf, message: String },
+    Decode { path: PathBuf, message: String },
+}
+
+impl Display for ImageError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        match self {
+            ImageError::UnknownFormat(path) => write!(
+                f,
+                "{}: unknown image format, use .png, .ppm, .pfm, .hdr or .exr",
+                path.display()
+            ),
+            ImageError::Io { path, error } => {
+                write!(f, "{}: {}", path.display(), error)
+            }
+            ImageError::Encode { path, message }
+            | ImageError::Decode { path, message } => {
+                write!(f, "{}: {}", path.display(), message)
+            }
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ImageFormat {
+    Png,
+    Ppm,
+    Pfm,
+    Hdr,
+    Exr,
+}
+
+impl ImageFormat {
+    pub fn from_path(path: &Path) -> Option<Self> {
+        let ext = path.extension()?.to_str()?.to_ascii_lowercase();
+        match ext.as_str() {
+            "png" => Some(ImageFormat::Png),
+            "ppm" => Some(ImageFormat::Ppm),
+            "pfm" => Some(ImageFormat::Pfm),
+            "hdr" => Some(ImageFormat::Hdr),
+            "exr" => Some(ImageFormat::Exr),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ExrCompression {
+    None,
+    Zip,
+    Piz,
+}
+
+/// Format specific settings. Formats ignore what does not apply to them.
+#[derive(Debug, Clone, Copy)]
+pub struct WriteOptions {
+    /// 8 or 16.
+    pub png_bits: u8,
+    /// Full 32-bit floats instead of halfs.
+    pub exr_float: bool,
+    pub exr_compression: ExrCompression,
+}
+
+impl Default for WriteOptions {
+    fn default() -> Self {
+        WriteOptions {
+            png_bits: 8,
+            exr_float: false,
+            exr_compression: ExrCompression::Zip,
+        }
+    }
+}
+
+/// Linear RGB pixels, rows from top to bottom.
+#[derive(Debug, Clone)]
+pub struct Image {
+    pub width: usize,
+    pub height: usize,
+    pub pixels: Vec<Vec3>,
+}
+
+impl Image {
+    pub fn new(width: usize, height: usize) -> Self {
+        Image {
+            width,
+            height,
+            pixels: vec![Vec3::default(); width * height],
+        }
+    }
+
+    pub fn pixel(&self, x: usize, y: usize) -> Vec3 {
+        self.pixels[y * self.width + x]
+    }
+
+    pub fn set_pixel(&mut self, x: usize, y: usize, color: Vec3) {
+        self.pixels[y * self.width + x] = color;
+    }
+
+    /// Reads a PNG, JPEG, PFM or Radiance HDR image. PNG and JPEG values
+    /// are scaled to [0, 1] as stored, without undoing the gamma of color
+    /// images. Gray images fill all three channels and alpha is dropped.
+    pub fn read(path: &Path) -> Result<Image, ImageError> {
+        let io_error = |error| ImageError::Io {
+            path: path.to_path_buf(),
+            error,
+        };
+        let decode_error = |message| ImageError::Decode {
+            path: path.to_path_buf(),
+            message,
+        };
+        let file = File::open(path).map_err(io_error)?;
+        if let Some(ImageFormat::Png) = ImageFormat::from_path(path) {
+            return read_png(BufReader::new(file)).map_err(decode_error);
+        }
+        let mut data = Vec::new();
+        BufReader::new(file)
+            .read_to_end(&mut data)
+            .map_err(io_error)?;
+        // JPEGs can only be read, so they are not an `ImageFormat`.
+        let ext = path.extension().and_then(|e| e.to_str());
+        match ext.map(|e| e.to_ascii_lowercase()).as_deref() {
+            Some("jpg") | Some("jpeg") => {
+                return jpeg::decode(&data).map_err(decode_error);
+            }
+            _ => {}
+        }
+        match ImageFormat::from_path(path) {
+            Some(ImageFormat::Pfm) => read_pfm(&data).map_err(decode_error),
+            Some(ImageFormat::Hdr) => read_hdr(&data).map_err(decode_error),
+            _ => Err(ImageError::UnknownFormat(path.to_path_buf())),
+        }
+    }
+
+    /// Writes the image in the format given by the extension of `path`.
+    pub fn write(
+        &self, path: &Path, options: &WriteOptions,
+    ) -> Result<(), ImageError> {
+        let format = ImageFormat::from_path(path)
+            .ok_or_else(|| ImageError::UnknownFormat(path.to_path_buf()))?;
+        let io_error = |error| ImageError::Io {
+            path: path.to_path_buf(),
+            error,
+        };
+        if format == ImageFormat::Exr {
+            return self.write_exr(path, options);
+        }
+        let mut w = BufWriter::new(File::create(path).map_err(io_error)?);
+        match format {
+            ImageFormat::Png => self
+                .write_png(&mut w, options.png_bits)
+                .map_err(|e| ImageError::Encode {
+                    path: path.to_path_buf(),
+                    message: e.to_string(),
+                })?,
+            ImageFormat::Ppm => self.write_ppm(&mut w).map_err(io_error)?,
+            ImageFormat::Pfm => self.write_pfm(&mut w).map_err(io_error)?,
+            ImageFormat::Hdr => self.write_hdr(&mut w).map_err(io_error)?,
+            ImageFormat::Exr => {}
+        }
+        w.flush().map_err(io_error)
+    }
+
+    fn write_png<W: Write>(
+        &self, w: &mut W, bits: u8,
+    ) -> Result<(), ::png::EncodingError> {
+        let mut encoder =
+            ::png::Encoder::new(w, self.width as u32, self.height as u32);
+        encoder.set_color(::png::ColorType::Rgb);
+        let mut data = Vec::new();
+        if bits == 16 {
+            encoder.set_depth(::png::BitDepth::Sixteen);
+            for color in &self.pixels {
+                for c in display(*color) {
+                    let c = (c * 65535.0 + 0.5) as u16;
+                    data.extend(&c.to_be_bytes());
+                }
+            }
+        } else {
+            encoder.set_depth(::png::BitDepth::Eight);
+            for color in &self.pixels {
+                let (r, g, b) = color.sqrt().as_u8();
+                data.extend(&[r, g, b]);
+            }
+        }
+        encoder.write_header()?.write_image_data(&data)
+    }
+
+    /// Writes a binary (P6) PPM.
+    fn write_ppm<W: Write>(&self, w: &mut W) -> io::Result<()> {
+        writeln!(w, "P6\n{} {}\n255", self.width, self.height)?;
+        let mut data = Vec::with_capacity(3 * self.pixels.len());
+        for color in &self.pixels {
+            let (r, g, b) = color.sqrt().as_u8();
+            data.extend(&[r, g, b]);
+        }
+        w.write_all(&data)
+    }
+
+    /// Writes a little endian PFM. PFM stores rows from bottom to top.
+    fn write_pfm<W: Write>(&self, w: &mut W) -> io::Result<()> {
+        writeln!(w, "PF\n{} {}\n-1.0", self.width, self.height)?;
+        let mut data = Vec::with_capacity(12 * self.pixels.len());
+        for row in self.pixels.chunks(self.width).rev() {
+            for color in row {
+                for c in &[color.x(), color.y(), color.z()] {
+                    data.extend(&c.to_le_bytes());
+                }
+            }
+        }
+        w.write_all(&data)
+    }
+
+    /// Writes a Radiance picture with flat (not run-length encoded)
+    /// scanlines, which every reader accepts.
+    fn write_hdr<W: Write>(&self, w: &mut W) -> io::Result<()> {
+        writeln!(w, "#?RADIANCE\nFORMAT=32-bit_rle_rgbe\n")?;
+        writeln!(w, "-Y {} +X {}", self.height, self.width)?;
+        let mut data = Vec::with_capacity(4 * self.pixels.len());
+        for color in &self.pixels {
+            data.extend(&rgbe(*color));
+        }
+        w.write_all(&data)
+    }
+
+    fn write_exr(
+        &self, path: &Path, options: &WriteOptions,
+    ) -> Result<(), ImageError> {
+        let encoding = exr::Encoding {
+            compression: match options.exr_compression {
+                ExrCompression::None => exr::Compression::Uncompressed,
+                ExrCompression::Zip => exr::Compression::ZIP16,
+                ExrCompression::Piz => exr::Compression::PIZ,
+            },
+            ..exr::Encoding::default()
+        };
+        let size = (self.width, self.height);
+        let pixel = |p: exr::Vec2<usize>| {
+            let c = self.pixel(p.x(), p.y());
+            (c.x(), c.y(), c.z())
+        };
+        let result = if options.exr_float {
+            exr::Image::from_encoded_channels(
+                size,
+                encoding,
+                exr::SpecificChannels::rgb(pixel),
+            )
+            .write()
+            .to_file(path)
+        } else {
+            let half = |p| {
+                let (r, g, b) = pixel(p);
+                (f16::from_f32(r), f16::from_f32(g), f16::from_f32(b))
+            };
+            exr::Image::from_encoded_channels(
+                size,
+                encoding,
+                exr::SpecificChannels::rgb(half),
+            )
+            .write()
+            .to_file(path)
+        };
+        result.map_err(|e| match e {
+            exr::Error::Io(error) => ImageError::Io {
+                path: path.to_path_buf(),
+                error,
+            },
+            e => ImageError::Encode {
+                path: path.to_path_buf(),
+                message: e.to_string(),
+            },
+        })
+    }
+}
+
+fn read_png<R: Read>(r: R) -> Result<Image, String> {
+    let mut decoder = ::png::Decoder::new(r);
+    // Palettes and fewer than 8 bits become 8 bits per channel.
+    decoder.set_transformations(::png::Transformations::EXPAND);
+    let mut reader = decoder.read_info().map_err(|e| e.to_string())?;
+    let mut data = vec![0; reader.output_buffer_size()];
+    let info = reader.next_frame(&mut data).map_err(|e| e.to_string())?;
+    let channels = info.color_type.samples();
+    let (width, height) = (info.width as usize, info.height as usize);
+    let sample = |row: &[u8], i: usize| match info.bit_depth {
+        ::png::BitDepth::Sixteen => {
+            u16::from_be_bytes([row[2 * i], row[2 * i + 1]]) as f32 / 65535.0
+        }
+        _ => row[i] as f32 / 255.0,
+    };
+    let mut image = Image::new(width, height);
+    for (y, row) in data.chunks(info.line_size).take(height).enumerate() {
+        for x in 0..width {
+            let i = x * channels;
+            let color = if channels < 3 {
+                let v = sample(row, i);
+                Vec3::new(v, v, v)
+            } else {
+                Vec3::new(
+                    sample(row, i),
+                    sample(row, i + 1),
+                    sample(row, i + 2),
+                )
+            };
+            image.set_pixel(x, y, color);
+        }
+    }
+    Ok(image)
+}
+
+/// Reads a color (`PF`) or gray (`Pf`) PFM. The sign of the scale gives
+/// the byte order, and rows are stored from bottom to top.
+fn read_pfm(data: &[u8]) -> Result<Image, String> {
+    // The header is three tokens after the magic number, each followed by
+    // white space, and exactly one white space character before the data.
+    let mut tokens = Vec::new();
+    let mut pos = 2;
+    while tokens.len() < 3 {
+        while pos < data.len() && data[pos].is_ascii_whitespace() {
+            pos += 1;
+        }
+        let start = pos;
+        while pos < data.len() && !data[pos].is_ascii_whitespace() {
+            pos += 1;
+        }
+        if start == pos {
+            return Err("truncated PFM header".to_string());
+        }
+        tokens.push(String::from_utf8_lossy(&data[start..pos]).to_string());
+    }
+    let channels = match &data[..2] {
+        b"PF" => 3,
+        b"Pf" => 1,
+        _ => return Err("not a PFM file".to_string()),
+    };
+    let parse = |s: &str| {
+        s.parse::<usize>()
+            .map_err(|_| format!("bad PFM size '{}'", s))
+    };
+    let (width, height) = (parse(&tokens[0])?, parse(&tokens[1])?);
+    let scale: f32 = tokens[2]
+        .parse()
+        .map_err(|_| format!("bad PFM scale '{}'", tokens[2]))?;
+    if width == 0 || height == 0 {
+        return Err("empty PFM image".to_string());
+    }
+    let size = width
+        .checked_mul(height)
+        .and_then(|n| n.checked_mul(4 * channels));
+    let data = match (data.get(pos + 1..), size) {
+        (Some(data), Some(size)) if data.len() >= size => data,
+        _ => return Err("truncated PFM data".to_string()),
+    };
+    let value = |i: usize| {
+        let bytes = [
+            data[4 * i],
+            data[4 * i + 1],
+            data[4 * i + 2],
+            data[4 * i + 3],
+        ];
+        if scale < 0.0 {
+            f32::from_le_bytes(bytes)
+        } else {
+            f32::from_be_bytes(bytes)
+        }
+    };
+    let mut image = Image::new(width, height);
+    for y in 0..height {
+        for x in 0..width {
+            let i = channels * ((height - 1 - y) * width + x);
+            let color = if channels == 1 {
+                Vec3::new(value(i), value(i), value(i))
+            } else {
+                Vec3::new(value(i), value(i + 1), value(i + 2))
+            };
+            image.set_pixel(x, y, color);
+        }
+    }
+    Ok(image)
+}
+
+/// Reads a Radiance picture with the usual `-Y height +X width`
+/// orientation, with flat or run-length encoded scanlines.
+fn read_hdr(data: &[u8]) -> Result<Image, String> {
+    if !data.starts_with(b"#?") {
+        return Err("not a Radiance HDR file".to_string());
+    }
+    // Header lines up to an empty one, then the resolution line.
+    let mut pos = 0;
+    let line = |pos: &mut usize| {
+        let start = *pos;
+        while *pos < data.len() && data[*pos] != b'\n' {
+            *pos += 1;
+        }
+        *pos += 1;
+        String::from_utf8_lossy(&data[start..*pos - 1]).to_string()
+    };
+    loop {
+        let header = line(&mut pos);
+        if header.is_empty() {
+            break;
+        }
+        if let Some(format) = header.strip_prefix("FORMAT=") {
+            if format != "32-bit_rle_rgbe" {
+                return Err(format!("unsupported HDR format '{}'", format));
+            }
+        }
+        if pos >= data.len() {
+            return Err("truncated HDR header".to_string());
+        }
+    }
+    let resolution = line(&mut pos);
+    let size = match resolution.split_whitespace().collect::<Vec<_>>()[..] {
+        ["-Y", height, "+X", width] => height
+            .parse::<usize>()
+            .and_then(|h| width.parse::<usize>().map(|w| (w, h)))
+            .ok(),
+        _ => None,
+    };
+    let (width, height) = size.ok_or_else(|| {
+        format!("unsupported HDR resolution '{}'", resolution)
+    })?;
+    // Check the claimed size against the data before allocating: a scanline
+    // takes at least 4 bytes, and even as runs of 127 pixels 8 bytes per
+    // 127 pixels.
+    let per_line = cmp::max(width / 127 * 8, 4);
+    let needed = width
+        .checked_mul(height)
+        .and_then(|_| per_line.checked_mul(height));
+    match needed {
+        Some(needed) if needed <= data.len().saturating_sub(pos) => {}
+        _ => return Err("truncated HDR data".to_string()),
+    }
+    let mut image = Image::new(width, height);
+    // Red, green, blue and exponent of one scanline.
+    let mut channels = [(); 4].map(|_| vec![0u8; width]);
+    let truncated = || "truncated HDR data".to_string();
+    for y in 0..height {
+        let start = data.get(pos..pos + 4).ok_or_else(truncated)?;
+        let rle = (8..0x8000).contains(&width)
+            && start[0] == 2
+            && start[1] == 2
+            && ((start[2] as usize) << 8 | start[3] as usize) == width;
+        if rle {
+            // Each channel in turn, as runs (count above 128) and dumps.
+            pos += 4;
+            for channel in &mut channels {
+                let mut x = 0;
+                while x < width {
+                    let count = *data.get(pos).ok_or_else(truncated)? as usize;
+                    pos += 1;
+                    let (n, run) = if count > 128 {
+                        (count - 128, true)
+                    } else {
+                        (count, false)
+                    };
+                    if n == 0 || x + n > width {
+                        return Err("bad HDR run length".to_string());
+                    }
+                    for i in 0..n {
+                        let at = if run { pos } else { pos + i };
+                        channel[x + i] = *data.get(at).ok_or_else(truncated)?;
+                    }
+                    pos += if run { 1 } else { n };
+                    x += n;
+                }
+            }
+        } else {
+            let flat = data.get(pos..pos + 4 * width).ok_or_else(truncated)?;
+            for (x, bytes) in flat.chunks(4).enumerate() {
+                for (channel, &byte) in channels.iter_mut().zip(bytes) {
+                    channel[x] = byte;
+                }
+            }
+            pos += 4 * width;
+        }
+        for x in 0..width {
+            let [r, g, b, e] = &channels;
+            image.set_pixel(x, y, from_rgbe([r[x], g[x], b[x], e[x]]));
+        }
+    }
+    Ok(image)
+}
+
+/// Gamma 2 encoded and clamped to [0, 1].
+fn display(color: Vec3) -> [f32; 3] {
+    let c = color.sqrt();
+    [c.x(), c.y(), c.z()].map(|c| if c < 1.0 { c.max(0.0) } else { 1.0 })
+}
+
+/// Shared exponent encoding, see Ward, "Real Pixels", Graphics Gems II.
+fn rgbe(color: Vec3) -> [u8; 4] {
+    let (r, g, b) =
+        (color.x().max(0.0), color.y().max(0.0), color.z().max(0.0));
+    let v = r.max(g).max(b);
+    if v < 1e-32 || !v.is_finite() {
+        return [0, 0, 0, 0];
+    }
+    // v = m * 2^e with m in [0.5, 1).
+    let e = ((v.to_bits() >> 23) & 0xff) as i32 - 126;
+    let scale = 256.0 / f32::from_bits(((e + 127) as u32) << 23);
+    [
+        (r * scale) as u8,
+        (g * scale) as u8,
+        (b * scale) as u8,
+        (e + 128) as u8,
+    ]
+}
+
+/// The inverse of `rgbe`.
+fn from_rgbe([r, g, b, e]: [u8; 4]) -> Vec3 {
+    if e == 0 {
+        return Vec3::default();
+    }
+    let scale = 2.0f32.powi(e as i32 - 128 - 8);
+    Vec3::new(r as f32 * scale, g as f32 * scale, b as f32 * scale)
+}
+
+#[cfg(test)]
+pub mod tests {
+    use super::*;
+    use ::std::env;
+    use ::std::fs;
+    use ::std::{assert, assert_eq};
+
+    fn test_image() -> Image {
+        let mut image = Image::new(3, 2);
+        image.set_pixel(0, 0, Vec3::new(0.25, 1.0, 4.0));
+        image.set_pixel(2, 1, Vec3::new(1.5, 0.0, 0.0));
+        image
+    }
+
+    #[test]
+    fn test_format_from_path() {
+        let format = |p| ImageFormat::from_path(Path::new(p));
+        assert_eq!(format("out.PNG"), Some(ImageFormat::Png));
+        assert_eq!(format("a/b.exr"), Some(ImageFormat::Exr));
+        assert_eq!(format("out.jpg"), None);
+        assert_eq!(format("out"), None);
+    }
+
+    #[test]
+    fn test_rgbe() {
+        assert_eq!(rgbe(Vec3::new(1.0, 0.5, 0.0)), [128, 64, 0, 129]);
+        assert_eq!(rgbe(Vec3::new(0.0, 0.0, 0.0)), [0, 0, 0, 0]);
+        assert_eq!(rgbe(Vec3::new(3.0, 0.0, 0.0))[3], 130);
+    }
+
+    #[test]
+    fn test_write_pfm() {
+        let path = env::temp_dir().join("raytracer_test_write.pfm");
+        test_image().write(&path, &WriteOptions::default()).unwrap();
+        let data = fs::read(&path).unwrap();
+        let _ = fs::remove_file(&path);
+        let header = b"PF\n3 2\n-1.0\n";
+        assert_eq!(&data[..header.len()], header);
+        assert_eq!(data.len(), header.len() + 3 * 2 * 12);
+        // The top left pixel starts the second stored row.
+        let first = &data[header.len() + 3 * 12..];
+        assert_eq!(&first[8..12], &4.0f32.to_le_bytes());
+    }
+
+    #[test]
+    fn test_read() {
+        let image = test_image();
+        let path = env::temp_dir().join("raytracer_test_read.pfm");
+        image.write(&path, &WriteOptions::default()).unwrap();
+        let pfm = Image::read(&path).unwrap();
+        let _ = fs::remove_file(&path);
+        assert_eq!(pfm.pixels, image.pixels);
+
+        // 16-bit PNGs keep their precision, as gamma encoded values.
+        let path = env::temp_dir().join("raytracer_test_read.png");
+        let options = WriteOptions {
+            png_bits: 16,
+            ..WriteOptions::default()
+        };
+        image.write(&path, &options).unwrap();
+        let png = Image::read(&path).unwrap();
+        let _ = fs::remove_file(&path);
+        assert_eq!((png.width, png.height), (3, 2));
+        let first = png.pixel(0, 0) - Vec3::new(0.5, 1.0, 1.0);
+        assert!(first.length() < 1e-4);
+        assert_eq!(png.pixel(2, 1), Vec3::new(1.0, 0.0, 0.0));
+
+        // A big endian gray PFM.
+        let mut data = b"Pf 2 1 1.0\n".to_vec();
+        data.extend(&0.5f32.to_be_bytes());
+        data.extend(&2.0f32.to_be_bytes());
+        let gray = read_pfm(&data).unwrap();
+        assert_eq!(gray.pixels[1], Vec3::new(2.0, 2.0, 2.0));
+        assert!(read_pfm(b"Pf 2 1 1.0\n").is_err());
+        assert!(read_pfm(b"P6 2 1 1.0\n").is_err());
+        assert!(read_pfm(b"Pf\n2 2\n-1.0").is_err());
+        assert!(read_pfm(b"Pf 0 0 -1.0\n").is_err());
+        let huge = format!("PF {} {} -1.0\n", usize::MAX / 2, 3);
+        assert!(read_pfm(huge.as_bytes()).is_err());
+
+        // HDR written flat.
+        let path = env::temp_dir().join("raytracer_test_read.hdr");
+        image.write(&path, &WriteOptions::default()).unwrap();
+        let hdr = Image::read(&path).unwrap();
+        let _ = fs::remove_file(&path);
+        assert_eq!(hdr.pixels, image.pixels);
+    }
+
+    #[test]
+    fn test_read_hdr_rle() {
+        // One run-length encoded scanline of 8 pixels: a run of 8 for red,
+        // green and the exponent, and a dump of 8 for blue.
+        let mut data =
+            b"#?RADIANCE\nFORMAT=32-bit_rle_rgbe\n\n-Y 1 +X 8\n".to_vec();
+        data.extend(&[2, 2, 0, 8]);
+        data.extend(&[128 + 8, 128, 128 + 8, 64]);
+        data.push(8);
+        data.extend(0..8u8);
+        data.extend(&[128 + 8, 129]);
+        let image = read_hdr(&data).unwrap();
+        assert_eq!((image.width, image.height), (8, 1));
+        assert_eq!(image.pixel(0, 0), Vec3::new(1.0, 0.5, 0.0));
+        assert_eq!(image.pixel(7, 0), Vec3::new(1.0, 0.5, 7.0 / 128.0));
+        assert!(read_hdr(&data[..data.len() - 1]).is_err());
+        assert!(read_hdr(b"#?RADIANCE\n\n+X 8 -Y 1\n").is_err());
+        // A huge claimed size with no data to back it.
+        assert!(read_hdr(b"#?RADIANCE\n\n-Y 200000 +X 200000\n").is_err());
+        let wide = format!("#?RADIANCE\n\n-Y 1 +X {}\n", usize::MAX / 2);
+        assert!(read_hdr(wide.as_bytes()).is_err());
+    }
+
+    #[test]
+    fn test_write_exr() {
+        let path = env::temp_dir().join("raytracer_test_write.exr");
+        let options = WriteOptions {
+            exr_compression: ExrCompression::Piz,
+            ..WriteOptions::default()
+        };
+        test_image().write(&path, &options).unwrap();
+        let image = exr::read_first_rgba_layer_from_file(
+            &path,
+            |size, _| vec![(0.0, 0.0, 0.0); size.width() * size.height()],
+            |pixels, p, (r, g, b, _): (f32, f32, f32, f32)| {
+                pixels[p.y() * 3 + p.x()] = (r, g, b)
+            },
+        )
+        .unwrap();
+        let _ = fs::remove_file(&path);
+        let pixels = image.layer_data.channel_data.pixels;
+        assert_eq!(pixels[0], (0.25, 1.0, 4.0));
+        assert_eq!(pixels[5], (1.5, 0.0, 0.0));
+    }
+}
@@ -0,0 +1,756 @@
+//! A decoder for baseline JPEG images, for textures.
+//!
+//! Handles sequential Huffman coded files with 8-bit samples, one (gray)
+//! or three (YCbCr, or RGB when an Adobe marker says so) components with
+//! any chroma subsampling, interleaved or one scan per component, and
+//! restart intervals. Subsampled chroma is replicated, not interpolated.
+//! Progressive and arithmetic coded files are rejected.
+
+use ::std::clone::Clone;
+use ::std::default::Default;
+use ::std::iter::Iterator;
+use ::std::option::Option::Some;
+use ::std::result::Result::{self, Err, Ok};
+use ::std::string::{String, ToString};
+use ::std::vec::Vec;
+use ::std::{format, unreachable, vec};
+
+use ::math::Vec3;
+
+use crate::image::Image;
+
+const PI: f32 = ::std::f32::consts::PI;
+
+/// The position in a block of the i-th coefficient in the stream.
+const ZIGZAG: [usize; 64] = [
+    0, 1, 8, 16, 9, 2, 3, 10, 17, 24, 32, 25, 18, 11, 4, 5, 12, 19, 26, 33, 40,
+    48, 41, 34, 27, 20, 13, 6, 7, 14, 21, 28, 35, 42, 49, 56, 57, 50, 43, 36,
+    29, 22, 15, 23, 30, 37, 44, 51, 58, 59, 52, 45, 38, 31, 39, 46, 53, 60, 61,
+    54, 47, 55, 62, 63,
+];
+
+/// A canonical Huffman code as listed in a DHT segment.
+#[derive(Debug, Clone, Default)]
+struct Huffman {
+    /// The largest code of each length, or -1 if there is none.
+    max_code: [i32; 17],
+    /// The index in `symbols` of the first code of each length, minus
+    /// that code.
+    offset: [i32; 17],
+    symbols: Vec<u8>,
+}
+
+impl Huffman {
+    fn new(counts: &[u8], symbols: &[u8]) -> Self {
+        let mut table = Huffman {
+            max_code: [-1; 17],
+            offset: [0; 17],
+            symbols: symbols.to_vec(),
+        };
+        let (mut code, mut index) = (0i32, 0i32);
+        for len in 1..=16 {
+            let count = counts[len - 1] as i32;
+            if count > 0 {
+                table.offset[len] = index - code;
+                code += count;
+                index += count;
+                table.max_code[len] = code - 1;
+            }
+            code <<= 1;
+        }
+        table
+    }
+}
+
+#[derive(Debug, Clone, Default)]
+struct Component {
+    id: u8,
+    h: usize,
+    v: usize,
+    quant: usize,
+    /// Blocks per line and lines of blocks in `samples`, padded to whole
+    /// MCUs.
+    blocks_x: usize,
+    blocks_y: usize,
+    samples: Vec<u8>,
+    dc_table: usize,
+    ac_table: usize,
+    prediction: i32,
+}
+
+/// Everything the segments before a scan set up.
+#[derive(Debug)]
+struct Frame {
+    width: usize,
+    height: usize,
+    h_max: usize,
+    v_max: usize,
+    components: Vec<Component>,
+    quant: [[u16; 64]; 4],
+    dc_tables: [Huffman; 4],
+    ac_tables: [Huffman; 4],
+    restart_interval: usize,
+    /// The inverse DCT basis: `idct[x][u]` weighs frequency u at x.
+    idct: [[f32; 8]; 8],
+}
+
+/// Reads the entropy coded data of a scan, one bit at a time. Stuffed
+/// zero bytes are dropped; at a marker, zeros are fed instead.
+struct Bits<'a> {
+    data: &'a [u8],
+    pos: usize,
+    buffer: u32,
+    count: u32,
+}
+
+impl<'a> Bits<'a> {
+    fn bit(&mut self) -> u32 {
+        if self.count == 0 {
+            let mut byte = 0;
+            if self.pos < self.data.len() {
+                byte = self.data[self.pos];
+                if byte == 0xff {
+                    match self.data.get(self.pos + 1) {
+                        Some(&0) => self.pos += 2,
+                        _ => byte = 0,
+                    }
+                } else {
+                    self.pos += 1;
+                }
+            }
+            self.buffer = byte as u32;
+            self.count = 8;
+        }
+        self.count -= 1;
+        (self.buffer >> self.count) & 1
+    }
+
+    fn bits(&mut self, n: u32) -> u32 {
+        (0..n).fold(0, |v, _| (v << 1) | self.bit())
+    }
+
+    /// A value of category `n`, sign extended as in F.2.2.1.
+    fn receive(&mut self, n: u32) -> i32 {
+        if n == 0 {
+            return 0;
+        }
+        let v = self.bits(n) as i32;
+        if v < 1 << (n - 1) {
+            v - (1 << n) + 1
+        } else {
+            v
+        }
+    }
+
+    fn decode(&mut self, table: &Huffman) -> Result<u8, String> {
+        let mut code = 0i32;
+        for len in 1..=16 {
+            code = (code << 1) | self.bit() as i32;
+            if code <= table.max_code[len] {
+                let index = (table.offset[len] + code) as usize;
+                return table
+                    .symbols
+                    .get(index)
+                    .cloned()
+                    .ok_or_else(|| "bad Huffman table".to_string());
+            }
+        }
+        Err("bad Huffman code".to_string())
+    }
+
+    /// Skips to the byte after the next restart marker and starts over
+    /// with empty bits.
+    fn restart(&mut self) -> Result<(), String> {
+        self.count = 0;
+        while self.pos + 1 < self.data.len() {
+            if self.data[self.pos] == 0xff
+                && (0xd0..=0xd7).contains(&self.data[self.pos + 1])
+            {
+                self.pos += 2;
+                return Ok(());
+            }
+            self.pos += 1;
+        }
+        Err("missing restart marker".to_string())
+    }
+}
+
+/// Decodes a JPEG file to values in [0, 1] as stored, without undoing
+/// their gamma.
+pub fn decode(data: &[u8]) -> Result<Image, String> {
+    if data.len() < 4 || data[0] != 0xff || data[1] != 0xd8 {
+        return Err("not a JPEG file".to_string());
+    }
+    let mut frame = Frame {
+        width: 0,
+        height: 0,
+        h_max: 1,
+        v_max: 1,
+        components: Vec::new(),
+        quant: [[0; 64]; 4],
+        dc_tables: Default::default(),
+        ac_tables: Default::default(),
+        restart_interval: 0,
+        idct: [[0.0; 8]; 8],
+    };
+    for (x, row) in frame.idct.iter_mut().enumerate() {
+        for (u, b) in row.iter_mut().enumerate() {
+            let c = if u == 0 {
+                ::std::f32::consts::FRAC_1_SQRT_2
+            } else {
+                1.0
+            };
+            let angle = (2 * x + 1) as f32 * u as f32 * PI / 16.0;
+            *b = c * angle.cos();
+        }
+    }
+    let mut adobe_rgb = false;
+    let mut pos = 2;
+    loop {
+        // Markers may be padded with any number of 0xff bytes.
+        while pos < data.len() && data[pos] == 0xff {
+            pos += 1;
+        }
+        let marker = *data.get(pos).ok_or("truncated JPEG")?;
+        pos += 1;
+        if marker == 0xd9 {
+            break;
+        }
+        if pos + 2 > data.len() {
+            return Err("truncated JPEG".to_string());
+        }
+        let length = u16::from_be_bytes([data[pos], data[pos + 1]]) as usize;
+        if length < 2 || pos + length > data.len() {
+            return Err("truncated JPEG segment".to_string());
+        }
+        let segment = &data[pos + 2..pos + length];
+        pos += length;
+        match marker {
+            0xdb => frame.read_quant(segment)?,
+            0xc4 => frame.read_huffman(segment)?,
+            0xdd => {
+                let ri = segment.get(..2).ok_or("bad DRI segment")?;
+                frame.restart_interval =
+                    u16::from_be_bytes([ri[0], ri[1]]) as usize;
+            }
+            0xee => {
+                // The transform flag of an Adobe marker: 0 for RGB.
+                adobe_rgb = segment.len() >= 12
+                    && &segment[..5] == b"Adobe"
+                    && segment[11] == 0;
+            }
+            0xc0 | 0xc1 => frame.read_header(segment)?,
+            0xc2 | 0xc3 | 0xc5..=0xc7 | 0xc9..=0xcb | 0xcd..=0xcf => {
+                return Err("only baseline JPEGs are supported, not \
+                            progressive, lossless or arithmetic coded ones"
+                    .to_string());
+            }
+            0xda => pos += frame.decode_scan(segment, &data[pos..])?,
+            _ => {}
+        }
+    }
+    if frame.components.is_empty() {
+        return Err("JPEG without a frame".to_string());
+    }
+    let mut image = Image::new(frame.width, frame.height);
+    let sample = |c: &Component, x: usize, y: usize| {
+        let (cx, cy) = (x * c.h / frame.h_max, y * c.v / frame.v_max);
+        c.samples[cy * 8 * c.blocks_x + cx] as f32
+    };
+    for y in 0..frame.height {
+        for x in 0..frame.width {
+            let color = match &frame.components[..] {
+                [gray] => {
+                    let v = sample(gray, x, y) / 255.0;
+                    Vec3::new(v, v, v)
+                }
+                [a, b, c] => {
+                    let (a, b, c) =
+                        (sample(a, x, y), sample(b, x, y), sample(c, x, y));
+                    let (r, g, b) = if adobe_rgb {
+                        (a, b, c)
+                    } else {
+                        let (cb, cr) = (b - 128.0, c - 128.0);
+                        (
+                            a + 1.402 * cr,
+                            a - 0.344_136 * cb - 0.714_136 * cr,
+                            a + 1.772 * cb,
+                        )
+                    };
+                    let clamp = |v: f32| v.clamp(0.0, 255.0) / 255.0;
+                    Vec3::new(clamp(r), clamp(g), clamp(b))
+                }
+                _ => unreachable!(),
+            };
+            image.set_pixel(x, y, color);
+        }
+    }
+    Ok(image)
+}
+
+impl Frame {
+    fn read_quant(&mut self, segment: &[u8]) -> Result<(), String> {
+        let mut pos = 0;
+        while pos < segment.len() {
+            let precision = segment[pos] >> 4;
+            let id = (segment[pos] & 3) as usize;
+            pos += 1;
+            let size = if precision == 0 { 64 } else { 128 };
+            let values =
+                segment.get(pos..pos + size).ok_or("bad DQT segment")?;
+            for (k, q) in self.quant[id].iter_mut().enumerate() {
+                *q = if precision == 0 {
+                    values[k] as u16
+                } else {
+                    u16::from_be_bytes([values[2 * k], values[2 * k + 1]])
+                };
+            }
+            pos += size;
+        }
+        Ok(())
+    }
+
+    fn read_huffman(&mut self, segment: &[u8]) -> Result<(), String> {
+        let mut pos = 0;
+        while pos < segment.len() {
+            let (class, id) = (segment[pos] >> 4, (segment[pos] & 3) as usize);
+            let counts =
+                segment.get(pos + 1..pos + 17).ok_or("bad DHT segment")?;
+            let total = counts.iter().map(|&c| c as usize).sum::<usize>();
+            let symbols = segment
+                .get(pos + 17..pos + 17 + total)
+                .ok_or("bad DHT segment")?;
+            let table = Huffman::new(counts, symbols);
+            if class == 0 {
+                self.dc_tables[id] = table;
+            } else {
+                self.ac_tables[id] = table;
+            }
+            pos += 17 + total;
+        }
+        Ok(())
+    }
+
+    fn read_header(&mut self, segment: &[u8]) -> Result<(), String> {
+        if segment.len() < 6 || segment[0] != 8 {
+            return Err("only 8-bit JPEGs are supported".to_string());
+        }
+        self.height = u16::from_be_bytes([segment[1], segment[2]]) as usize;
+        self.width = u16::from_be_bytes([segment[3], segment[4]]) as usize;
+        let count = segment[5] as usize;
+        if self.width == 0 || self.height == 0 {
+            return Err("empty JPEG".to_string());
+        }
+        if count != 1 && count != 3 {
+            return Err(format!(
+                "JPEGs with {} components are not supported",
+                count
+            ));
+        }
+        let specs = segment.get(6..6 + 3 * count).ok_or("bad SOF segment")?;
+        self.components.clear();
+        for c in specs.chunks(3) {
+            let (h, v) = ((c[1] >> 4) as usize, (c[1] & 15) as usize);
+            if !(1..=4).contains(&h) || !(1..=4).contains(&v) {
+                return Err("bad sampling factors".to_string());
+            }
+            self.components.push(Component {
+                id: c[0],
+                h,
+                v,
+                quant: (c[2] & 3) as usize,
+                ..Component::default()
+            });
+        }
+        self.h_max = self.components.iter().map(|c| c.h).max().unwrap_or(1);
+        self.v_max = self.components.iter().map(|c| c.v).max().unwrap_or(1);
+        let (mcus_x, mcus_y) = self.mcus();
+        for c in &mut self.components {
+            c.blocks_x = mcus_x * c.h;
+            c.blocks_y = mcus_y * c.v;
+            c.samples = vec![0; 64 * c.blocks_x * c.blocks_y];
+        }
+        Ok(())
+    }
+
+    /// The number of MCUs across and down an interleaved scan.
+    fn mcus(&self) -> (usize, usize) {
+        let (w, h) = (8 * self.h_max, 8 * self.v_max);
+        (self.width.div_ceil(w), self.height.div_ceil(h))
+    }
+
+    /// Decodes the scan with the header `header` whose entropy coded data
+    /// starts `data`, into the samples of the components. Returns the
+    /// length of that data.
+    fn decode_scan(
+        &mut self, header: &[u8], data: &[u8],
+    ) -> Result<usize, String> {
+        if self.components.is_empty() {
+            return Err("scan before frame header".to_string());
+        }
+        let count = *header.first().ok_or("bad SOS segment")? as usize;
+        if count == 0 || header.len() < 1 + 2 * count {
+            return Err("bad SOS segment".to_string());
+        }
+        let mut scan = Vec::new();
+        for c in header[1..1 + 2 * count].chunks(2) {
+            let index = self
+                .components
+                .iter()
+                .position(|comp| comp.id == c[0])
+                .ok_or("scan of an unknown component")?;
+            let comp = &mut self.components[index];
+            comp.dc_table = (c[1] >> 4) as usize & 3;
+            comp.ac_table = (c[1] & 3) as usize;
+            comp.prediction = 0;
+            scan.push(index);
+        }
+        // The blocks of each MCU, as (component, block x, block y)
+        // offsets. A scan of one component goes through its blocks in
+        // raster order, without padding them to whole MCUs.
+        let (mcus_x, mcus_y, layout) = if let [index] = scan[..] {
+            let c = &self.components[index];
+            let w = (self.width * c.h).div_ceil(self.h_max);
+            let h = (self.height * c.v).div_ceil(self.v_max);
+            (w.div_ceil(8), h.div_ceil(8), vec![(index, 0, 0)])
+        } else {
+            let mut layout = Vec::new();
+            for &index in &scan {
+                let c = &self.components[index];
+                for by in 0..c.v {
+                    for bx in 0..c.h {
+                        layout.push((index, bx, by));
+                    }
+                }
+            }
+            let (mcus_x, mcus_y) = self.mcus();
+            (mcus_x, mcus_y, layout)
+        };
+        let mut bits = Bits {
+            data,
+            pos: 0,
+            buffer: 0,
+            count: 0,
+        };
+        let mut coefficients = [0i32; 64];
+        for mcu in 0..mcus_x * mcus_y {
+            let interval = self.restart_interval;
+            if interval > 0 && mcu > 0 && mcu % interval == 0 {
+                bits.restart()?;
+                for &index in &scan {
+                    self.components[index].prediction = 0;
+                }
+            }
+            let (mx, my) = (mcu % mcus_x, mcu / mcus_x);
+            for &(index, bx, by) in &layout {
+                let c = &mut self.components[index];
+                let (x, y) = if scan.len() == 1 {
+                    (mx, my)
+                } else {
+                    (mx * c.h + bx, my * c.v + by)
+                };
+                coefficients.iter_mut().for_each(|v| *v = 0);
+                let q = &self.quant[c.quant];
+                // Differences of 8-bit samples fit in 11 bits. Corrupt
+                // data may still add up to anything, so wrap rather than
+                // overflow.
+                let category = bits.decode(&self.dc_tables[c.dc_table])?;
+                if category > 11 {
+                    return Err("bad DC difference".to_string());
+                }
+                c.prediction =
+                    c.prediction.wrapping_add(bits.receive(category as u32));
+                coefficients[0] = c.prediction.wrapping_mul(q[0] as i32);
+                let mut k = 1;
+                while k < 64 {
+                    let rs = bits.decode(&self.ac_tables[c.ac_table])?;
+                    let (run, size) = ((rs >> 4) as usize, (rs & 15) as u32);
+                    if size == 0 {
+                        if run == 15 {
+                            k += 16;
+                            continue;
+                        }
+                        break;
+                    }
+                    k += run;
+                    if k > 63 {
+                        return Err("bad AC coefficients".to_string());
+                    }
+                    coefficients[ZIGZAG[k]] =
+                        bits.receive(size).wrapping_mul(q[k] as i32);
+                    k += 1;
+                }
+                let stride = 8 * c.blocks_x;
+                let start = 8 * y * stride + 8 * x;
+                idct(
+                    &self.idct,
+                    &coefficients,
+                    &mut c.samples[start..],
+                    stride,
+                );
+            }
+        }
+        // The data ends at the first marker that is not a restart.
+        let mut end = bits.pos;
+        while end + 1 < data.len()
+            && !(data[end] == 0xff
+                && data[end + 1] != 0
+                && !(0xd0..=0xd7).contains(&data[end + 1]))
+        {
+            end += 1;
+        }
+        Ok(end)
+    }
+}
+
+/// The inverse DCT of one block with the basis `table`, level shifted and
+/// clamped into rows of `stride` samples. Separable and in floating point,
+/// which is plenty for textures.
+fn idct(
+    table: &[[f32; 8]; 8], coefficients: &[i32; 64], out: &mut [u8],
+    stride: usize,
+) {
+    let mut rows = [0.0f32; 64];
+    for v in 0..8 {
+        for x in 0..8 {
+            rows[8 * v + x] = (0..8)
+                .map(|u| table[x][u] * coefficients[8 * v + u] as f32)
+                .sum::<f32>()
+                / 2.0;
+        }
+    }
+    for y in 0..8 {
+        for x in 0..8 {
+            let value =
+                (0..8).map(|v| table[y][v] * rows[8 * v + x]).sum::<f32>()
+                    / 2.0;
+            out[y * stride + x] =
+                (value + 128.0).round().clamp(0.0, 255.0) as u8;
+        }
+    }
+}
+
+#[cfg(test)]
+pub mod tests {
+    use super::*;
+    use ::std::iter::Extend;
+    use ::std::ops::Fn;
+    use ::std::{assert, assert_eq};
+
+    /// Writes the bits of a scan with byte stuffing.
+    struct Writer {
+        data: Vec<u8>,
+        buffer: u32,
+        count: u32,
+    }
+
+    impl Writer {
+        fn put(&mut self, value: u32, n: u32) {
+            for i in (0..n).rev() {
+                self.buffer = (self.buffer << 1) | ((value >> i) & 1);
+                self.count += 1;
+                if self.count == 8 {
+                    self.flush();
+                }
+            }
+        }
+
+        fn flush(&mut self) {
+            if self.count > 0 {
+                // Pads with ones.
+                let byte = (self.buffer << (8 - self.count)) as u8
+                    | ((1u32 << (8 - self.count)) - 1) as u8;
+                self.data.push(byte);
+                if byte == 0xff {
+                    self.data.push(0);
+                }
+            }
+            self.buffer = 0;
+            self.count = 0;
+        }
+
+        /// A DC difference coded with 4-bit codes for every category.
+        fn dc(&mut self, diff: i32) {
+            let size = 32 - diff.unsigned_abs().leading_zeros();
+            self.put(size, 4);
+            let bits = if diff < 0 { diff - 1 } else { diff };
+            self.put(bits as u32 & ((1 << size) - 1), size);
+        }
+    }
+
+    fn segment(out: &mut Vec<u8>, marker: u8, body: &[u8]) {
+        out.extend(&[0xff, marker]);
+        out.extend(&((body.len() + 2) as u16).to_be_bytes());
+        out.extend(body);
+    }
+
+    /// A JPEG of flat 8x8 blocks: `dc(component, block x, block y)` is
+    /// the level of each, minus 128. The first block of each component
+    /// also gets `ac` of the first horizontal cosine.
+    fn encode(
+        width: usize, height: usize, sampling: &[(u8, u8)], restart: u16,
+        ac: i32, dc: &dyn Fn(usize, usize, usize) -> i32,
+    ) -> Vec<u8> {
+        let mut out = vec![0xff, 0xd8];
+        let mut dqt = vec![0];
+        dqt.extend(&[1; 64]);
+        segment(&mut out, 0xdb, &dqt);
+        let mut dht = vec![0x00];
+        dht.extend(&[0, 0, 0, 12, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0]);
+        dht.extend(0..12u8);
+        // EOB is 0, the one AC symbol of category 8 is 10.
+        dht.push(0x10);
+        dht.extend(&[1, 1, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0]);
+        dht.extend(&[0x00, 0x08]);
+        segment(&mut out, 0xc4, &dht);
+        let mut sof = vec![8];
+        sof.extend(&(height as u16).to_be_bytes());
+        sof.extend(&(width as u16).to_be_bytes());
+        sof.push(sampling.len() as u8);
+        for (i, &(h, v)) in sampling.iter().enumerate() {
+            sof.extend(&[i as u8 + 1, (h << 4) | v, 0]);
+        }
+        segment(&mut out, 0xc0, &sof);
+        if restart > 0 {
+            segment(&mut out, 0xdd, &restart.to_be_bytes());
+        }
+        let mut sos = vec![sampling.len() as u8];
+        for i in 0..sampling.len() {
+            sos.extend(&[i as u8 + 1, 0x00]);
+        }
+        sos.extend(&[0, 63, 0]);
+        segment(&mut out, 0xda, &sos);
+
+        let h_max = sampling.iter().map(|s| s.0 as usize).max().unwrap();
+        let v_max = sampling.iter().map(|s| s.1 as usize).max().unwrap();
+        let mcus_x = width.div_ceil(8 * h_max);
+        let mcus_y = height.div_ceil(8 * v_max);
+        let mut w = Writer {
+            data: Vec::new(),
+            buffer: 0,
+            count: 0,
+        };
+        let mut predictions = vec![0; sampling.len()];
+        for mcu in 0..mcus_x * mcus_y {
+            if restart > 0 && mcu > 0 && mcu % restart as usize == 0 {
+                w.flush();
+                let n = (mcu / restart as usize - 1) % 8;
+                w.data.extend(&[0xff, 0xd0 + n as u8]);
+                predictions.iter_mut().for_each(|p| *p = 0);
+            }
+            let (mx, my) = (mcu % mcus_x, mcu / mcus_x);
+            for (i, &(h, v)) in sampling.iter().enumerate() {
+                for by in 0..v as usize {
+                    for bx in 0..h as usize {
+                        let (x, y) =
+                            (mx * h as usize + bx, my * v as usize + by);
+                        // F(0, 0) is 8 times the level of a flat block.
+                        let value = 8 * dc(i, x, y);
+                        w.dc(value - predictions[i]);
+                        predictions[i] = value;
+                        if ac != 0 && x == 0 && y == 0 {
+                            w.put(0b10, 2);
+                            w.put(
+                                (ac + if ac < 0 { 255 } else { 0 }) as u32,
+                                8,
+                            );
+                        }
+                        w.put(0, 1);
+                    }
+                }
+            }
+        }
+        w.flush();
+        out.extend(&w.data);
+        out.extend(&[0xff, 0xd9]);
+        out
+    }
+
+    #[test]
+    fn test_huffman() {
+        let mut counts = [0u8; 16];
+        counts[1] = 1;
+        counts[2] = 2;
+        let table = Huffman::new(&counts, &[7, 8, 9]);
+        // 00, 010 and 011.
+        let data = [0b0001_0011, 0b1111_1111, 0];
+        let mut bits = Bits {
+            data: &data,
+            pos: 0,
+            buffer: 0,
+            count: 0,
+        };
+        assert_eq!(bits.decode(&table), Ok(7));
+        assert_eq!(bits.decode(&table), Ok(8));
+        assert_eq!(bits.decode(&table), Ok(9));
+        // The stuffed 0xff reads as ones, then the zero byte is skipped.
+        assert_eq!(bits.bits(8), 0xff);
+        assert_eq!(bits.pos, 3);
+        assert_eq!(bits.receive(0), 0);
+    }
+
+    #[test]
+    fn test_decode_gray() {
+        let levels =
+            |_: usize, x: usize, y: usize| [[-100, 20], [0, 127]][y][x];
+        let data = encode(16, 12, &[(1, 1)], 1, 0, &levels);
+        let image = decode(&data).unwrap();
+        assert_eq!((image.width, image.height), (16, 12));
+        let level = |x, y| (image.pixel(x, y).x() * 255.0).round() as i32;
+        assert_eq!(level(0, 0), 28);
+        assert_eq!(level(15, 0), 148);
+        assert_eq!(level(3, 11), 128);
+        assert_eq!(level(12, 9), 255);
+        assert_eq!(image.pixel(12, 9), Vec3::new(1.0, 1.0, 1.0));
+
+        // A horizontal cosine in the first block: brighter on the left,
+        // with the same mean.
+        let data = encode(8, 8, &[(1, 1)], 0, 200, &|_, _, _| 0);
+        let image = decode(&data).unwrap();
+        let row = (0..8).map(|x| image.pixel(x, 3).x()).collect::<Vec<_>>();
+        assert!(row.windows(2).all(|w| w[0] > w[1]));
+        let mean = row.iter().sum::<f32>() / 8.0;
+        assert!((mean - 128.0 / 255.0).abs() < 1.0 / 255.0);
+    }
+
+    #[test]
+    fn test_decode_color() {
+        // 4:2:0: a gray left half and a red right half, whose chroma
+        // blocks each cover 16x16 pixels.
+        let levels = |c: usize, x: usize, _: usize| match (c, x) {
+            (0, 0) | (0, 1) => 0,
+            (0, _) => -52,
+            (1, 0) => 0,
+            (1, _) => -43,
+            (2, 0) => 0,
+            _ => 127,
+        };
+        let data = encode(32, 16, &[(2, 2), (1, 1), (1, 1)], 0, 0, &levels);
+        let image = decode(&data).unwrap();
+        let gray = image.pixel(5, 7);
+        assert!((gray - Vec3::new(0.5, 0.5, 0.5)).length() < 0.01);
+        let red = image.pixel(20, 3);
+        assert!(red.x() > 0.95 && red.y() < 0.05 && red.z() < 0.1);
+    }
+
+    #[test]
+    fn test_decode_errors() {
+        assert!(decode(b"\x89PNG").is_err());
+        let mut data = encode(8, 8, &[(1, 1)], 0, 0, &|_, _, _| 0);
+        // Turns the frame into a progressive one.
+        let sof = data.windows(2).position(|w| w == [0xff, 0xc0]).unwrap();
+        data[sof + 1] = 0xc2;
+        assert!(decode(&data).unwrap_err().contains("progressive"));
+        assert!(decode(&[0xff, 0xd8, 0xff, 0xd9]).is_err());
+
+        // The DC table's first code, which the scan starts with, is turned
+        // into categories that no 8-bit difference needs.
+        let data = encode(8, 8, &[(1, 1)], 0, 0, &|_, _, _| 0);
+        let dht = data.windows(2).position(|w| w == [0xff, 0xc4]).unwrap();
+        for category in [12, 31, 32, 255] {
+            let mut data = data.clone();
+            data[dht + 4 + 1 + 16] = category;
+            assert_eq!(decode(&data).unwrap_err(), "bad DC difference");
+        }
+    }
+}
@@ -0,0 +1,676 @@
+//! Materials made of other materials: a blend of two, a dielectric coating
+//! over any base, and different materials on the two sides of a surface.
+//!
+//! The coating follows the stochastic layering of pbrt-v4 (after Guo et
+//! al., "Position-Free Monte Carlo Simulation for Arbitrary Layered
+//! BSDFs", 2018): light is followed on a random walk between the coating
+//! and the base, so `eval` and `pdf` are unbiased estimates rather than
+//! exact values.
+
+use ::std::boxed::Box;
+use ::std::clone::Clone;
+use ::std::default::Default;
+use ::std::option::Option::{self, None, Some};
+use ::std::sync::Arc;
+
+use ::math::{Point3, Vec3};
+
+use crate::material::Dielectric;
+use crate::microfacet::Frame;
+use crate::pbrt::{
+    power_heuristic, BsdfSample, HitRecord, Material, Ray, Texture, RNG,
+};
+
+const INV_4PI: f32 = 1.0 / (4.0 * ::std::f32::consts::PI);
+
+/// Random walks per estimate of a coated material's BSDF.
+const WALKS: usize = 1;
+
+/// Bounces between the coating and the base before a walk gives up.
+const MAX_BOUNCES: usize = 10;
+
+/// The mean of a texture's channels clamped to [0, 1], for textures that
+/// weigh rather than color.
+fn fraction(t: &dyn Texture, rec: &HitRecord) -> f32 {
+    let c = t.filtered(rec);
+    ((c.x() + c.y() + c.z()) / 3.0).clamp(0.0, 1.0)
+}
+
+fn max_component(v: Vec3) -> f32 {
+    v.x().max(v.y()).max(v.z())
+}
+
+/// Blends `a` into `b` by `amount`, which may vary over the surface. Each
+/// sample comes from one of them, weighted against both.
+pub struct Mix {
+    pub a: Box<dyn Material>,
+    pub b: Box<dyn Material>,
+    pub amount: Arc<dyn Texture>,
+}
+
+impl Material for Mix {
+    fn sample(
+        &self, rng: &mut RNG, ray: &Ray, rec: &HitRecord,
+    ) -> Option<BsdfSample> {
+        let t = fraction(&*self.amount, rec);
+        let (first, second, p) = if rng.rand() < t {
+            (&self.b, &self.a, t)
+        } else {
+            (&self.a, &self.b, 1.0 - t)
+        };
+        let s = first.sample(rng, ray, rec)?;
+        if s.delta {
+            // The other material has no delta lobes in the same
+            // direction to add.
+            return Some(BsdfSample {
+                f: p * s.f,
+                pdf: p * s.pdf,
+                ..s
+            });
+        }
+        let q = 1.0 - p;
+        Some(BsdfSample {
+            f: p * s.f + q * second.eval(ray, rec, s.direction),
+            pdf: p * s.pdf + q * second.pdf(ray, rec, s.direction),
+            ..s
+        })
+    }
+
+    fn eval(&self, ray: &Ray, rec: &HitRecord, direction: Vec3) -> Vec3 {
+        let t = fraction(&*self.amount, rec);
+        (1.0 - t) * self.a.eval(ray, rec, direction)
+            + t * self.b.eval(ray, rec, direction)
+    }
+
+    fn pdf(&self, ray: &Ray, rec: &HitRecord, direction: Vec3) -> f32 {
+        let t = fraction(&*self.amount, rec);
+        (1.0 - t) * self.a.pdf(ray, rec, direction)
+            + t * self.b.pdf(ray, rec, direction)
+    }
+
+    fn is_delta(&self) -> bool {
+        self.a.is_delta() && self.b.is_delta()
+    }
+
+    fn emitted(&self, u: f32, v: f32, p: Point3) -> Vec3 {
+        let c = self.amount.value(u, v, p);
+        let t = ((c.x() + c.y() + c.z()) / 3.0).clamp(0.0, 1.0);
+        (1.0 - t) * self.a.emitted(u, v, p) + t * self.b.emitted(u, v, p)
+    }
+
+    fn is_emissive(&self) -> bool {
+        self.a.is_emissive() || self.b.is_emissive()
+    }
+
+    fn is_light(&self) -> bool {
+        self.a.is_light() || self.b.is_light()
+    }
+}
+
+/// `front` on the side the normal points to and `back` on the other,
+/// which sees the surface as if it were its front. Only the front emits.
+pub struct TwoSided {
+    pub front: Box<dyn Material>,
+    pub back: Box<dyn Material>,
+}
+
+impl TwoSided {
+    fn side(&self, ray: &Ray, rec: &HitRecord) -> (&dyn Material, HitRecord) {
+        if ray.direction.dot(rec.normal) <= 0.0 {
+            return (&*self.front, rec.clone());
+        }
+        let flipped = HitRecord {
+            normal: -rec.normal,
+            ..rec.clone()
+        };
+        (&*self.back, flipped)
+    }
+}
+
+impl Material for TwoSided {
+    fn sample(
+        &self, rng: &mut RNG, ray: &Ray, rec: &HitRecord,
+    ) -> Option<BsdfSample> {
+        let (mat, rec) = self.side(ray, rec);
+        mat.sample(rng, ray, &rec)
+    }
+
+    fn eval(&self, ray: &Ray, rec: &HitRecord, direction: Vec3) -> Vec3 {
+        let (mat, rec) = self.side(ray, rec);
+        mat.eval(ray, &rec, direction)
+    }
+
+    fn pdf(&self, ray: &Ray, rec: &HitRecord, direction: Vec3) -> f32 {
+        let (mat, rec) = self.side(ray, rec);
+        mat.pdf(ray, &rec, direction)
+    }
+
+    fn is_delta(&self) -> bool {
+        self.front.is_delta() && self.back.is_delta()
+    }
+
+    fn emitted(&self, u: f32, v: f32, p: Point3) -> Vec3 {
+        self.front.emitted(u, v, p)
+    }
+
+    fn is_emissive(&self) -> bool {
+        self.front.is_emissive()
+    }
+
+    fn is_light(&self) -> bool {
+        self.front.is_light()
+    }
+}
+
+/// A dielectric `coat` of some `thickness` over a `base`, like varnish or
+/// the clear coat of car paint. The coat absorbs by its `absorption` on
+/// the way through and faces the ray from both sides. Light that the base
+/// transmits is lost.
+pub struct Coated {
+    pub coat: Dielectric,
+    pub base: Box<dyn Material>,
+    pub thickness: f32,
+}
+
+/// A direction, its `f` and its density sampled from one of the layers.
+struct LayerSample {
+    wi: Vec3,
+    f: Vec3,
+    pdf: f32,
+    delta: bool,
+}
+
+/// A coated material at one hit, in the frame facing the ray. The base is
+/// called with rays made up in that frame.
+struct Layers<'a> {
+    coated: &'a Coated,
+    frame: Frame,
+    ray: &'a Ray,
+    rec: HitRecord,
+}
+
+impl<'a> Layers<'a> {
+    fn new(coated: &'a Coated, ray: &'a Ray, rec: &HitRecord) -> Self {
+        let frame = Frame::facing(rec, ray.direction);
+        Layers {
+            coated,
+            frame,
+            ray,
+            rec: HitRecord {
+                normal: frame.z,
+                ..rec.clone()
+            },
+        }
+    }
+
+    /// The fraction of light left after crossing the coat along `w`.
+    fn transmittance(&self, w: Vec3) -> Vec3 {
+        let a = self.coated.coat.absorption * self.coated.thickness
+            / w.z().abs().max(1e-4);
+        Vec3::new((-a.x()).exp(), (-a.y()).exp(), (-a.z()).exp())
+    }
+
+    /// The coat's `f` and density for `wo` on either side.
+    fn coat_f_pdf(&self, wo: Vec3, wi: Vec3) -> (f32, f32) {
+        let coat = &self.coated.coat;
+        if coat.is_delta() {
+            return (0.0, 0.0);
+        }
+        if wo.z() > 0.0 {
+            coat.local_f_pdf(wo, wi, coat.ref_idx)
+        } else {
+            coat.local_f_pdf(-wo, -wi, 1.0 / coat.ref_idx)
+        }
+    }
+
+    fn coat_sample(&self, rng: &mut RNG, wo: Vec3) -> Option<LayerSample> {
+        let coat = &self.coated.coat;
+        let (wi, f, pdf) = if wo.z() > 0.0 {
+            coat.sample_local(rng, wo, coat.ref_idx)?
+        } else {
+            let (wi, f, pdf) =
+                coat.sample_local(rng, -wo, 1.0 / coat.ref_idx)?;
+            (-wi, f, pdf)
+        };
+        Some(LayerSample {
+            wi,
+            f: Vec3::new(f, f, f),
+            pdf,
+            delta: coat.is_delta(),
+        })
+    }
+
+    /// The ray that arrives at the base to leave towards `wo`.
+    fn base_ray(&self, wo: Vec3) -> Ray {
+        let direction = -self.frame.world(wo);
+        Ray::new(
+            self.rec.p - direction,
+            direction,
+            self.ray.time,
+            self.ray.max_depth,
+        )
+    }
+
+    fn base_f_pdf(&self, wo: Vec3, wi: Vec3) -> (Vec3, f32) {
+        let ray = self.base_ray(wo);
+        let direction = self.frame.world(wi);
+        let base = &self.coated.base;
+        (
+            base.eval(&ray, &self.rec, direction),
+            base.pdf(&ray, &self.rec, direction),
+        )
+    }
+
+    fn base_sample(&self, rng: &mut RNG, wo: Vec3) -> Option<LayerSample> {
+        let s = self
+            .coated
+            .base
+            .sample(rng, &self.base_ray(wo), &self.rec)?;
+        Some(LayerSample {
+            wi: self.frame.local(s.direction),
+            f: s.f,
+            pdf: s.pdf,
+            delta: s.delta,
+        })
+    }
+
+    /// A coat sample for `w` that enters the layer, None if it reflects.
+    fn enter(&self, rng: &mut RNG, w: Vec3) -> Option<LayerSample> {
+        self.coat_sample(rng, w)
+            .filter(|s| s.wi.z() < 0.0 && s.pdf > 0.0)
+    }
+
+    /// Estimates the BSDF times the cosine for light from `wi` that
+    /// leaves towards `wo`, by walks from both ends that are joined where
+    /// they meet the other layer.
+    fn f(&self, rng: &mut RNG, wo: Vec3, wi: Vec3) -> Vec3 {
+        if wo.z() <= 0.0 || wi.z() <= 0.0 {
+            return Vec3::default();
+        }
+        let reflected = self.coat_f_pdf(wo, wi).0;
+        let mut f = WALKS as f32 * Vec3::new(reflected, reflected, reflected);
+        let coat_delta = self.coated.coat.is_delta();
+        for _ in 0..WALKS {
+            let wos = match self.enter(rng, wo) {
+                Some(s) => s,
+                None => continue,
+            };
+            let wis = match self.enter(rng, wi) {
+                Some(s) => s,
+                None => continue,
+            };
+            // The walk from `wi` carries importance, whose transmission
+            // is not scaled by the squared ratio of the indices, and its
+            // `f` has the cosine of `wi` rather than that inside.
+            let eta = self.coated.coat.ref_idx;
+            let wis_f = eta * eta * wi.z() / wis.wi.z().abs() * wis.f;
+            let mut beta = wos.f / wos.pdf;
+            let mut w = wos.wi;
+            let mut at_base = false;
+            for bounce in 0..MAX_BOUNCES {
+                if bounce > 3 && max_component(beta) < 0.25 {
+                    let q = (1.0 - max_component(beta)).max(0.0);
+                    if rng.rand() < q {
+                        break;
+                    }
+                    beta /= 1.0 - q;
+                }
+                at_base = !at_base;
+                beta = beta * self.transmittance(w);
+                if !at_base {
+                    // Light that leaves through the coat here is counted
+                    // where the walks are joined.
+                    let s = match self.coat_sample(rng, -w) {
+                        Some(s) if s.wi.z() < 0.0 && s.pdf > 0.0 => s,
+                        _ => break,
+                    };
+                    beta = beta * s.f / s.pdf;
+                    w = s.wi;
+                    continue;
+                }
+                // Join with the walk from `wi` through the base.
+                let (base_f, base_pdf) = self.base_f_pdf(-w, -wis.wi);
+                let weight = if coat_delta {
+                    1.0
+                } else {
+                    power_heuristic(wis.pdf, base_pdf)
+                };
+                f += beta
+                    * base_f
+                    * self.transmittance(wis.wi)
+                    * wis_f
+                    * (weight / wis.pdf);
+                let s = match self.base_sample(rng, -w) {
+                    Some(s) if s.wi.z() > 0.0 && s.pdf > 0.0 => s,
+                    _ => break,
+                };
+                beta = beta * s.f / s.pdf;
+                w = s.wi;
+                // Or through the coat.
+                if !coat_delta {
+                    let exit_f = self.coat_f_pdf(-w, wi).0;
+                    if exit_f > 0.0 {
+                        // Against the walk from `wi` finding the same
+                        // direction inside.
+                        let weight = if s.delta {
+                            1.0
+                        } else {
+                            power_heuristic(s.pdf, self.coat_f_pdf(wi, -w).1)
+                        };
+                        f += exit_f * weight * beta * self.transmittance(w);
+                    }
+                }
+            }
+        }
+        f / WALKS as f32
+    }
+
+    /// An approximation of the density of `sample` picking `wi`, mixed with
+    /// a uniform one so that it is never too small.
+    fn pdf(&self, rng: &mut RNG, wo: Vec3, wi: Vec3) -> f32 {
+        if wo.z() <= 0.0 || wi.z() <= 0.0 {
+            return 0.0;
+        }
+        let mut pdf = WALKS as f32 * self.coat_f_pdf(wo, wi).1;
+        for _ in 0..WALKS {
+            let (wos, wis) = match (self.enter(rng, wo), self.enter(rng, wi)) {
+                (Some(wos), Some(wis)) => (wos, wis),
+                _ => continue,
+            };
+            if wos.delta {
+                pdf += self.base_f_pdf(-wos.wi, -wis.wi).1;
+                continue;
+            }
+            let s = match self.base_sample(rng, -wos.wi) {
+                Some(s) if s.wi.z() > 0.0 => s,
+                _ => continue,
+            };
+            if s.delta {
+                pdf += self.coat_f_pdf(-s.wi, wi).1;
+                continue;
+            }
+            let base_pdf = self.base_f_pdf(-wos.wi, -wis.wi).1;
+            pdf += power_heuristic(wis.pdf, base_pdf) * base_pdf;
+            let exit_pdf = self.coat_f_pdf(-s.wi, wi).1;
+            pdf += power_heuristic(s.pdf, exit_pdf) * exit_pdf;
+        }
+        0.1 * INV_4PI + 0.9 * pdf / WALKS as f32
+    }
+
+    /// Follows light into the layer until it leaves again. The returned
+    /// density is only proportional to the true one.
+    fn sample(&self, rng: &mut RNG, wo: Vec3) -> Option<LayerSample> {
+        let s = self.coat_sample(rng, wo)?;
+        if s.wi.z() > 0.0 {
+            return Some(s);
+        }
+        let (mut f, mut pdf, mut delta) = (s.f, s.pdf, s.delta);
+        let mut w = s.wi;
+        let mut at_base = false;
+        for bounce in 0..MAX_BOUNCES {
+            let m = max_component(f) / pdf;
+            if bounce > 3 && m < 0.25 {
+                let q = (1.0 - m).max(0.0);
+                if rng.rand() < q {
+                    return None;
+                }
+                pdf *= 1.0 - q;
+            }
+            if w.z() == 0.0 {
+                return None;
+            }
+            at_base = !at_base;
+            f = f * self.transmittance(w);
+            let s = if at_base {
+                // What the base transmits is lost.
+                self.base_sample(rng, -w).filter(|s| s.wi.z() > 0.0)?
+            } else {
+                self.coat_sample(rng, -w)?
+            };
+            f = f * s.f;
+            pdf *= s.pdf;
+            delta &= s.delta;
+            w = s.wi;
+            if !at_base && w.z() > 0.0 {
+                return Some(LayerSample {
+                    wi: w,
+                    f,
+                    pdf,
+                    delta,
+                });
+            }
+        }
+        None
+    }
+}
+
+/// A generator for the walks that estimate a coated material's BSDF
+/// between two directions, so that the same query gives the same answer.
+fn pair_rng(wo: Vec3, wi: Vec3) -> RNG {
+    RNG::hashed(&[
+        wo.x().to_bits(),
+        wo.y().to_bits(),
+        wo.z().to_bits(),
+        wi.x().to_bits(),
+        wi.y().to_bits(),
+        wi.z().to_bits(),
+    ])
+}
+
+impl Material for Coated {
+    fn sample(
+        &self, rng: &mut RNG, ray: &Ray, rec: &HitRecord,
+    ) -> Option<BsdfSample> {
+        let layers = Layers::new(self, ray, rec);
+        let wo = layers.frame.local(-ray.direction);
+        let s = layers.sample(rng, wo)?;
+        if s.pdf <= 0.0 {
+            return None;
+        }
+        let direction = layers.frame.world(s.wi).unit();
+        if s.delta {
+            return Some(BsdfSample {
+                direction,
+                f: s.f,
+                pdf: s.pdf,
+                delta: true,
+            });
+        }
+        // The walk's weight with the density that light sampling is
+        // weighted against.
+        let pdf = layers.pdf(&mut pair_rng(wo, s.wi), wo, s.wi);
+        Some(BsdfSample {
+            direction,
+            f: s.f * (pdf / s.pdf),
+            pdf,
+            delta: false,
+        })
+    }
+
+    fn eval(&self, ray: &Ray, rec: &HitRecord, direction: Vec3) -> Vec3 {
+        let layers = Layers::new(self, ray, rec);
+        let wo = layers.frame.local(-ray.direction);
+        let wi = layers.frame.local(direction);
+        layers.f(&mut pair_rng(wo, wi), wo, wi)
+    }
+
+    fn pdf(&self, ray: &Ray, rec: &HitRecord, direction: Vec3) -> f32 {
+        let layers = Layers::new(self, ray, rec);
+        let wo = layers.frame.local(-ray.direction);
+        let wi = layers.frame.local(direction);
+        layers.pdf(&mut pair_rng(wo, wi), wo, wi)
+    }
+
+    fn is_delta(&self) -> bool {
+        self.coat.is_delta() && self.base.is_delta()
+    }
+}
+
+#[cfg(test)]
+pub mod tests {
+    use super::*;
+    use ::std::assert;
+
+    use crate::material::{fresnel_dielectric, Lambertian, Metal};
+    use crate::microfacet::TrowbridgeReitz;
+    use crate::texture::ConstTexture;
+
+    const PI: f32 = ::std::f32::consts::PI;
+
+    fn gray(v: f32) -> Arc<dyn Texture> {
+        Arc::new(ConstTexture(Vec3::new(v, v, v)))
+    }
+
+    fn lambertian(v: f32) -> Box<dyn Material> {
+        Box::new(Lambertian::new(gray(v)))
+    }
+
+    fn ray() -> Ray {
+        Ray::new(
+            Point3::new(-1.0, 1.0, 0.0),
+            Vec3::new(1.0, -1.0, 0.0),
+            0.0,
+            1,
+        )
+    }
+
+    fn outside() -> HitRecord {
+        HitRecord {
+            normal: Vec3::new(0.0, 1.0, 0.0),
+            t: 1.0,
+            ..HitRecord::default()
+        }
+    }
+
+    /// The average path weight of `mat`'s samples and the same integrated
+    /// from `eval` over uniform directions.
+    fn albedo(mat: &dyn Material, rec: &HitRecord) -> (f32, f32) {
+        let mut rng = RNG::default();
+        let n = 100000;
+        let (mut sampled, mut uniform) = (0.0, 0.0);
+        for _ in 0..n {
+            if let Some(s) = mat.sample(&mut rng, &ray(), rec) {
+                sampled += s.f.y() / s.pdf;
+            }
+            let w = rng.random_unit_vector();
+            uniform += mat.eval(&ray(), rec, w).y() * 4.0 * PI;
+        }
+        (sampled / n as f32, uniform / n as f32)
+    }
+
+    #[test]
+    fn test_mix() {
+        let mix = Mix {
+            a: lambertian(0.8),
+            b: lambertian(0.2),
+            amount: gray(0.25),
+        };
+        let rec = outside();
+        let up = Vec3::new(0.0, 1.0, 0.0);
+        let f = mix.eval(&ray(), &rec, up).y();
+        assert!((f - (0.75 * 0.8 + 0.25 * 0.2) / PI).abs() < 1e-5);
+        let (sampled, uniform) = albedo(&mix, &rec);
+        assert!((sampled - 0.65).abs() < 0.01);
+        assert!((uniform - 0.65).abs() < 0.03);
+
+        // A mirror mixed in keeps its delta samples.
+        let chrome = Mix {
+            a: lambertian(0.8),
+            b: Box::new(Metal::new(Vec3::new(1.0, 1.0, 1.0), 0.0)),
+            amount: gray(0.5),
+        };
+        assert!(!chrome.is_delta());
+        let mut rng = RNG::default();
+        let mut deltas = 0;
+        for _ in 0..1000 {
+            let s = chrome.sample(&mut rng, &ray(), &rec).unwrap();
+            if s.delta {
+                deltas += 1;
+                assert!((s.f.y() / s.pdf - 1.0).abs() < 1e-5);
+            }
+        }
+        assert!(deltas > 400 && deltas < 600);
+    }
+
+    #[test]
+    fn test_two_sided() {
+        let sheet = TwoSided {
+            front: lambertian(0.8),
+            back: lambertian(0.2),
+        };
+        let down = ray();
+        let up = Ray::new(
+            Point3::new(-1.0, -1.0, 0.0),
+            Vec3::new(1.0, 1.0, 0.0),
+            0.0,
+            1,
+        );
+        let rec = outside();
+        let f = sheet.eval(&down, &rec, Vec3::new(0.0, 1.0, 0.0)).y();
+        assert!((f - 0.8 / PI).abs() < 1e-5);
+        let f = sheet.eval(&up, &rec, Vec3::new(0.0, -1.0, 0.0)).y();
+        assert!((f - 0.2 / PI).abs() < 1e-5);
+        // The back reflects to its own side.
+        let mut rng = RNG::default();
+        let s = sheet.sample(&mut rng, &up, &rec).unwrap();
+        assert!(s.direction.y() < 0.0);
+    }
+
+    #[test]
+    fn test_coated() {
+        // Nothing is absorbed, so what the base does not reflect right
+        // away comes out after bouncing inside the coat.
+        let rec = outside();
+        for &roughness in &[0.0, 0.3] {
+            let coated = Coated {
+                coat: Dielectric {
+                    distribution: TrowbridgeReitz::from_roughness(
+                        roughness, roughness,
+                    ),
+                    ..Dielectric::new(1.5)
+                },
+                base: lambertian(1.0),
+                thickness: 0.01,
+            };
+            assert!(!coated.is_delta());
+            let (sampled, uniform) = albedo(&coated, &rec);
+            assert!(sampled > 0.85 && sampled <= 1.01);
+            // `eval` leaves out the mirror reflection of a smooth coat.
+            let mirror = if roughness == 0.0 {
+                fresnel_dielectric(0.5f32.sqrt(), 1.5)
+            } else {
+                0.0
+            };
+            assert!((sampled - uniform - mirror).abs() < 0.03);
+        }
+
+        // A tinted coat darkens the base, more so when it is thicker.
+        let tinted = |thickness| Coated {
+            coat: Dielectric {
+                absorption: Vec3::new(0.0, 20.0, 20.0),
+                ..Dielectric::new(1.5)
+            },
+            base: lambertian(0.8),
+            thickness,
+        };
+        let up = Vec3::new(0.0, 1.0, 0.0).unit();
+        let thin = tinted(0.01).eval(&ray(), &rec, up);
+        let thick = tinted(0.05).eval(&ray(), &rec, up);
+        assert!(thin.x() > thin.y() && thin.y() > thick.y());
+
+        // A smooth coat over a mirror is a mirror.
+        let lacquered = Coated {
+            coat: Dielectric::new(1.5),
+            base: Box::new(Metal::new(Vec3::new(0.9, 0.9, 0.9), 0.0)),
+            thickness: 0.01,
+        };
+        assert!(lacquered.is_delta());
+        let mut rng = RNG::default();
+        let s = lacquered.sample(&mut rng, &ray(), &rec).unwrap();
+        assert!(
+            s.delta
+                && (s.direction - Vec3::new(1.0, 1.0, 0.0).unit()).length()
+                    < 1e-4
+        );
+    }
+}
@@ -3,343 +3,98 @@
 
 use ::std::boxed::Box;
 use ::std::clone::Clone;
-use ::std::convert::From;
 use ::std::default::Default;
-use ::std::fs::File;
-use ::std::io::{self, BufWriter, Write};
-use ::std::iter::Iterator;
+use ::std::env;
+use ::std::fs;
+use ::std::io::{self, Write};
+use ::std::iter::{Extend, Iterator};
 use ::std::option::Option::{self, None, Some};
-use ::std::rc::Rc;
-use ::std::{print, write};
+use ::std::path::Path;
+use ::std::process;
+use ::std::result::Result::{self, Err, Ok};
+use ::std::str::FromStr;
+use ::std::string::{String, ToString};
+use ::std::thread;
+use ::std::vec::Vec;
+use ::std::{eprintln, format, print, vec};
 
 use ::math::{Point3, Vec3};
 
+mod bezier;
+mod csg;
+mod curve;
 mod executor;
+mod film;
+mod hair;
+mod heightfield;
+mod image;
+mod jpeg;
+mod layered;
+mod material;
+mod medium;
+mod mesh;
+mod microfacet;
+mod obj;
 mod pbrt;
-
-use pbrt::{
-    Camera, HitRecord, Hitable, HitableList, Material, MaterialLibrary, Ray,
-    Texture, AABB, BVH, RNG,
+mod pbrtv3;
+mod principled;
+mod quadric;
+mod render;
+mod scene;
+mod sdf;
+mod shapes;
+mod subdiv;
+mod texture;
+
+use image::{ExrCompression, ImageError, ImageFormat, WriteOptions};
+use pbrt::{Camera, Hitable, HitableList, MaterialLibrary, RNG};
+use scene::{
+    CameraDesc, ColorOrTexture, MaterialDesc, ObjectDesc, RenderSettings,
+    Scene, SceneDesc, TextureDesc, TransformDesc,
 };
-
-#[derive(Debug)]
-struct Sphere {
-    center: Point3,
-    radius: f32,
-    radius_squared: f32,
-    material: usize,
-}
-
-impl Sphere {
-    fn new(center: Point3, radius: f32, material: usize) -> Self {
-        Sphere {
+use shapes::Plane;
+
+fn random_scene(rng: &mut RNG) -> SceneDesc {
+    let mut desc = SceneDesc {
+        camera: CameraDesc {
+            look_from: Point3::new(13.0, 2.0, 3.0),
+            look_at: Point3::new(0.0, 0.0, 0.0),
+            fov: 20.0,
+            focus_distance: 10.0,
+            ..CameraDesc::default()
+        },
+        ..SceneDesc::default()
+    };
+    let sphere = |desc: &mut SceneDesc, center, radius, material: &str| {
+        desc.objects.push(ObjectDesc::Sphere {
             center,
             radius,
-            radius_squared: radius * radius,
-            material,
-        }
-    }
-}
-
-const PI: f32 = ::std::f32::consts::PI;
-
-impl Hitable for Sphere {
-    fn hit(
-        &self, r: &Ray, t_min: f32, t_max: f32, rec: &mut HitRecord,
-    ) -> bool {
-        let oc = r.origin - self.center;
-        let a = r.direction.dot(r.direction);
-        let b = oc.dot(r.direction);
-        let c = oc.dot(oc) - self.radius_squared;
-        let discriminat = b * b - a * c;
-        if discriminat > 0.0 {
-            let dsqrt = discriminat.sqrt();
-            let temp = (-b - dsqrt) / a;
-            let rec_p = r.point_at_param(temp);
-            let normal = (rec_p - self.center) / self.radius;
-            let phi = normal.z().atan2(normal.x());
-            let theta = normal.y().asin();
-            if temp < t_max && temp > t_min {
-                rec.t = temp;
-                rec.p = rec_p;
-                rec.normal = normal;
-                rec.material = self.material;
-                rec.u = 1.0 - (phi + PI) / (2.0 * PI);
-                rec.v = (theta - PI / 2.0) / PI;
-                return true;
-            }
-            let temp = (-b + dsqrt) / a;
-            if temp < t_max && temp > t_min {
-                rec.t = temp;
-                rec.p = rec_p;
-                rec.normal = normal;
-                rec.material = self.material;
-                rec.u = 1.0 - (phi + PI) / (2.0 * PI);
-                rec.v = (theta - PI / 2.0) / PI;
-                return true;
-            }
-        }
-        return false;
-    }
-
-    fn bounding_box(&self, _: f32, _: f32) -> Option<AABB> {
-        let rv = Vec3::new(self.radius, self.radius, self.radius);
-        Some(AABB::new(self.center - rv, self.center + rv))
-    }
-}
-
-#[derive(Debug)]
-struct MovingSphere {
-    center0: Point3,
-    center1: Point3,
-    t0: f32,
-    t1: f32,
-    radius: f32,
-    radius_squared: f32,
-    material: usize,
-}
-
-impl MovingSphere {
-    fn new(
-        center0: Point3, center1: Point3, t0: f32, t1: f32, radius: f32,
-        material: usize,
-    ) -> Self {
-        MovingSphere {
-            center0,
-            center1,
-            t0,
-            t1,
-            radius,
-            radius_squared: radius * radius,
-            material,
-        }
-    }
-
-    fn center(&self, t: f32) -> Point3 {
-        self.center0
-            + ((t - self.t0) / (self.t1 - self.t0))
-                * (self.center1 - self.center0)
-    }
-}
-
-impl Hitable for MovingSphere {
-    fn hit(
-        &self, r: &Ray, t_min: f32, t_max: f32, rec: &mut HitRecord,
-    ) -> bool {
-        let oc = r.origin - self.center(r.time);
-        let a = r.direction.dot(r.direction);
-        let b = oc.dot(r.direction);
-        let c = oc.dot(oc) - self.radius_squared;
-        let discriminat = b * b - a * c;
-        if discriminat > 0.0 {
-            let dsqrt = discriminat.sqrt();
-            let temp = (-b - dsqrt) / a;
-            if temp < t_max && temp > t_min {
-                rec.t = temp;
-                rec.p = r.point_at_param(temp);
-                rec.normal = (rec.p - self.center(r.time)) / self.radius;
-                rec.material = self.material;
-                return true;
-            }
-            let temp = (-b + dsqrt) / a;
-            if temp < t_max && temp > t_min {
-                rec.t = temp;
-                rec.p = r.point_at_param(temp);
-                rec.normal = (rec.p - self.center(r.time)) / self.radius;
-                rec.material = self.material;
-                return true;
-            }
-        }
-        return false;
-    }
-
-    fn bounding_box(&self, _: f32, _: f32) -> Option<AABB> {
-        let rv = Vec3::new(self.radius, self.radius, self.radius);
-        let b0 = AABB::new(self.center0 - rv, self.center0 + rv);
-        let b1 = AABB::new(self.center1 - rv, self.center1 + rv);
-        Some(AABB::surround(b0, b1))
-    }
-}
-
-#[derive(Debug, Clone, Copy)]
-struct ConstTexture(Vec3);
-
-impl Texture for ConstTexture {
-    fn value(&self, _: f32, _: f32, _: Point3) -> Vec3 {
-        self.0
-    }
-}
-
-#[derive(Debug)]
-struct CheckerTexture {
-    odd: Rc<dyn Texture>,
-    even: Rc<dyn Texture>,
-}
-
-impl Texture for CheckerTexture {
-    fn value(&self, u: f32, v: f32, p: Point3) -> Vec3 {
-        let sines: Vec3 = Vec3::from(p) * 10.0;
-        let o = sines[0].sin() * sines[1].sin() * sines[2].sin();
-        if o < 0.0 {
-            self.odd.value(u, v, p)
-        } else {
-            self.even.value(u, v, p)
-        }
-    }
-}
-
-struct Lambertian {
-    albedo: Rc<dyn Texture>,
-}
-
-impl Lambertian {
-    fn new(albedo: Rc<dyn Texture>) -> Self {
-        Lambertian {
-            albedo: albedo.clone(),
-        }
-    }
-}
-
-impl Material for Lambertian {
-    fn scatter(
-        &self, rng: &mut RNG, ray: &Ray, rec: &mut HitRecord,
-        attenuation: &mut Vec3, scattered: &mut Ray,
-    ) -> bool {
-        let target = rec.p + rec.normal + rng.random_in_unit_sphere();
-        *scattered =
-            Ray::new(rec.p, target - rec.p, ray.time, ray.max_depth - 1);
-        *attenuation = self.albedo.value(0.0, 0.0, rec.p);
-        true
-    }
-}
-
-struct Metal {
-    albedo: Vec3,
-    fuzz: f32,
-}
-
-#[inline(always)]
-fn reflect(v: Vec3, n: Vec3) -> Vec3 {
-    v - 2.0 * v.dot(n) * n
-}
-
-impl Metal {
-    fn new(albedo: Vec3, fuzz: f32) -> Self {
-        Metal {
-            albedo,
-            fuzz: if fuzz < 1.0 { fuzz } else { 1.0 },
-        }
-    }
-}
-
-impl Material for Metal {
-    fn scatter(
-        &self, rng: &mut RNG, ray: &Ray, rec: &mut HitRecord,
-        attenuation: &mut Vec3, scattered: &mut Ray,
-    ) -> bool {
-        let reflected = reflect(ray.direction, rec.normal);
-        *scattered = Ray::new(
-            rec.p,
-            reflected + self.fuzz * rng.random_in_unit_sphere(),
-            ray.time,
-            ray.max_depth - 1,
-        );
-        *attenuation = self.albedo;
-        scattered.direction.dot(rec.normal) > 0.0
-    }
-}
-
-fn refract(v: Vec3, n: Vec3, ni_over_nt: f32) -> Option<Vec3> {
-    let dt = v.dot(n);
-    let discriminant = 1.0 - ni_over_nt * ni_over_nt * (1.0 - dt * dt);
-    if discriminant > 0.0 {
-        return Some(ni_over_nt * (v - n * dt) - n * discriminant.sqrt());
-    }
-    None
-}
-
-struct Dielectric {
-    ref_idx: f32,
-}
-
-impl Material for Dielectric {
-    fn scatter(
-        &self, rng: &mut RNG, ray: &Ray, rec: &mut HitRecord,
-        attenuation: &mut Vec3, scattered: &mut Ray,
-    ) -> bool {
-        let reflected = reflect(ray.direction, rec.normal);
-        let outward_normal;
-        let ni_over_nt;
-        let cosine;
-        *attenuation = Vec3::new(1.0, 1.0, 1.0);
-        let dir_dot_nrm = ray.direction.dot(rec.normal);
-        if dir_dot_nrm > 0.0 {
-            outward_normal = -rec.normal;
-            ni_over_nt = self.ref_idx;
-            cosine = self.ref_idx * dir_dot_nrm / ray.direction.length();
-        } else {
-            outward_normal = rec.normal;
-            ni_over_nt = 1.0 / self.ref_idx;
-            cosine = -dir_dot_nrm / ray.direction.length();
-        }
-        let some_refracted = refract(ray.direction, outward_normal, ni_over_nt);
-        let reflect_prob = if some_refracted.is_some() {
-            schlick(cosine, self.ref_idx)
-        } else {
-            1.0
-        };
-        if rng.rand() < reflect_prob {
-            *scattered =
-                Ray::new(rec.p, reflected, ray.time, ray.max_depth - 1);
-        } else {
-            *scattered = Ray::new(
-                rec.p,
-                some_refracted.unwrap(),
-                ray.time,
-                ray.max_depth - 1,
-            );
-        }
-        true
-    }
-}
-
-fn schlick(cosine: f32, ref_idx: f32) -> f32 {
-    let r0 = (1.0 - ref_idx) / (1.0 + ref_idx);
-    let r0 = r0 * r0;
-    r0 + (1.0 - r0) * (1.0 - cosine).powf(5.0)
-}
-
-struct DiffuseLight {
-    emit: Rc<dyn Texture>,
-}
-
-impl Material for DiffuseLight {
-    fn scatter(
-        &self, _rng: &mut RNG, _ray: &Ray, _rec: &mut HitRecord,
-        _attenuation: &mut Vec3, _scattered: &mut Ray,
-    ) -> bool {
-        false
-    }
-
-    fn emitted(&self, u: f32, v: f32, p: Point3) -> Vec3 {
-        self.emit.value(u, v, p)
-    }
-}
-
-fn random_scene(rng: &mut RNG, matlib: &mut MaterialLibrary) -> HitableList {
-    let mut hitables = HitableList::default();
-
-    matlib.lib.push(Box::new(Lambertian {
-        albedo: Rc::new(CheckerTexture {
-            odd: Rc::new(ConstTexture(Vec3::new(0.2, 0.3, 0.1))),
-            even: Rc::new(ConstTexture(Vec3::new(0.9, 0.9, 0.9))),
-        }),
-    }));
-    hitables.list.push(Rc::new(Sphere::new(
-        Point3::new(0.0, -1000.0, 0.0),
-        1000.0,
-        matlib.lib.len() - 1,
-    )));
+            material: material.to_string(),
+        })
+    };
+
+    desc.textures.insert(
+        "checker".to_string(),
+        TextureDesc::Checker {
+            odd: ColorOrTexture::Color(Vec3::new(0.2, 0.3, 0.1)),
+            even: ColorOrTexture::Color(Vec3::new(0.9, 0.9, 0.9)),
+        },
+    );
+    desc.materials.insert(
+        "ground".to_string(),
+        MaterialDesc::Lambertian {
+            albedo: ColorOrTexture::Texture("checker".to_string()),
+        },
+    );
+    sphere(&mut desc, Point3::new(0.0, -1000.0, 0.0), 1000.0, "ground");
+    desc.materials.insert(
+        "glass".to_string(),
+        MaterialDesc::Dielectric {
+            ior: 1.5,
+            roughness: (0.0, 0.0),
+            absorption: Vec3::default(),
+        },
+    );
 
     for a in -5..5 {
         for b in -5..5 {
@@ -352,155 +107,298 @@ fn random_scene(rng: &mut RNG, matlib: &mut MaterialLibrary) -> HitableList {
             if (center - Point3::new(4.0, 0.2, 0.0)).length() <= 0.9 {
                 continue;
             }
+            let name = format!("sphere_{}_{}", a + 5, b + 5);
             if choose_mat < 0.8 {
-                matlib.lib.push(Box::new(Lambertian {
-                    albedo: Rc::new(ConstTexture(Vec3::new(
-                        rng.rand() * rng.rand(),
-                        rng.rand() * rng.rand(),
-                        rng.rand() * rng.rand(),
-                    ))),
-                }));
-                hitables.list.push(Rc::new(Sphere::new(
-                    center,
-                    // center + Vec3::new(0.0, 0.5 * rng.rand(), 0.0),
-                    // 0.0,
-                    // 1.0,
-                    0.2,
-                    matlib.lib.len() - 1,
-                )));
+                let albedo = Vec3::new(
+                    rng.rand() * rng.rand(),
+                    rng.rand() * rng.rand(),
+                    rng.rand() * rng.rand(),
+                );
+                desc.materials.insert(
+                    name.clone(),
+                    MaterialDesc::Lambertian {
+                        albedo: ColorOrTexture::Color(albedo),
+                    },
+                );
+                sphere(&mut desc, center, 0.2, &name);
             } else if choose_mat < 0.95 {
-                matlib.lib.push(Box::new(Metal {
-                    albedo: Vec3::new(
-                        0.5 * (1.0 + rng.rand()),
-                        0.5 * (1.0 + rng.rand()),
-                        0.5 * (1.0 + rng.rand()),
-                    ),
-                    fuzz: 0.5 * rng.rand(),
-                }));
-                hitables.list.push(Rc::new(Sphere::new(
-                    center,
-                    0.2,
-                    matlib.lib.len() - 1,
-                )));
+                let albedo = Vec3::new(
+                    0.5 * (1.0 + rng.rand()),
+                    0.5 * (1.0 + rng.rand()),
+                    0.5 * (1.0 + rng.rand()),
+                );
+                let fuzz = 0.5 * rng.rand();
+                desc.materials
+                    .insert(name.clone(), MaterialDesc::Metal { albedo, fuzz });
+                sphere(&mut desc, center, 0.2, &name);
             } else {
-                matlib.lib.push(Box::new(Dielectric { ref_idx: 1.5 }));
-                hitables.list.push(Rc::new(Sphere::new(
-                    center,
-                    0.2,
-                    matlib.lib.len() - 1,
-                )));
+                sphere(&mut desc, center, 0.2, "glass");
             }
         }
     }
 
-    matlib.lib.push(Box::new(Dielectric { ref_idx: 1.5 }));
-    hitables.list.push(Rc::new(Sphere::new(
-        Point3::new(0.0, 1.0, 0.0),
-        1.0,
-        matlib.lib.len() - 1,
-    )));
+    sphere(&mut desc, Point3::new(0.0, 1.0, 0.0), 1.0, "glass");
 
-    // matlib.lib.push(Box::new(Lambertian {
-    //     albedo: Rc::new(ConstTexture(Vec3::new(0.4, 0.2, 0.1))),
-    // }));
-    matlib.lib.push(Box::new(DiffuseLight {
-        emit: Rc::new(ConstTexture(Vec3::new(4.0, 4.0, 4.0))),
-    }));
-    hitables.list.push(Rc::new(Sphere::new(
-        Point3::new(4.0, 1.0, 0.0),
-        1.0,
-        matlib.lib.len() - 1,
-    )));
+    desc.materials.insert(
+        "light".to_string(),
+        MaterialDesc::DiffuseLight {
+            emit: ColorOrTexture::Color(Vec3::new(4.0, 4.0, 4.0)),
+        },
+    );
+    sphere(&mut desc, Point3::new(4.0, 1.0, 0.0), 1.0, "light");
+
+    desc.materials.insert(
+        "mirror".to_string(),
+        MaterialDesc::Metal {
+            albedo: Vec3::new(0.7, 0.6, 0.5),
+            fuzz: 0.0,
+        },
+    );
+    sphere(&mut desc, Point3::new(-4.0, 1.0, 0.0), 1.0, "mirror");
 
-    matlib.lib.push(Box::new(Metal {
-        albedo: Vec3::new(0.7, 0.6, 0.5),
-        fuzz: 0.0,
-    }));
-    hitables.list.push(Rc::new(Sphere::new(
-        Point3::new(-4.0, 1.0, 0.0),
-        1.0,
-        matlib.lib.len() - 1,
-    )));
+    desc
+}
+
+/// The Cornell box, 555 units wide, with a square light in the ceiling and
+/// two turned boxes on the floor.
+fn cornell_box() -> SceneDesc {
+    let mut desc = SceneDesc {
+        settings: RenderSettings {
+            width: 400,
+            height: 400,
+            ..RenderSettings::default()
+        },
+        camera: CameraDesc {
+            look_from: Point3::new(278.0, 278.0, -800.0),
+            look_at: Point3::new(278.0, 278.0, 0.0),
+            fov: 40.0,
+            focus_distance: 10.0,
+            ..CameraDesc::default()
+        },
+        ..SceneDesc::default()
+    };
+    let mut diffuse = |name: &str, r, g, b| {
+        desc.materials.insert(
+            name.to_string(),
+            MaterialDesc::Lambertian {
+                albedo: ColorOrTexture::Color(Vec3::new(r, g, b)),
+            },
+        )
+    };
+    diffuse("red", 0.65, 0.05, 0.05);
+    diffuse("white", 0.73, 0.73, 0.73);
+    diffuse("green", 0.12, 0.45, 0.15);
+    desc.materials.insert(
+        "light".to_string(),
+        MaterialDesc::DiffuseLight {
+            emit: ColorOrTexture::Color(Vec3::new(15.0, 15.0, 15.0)),
+        },
+    );
+
+    let rect = |plane, a, b, k, material: &str| ObjectDesc::Rect {
+        plane,
+        a,
+        b,
+        k,
+        material: material.to_string(),
+    };
+    let wall = (0.0, 555.0);
+    desc.objects.extend(vec![
+        rect(Plane::YZ, wall, wall, 555.0, "green"),
+        rect(Plane::YZ, wall, wall, 0.0, "red"),
+        rect(Plane::XZ, (213.0, 343.0), (227.0, 332.0), 554.0, "light"),
+        rect(Plane::XZ, wall, wall, 0.0, "white"),
+        rect(Plane::XZ, wall, wall, 555.0, "white"),
+        rect(Plane::XY, wall, wall, 555.0, "white"),
+    ]);
+
+    let turned_box =
+        |height: f32, rotate: f32, translate: Vec3| ObjectDesc::Transformed {
+            transform: TransformDesc {
+                translate,
+                rotate,
+                ..TransformDesc::default()
+            },
+            object: Box::new(ObjectDesc::Cuboid {
+                min: Point3::new(0.0, 0.0, 0.0),
+                max: Point3::new(165.0, height, 165.0),
+                material: "white".to_string(),
+            }),
+        };
+    desc.objects
+        .push(turned_box(330.0, 15.0, Vec3::new(265.0, 0.0, 295.0)));
+    desc.objects
+        .push(turned_box(165.0, -18.0, Vec3::new(130.0, 0.0, 65.0)));
 
-    hitables
+    desc
 }
 
-struct Progress {
-    current: usize,
-    total: usize,
+/// Places the camera so that the whole scene fits into the vertical fov.
+fn frame(scene: &HitableList, fov: f32) -> (Point3, Point3) {
+    let bb = scene.bounding_box(0.0, 0.0).unwrap();
+    let center = bb.min() + 0.5 * (bb.max() - bb.min());
+    let radius = 0.5 * (bb.max() - bb.min()).length();
+    let distance = radius / (0.5 * fov).to_radians().sin();
+    let look_from = center + distance * Vec3::new(0.3, 0.2, 1.0).unit();
+    (look_from, center)
 }
 
-impl Progress {
-    fn new(total: usize) -> Self {
-        Progress { current: 0, total }
-    }
+const USAGE: &str = "\
+usage: raytracer [render] <scene.toml|scene.pbrt|model.obj|random|cornell>
+                          [-o out.png|.ppm|.pfm|.hdr|.exr] [--threads N]
+                          [--seed N] [--png-16] [--exr-float]
+                          [--exr-compression none|zip|piz]
+       raytracer export <scene.toml|random|cornell> [-o out.toml]";
+
+/// Parsed command line.
+struct Args {
+    command: String,
+    input: String,
+    output: Option<String>,
+    threads: usize,
+    seed: u64,
+    write_options: WriteOptions,
+}
 
-    fn increment(&mut self) {
-        self.current += 1
+fn parse_args<I: Iterator<Item = String>>(mut args: I) -> Result<Args, String> {
+    let mut positional = Vec::new();
+    let mut output = None;
+    let mut threads = thread::available_parallelism().map_or(1, |n| n.get());
+    let mut seed = 0;
+    let mut write_options = WriteOptions::default();
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "-o" | "--output" => match args.next() {
+                Some(path) => output = Some(path),
+                None => return Err(format!("{} needs a file name", arg)),
+            },
+            "-t" | "--threads" => threads = number_arg(&arg, args.next())?,
+            "--seed" => seed = number_arg(&arg, args.next())?,
+            "--png-16" => write_options.png_bits = 16,
+            "--exr-float" => write_options.exr_float = true,
+            "--exr-compression" => {
+                write_options.exr_compression = match args.next().as_deref() {
+                    Some("none") => ExrCompression::None,
+                    Some("zip") => ExrCompression::Zip,
+                    Some("piz") => ExrCompression::Piz,
+                    _ => return Err(format!("{} needs none, zip or piz", arg)),
+                }
+            }
+            "-h" | "--help" => return Err(USAGE.to_string()),
+            _ if arg.starts_with('-') => {
+                return Err(format!("unknown option '{}'", arg))
+            }
+            _ => positional.push(arg),
+        }
     }
+    let (command, input) = match positional.as_slice() {
+        [] => ("render".to_string(), "random".to_string()),
+        [input] => ("render".to_string(), input.clone()),
+        [command, input] if command == "render" || command == "export" => {
+            (command.clone(), input.clone())
+        }
+        _ => return Err(USAGE.to_string()),
+    };
+    Ok(Args {
+        command,
+        input,
+        output,
+        threads,
+        seed,
+        write_options,
+    })
+}
 
-    fn update(&mut self, current: usize) {
-        self.current = current
-    }
+fn number_arg<T: FromStr>(
+    name: &str, arg: Option<String>,
+) -> Result<T, String> {
+    arg.and_then(|a| a.parse().ok())
+        .ok_or_else(|| format!("{} needs a number", name))
+}
 
-    fn fraction(&self) -> f32 {
-        self.current as f32 / self.total as f32
+/// Reads a native scene description. `random` and `cornell` are built in.
+fn load_desc(input: &str, rng: &mut RNG) -> Result<SceneDesc, String> {
+    if input == "random" {
+        Ok(random_scene(rng))
+    } else if input == "cornell" {
+        Ok(cornell_box())
+    } else if input.ends_with(".toml") {
+        SceneDesc::load(Path::new(input)).map_err(|e| e.to_string())
+    } else {
+        Err(format!("{}: only .toml scenes can be exported", input))
     }
 }
 
-fn main() {
-    let width: usize = 400;
-    let height: usize = 200;
-    let samples: usize = 100;
-    let ray_depth: usize = 50;
-
-    let mut rng = RNG::default();
+fn load_scene(input: &str, rng: &mut RNG) -> Result<Scene, String> {
+    if input.ends_with(".pbrt") {
+        return pbrtv3::load_pbrt(Path::new(input), rng)
+            .map_err(|e| e.to_string());
+    }
+    if input == "random" || input == "cornell" || input.ends_with(".toml") {
+        let desc = load_desc(input, rng)?;
+        return desc.build(Path::new(input), rng).map_err(|e| e.to_string());
+    }
 
     let mut matlib = MaterialLibrary::default();
-    let scene = random_scene(&mut rng, &mut matlib);
-    let bvh = BVH::new(scene, 0.0, 0.1, &mut rng);
-    //::std::dbg!(&bvh);
-
-    let look_from = Point3::new(13.0, 2.0, 3.0);
-    let look_at = Point3::new(0.0, 0.0, 0.0);
-    let dist_to_focus = 10.0; //(look_from - look_at).length();
-    let aperture = 0.0;
+    let mut hitables = HitableList::default();
+    obj::load_obj(Path::new(input), &mut hitables, &mut matlib)
+        .map_err(|e| e.to_string())?;
+    if hitables.list.is_empty() {
+        return Err(format!("{}: no faces", input));
+    }
+    let settings = RenderSettings::default();
     let fov = 20.0;
-    let cam = Camera::new(
+    let (look_from, look_at) = frame(&hitables, fov);
+    let camera = Camera::new(
         look_from,
         look_at,
         &Vec3::new(0.0, 1.0, 0.0),
         fov,
-        (width as f32) / (height as f32),
-        aperture,
-        dist_to_focus,
+        (settings.width as f32) / (settings.height as f32),
+        0.0,
+        1.0,
         0.0,
         1.0,
     );
+    Ok(Scene::new(
+        settings, camera, hitables, matlib, 0.0, 1.0, rng,
+    ))
+}
 
-    let _ = io::stderr().write_all(b"Setup complete\n");
+fn run(args: Args) -> Result<(), String> {
+    let mut rng = RNG::default();
 
-    let mut progress = Progress::new(width * height);
+    if args.command == "export" {
+        let toml = load_desc(&args.input, &mut rng)?.to_toml();
+        return match &args.output {
+            Some(path) => {
+                fs::write(path, toml).map_err(|e| format!("{}: {}", path, e))
+            }
+            None => {
+                print!("{}", toml);
+                Ok(())
+            }
+        };
+    }
 
-    let f = File::create("pic.ppm").unwrap();
-    let mut w = BufWriter::new(f);
+    let output = args.output.unwrap_or_else(|| "pic.ppm".to_string());
+    let output = Path::new(&output);
+    if ImageFormat::from_path(output).is_none() {
+        return Err(ImageError::UnknownFormat(output.to_path_buf()).to_string());
+    }
+    let scene = load_scene(&args.input, &mut rng)?;
+    let _ = io::stderr().write_all(b"Setup complete\n");
+    let film = render::render(&scene, args.threads, args.seed)
+        .map_err(|e| format!("render failed: {}", e))?;
+    film.image(1.0)
+        .write(output, &args.write_options)
+        .map_err(|e| e.to_string())
+}
 
-    write!(&mut w, "P3\n{} {}\n255\n", width, height).unwrap();
-    for y in (0..height).rev() {
-        for x in 0..width {
-            let mut color = Vec3::new(0.0, 0.0, 0.0);
-            for _ in 0..samples {
-                let u = (x as f32 + rng.rand()) / (width as f32);
-                let v = (y as f32 + rng.rand()) / (height as f32);
-                let r = cam.get_ray(&mut rng, u, v, ray_depth);
-                color += r.trace(&mut rng, &bvh, &matlib, 0);
-            }
-            color /= samples as f32;
-            color = color.sqrt(); // Vec3::new(color.r.sqrt(), color.g.sqrt(), color.b.sqrt());
-            let (r, g, b) = color.as_u8();
-            write!(&mut w, "{} {} {}\n", r, g, b).unwrap();
-            progress.increment();
-        }
-        w.flush().unwrap();
+fn main() {
+    let result = parse_args(env::args().skip(1)).and_then(run);
+    if let Err(e) = result {
+        eprintln!("{}", e);
+        process::exit(1);
     }
 }
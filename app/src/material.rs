@@ -0,0 +1,965 @@
+use ::std::clone::Clone;
+use ::std::default::Default;
+use ::std::option::Option::{self, None, Some};
+use ::std::sync::Arc;
+
+use ::math::{Point3, Vec3};
+
+use crate::microfacet::{reflect_about, refract_about, Frame, TrowbridgeReitz};
+use crate::pbrt::{
+    orthonormal_basis, BsdfSample, HitRecord, Material, Ray, Texture, RNG,
+};
+
+const PI: f32 = ::std::f32::consts::PI;
+
+pub struct Lambertian {
+    pub albedo: Arc<dyn Texture>,
+}
+
+impl Lambertian {
+    pub fn new(albedo: Arc<dyn Texture>) -> Self {
+        Lambertian {
+            albedo: albedo.clone(),
+        }
+    }
+}
+
+/// Flips `n` into the hemisphere the ray `v` arrives from. Opaque
+/// materials shade both sides because mesh winding is arbitrary.
+#[inline(always)]
+pub fn face_forward(n: Vec3, v: Vec3) -> Vec3 {
+    if v.dot(n) > 0.0 {
+        -n
+    } else {
+        n
+    }
+}
+
+impl Material for Lambertian {
+    fn sample(
+        &self, rng: &mut RNG, ray: &Ray, rec: &HitRecord,
+    ) -> Option<BsdfSample> {
+        // The normal plus a uniform unit vector is cosine distributed.
+        let normal = face_forward(rec.normal, ray.direction);
+        let mut direction = normal + rng.random_unit_vector();
+        if direction.dot(direction) < 1e-8 {
+            direction = normal;
+        }
+        let direction = direction.unit();
+        Some(BsdfSample {
+            direction,
+            f: self.eval(ray, rec, direction),
+            pdf: self.pdf(ray, rec, direction),
+            delta: false,
+        })
+    }
+
+    fn eval(&self, ray: &Ray, rec: &HitRecord, direction: Vec3) -> Vec3 {
+        self.pdf(ray, rec, direction) * self.albedo.filtered(rec)
+    }
+
+    fn pdf(&self, ray: &Ray, rec: &HitRecord, direction: Vec3) -> f32 {
+        let normal = face_forward(rec.normal, ray.direction);
+        normal.dot(direction).max(0.0) / PI
+    }
+}
+
+pub struct Metal {
+    pub albedo: Vec3,
+    pub fuzz: f32,
+}
+
+#[inline(always)]
+pub fn reflect(v: Vec3, n: Vec3) -> Vec3 {
+    v - 2.0 * v.dot(n) * n
+}
+
+impl Metal {
+    pub fn new(albedo: Vec3, fuzz: f32) -> Self {
+        Metal {
+            albedo,
+            fuzz: if fuzz < 1.0 { fuzz } else { 1.0 },
+        }
+    }
+}
+
+impl Metal {
+    /// One minus the cosine of the half angle of the reflection cone.
+    fn cone_height(&self) -> f32 {
+        let sin2 = self.fuzz * self.fuzz;
+        sin2 / (1.0 + (1.0 - sin2).sqrt())
+    }
+}
+
+/// Reflects into a cone around the mirror direction, uniformly in solid
+/// angle. The sine of the cone's half angle is `fuzz`, so it covers the same
+/// directions as perturbing the mirror direction by `fuzz` times a point in
+/// the unit ball. Without fuzz the reflection is a delta lobe.
+impl Material for Metal {
+    fn sample(
+        &self, rng: &mut RNG, ray: &Ray, rec: &HitRecord,
+    ) -> Option<BsdfSample> {
+        let normal = face_forward(rec.normal, ray.direction);
+        let reflected = reflect(ray.direction, normal);
+        if self.is_delta() {
+            return Some(BsdfSample {
+                direction: reflected,
+                f: self.albedo,
+                pdf: 1.0,
+                delta: true,
+            });
+        }
+        let (u, v) = orthonormal_basis(reflected);
+        let cos_theta = 1.0 - rng.rand() * self.cone_height();
+        let sin_theta = (1.0 - cos_theta * cos_theta).max(0.0).sqrt();
+        let phi = 2.0 * PI * rng.rand();
+        let direction = sin_theta * phi.cos() * u
+            + sin_theta * phi.sin() * v
+            + cos_theta * reflected;
+        // Directions below the surface are absorbed.
+        if direction.dot(normal) <= 0.0 {
+            return None;
+        }
+        let pdf = 1.0 / (2.0 * PI * self.cone_height());
+        Some(BsdfSample {
+            direction,
+            f: pdf * self.albedo,
+            pdf,
+            delta: false,
+        })
+    }
+
+    fn eval(&self, ray: &Ray, rec: &HitRecord, direction: Vec3) -> Vec3 {
+        self.pdf(ray, rec, direction) * self.albedo
+    }
+
+    fn pdf(&self, ray: &Ray, rec: &HitRecord, direction: Vec3) -> f32 {
+        if self.is_delta() {
+            return 0.0;
+        }
+        let normal = face_forward(rec.normal, ray.direction);
+        let reflected = reflect(ray.direction, normal);
+        if direction.dot(normal) <= 0.0
+            || 1.0 - direction.dot(reflected) > self.cone_height()
+        {
+            return 0.0;
+        }
+        1.0 / (2.0 * PI * self.cone_height())
+    }
+
+    fn is_delta(&self) -> bool {
+        self.fuzz <= 0.0
+    }
+}
+
+/// The complex index of refraction `eta + ik` of a few metals at the red,
+/// green and blue primaries.
+pub fn conductor_preset(name: &str) -> Option<(Vec3, Vec3)> {
+    Some(match name {
+        "gold" => (
+            Vec3::new(0.1431, 0.3749, 1.4424),
+            Vec3::new(3.9831, 2.3857, 1.6032),
+        ),
+        "copper" => (
+            Vec3::new(0.2004, 0.9240, 1.1022),
+            Vec3::new(3.9129, 2.4528, 2.1421),
+        ),
+        "aluminium" => (
+            Vec3::new(1.6574, 0.8803, 0.5212),
+            Vec3::new(9.2238, 6.2695, 4.8370),
+        ),
+        "silver" => (
+            Vec3::new(0.1552, 0.1167, 0.1383),
+            Vec3::new(4.8283, 3.1222, 2.1469),
+        ),
+        "chrome" => (
+            Vec3::new(4.3696, 2.9167, 1.6547),
+            Vec3::new(5.2064, 4.2313, 3.7549),
+        ),
+        _ => return None,
+    })
+}
+
+/// The names `conductor_preset` knows.
+pub const CONDUCTOR_PRESETS: [&str; 5] =
+    ["gold", "copper", "aluminium", "silver", "chrome"];
+
+/// The exact Fresnel reflectance of unpolarized light arriving at `cos_i`
+/// to the normal of a conductor with the complex index `eta + ik`, per
+/// channel.
+pub fn fresnel_conductor(cos_i: f32, eta: Vec3, k: Vec3) -> Vec3 {
+    let channel = |eta: f32, k: f32| {
+        let cos2 = cos_i.clamp(0.0, 1.0).powi(2);
+        let sin2 = 1.0 - cos2;
+        let t0 = eta * eta - k * k - sin2;
+        let a2b2 = (t0 * t0 + 4.0 * eta * eta * k * k).sqrt();
+        let a = (0.5 * (a2b2 + t0)).max(0.0).sqrt();
+        let t1 = a2b2 + cos2;
+        let t2 = 2.0 * cos2.sqrt() * a;
+        let rs = (t1 - t2) / (t1 + t2);
+        let t3 = cos2 * a2b2 + sin2 * sin2;
+        let t4 = t2 * sin2;
+        let rp = rs * (t3 - t4) / (t3 + t4);
+        0.5 * (rp + rs)
+    };
+    Vec3::new(
+        channel(eta.x(), k.x()),
+        channel(eta.y(), k.y()),
+        channel(eta.z(), k.z()),
+    )
+}
+
+/// A rough metal with microfacets after the Trowbridge-Reitz (GGX)
+/// distribution, coloured by the Fresnel reflectance of its complex index
+/// of refraction. Roughness along `dpdu` and across it may differ, which
+/// gives the stretched highlights of brushed metal. Below a small roughness
+/// it is a perfect mirror.
+pub struct Conductor {
+    pub eta: Vec3,
+    pub k: Vec3,
+    pub distribution: TrowbridgeReitz,
+}
+
+impl Conductor {
+    pub fn new(eta: Vec3, k: Vec3, distribution: TrowbridgeReitz) -> Self {
+        Conductor {
+            eta,
+            k,
+            distribution,
+        }
+    }
+}
+
+impl Material for Conductor {
+    fn sample(
+        &self, rng: &mut RNG, ray: &Ray, rec: &HitRecord,
+    ) -> Option<BsdfSample> {
+        let frame = Frame::facing(rec, ray.direction);
+        let wo = frame.local(-ray.direction.unit());
+        if wo.z() <= 0.0 {
+            return None;
+        }
+        if self.is_delta() {
+            return Some(BsdfSample {
+                direction: frame.world(Vec3::new(-wo.x(), -wo.y(), wo.z())),
+                f: fresnel_conductor(wo.z(), self.eta, self.k),
+                pdf: 1.0,
+                delta: true,
+            });
+        }
+        let wm = self.distribution.sample_wm(wo, rng.rand(), rng.rand());
+        let wi = reflect_about(wo, wm);
+        if wi.z() <= 0.0 {
+            return None;
+        }
+        Some(BsdfSample {
+            direction: frame.world(wi),
+            f: self.local_f(wo, wi, wm),
+            pdf: self.distribution.visible_d(wo, wm) / (4.0 * wo.dot(wm)),
+            delta: false,
+        })
+    }
+
+    fn eval(&self, ray: &Ray, rec: &HitRecord, direction: Vec3) -> Vec3 {
+        let zero = Vec3::new(0.0, 0.0, 0.0);
+        if self.is_delta() {
+            return zero;
+        }
+        let frame = Frame::facing(rec, ray.direction);
+        let wo = frame.local(-ray.direction.unit());
+        let wi = frame.local(direction);
+        match half_vector(wo, wi) {
+            Some(wm) => self.local_f(wo, wi, wm),
+            None => zero,
+        }
+    }
+
+    fn pdf(&self, ray: &Ray, rec: &HitRecord, direction: Vec3) -> f32 {
+        if self.is_delta() {
+            return 0.0;
+        }
+        let frame = Frame::facing(rec, ray.direction);
+        let wo = frame.local(-ray.direction.unit());
+        let wi = frame.local(direction);
+        match half_vector(wo, wi) {
+            Some(wm) => {
+                self.distribution.visible_d(wo, wm) / (4.0 * wo.dot(wm))
+            }
+            None => 0.0,
+        }
+    }
+
+    fn is_delta(&self) -> bool {
+        self.distribution.effectively_smooth()
+    }
+}
+
+impl Conductor {
+    /// The BSDF times the cosine to `wi` in the local frame.
+    fn local_f(&self, wo: Vec3, wi: Vec3, wm: Vec3) -> Vec3 {
+        let f = fresnel_conductor(wo.dot(wm).abs(), self.eta, self.k);
+        let d = self.distribution.d(wm);
+        let g = self.distribution.g(wo, wi);
+        (d * g / (4.0 * wo.z())) * f
+    }
+}
+
+/// The normal of the microfacet that reflects `wo` into `wi`, if both are
+/// above the surface.
+fn half_vector(wo: Vec3, wi: Vec3) -> Option<Vec3> {
+    if wo.z() <= 0.0 || wi.z() <= 0.0 {
+        return None;
+    }
+    let wm = wo + wi;
+    if wm.dot(wm) == 0.0 {
+        return None;
+    }
+    Some(wm.unit())
+}
+
+/// Glass, water and other transparent materials. The interface is smooth
+/// or rough after the Trowbridge-Reitz distribution, as in Walter et al.,
+/// "Microfacet Models for Refraction through Rough Surfaces", 2007. Light
+/// inside is absorbed after the Beer-Lambert law. The length of the path
+/// inside is the distance to the hit of a ray that arrives from inside, so
+/// this assumes that rays start inside only at the same surface, which
+/// holds for closed objects that do not overlap.
+pub struct Dielectric {
+    pub ref_idx: f32,
+    pub distribution: TrowbridgeReitz,
+    /// The absorption coefficient inside, per unit length.
+    pub absorption: Vec3,
+}
+
+impl Dielectric {
+    /// Smooth, clear glass.
+    pub fn new(ref_idx: f32) -> Self {
+        Dielectric {
+            ref_idx,
+            distribution: TrowbridgeReitz::new(0.0, 0.0),
+            absorption: Vec3::default(),
+        }
+    }
+
+    /// The frame facing the ray, the ratio of the indices of refraction
+    /// across the surface from that side, and the fraction of light left
+    /// after the path inside that ends at the hit.
+    fn setup(&self, ray: &Ray, rec: &HitRecord) -> (Frame, f32, Vec3) {
+        let frame = Frame::facing(rec, ray.direction);
+        if ray.direction.dot(rec.normal) <= 0.0 {
+            return (frame, self.ref_idx, Vec3::new(1.0, 1.0, 1.0));
+        }
+        let a = self.absorption * rec.t;
+        let transmittance =
+            Vec3::new((-a.x()).exp(), (-a.y()).exp(), (-a.z()).exp());
+        (frame, 1.0 / self.ref_idx, transmittance)
+    }
+
+    /// The normal of the microfacet that scatters `wo` into `wi` and the
+    /// Fresnel reflectance at it, if it faces both.
+    fn microfacet(&self, wo: Vec3, wi: Vec3, eta: f32) -> Option<(Vec3, f32)> {
+        if wo.z() <= 0.0 || wi.z() == 0.0 {
+            return None;
+        }
+        let reflect = wi.z() > 0.0;
+        let wm = if reflect { wo + wi } else { wo + eta * wi };
+        if wm.dot(wm) == 0.0 {
+            return None;
+        }
+        let wm = wm.unit();
+        let wm = if wm.z() < 0.0 { -wm } else { wm };
+        if wm.dot(wo) <= 0.0 || (wm.dot(wi) > 0.0) != reflect {
+            return None;
+        }
+        Some((wm, fresnel_dielectric(wo.dot(wm), eta)))
+    }
+
+    /// The BSDF times the cosine to `wi` and the density of sampling
+    /// `wi`, both in the local frame where `wo` is above the surface and
+    /// `eta` the ratio of the indices below and above it.
+    pub fn local_f_pdf(&self, wo: Vec3, wi: Vec3, eta: f32) -> (f32, f32) {
+        let (wm, r) = match self.microfacet(wo, wi, eta) {
+            Some(facet) => facet,
+            None => return (0.0, 0.0),
+        };
+        let tr = &self.distribution;
+        let visible = tr.visible_d(wo, wm);
+        if wi.z() > 0.0 {
+            let dg = tr.d(wm) * tr.g(wo, wi);
+            (dg * r / (4.0 * wo.z()), visible / (4.0 * wo.dot(wm)) * r)
+        } else {
+            let (f, dwm_dwi) = tr.transmission(wo, wi, wm, eta);
+            ((1.0 - r) * f, visible * dwm_dwi * (1.0 - r))
+        }
+    }
+
+    /// Samples `wi` for `wo` in the same frame as `local_f_pdf`, with its
+    /// `f` and density. For smooth surfaces both leave out the delta
+    /// function and the density is the probability of the chosen lobe.
+    pub fn sample_local(
+        &self, rng: &mut RNG, wo: Vec3, eta: f32,
+    ) -> Option<(Vec3, f32, f32)> {
+        if self.is_delta() {
+            // Reflection and transmission are chosen by their Fresnel
+            // weights, which then cancel out.
+            let r = fresnel_dielectric(wo.z(), eta);
+            if rng.rand() < r {
+                return Some((Vec3::new(-wo.x(), -wo.y(), wo.z()), r, r));
+            }
+            let wi = refract_about(wo, Vec3::new(0.0, 0.0, 1.0), eta)?;
+            return Some((wi, (1.0 - r) / (eta * eta), 1.0 - r));
+        }
+        let wm = self.distribution.sample_wm(wo, rng.rand(), rng.rand());
+        let r = fresnel_dielectric(wo.dot(wm), eta);
+        // Reflections below the surface and transmissions above it are
+        // lost; `local_f_pdf` would take them for the other lobe.
+        let wi = if rng.rand() < r {
+            Some(reflect_about(wo, wm)).filter(|wi| wi.z() > 0.0)?
+        } else {
+            refract_about(wo, wm, eta).filter(|wi| wi.z() < 0.0)?
+        };
+        let (f, pdf) = self.local_f_pdf(wo, wi, eta);
+        if pdf <= 0.0 {
+            return None;
+        }
+        Some((wi, f, pdf))
+    }
+}
+
+impl Material for Dielectric {
+    fn sample(
+        &self, rng: &mut RNG, ray: &Ray, rec: &HitRecord,
+    ) -> Option<BsdfSample> {
+        let (frame, eta, transmittance) = self.setup(ray, rec);
+        let wo = frame.local(-ray.direction);
+        let (wi, f, pdf) = self.sample_local(rng, wo, eta)?;
+        Some(BsdfSample {
+            direction: frame.world(wi).unit(),
+            f: f * transmittance,
+            pdf,
+            delta: self.is_delta(),
+        })
+    }
+
+    fn eval(&self, ray: &Ray, rec: &HitRecord, direction: Vec3) -> Vec3 {
+        if self.is_delta() {
+            return Vec3::default();
+        }
+        let (frame, eta, transmittance) = self.setup(ray, rec);
+        let wo = frame.local(-ray.direction);
+        let wi = frame.local(direction);
+        self.local_f_pdf(wo, wi, eta).0 * transmittance
+    }
+
+    fn pdf(&self, ray: &Ray, rec: &HitRecord, direction: Vec3) -> f32 {
+        if self.is_delta() {
+            return 0.0;
+        }
+        let (frame, eta, _) = self.setup(ray, rec);
+        let wo = frame.local(-ray.direction);
+        let wi = frame.local(direction);
+        self.local_f_pdf(wo, wi, eta).1
+    }
+
+    fn is_delta(&self) -> bool {
+        self.distribution.effectively_smooth()
+    }
+}
+
+/// A thin sheet of glass such as a window pane. Light that passes through
+/// leaves parallel to where it came from, so it is not bent. The
+/// reflectance sums all the reflections between the two sides.
+pub struct ThinDielectric {
+    pub ref_idx: f32,
+}
+
+impl Material for ThinDielectric {
+    fn sample(
+        &self, rng: &mut RNG, ray: &Ray, rec: &HitRecord,
+    ) -> Option<BsdfSample> {
+        let normal = face_forward(rec.normal, ray.direction);
+        let cos_i = -ray.direction.dot(normal) / ray.direction.length();
+        let mut r = fresnel_dielectric(cos_i, self.ref_idx);
+        if r < 1.0 {
+            let t = 1.0 - r;
+            r += t * t * r / (1.0 - r * r);
+        }
+        let (direction, prob) = if rng.rand() < r {
+            (reflect(ray.direction, normal).unit(), r)
+        } else {
+            (ray.direction.unit(), 1.0 - r)
+        };
+        Some(BsdfSample {
+            direction,
+            f: Vec3::new(prob, prob, prob),
+            pdf: prob,
+            delta: true,
+        })
+    }
+
+    fn is_delta(&self) -> bool {
+        true
+    }
+}
+
+/// The exact Fresnel reflectance of unpolarized light arriving at
+/// `cos_i` to the normal of a boundary into a dielectric with the relative
+/// index `eta`. Negative cosines arrive from inside.
+pub fn fresnel_dielectric(cos_i: f32, eta: f32) -> f32 {
+    let (cos_i, eta) = if cos_i < 0.0 {
+        (-cos_i, 1.0 / eta)
+    } else {
+        (cos_i, eta)
+    };
+    let cos_i = cos_i.min(1.0);
+    let sin_t = (1.0 - cos_i * cos_i).max(0.0).sqrt() / eta;
+    if sin_t >= 1.0 {
+        return 1.0;
+    }
+    let cos_t = (1.0 - sin_t * sin_t).max(0.0).sqrt();
+    let parallel = (eta * cos_i - cos_t) / (eta * cos_i + cos_t);
+    let perpendicular = (cos_i - eta * cos_t) / (cos_i + eta * cos_t);
+    0.5 * (parallel * parallel + perpendicular * perpendicular)
+}
+
+pub struct DiffuseLight {
+    pub emit: Arc<dyn Texture>,
+}
+
+impl Material for DiffuseLight {
+    fn sample(
+        &self, _rng: &mut RNG, _ray: &Ray, _rec: &HitRecord,
+    ) -> Option<BsdfSample> {
+        None
+    }
+
+    fn emitted(&self, u: f32, v: f32, p: Point3) -> Vec3 {
+        self.emit.value(u, v, p)
+    }
+
+    fn is_emissive(&self) -> bool {
+        true
+    }
+}
+
+/// Scatters equally in all directions. The phase function of most media.
+pub struct Isotropic {
+    pub albedo: Arc<dyn Texture>,
+}
+
+const INV_4PI: f32 = 1.0 / (4.0 * PI);
+
+impl Material for Isotropic {
+    fn sample(
+        &self, rng: &mut RNG, ray: &Ray, rec: &HitRecord,
+    ) -> Option<BsdfSample> {
+        let direction = rng.random_unit_vector();
+        Some(BsdfSample {
+            direction,
+            f: self.eval(ray, rec, direction),
+            pdf: INV_4PI,
+            delta: false,
+        })
+    }
+
+    fn eval(&self, _ray: &Ray, rec: &HitRecord, _direction: Vec3) -> Vec3 {
+        INV_4PI * self.albedo.filtered(rec)
+    }
+
+    fn pdf(&self, _ray: &Ray, _rec: &HitRecord, _direction: Vec3) -> f32 {
+        INV_4PI
+    }
+}
+
+/// The Henyey-Greenstein phase function. `g` in (-1, 1) is the mean cosine
+/// between the incoming and the scattered direction: positive values
+/// scatter forward, negative ones back and zero is isotropic.
+pub struct HenyeyGreenstein {
+    pub albedo: Arc<dyn Texture>,
+    pub g: f32,
+}
+
+impl HenyeyGreenstein {
+    /// The density of scattering by an angle with the given cosine.
+    fn phase(&self, cos_theta: f32) -> f32 {
+        let g = self.g;
+        let denom = 1.0 + g * g - 2.0 * g * cos_theta;
+        INV_4PI * (1.0 - g * g) / (denom * denom.sqrt())
+    }
+}
+
+impl Material for HenyeyGreenstein {
+    fn sample(
+        &self, rng: &mut RNG, ray: &Ray, rec: &HitRecord,
+    ) -> Option<BsdfSample> {
+        let g = self.g;
+        let cos_theta = if g.abs() < 1e-3 {
+            1.0 - 2.0 * rng.rand()
+        } else {
+            let s = (1.0 - g * g) / (1.0 - g + 2.0 * g * rng.rand());
+            ((1.0 + g * g - s * s) / (2.0 * g)).clamp(-1.0, 1.0)
+        };
+        let sin_theta = (1.0 - cos_theta * cos_theta).max(0.0).sqrt();
+        let phi = 2.0 * PI * rng.rand();
+        let w = ray.direction.unit();
+        let (u, v) = orthonormal_basis(w);
+        let direction = sin_theta * phi.cos() * u
+            + sin_theta * phi.sin() * v
+            + cos_theta * w;
+        let pdf = self.phase(cos_theta);
+        Some(BsdfSample {
+            direction,
+            f: pdf * self.albedo.filtered(rec),
+            pdf,
+            delta: false,
+        })
+    }
+
+    fn eval(&self, ray: &Ray, rec: &HitRecord, direction: Vec3) -> Vec3 {
+        self.pdf(ray, rec, direction) * self.albedo.filtered(rec)
+    }
+
+    fn pdf(&self, ray: &Ray, _rec: &HitRecord, direction: Vec3) -> f32 {
+        self.phase(ray.direction.unit().dot(direction))
+    }
+}
+
+#[cfg(test)]
+pub mod tests {
+    use super::*;
+    use ::std::assert;
+    use ::std::default::Default;
+
+    use crate::texture::ConstTexture;
+
+    fn hit() -> (Ray, HitRecord) {
+        let ray = Ray::new(
+            Point3::new(-1.0, 1.0, 0.0),
+            Vec3::new(1.0, -1.0, 0.0),
+            0.0,
+            1,
+        );
+        let rec = HitRecord {
+            t: 2.0f32.sqrt(),
+            normal: Vec3::new(0.0, 1.0, 0.0),
+            ..HitRecord::default()
+        };
+        (ray, rec)
+    }
+
+    /// Checks that samples agree with `eval` and `pdf` and that the
+    /// average path weight is the reflectance.
+    fn check_sampling(mat: &dyn Material, reflectance: f32) {
+        let (ray, rec) = hit();
+        let mut rng = RNG::default();
+        let n = 10000;
+        let mut sum = 0.0;
+        for _ in 0..n {
+            if let Some(s) = mat.sample(&mut rng, &ray, &rec) {
+                assert!(!s.delta);
+                let pdf = mat.pdf(&ray, &rec, s.direction);
+                assert!((pdf - s.pdf).abs() < 1e-3 * s.pdf);
+                let f = mat.eval(&ray, &rec, s.direction);
+                assert!((f.y() - s.f.y()).abs() < 1e-3 * s.f.y());
+                sum += s.f.y() / s.pdf;
+            }
+        }
+        assert!((sum / n as f32 - reflectance).abs() < 0.02);
+    }
+
+    #[test]
+    fn test_lambertian() {
+        let albedo = Vec3::new(0.5, 0.5, 0.5);
+        let mat = Lambertian::new(Arc::new(ConstTexture(albedo)));
+        check_sampling(&mat, 0.5);
+        let (ray, rec) = hit();
+        let below = Vec3::new(0.0, -1.0, 0.0);
+        assert!(mat.pdf(&ray, &rec, below) == 0.0);
+    }
+
+    #[test]
+    fn test_phase_functions() {
+        let albedo = Arc::new(ConstTexture(Vec3::new(0.6, 0.6, 0.6)));
+        check_sampling(
+            &Isotropic {
+                albedo: albedo.clone(),
+            },
+            0.6,
+        );
+        let (ray, rec) = hit();
+        let mut rng = RNG::default();
+        for &g in &[0.7, -0.4] {
+            let mat = HenyeyGreenstein {
+                albedo: albedo.clone(),
+                g,
+            };
+            check_sampling(&mat, 0.6);
+            // g is the mean cosine of the scattering angle.
+            let n = 10000;
+            let mut sum = 0.0;
+            for _ in 0..n {
+                let s = mat.sample(&mut rng, &ray, &rec).unwrap();
+                sum += s.direction.dot(ray.direction);
+            }
+            assert!((sum / n as f32 - g).abs() < 0.02);
+        }
+    }
+
+    #[test]
+    fn test_metal() {
+        let fuzzy = Metal::new(Vec3::new(0.8, 0.8, 0.8), 0.3);
+        assert!(!fuzzy.is_delta());
+        // The cone around the mirror direction stays above the surface.
+        check_sampling(&fuzzy, 0.8);
+
+        let mirror = Metal::new(Vec3::new(0.8, 0.8, 0.8), 0.0);
+        assert!(mirror.is_delta());
+        let (ray, rec) = hit();
+        let s = mirror.sample(&mut RNG::default(), &ray, &rec).unwrap();
+        assert!(s.delta);
+        assert!((s.direction.x() - 0.5f32.sqrt()).abs() < 1e-6);
+        assert!(mirror.pdf(&ray, &rec, s.direction) == 0.0);
+    }
+
+    #[test]
+    fn test_fresnel_conductor() {
+        // Normal incidence has the closed form |eta + ik - 1|^2 / |eta +
+        // ik + 1|^2, and grazing light is always reflected.
+        let (eta, k) = conductor_preset("gold").unwrap();
+        let r = fresnel_conductor(1.0, eta, k);
+        for i in 0..3 {
+            let (a, b, k) = (eta[i] - 1.0, eta[i] + 1.0, k[i]);
+            let expected = (a * a + k * k) / (b * b + k * k);
+            assert!((r[i] - expected).abs() < 1e-5);
+        }
+        // Gold reflects red more than blue.
+        assert!(r.x() > 0.9 && r.z() < 0.5);
+        let grazing = fresnel_conductor(0.0, eta, k);
+        assert!((grazing.x() - 1.0).abs() < 1e-5);
+        // Without absorption it is the dielectric's.
+        let eta = Vec3::new(1.5, 1.5, 1.5);
+        let zero = Vec3::new(0.0, 0.0, 0.0);
+        for &c in &[0.1, 0.5, 0.9] {
+            let r = fresnel_conductor(c, eta, zero);
+            assert!((r.y() - fresnel_dielectric(c, 1.5)).abs() < 1e-5);
+        }
+        for name in &CONDUCTOR_PRESETS {
+            assert!(conductor_preset(name).is_some());
+        }
+    }
+
+    #[test]
+    fn test_conductor() {
+        // A conductor that reflects everything loses only the light that
+        // is shadowed by other microfacets. Importance sampling agrees
+        // with integrating `eval` over uniform directions.
+        let (eta, k) = (Vec3::new(1.0, 1.0, 1.0), Vec3::new(1e4, 1e4, 1e4));
+        let (ray, rec) = hit();
+        let mut rng = RNG::default();
+        for &(ax, ay, min) in &[(0.05, 0.05, 0.99), (0.3, 0.1, 0.88)] {
+            let mat = Conductor::new(eta, k, TrowbridgeReitz::new(ax, ay));
+            assert!(!mat.is_delta());
+            let n = 20000;
+            let (mut sampled, mut uniform) = (0.0, 0.0);
+            for _ in 0..n {
+                if let Some(s) = mat.sample(&mut rng, &ray, &rec) {
+                    assert!(!s.delta && s.direction.y() > 0.0);
+                    let pdf = mat.pdf(&ray, &rec, s.direction);
+                    assert!((pdf - s.pdf).abs() < 1e-3 * s.pdf);
+                    let f = mat.eval(&ray, &rec, s.direction);
+                    assert!((f.y() - s.f.y()).abs() < 1e-3 * s.f.y());
+                    sampled += s.f.y() / s.pdf;
+                }
+                let mut w = rng.random_unit_vector();
+                if w.y() < 0.0 {
+                    w = -w;
+                }
+                uniform += mat.eval(&ray, &rec, w).y() * 2.0 * PI;
+            }
+            let albedo = sampled / n as f32;
+            assert!(albedo > min && albedo <= 1.0);
+            if ax > 0.1 {
+                assert!((uniform / n as f32 - albedo).abs() < 0.03);
+            }
+        }
+
+        let (eta, k) = conductor_preset("silver").unwrap();
+        let mirror = Conductor::new(eta, k, TrowbridgeReitz::new(0.0, 0.0));
+        assert!(mirror.is_delta());
+        let s = mirror.sample(&mut rng, &ray, &rec).unwrap();
+        assert!(s.delta);
+        assert!((s.direction.x() - 0.5f32.sqrt()).abs() < 1e-5);
+        assert!((s.direction.y() - 0.5f32.sqrt()).abs() < 1e-5);
+        assert!(mirror.pdf(&ray, &rec, s.direction) == 0.0);
+    }
+
+    #[test]
+    fn test_conductor_anisotropy() {
+        // The highlight stretches along the rougher direction of `dpdu`.
+        let (ray, mut rec) = hit();
+        let mut rng = RNG::default();
+        let (eta, k) = conductor_preset("aluminium").unwrap();
+        let mat = Conductor::new(eta, k, TrowbridgeReitz::new(0.05, 0.5));
+        let spread = |rng: &mut RNG, rec: &HitRecord| {
+            let mut sum = 0.0;
+            for _ in 0..1000 {
+                if let Some(s) = mat.sample(rng, &ray, rec) {
+                    sum += s.direction.z().abs();
+                }
+            }
+            sum
+        };
+        rec.dpdu = Vec3::new(0.0, 0.0, 1.0);
+        let along = spread(&mut rng, &rec);
+        rec.dpdu = Vec3::new(1.0, 0.0, 0.0);
+        let across = spread(&mut rng, &rec);
+        assert!(across > 3.0 * along);
+        // Smooth along one axis only is still a finite, rough lobe.
+        let mat = Conductor::new(eta, k, TrowbridgeReitz::new(0.0, 0.5));
+        for _ in 0..1000 {
+            if let Some(s) = mat.sample(&mut rng, &ray, &rec) {
+                assert!(!s.delta && s.pdf.is_finite());
+                assert!(s.f.x().is_finite() && s.f.z().is_finite());
+            }
+        }
+    }
+
+    #[test]
+    fn test_smooth_dielectric() {
+        let glass = Dielectric::new(1.5);
+        assert!(glass.is_delta());
+        let (ray, rec) = hit();
+        let mut rng = RNG::default();
+        let r = fresnel_dielectric(0.5f32.sqrt(), 1.5);
+        let n = 20000;
+        let mut reflected = 0;
+        for _ in 0..n {
+            let s = glass.sample(&mut rng, &ray, &rec).unwrap();
+            assert!(s.delta);
+            if s.direction.y() > 0.0 {
+                reflected += 1;
+                assert!((s.f.y() / s.pdf - 1.0).abs() < 1e-5);
+            } else {
+                // Snell's law, and radiance scaled by the squared ratio
+                // of the indices.
+                let sin_t = s.direction.x();
+                assert!((1.5 * sin_t - 0.5f32.sqrt()).abs() < 1e-5);
+                assert!((s.f.y() / s.pdf - 1.0 / 2.25).abs() < 1e-5);
+            }
+        }
+        assert!((reflected as f32 / n as f32 - r).abs() < 0.01);
+
+        // From inside beyond the critical angle everything is reflected.
+        let inside = HitRecord {
+            normal: Vec3::new(0.0, -1.0, 0.0),
+            ..rec
+        };
+        for _ in 0..100 {
+            let s = glass.sample(&mut rng, &ray, &inside).unwrap();
+            assert!(s.direction.y() > 0.0);
+        }
+    }
+
+    #[test]
+    fn test_rough_dielectric() {
+        // Importance sampling agrees with integrating `eval` over all
+        // directions, from both sides. Without the scaling of radiance
+        // the interface neither creates nor absorbs much light.
+        let (ray, rec) = hit();
+        let inside = HitRecord {
+            normal: Vec3::new(0.0, -1.0, 0.0),
+            ..rec.clone()
+        };
+        let mut rng = RNG::default();
+        for &(ax, ay) in &[(0.2, 0.2), (0.4, 0.1)] {
+            let glass = Dielectric {
+                distribution: TrowbridgeReitz::new(ax, ay),
+                ..Dielectric::new(1.5)
+            };
+            assert!(!glass.is_delta());
+            for (rec, eta) in &[(&rec, 1.5f32), (&inside, 1.0 / 1.5)] {
+                let n = 100000;
+                let (mut sampled, mut unscaled, mut uniform) = (0.0, 0.0, 0.0);
+                for _ in 0..n {
+                    if let Some(s) = glass.sample(&mut rng, &ray, rec) {
+                        assert!(!s.delta);
+                        // Loose for the tiny densities at grazing angles.
+                        let pdf = glass.pdf(&ray, rec, s.direction);
+                        assert!((pdf - s.pdf).abs() < 1e-3 * s.pdf.max(1.0));
+                        let f = glass.eval(&ray, rec, s.direction);
+                        assert!(
+                            (f.y() - s.f.y()).abs() < 1e-3 * s.pdf.max(1.0)
+                        );
+                        sampled += s.f.y() / s.pdf;
+                        let transmitted = s.direction.dot(rec.normal)
+                            * ray.direction.dot(rec.normal)
+                            > 0.0;
+                        let scale = if transmitted { eta * eta } else { 1.0 };
+                        unscaled += scale * s.f.y() / s.pdf;
+                    }
+                    let w = rng.random_unit_vector();
+                    uniform += glass.eval(&ray, rec, w).y() * 4.0 * PI;
+                }
+                let (sampled, uniform) =
+                    (sampled / n as f32, uniform / n as f32);
+                assert!((sampled - uniform).abs() < 0.06 * sampled);
+                let unscaled = unscaled / n as f32;
+                assert!(unscaled > 0.8 && unscaled <= 1.01);
+            }
+        }
+    }
+
+    #[test]
+    fn test_dielectric_absorption() {
+        // Light that leaves the glass was absorbed along the way from
+        // where it entered.
+        let glass = Dielectric {
+            absorption: Vec3::new(0.5, 0.0, 2.0),
+            ..Dielectric::new(1.5)
+        };
+        let (ray, rec) = hit();
+        let mut rng = RNG::default();
+        let s = glass.sample(&mut rng, &ray, &rec).unwrap();
+        assert!((s.f.x() - s.f.y()).abs() < 1e-6);
+        let inside = HitRecord {
+            normal: Vec3::new(0.0, -1.0, 0.0),
+            t: 2.0,
+            ..rec
+        };
+        let s = glass.sample(&mut rng, &ray, &inside).unwrap();
+        assert!((s.f.x() - (-1.0f32).exp() * s.f.y()).abs() < 1e-6);
+        assert!((s.f.z() - (-4.0f32).exp() * s.f.y()).abs() < 1e-6);
+    }
+
+    #[test]
+    fn test_thin_dielectric() {
+        // A pane passes light straight through and reflects from both of
+        // its sides.
+        let pane = ThinDielectric { ref_idx: 1.5 };
+        let ray = Ray::new(
+            Point3::new(0.0, 1.0, 0.0),
+            Vec3::new(0.0, -1.0, 0.0),
+            0.0,
+            1,
+        );
+        let rec = HitRecord {
+            normal: Vec3::new(0.0, 1.0, 0.0),
+            ..HitRecord::default()
+        };
+        let mut rng = RNG::default();
+        let n = 20000;
+        let mut reflected = 0;
+        for _ in 0..n {
+            let s = pane.sample(&mut rng, &ray, &rec).unwrap();
+            assert!(s.delta && (s.f.y() / s.pdf - 1.0).abs() < 1e-6);
+            if s.direction.y() > 0.0 {
+                reflected += 1;
+            } else {
+                assert!((s.direction - ray.direction).length() < 1e-6);
+            }
+        }
+        // 0.04 from the first side and 0.96² × 0.04 / (1 - 0.04²) more.
+        let expected = 0.04 + 0.96 * 0.96 * 0.04 / (1.0 - 0.04 * 0.04);
+        assert!((reflected as f32 / n as f32 - expected).abs() < 0.006);
+    }
+}
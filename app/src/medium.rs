@@ -0,0 +1,755 @@
+//! Participating media: volumes of smoke, fog or murky liquid inside a
+//! boundary shape, and clouds and fire defined by voxel grids.
+//!
+//! A medium is a `Hitable` like any surface. Its `hit` picks a random
+//! distance at which the ray collides inside the medium and reports a hit
+//! there, with a phase function such as `Isotropic` as the material. Rays
+//! that get through without a collision miss it. The random numbers are
+//! drawn from a hash of the ray, so testing the same ray twice, as the BVH
+//! and light sampling do, gives the same answer. Shadow rays pass through
+//! media and are attenuated by their `transmittance`.
+//!
+//! Grids are read from a simple dense format. All numbers are little
+//! endian:
+//!
+//! ```text
+//! "GRID"                      magic
+//! u32                         version, 1
+//! u32                         number of grids
+//! per grid:
+//!   u32, bytes                length and UTF-8 name, e.g. "density"
+//!   u32 x 3                   cells along x, y and z
+//!   f32 x 3, f32 x 3          minimum and maximum corner of the bounds
+//!   f32 x (x * y * z)         cell values, x fastest, then y, then z
+//! ```
+
+use ::std::clone::Clone;
+use ::std::cmp;
+use ::std::convert::TryInto;
+use ::std::default::Default;
+use ::std::fmt::{self, Display, Formatter};
+use ::std::fs;
+use ::std::io;
+use ::std::iter::Iterator;
+use ::std::option::Option::{self, None, Some};
+use ::std::path::{Path, PathBuf};
+use ::std::result::Result::{self, Err, Ok};
+use ::std::string::{String, ToString};
+use ::std::sync::Arc;
+use ::std::vec::Vec;
+use ::std::{assert_eq, format, write};
+
+use ::math::{Point3, Vec3};
+
+use crate::pbrt::{BsdfSample, HitRecord, Hitable, Material, Ray, AABB, RNG};
+
+/// The part of `r` between `t_min` and `t_max` that is inside `boundary`.
+/// The boundary must be convex, so that the ray leaves it at the next
+/// intersection after it enters.
+fn segment(
+    boundary: &dyn Hitable, r: &Ray, t_min: f32, t_max: f32,
+) -> Option<(f32, f32)> {
+    let mut enter = HitRecord::default();
+    if !boundary.hit(r, f32::MIN, f32::MAX, &mut enter) {
+        return None;
+    }
+    let mut exit = HitRecord::default();
+    if !boundary.hit(r, enter.t + 0.0001, f32::MAX, &mut exit) {
+        return None;
+    }
+    let (t0, t1) = (enter.t.max(t_min), exit.t.min(t_max));
+    if t0 >= t1 {
+        return None;
+    }
+    Some((t0, t1))
+}
+
+/// Random numbers for the segment of `r` that starts at `t`.
+fn segment_rng(r: &Ray, t: f32) -> RNG {
+    let (o, d) = (r.origin, r.direction);
+    RNG::hashed(&[
+        o.x().to_bits(),
+        o.y().to_bits(),
+        o.z().to_bits(),
+        d.x().to_bits(),
+        d.y().to_bits(),
+        d.z().to_bits(),
+        r.time.to_bits(),
+        t.to_bits(),
+    ])
+}
+
+/// Fills in a scattering event at `t`. Phase functions do not use the
+/// normal; it points back along the ray.
+fn scatter(r: &Ray, t: f32, material: usize, rec: &mut HitRecord) {
+    rec.t = t;
+    rec.p = r.point_at_param(t);
+    rec.normal = -r.direction.unit();
+    rec.material = material;
+    rec.u = 0.0;
+    rec.v = 0.0;
+}
+
+/// A medium of the same density everywhere inside a convex `boundary`.
+/// `phase` is the material index of its phase function.
+#[derive(Debug)]
+pub struct ConstantMedium {
+    boundary: Arc<dyn Hitable>,
+    density: f32,
+    phase: usize,
+}
+
+impl ConstantMedium {
+    pub fn new(boundary: Arc<dyn Hitable>, density: f32, phase: usize) -> Self {
+        ConstantMedium {
+            boundary,
+            density,
+            phase,
+        }
+    }
+}
+
+impl Hitable for ConstantMedium {
+    fn hit(
+        &self, r: &Ray, t_min: f32, t_max: f32, rec: &mut HitRecord,
+    ) -> bool {
+        if r.shadow {
+            return false;
+        }
+        let (t0, t1) = match segment(&*self.boundary, r, t_min, t_max) {
+            Some(segment) => segment,
+            None => return false,
+        };
+        let mut rng = segment_rng(r, t0);
+        // An exponentially distributed free-flight distance. 1 - u is never
+        // zero.
+        let distance = -(1.0 - rng.rand()).ln() / self.density;
+        let t = t0 + distance / r.direction.length();
+        if t >= t1 {
+            return false;
+        }
+        scatter(r, t, self.phase, rec);
+        true
+    }
+
+    fn bounding_box(&self, t0: f32, t1: f32) -> Option<AABB> {
+        self.boundary.bounding_box(t0, t1)
+    }
+
+    fn material(&self) -> Option<usize> {
+        Some(self.phase)
+    }
+
+    fn transmittance(&self, r: &Ray, t_min: f32, t_max: f32) -> f32 {
+        match segment(&*self.boundary, r, t_min, t_max) {
+            Some((t0, t1)) => {
+                (-self.density * (t1 - t0) * r.direction.length()).exp()
+            }
+            None => 1.0,
+        }
+    }
+}
+
+#[derive(Debug)]
+pub enum GridError {
+    Io { path: PathBuf, error: io::Error },
+    Format { path: PathBuf, message: String },
+}
+
+impl Display for GridError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        match self {
+            GridError::Io { path, error } => {
+                write!(f, "{}: {}", path.display(), error)
+            }
+            GridError::Format { path, message } => {
+                write!(f, "{}: {}", path.display(), message)
+            }
+        }
+    }
+}
+
+/// Values on a regular grid of cells that fills `bounds`. The values sit
+/// at the cell centers and are interpolated trilinearly in between. Outside
+/// the bounds the grid is zero.
+#[derive(Debug, Clone)]
+pub struct Grid {
+    size: [usize; 3],
+    bounds: AABB,
+    values: Vec<f32>,
+}
+
+impl Grid {
+    /// `values` are ordered with x varying fastest, then y, then z.
+    pub fn new(size: [usize; 3], bounds: AABB, values: Vec<f32>) -> Self {
+        assert_eq!(values.len(), size[0] * size[1] * size[2]);
+        Grid {
+            size,
+            bounds,
+            values,
+        }
+    }
+
+    pub fn bounds(&self) -> AABB {
+        self.bounds
+    }
+
+    pub fn max(&self) -> f32 {
+        self.values.iter().cloned().fold(0.0, f32::max)
+    }
+
+    fn at(&self, x: usize, y: usize, z: usize) -> f32 {
+        self.values[x + self.size[0] * (y + self.size[1] * z)]
+    }
+
+    pub fn lookup(&self, p: Point3) -> f32 {
+        let (min, max) = (self.bounds.min(), self.bounds.max());
+        let mut lower = [0; 3];
+        let mut upper = [0; 3];
+        let mut frac = [0.0; 3];
+        for (axis, &n) in self.size.iter().enumerate() {
+            let x = (p[axis] - min[axis]) / (max[axis] - min[axis]);
+            if !(0.0..=1.0).contains(&x) {
+                return 0.0;
+            }
+            // Continuous cell coordinate, with cell centers at integers.
+            let c = (x * n as f32 - 0.5).max(0.0);
+            let i = cmp::min(c as usize, n - 1);
+            lower[axis] = i;
+            upper[axis] = cmp::min(i + 1, n - 1);
+            frac[axis] = (c - i as f32).min(1.0);
+        }
+        let lerp = |a: f32, b: f32, t: f32| a + t * (b - a);
+        let plane = |z: usize| {
+            let row = |y: usize| {
+                lerp(self.at(lower[0], y, z), self.at(upper[0], y, z), frac[0])
+            };
+            lerp(row(lower[1]), row(upper[1]), frac[1])
+        };
+        lerp(plane(lower[2]), plane(upper[2]), frac[2])
+    }
+}
+
+/// Reads the little endian fields of a grid file.
+struct GridReader<'a> {
+    bytes: &'a [u8],
+    path: &'a Path,
+}
+
+impl<'a> GridReader<'a> {
+    fn error<T>(&self, message: &str) -> Result<T, GridError> {
+        Err(GridError::Format {
+            path: self.path.to_path_buf(),
+            message: message.to_string(),
+        })
+    }
+
+    fn take(&mut self, n: usize) -> Result<&'a [u8], GridError> {
+        if self.bytes.len() < n {
+            return self.error("unexpected end of file");
+        }
+        let (head, tail) = self.bytes.split_at(n);
+        self.bytes = tail;
+        Ok(head)
+    }
+
+    fn u32(&mut self) -> Result<u32, GridError> {
+        Ok(u32::from_le_bytes(self.take(4)?.try_into().unwrap()))
+    }
+
+    fn f32(&mut self) -> Result<f32, GridError> {
+        Ok(f32::from_le_bytes(self.take(4)?.try_into().unwrap()))
+    }
+
+    fn point3(&mut self) -> Result<Point3, GridError> {
+        Ok(Point3::new(self.f32()?, self.f32()?, self.f32()?))
+    }
+}
+
+/// Loads the named grids of a grid file.
+pub fn load_grids(path: &Path) -> Result<Vec<(String, Grid)>, GridError> {
+    let bytes = fs::read(path).map_err(|error| GridError::Io {
+        path: path.to_path_buf(),
+        error,
+    })?;
+    parse_grids(&bytes, path)
+}
+
+/// Parses the contents of a grid file. `path` is only used for messages.
+pub fn parse_grids(
+    bytes: &[u8], path: &Path,
+) -> Result<Vec<(String, Grid)>, GridError> {
+    let mut r = GridReader { bytes, path };
+    if r.take(4).ok() != Some(&b"GRID"[..]) {
+        return r.error("not a grid file");
+    }
+    if r.u32()? != 1 {
+        return r.error("unsupported version");
+    }
+    let count = r.u32()?;
+    let mut grids = Vec::new();
+    for _ in 0..count {
+        let len = r.u32()? as usize;
+        let name = match ::std::str::from_utf8(r.take(len)?) {
+            Ok(name) => name.to_string(),
+            Err(_) => return r.error("grid name is not UTF-8"),
+        };
+        let size = [r.u32()? as usize, r.u32()? as usize, r.u32()? as usize];
+        let (min, max) = (r.point3()?, r.point3()?);
+        if size.contains(&0) {
+            return r.error(&format!("grid '{}' is empty", name));
+        }
+        if !(min.x() < max.x() && min.y() < max.y() && min.z() < max.z()) {
+            return r.error(&format!("grid '{}' has empty bounds", name));
+        }
+        let n = size[0]
+            .checked_mul(size[1])
+            .and_then(|n| n.checked_mul(size[2]))
+            .filter(|&n| n <= r.bytes.len() / 4);
+        let n = match n {
+            Some(n) => n,
+            None => return r.error("unexpected end of file"),
+        };
+        let values = (0..n).map(|_| r.f32()).collect::<Result<_, _>>()?;
+        grids.push((name, Grid::new(size, AABB::new(min, max), values)));
+    }
+    Ok(grids)
+}
+
+/// The part of `r` between `t_min` and `t_max` inside `bounds`.
+fn box_segment(
+    bounds: &AABB, r: &Ray, t_min: f32, t_max: f32,
+) -> Option<(f32, f32)> {
+    let (mut t0, mut t1) = (t_min, t_max);
+    for axis in 0..3 {
+        let inv_dir = 1.0 / r.direction[axis];
+        let mut near = (bounds.min()[axis] - r.origin[axis]) * inv_dir;
+        let mut far = (bounds.max()[axis] - r.origin[axis]) * inv_dir;
+        if inv_dir < 0.0 {
+            ::std::mem::swap(&mut near, &mut far);
+        }
+        t0 = t0.max(near);
+        t1 = t1.min(far);
+    }
+    if t0 >= t1 {
+        return None;
+    }
+    Some((t0, t1))
+}
+
+/// A medium whose density comes from a grid, like a cloud or the smoke of
+/// an explosion. Collisions are found by delta tracking and shadow rays are
+/// attenuated by ratio tracking, both against the largest density in the
+/// grid.
+///
+/// A collision absorbs with probability `absorption` and scatters with the
+/// material `phase` otherwise. Absorption ends the path with the material
+/// `absorber`, and the temperature from the `temperature` grid, in kelvin,
+/// as `u`. With a `Blackbody` absorber the medium glows where it is hot.
+/// Since that light is found by chance, the medium has no material and is
+/// not sampled as a light.
+#[derive(Debug)]
+pub struct GridMedium {
+    density: Arc<Grid>,
+    temperature: Option<Arc<Grid>>,
+    scale: f32,
+    majorant: f32,
+    absorption: f32,
+    phase: usize,
+    absorber: usize,
+}
+
+impl GridMedium {
+    /// The density is `scale` times the values of the `density` grid.
+    pub fn new(
+        density: Arc<Grid>, temperature: Option<Arc<Grid>>, scale: f32,
+        absorption: f32, phase: usize, absorber: usize,
+    ) -> Self {
+        GridMedium {
+            majorant: scale * density.max(),
+            density,
+            temperature,
+            scale,
+            absorption,
+            phase,
+            absorber,
+        }
+    }
+
+    fn density(&self, p: Point3) -> f32 {
+        self.scale * self.density.lookup(p)
+    }
+
+    /// The next tentative collision after `t` in a medium as dense as the
+    /// majorant everywhere.
+    fn step(&self, rng: &mut RNG, r: &Ray, t: f32) -> f32 {
+        let distance = -(1.0 - rng.rand()).ln() / self.majorant;
+        t + distance / r.direction.length()
+    }
+}
+
+impl Hitable for GridMedium {
+    fn hit(
+        &self, r: &Ray, t_min: f32, t_max: f32, rec: &mut HitRecord,
+    ) -> bool {
+        if r.shadow || self.majorant <= 0.0 {
+            return false;
+        }
+        let bounds = self.density.bounds();
+        let (t0, t1) = match box_segment(&bounds, r, t_min, t_max) {
+            Some(segment) => segment,
+            None => return false,
+        };
+        let mut rng = segment_rng(r, t0);
+        let mut t = t0;
+        loop {
+            t = self.step(&mut rng, r, t);
+            if t >= t1 {
+                return false;
+            }
+            let p = r.point_at_param(t);
+            // Tentative collisions are real in proportion to the density;
+            // the rest are null collisions that the ray passes.
+            if rng.rand() * self.majorant >= self.density(p) {
+                continue;
+            }
+            if rng.rand() < self.absorption {
+                scatter(r, t, self.absorber, rec);
+                rec.u = self.temperature.as_ref().map_or(0.0, |g| g.lookup(p));
+            } else {
+                scatter(r, t, self.phase, rec);
+            }
+            return true;
+        }
+    }
+
+    fn bounding_box(&self, _: f32, _: f32) -> Option<AABB> {
+        Some(self.density.bounds())
+    }
+
+    fn transmittance(&self, r: &Ray, t_min: f32, t_max: f32) -> f32 {
+        if self.majorant <= 0.0 {
+            return 1.0;
+        }
+        let bounds = self.density.bounds();
+        let (t0, t1) = match box_segment(&bounds, r, t_min, t_max) {
+            Some(segment) => segment,
+            None => return 1.0,
+        };
+        let mut rng = segment_rng(r, t0);
+        let mut transmittance = 1.0;
+        let mut t = t0;
+        loop {
+            t = self.step(&mut rng, r, t);
+            if t >= t1 {
+                return transmittance;
+            }
+            let p = r.point_at_param(t);
+            transmittance *= 1.0 - self.density(p) / self.majorant;
+        }
+    }
+}
+
+/// The linear sRGB color of a black body at `kelvin`, with a luminance of
+/// one.
+pub fn blackbody(kelvin: f32) -> Vec3 {
+    // Planck's law, with wavelengths in meters.
+    let planck = |lambda: f64| {
+        let c1 = 1.191_042_972e-16;
+        let c2 = 1.438_776_877e-2;
+        c1 / (lambda.powi(5) * ((c2 / (lambda * kelvin as f64)).exp() - 1.0))
+    };
+    // The CIE 1931 observer, as fitted by Wyman, Sloan and Shirley.
+    let lobe = |lambda: f64, mu: f64, below: f64, above: f64| {
+        let sigma = if lambda < mu { below } else { above };
+        (-0.5 * ((lambda - mu) / sigma).powi(2)).exp()
+    };
+    let (mut x, mut y, mut z) = (0.0, 0.0, 0.0);
+    for nm in (360..=830).step_by(5) {
+        let l = nm as f64;
+        let b = planck(l * 1e-9);
+        x += b
+            * (1.056 * lobe(l, 599.8, 37.9, 31.0)
+                + 0.362 * lobe(l, 442.0, 16.0, 26.7)
+                - 0.065 * lobe(l, 501.1, 20.4, 26.2));
+        y += b
+            * (0.821 * lobe(l, 568.8, 46.9, 40.5)
+                + 0.286 * lobe(l, 530.9, 16.3, 31.1));
+        z += b
+            * (1.217 * lobe(l, 437.0, 11.8, 36.0)
+                + 0.681 * lobe(l, 459.0, 26.0, 13.8));
+    }
+    if !(y > 0.0 && y.is_finite()) {
+        return Vec3::default();
+    }
+    let (x, z) = ((x / y) as f32, (z / y) as f32);
+    Vec3::new(
+        (3.2406 * x - 1.5372 - 0.4986 * z).max(0.0),
+        (-0.9689 * x + 1.8758 + 0.0415 * z).max(0.0),
+        (0.0557 * x - 0.2040 + 1.0570 * z).max(0.0),
+    )
+}
+
+// Temperatures between table entries are interpolated.
+const BLACKBODY_STEP: f32 = 100.0;
+const BLACKBODY_ENTRIES: usize = 201;
+
+/// The glow of a hot medium: absorbs and emits black body radiation at the
+/// temperature in kelvin that `GridMedium` passes as `u`. The emitted light
+/// is `scale` times the color of the temperature times (T / 1000 K)^4,
+/// which grows with the total power of a black body.
+pub struct Blackbody {
+    scale: f32,
+    colors: Vec<Vec3>,
+}
+
+impl Blackbody {
+    pub fn new(scale: f32) -> Self {
+        let colors = (0..BLACKBODY_ENTRIES)
+            .map(|i| blackbody(i as f32 * BLACKBODY_STEP))
+            .collect();
+        Blackbody { scale, colors }
+    }
+}
+
+impl Material for Blackbody {
+    fn sample(
+        &self, _rng: &mut RNG, _ray: &Ray, _rec: &HitRecord,
+    ) -> Option<BsdfSample> {
+        None
+    }
+
+    fn emitted(&self, kelvin: f32, _v: f32, _p: Point3) -> Vec3 {
+        if kelvin.is_nan() || kelvin <= 0.0 || self.scale == 0.0 {
+            return Vec3::default();
+        }
+        let x = (kelvin / BLACKBODY_STEP).min((BLACKBODY_ENTRIES - 1) as f32);
+        let i = cmp::min(x as usize, BLACKBODY_ENTRIES - 2);
+        let t = x - i as f32;
+        let color = (1.0 - t) * self.colors[i] + t * self.colors[i + 1];
+        let power = (kelvin / 1000.0).powi(4);
+        (self.scale * power) * color
+    }
+
+    fn is_emissive(&self) -> bool {
+        self.scale != 0.0
+    }
+
+    fn is_light(&self) -> bool {
+        false
+    }
+}
+
+#[cfg(test)]
+pub mod tests {
+    use super::*;
+    use ::std::{assert, panic};
+
+    use ::std::vec;
+
+    use crate::shapes::Cuboid;
+
+    /// A grid's name, size, bounds as min then max, and values.
+    pub type GridData<'a> = (&'a str, [u32; 3], [f32; 6], &'a [f32]);
+
+    /// The bytes of a grid file with the given grids.
+    pub fn grid_file(grids: &[GridData]) -> Vec<u8> {
+        let mut bytes = b"GRID".to_vec();
+        bytes.extend_from_slice(&1u32.to_le_bytes());
+        bytes.extend_from_slice(&(grids.len() as u32).to_le_bytes());
+        for (name, size, bounds, values) in grids {
+            bytes.extend_from_slice(&(name.len() as u32).to_le_bytes());
+            bytes.extend_from_slice(name.as_bytes());
+            for n in size {
+                bytes.extend_from_slice(&n.to_le_bytes());
+            }
+            for x in bounds.iter().chain(values.iter()) {
+                bytes.extend_from_slice(&x.to_le_bytes());
+            }
+        }
+        bytes
+    }
+
+    /// A slab 2 thick along z, with a density of 0.2 on the far side and
+    /// 1 on the near side, and a linear ramp over the middle half.
+    fn ramp() -> Grid {
+        let bytes = grid_file(&[(
+            "density",
+            [1, 1, 2],
+            [-100.0, -100.0, -1.0, 100.0, 100.0, 1.0],
+            &[0.2, 1.0],
+        )]);
+        let mut grids = parse_grids(&bytes, Path::new("ramp.grid")).unwrap();
+        grids.pop().unwrap().1
+    }
+
+    #[test]
+    fn test_constant_medium() {
+        // A slab 2 thick along z.
+        let boundary = Cuboid::new(
+            Point3::new(-100.0, -100.0, -1.0),
+            Point3::new(100.0, 100.0, 1.0),
+            0,
+        );
+        let medium = ConstantMedium::new(Arc::new(boundary), 0.5, 3);
+        let mut rec = HitRecord::default();
+        let n = 10000;
+        let mut scattered = 0;
+        for i in 0..n {
+            let x = i as f32 / n as f32;
+            let r = Ray::new(
+                Point3::new(x, 0.0, 5.0),
+                Vec3::new(0.0, 0.0, -1.0),
+                0.0,
+                0,
+            );
+            if medium.hit(&r, 0.001, f32::MAX, &mut rec) {
+                scattered += 1;
+                assert!(rec.p.z().abs() <= 1.0);
+                assert_eq!(rec.material, 3);
+                // The same ray scatters at the same place.
+                let t = rec.t;
+                assert!(medium.hit(&r, 0.001, f32::MAX, &mut rec));
+                assert_eq!(rec.t, t);
+            }
+        }
+        // Transmittance through the slab is exp(-density * 2).
+        let expected = 1.0 - (-1.0f32).exp();
+        assert!((scattered as f32 / n as f32 - expected).abs() < 0.02);
+
+        // A ray that starts inside scatters ahead of its origin.
+        let inside = Ray::new(
+            Point3::new(0.0, 0.0, 0.5),
+            Vec3::new(0.0, 1.0, 0.0),
+            0.0,
+            0,
+        );
+        assert!(medium.hit(&inside, 0.001, f32::MAX, &mut rec));
+        assert!(rec.t > 0.001 && rec.t < 99.0);
+    }
+
+    #[test]
+    fn test_grid_lookup() {
+        let grid = ramp();
+        assert_eq!(grid.max(), 1.0);
+        let at = |z: f32| grid.lookup(Point3::new(3.0, -7.0, z));
+        assert_eq!(at(-0.9), 0.2);
+        assert_eq!(at(0.75), 1.0);
+        assert!((at(0.0) - 0.6).abs() < 1e-6);
+        assert!((at(-0.25) - 0.4).abs() < 1e-6);
+        assert_eq!(at(1.5), 0.0);
+    }
+
+    #[test]
+    fn test_parse_grids() {
+        let values: Vec<f32> = (0..24).map(|i| i as f32).collect();
+        let bytes = grid_file(&[
+            (
+                "density",
+                [2, 3, 4],
+                [0.0, 0.0, 0.0, 2.0, 3.0, 4.0],
+                &values,
+            ),
+            (
+                "temperature",
+                [1, 1, 1],
+                [0.0, 0.0, 0.0, 1.0, 1.0, 1.0],
+                &[5.0],
+            ),
+        ]);
+        let path = Path::new("test.grid");
+        let grids = parse_grids(&bytes, path).unwrap();
+        assert_eq!(grids.len(), 2);
+        assert_eq!(grids[1].0, "temperature");
+        // The cell (1, 2, 3) is centered at (1.5, 2.5, 3.5).
+        let grid = &grids[0].1;
+        assert_eq!(grid.lookup(Point3::new(1.5, 2.5, 3.5)), 23.0);
+        assert_eq!(grid.lookup(Point3::new(0.5, 0.5, 1.5)), 6.0);
+
+        let expect_error =
+            |bytes: &[u8], expected: &str| match parse_grids(bytes, path) {
+                Err(GridError::Format { message, .. }) => {
+                    assert!(message.contains(expected), "{}", message)
+                }
+                _ => panic!("expected '{}'", expected),
+            };
+        expect_error(b"VDB!", "not a grid file");
+        expect_error(&bytes[..bytes.len() - 1], "unexpected end of file");
+        let mut newer = grid_file(&[("d", [1, 0, 1], [0.0; 6], &[])]);
+        expect_error(&newer, "is empty");
+        newer[4] = 2;
+        expect_error(&newer, "unsupported version");
+    }
+
+    #[test]
+    fn test_grid_medium() {
+        let medium = GridMedium::new(Arc::new(ramp()), None, 1.0, 0.0, 1, 2);
+        let mut rec = HitRecord::default();
+        let n = 10000;
+        let (mut scattered, mut transmittance) = (0, 0.0);
+        for i in 0..n {
+            let origin = Point3::new(i as f32 / n as f32, 0.0, 5.0);
+            let down = Vec3::new(0.0, 0.0, -1.0);
+            let r = Ray::new(origin, down, 0.0, 0);
+            if medium.hit(&r, 0.001, f32::MAX, &mut rec) {
+                scattered += 1;
+                assert_eq!(rec.material, 1);
+            }
+            let shadow = Ray::shadow(origin, down, 0.0);
+            assert!(!medium.hit(&shadow, 0.001, f32::MAX, &mut rec));
+            transmittance += medium.transmittance(&shadow, 0.001, f32::MAX);
+        }
+        // The optical depth of the slab is 0.2 * 0.5 + 0.6 + 1 * 0.5.
+        let expected = (-1.2f32).exp();
+        assert!((transmittance / n as f32 - expected).abs() < 0.01);
+        let hit_fraction = scattered as f32 / n as f32;
+        assert!((hit_fraction - (1.0 - expected)).abs() < 0.02);
+
+        // Everything that collides is absorbed and reports the temperature.
+        let hot = Grid::new(
+            [1, 1, 1],
+            AABB::new(
+                Point3::new(-100.0, -100.0, -1.0),
+                Point3::new(100.0, 100.0, 1.0),
+            ),
+            vec![1500.0],
+        );
+        let burning = GridMedium::new(
+            Arc::new(ramp()),
+            Some(Arc::new(hot)),
+            10.0,
+            1.0,
+            1,
+            2,
+        );
+        let r = Ray::new(
+            Point3::new(0.0, 0.0, 5.0),
+            Vec3::new(0.0, 0.0, -1.0),
+            0.0,
+            0,
+        );
+        assert!(burning.hit(&r, 0.001, f32::MAX, &mut rec));
+        assert_eq!((rec.material, rec.u), (2, 1500.0));
+    }
+
+    #[test]
+    fn test_blackbody() {
+        let luminance =
+            |c: Vec3| 0.2126 * c.x() + 0.7152 * c.y() + 0.0722 * c.z();
+        // Near the white point of sRGB.
+        let white = blackbody(6500.0);
+        assert!((luminance(white) - 1.0).abs() < 0.01);
+        assert!((white.x() - white.z()).abs() < 0.1);
+        let red = blackbody(1500.0);
+        assert!(red.x() > red.y() && red.y() > red.z());
+        assert_eq!(blackbody(0.0).y(), 0.0);
+
+        let glow = Blackbody::new(2.0);
+        assert!(glow.is_emissive() && !glow.is_light());
+        let p = Point3::default();
+        let emitted = glow.emitted(6500.0, 0.0, p);
+        let expected = 2.0 * 6.5f32.powi(4);
+        assert!((luminance(emitted) - expected).abs() < 0.01 * expected);
+        assert_eq!(glow.emitted(0.0, 0.0, p).x(), 0.0);
+    }
+}
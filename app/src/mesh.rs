@@ -0,0 +1,292 @@
+use ::std::clone::Clone;
+use ::std::default::Default;
+use ::std::iter::Iterator;
+use ::std::option::Option::{self, None, Some};
+use ::std::sync::Arc;
+use ::std::vec::Vec;
+use ::std::{assert, assert_eq};
+
+use ::math::{Point3, Vec3};
+
+use crate::pbrt::{
+    HitRecord, Hitable, HitableList, Ray, ShapeSample, AABB, RNG,
+};
+
+// Triangles with zero extent along an axis would get a flat bounding box.
+const BBOX_PADDING: f32 = 0.0001;
+
+#[derive(Debug)]
+pub struct TriangleMesh {
+    pub positions: Vec<Point3>,
+    pub indices: Vec<usize>,
+    pub normals: Vec<Vec3>,
+    pub uvs: Vec<(f32, f32)>,
+    pub material: usize,
+}
+
+impl TriangleMesh {
+    pub fn new(
+        positions: Vec<Point3>, indices: Vec<usize>, normals: Vec<Vec3>,
+        uvs: Vec<(f32, f32)>, material: usize,
+    ) -> Self {
+        assert_eq!(indices.len() % 3, 0, "indices must form triangles");
+        assert!(
+            indices.iter().all(|&i| i < positions.len()),
+            "vertex index out of range"
+        );
+        assert!(normals.is_empty() || normals.len() == positions.len());
+        assert!(uvs.is_empty() || uvs.len() == positions.len());
+        TriangleMesh {
+            positions,
+            indices,
+            normals,
+            uvs,
+            material,
+        }
+    }
+
+    pub fn num_triangles(&self) -> usize {
+        self.indices.len() / 3
+    }
+
+    /// One Hitable per face, all sharing this mesh's vertex buffers. Pushing
+    /// them into the scene list lets the BVH split inside the mesh.
+    pub fn triangles(mesh: &Arc<TriangleMesh>) -> HitableList {
+        let mut hitables = HitableList::default();
+        for index in 0..mesh.num_triangles() {
+            hitables.list.push(Arc::new(Triangle {
+                mesh: mesh.clone(),
+                index,
+            }));
+        }
+        hitables
+    }
+}
+
+#[derive(Debug)]
+pub struct Triangle {
+    mesh: Arc<TriangleMesh>,
+    index: usize,
+}
+
+impl Triangle {
+    #[inline(always)]
+    fn vertex_indices(&self) -> (usize, usize, usize) {
+        let i = &self.mesh.indices[3 * self.index..3 * self.index + 3];
+        (i[0], i[1], i[2])
+    }
+
+    fn positions(&self) -> (Point3, Point3, Point3) {
+        let (i0, i1, i2) = self.vertex_indices();
+        let p = &self.mesh.positions;
+        (p[i0], p[i1], p[i2])
+    }
+
+    /// Converts the area density of a point at `distance` along the unit
+    /// `direction` into solid angle density.
+    fn solid_angle_pdf(&self, direction: Vec3, distance: f32) -> f32 {
+        let (p0, p1, p2) = self.positions();
+        let n = (p1 - p0).cross(&(p2 - p0));
+        let double_area = n.length();
+        let cosine = n.dot(direction).abs() / double_area;
+        if cosine < 1e-6 {
+            return 0.0;
+        }
+        2.0 * distance * distance / (cosine * double_area)
+    }
+}
+
+impl Hitable for Triangle {
+    fn hit(
+        &self, r: &Ray, t_min: f32, t_max: f32, rec: &mut HitRecord,
+    ) -> bool {
+        // Möller-Trumbore.
+        let (i0, i1, i2) = self.vertex_indices();
+        let p0 = self.mesh.positions[i0];
+        let p1 = self.mesh.positions[i1];
+        let p2 = self.mesh.positions[i2];
+        let e1 = p1 - p0;
+        let e2 = p2 - p0;
+        let pvec = r.direction.cross(&e2);
+        let det = e1.dot(pvec);
+        if det.abs() < 1e-8 {
+            return false;
+        }
+        let inv_det = 1.0 / det;
+        let tvec = r.origin - p0;
+        let b1 = tvec.dot(pvec) * inv_det;
+        if !(0.0..=1.0).contains(&b1) {
+            return false;
+        }
+        let qvec = tvec.cross(&e1);
+        let b2 = r.direction.dot(qvec) * inv_det;
+        if b2 < 0.0 || b1 + b2 > 1.0 {
+            return false;
+        }
+        let t = e2.dot(qvec) * inv_det;
+        if t >= t_max || t <= t_min {
+            return false;
+        }
+        let b0 = 1.0 - b1 - b2;
+
+        let geometric_normal = e1.cross(&e2).unit();
+        let normal = if self.mesh.normals.is_empty() {
+            geometric_normal
+        } else {
+            let n = b0 * self.mesh.normals[i0]
+                + b1 * self.mesh.normals[i1]
+                + b2 * self.mesh.normals[i2];
+            // Keep the shading normal on the geometric side so materials
+            // can still tell inside from outside.
+            if n.dot(geometric_normal) < 0.0 {
+                -n.unit()
+            } else {
+                n.unit()
+            }
+        };
+        let uvs = if self.mesh.uvs.is_empty() {
+            // pbrt's default parameterization.
+            [(0.0, 0.0), (1.0, 0.0), (1.0, 1.0)]
+        } else {
+            [self.mesh.uvs[i0], self.mesh.uvs[i1], self.mesh.uvs[i2]]
+        };
+        let [(u0, v0), (u1, v1), (u2, v2)] = uvs;
+        let (u, v) = (b0 * u0 + b1 * u1 + b2 * u2, b0 * v0 + b1 * v1 + b2 * v2);
+        // Solves e1 = du1 dpdu + dv1 dpdv, e2 = du2 dpdu + dv2 dpdv.
+        let (du1, dv1, du2, dv2) = (u1 - u0, v1 - v0, u2 - u0, v2 - v0);
+        let det = du1 * dv2 - dv1 * du2;
+        let (dpdu, dpdv) = if det.abs() > 1e-12 {
+            ((dv2 * e1 - dv1 * e2) / det, (du1 * e2 - du2 * e1) / det)
+        } else {
+            (Vec3::default(), Vec3::default())
+        };
+
+        rec.t = t;
+        rec.p = r.point_at_param(t);
+        rec.normal = normal;
+        rec.material = self.mesh.material;
+        rec.dpdu = dpdu;
+        rec.dpdv = dpdv;
+        rec.u = u;
+        rec.v = v;
+        true
+    }
+
+    fn bounding_box(&self, _: f32, _: f32) -> Option<AABB> {
+        let (i0, i1, i2) = self.vertex_indices();
+        let p0 = self.mesh.positions[i0];
+        let p1 = self.mesh.positions[i1];
+        let p2 = self.mesh.positions[i2];
+        let pad = Vec3::new(BBOX_PADDING, BBOX_PADDING, BBOX_PADDING);
+        Some(AABB::new(
+            p0.min(p1).min(p2) - pad,
+            p0.max(p1).max(p2) + pad,
+        ))
+    }
+
+    fn material(&self) -> Option<usize> {
+        Some(self.mesh.material)
+    }
+
+    /// Samples the triangle uniformly by area.
+    fn sample(
+        &self, rng: &mut RNG, origin: Point3, _time: f32,
+    ) -> Option<ShapeSample> {
+        let (p0, p1, p2) = self.positions();
+        let su = rng.rand().sqrt();
+        let r = rng.rand();
+        let (b1, b2) = (su * (1.0 - r), su * r);
+        let p = p0 + b1 * (p1 - p0) + b2 * (p2 - p0);
+        let to_p = p - origin;
+        let distance = to_p.length();
+        let pdf = self.solid_angle_pdf(to_p / distance, distance);
+        if pdf <= 0.0 || !pdf.is_finite() {
+            return None;
+        }
+        Some(ShapeSample { p, pdf })
+    }
+
+    fn pdf(&self, origin: Point3, direction: Vec3, time: f32) -> f32 {
+        let r = Ray::new(origin, direction, time, 0);
+        let mut rec = HitRecord::default();
+        if !self.hit(&r, 0.001, f32::MAX, &mut rec) {
+            return 0.0;
+        }
+        self.solid_angle_pdf(r.direction, rec.t)
+    }
+}
+
+#[cfg(test)]
+pub mod tests {
+    use super::*;
+    use ::std::vec;
+
+    fn quad() -> Arc<TriangleMesh> {
+        Arc::new(TriangleMesh::new(
+            vec![
+                Point3::new(-1.0, -1.0, 0.0),
+                Point3::new(1.0, -1.0, 0.0),
+                Point3::new(1.0, 1.0, 0.0),
+                Point3::new(-1.0, 1.0, 0.0),
+            ],
+            vec![0, 1, 2, 0, 2, 3],
+            Vec::new(),
+            vec![(0.0, 0.0), (1.0, 0.0), (1.0, 1.0), (0.0, 1.0)],
+            7,
+        ))
+    }
+
+    #[test]
+    fn test_triangle_hit() {
+        let tris = TriangleMesh::triangles(&quad());
+        assert_eq!(tris.list.len(), 2);
+        let r = Ray::new(
+            Point3::new(0.5, -0.5, 2.0),
+            Vec3::new(0.0, 0.0, -1.0),
+            0.0,
+            1,
+        );
+        let mut rec = HitRecord::default();
+        assert!(tris.hit(&r, 0.001, f32::MAX, &mut rec));
+        assert_eq!(rec.t, 2.0);
+        assert_eq!(rec.material, 7);
+        assert_eq!(rec.normal.z(), 1.0);
+        assert!((rec.u - 0.75).abs() < 1e-6);
+        assert!((rec.v - 0.25).abs() < 1e-6);
+        // u spans the width of 2 along x.
+        assert!((rec.dpdu - Vec3::new(2.0, 0.0, 0.0)).length() < 1e-6);
+    }
+
+    #[test]
+    fn test_triangle_sample() {
+        let tris = TriangleMesh::triangles(&quad());
+        let origin = Point3::new(0.0, 0.0, 2.0);
+        let mut rng = RNG::default();
+        // Averaging 1/pdf estimates the solid angle of the quad.
+        let n = 10000;
+        let mut solid_angle = 0.0;
+        for _ in 0..n {
+            let s = tris.sample(&mut rng, origin, 0.0).unwrap();
+            assert!(s.p.z().abs() < 1e-6);
+            let direction = (s.p - origin).unit();
+            assert!((tris.pdf(origin, direction, 0.0) - s.pdf).abs() < 1e-3);
+            solid_angle += 1.0 / s.pdf;
+        }
+        // A square of side 2 seen from 2 above its center.
+        let expected = 4.0 * (1.0f32 / 5.0).asin();
+        assert!((solid_angle / n as f32 - expected).abs() < 0.02 * expected);
+    }
+
+    #[test]
+    fn test_triangle_miss() {
+        let tris = TriangleMesh::triangles(&quad());
+        let r = Ray::new(
+            Point3::new(1.5, 0.0, 2.0),
+            Vec3::new(0.0, 0.0, -1.0),
+            0.0,
+            1,
+        );
+        let mut rec = HitRecord::default();
+        assert!(!tris.hit(&r, 0.001, f32::MAX, &mut rec));
+    }
+}
@@ -0,0 +1,312 @@
+//! The Trowbridge-Reitz (GGX) microfacet distribution with the Smith
+//! masking-shadowing function and sampling of the visible normals (Heitz,
+//! "Sampling the GGX Distribution of Visible Normals", 2018).
+//!
+//! Everything here works in a local shading frame in which the surface
+//! normal is +z, so the cosine of the angle to the normal is just `z`.
+
+use ::std::clone::Clone;
+use ::std::marker::Copy;
+use ::std::option::Option::{self, None, Some};
+
+use ::math::Vec3;
+
+use crate::material::face_forward;
+use crate::pbrt::{orthonormal_basis, HitRecord};
+
+const PI: f32 = ::std::f32::consts::PI;
+
+/// Below this roughness a surface is treated as a perfect mirror, the
+/// distribution would be too peaked to sample or evaluate reliably.
+const SMOOTH_ALPHA: f32 = 1e-3;
+
+/// An orthonormal shading frame with the normal as `z`.
+#[derive(Debug, Clone, Copy)]
+pub struct Frame {
+    pub x: Vec3,
+    pub y: Vec3,
+    pub z: Vec3,
+}
+
+impl Frame {
+    /// The frame around the unit `normal` with `x` along `dpdu` where the
+    /// shape provides it, so that anisotropic roughness has a well-defined
+    /// orientation.
+    pub fn new(normal: Vec3, dpdu: Vec3) -> Self {
+        let z = normal;
+        let along = dpdu - dpdu.dot(z) * z;
+        let x = if along.dot(along) > 1e-12 {
+            along.unit()
+        } else {
+            orthonormal_basis(z).0
+        };
+        Frame {
+            x,
+            y: z.cross(&x),
+            z,
+        }
+    }
+
+    /// The frame at a hit with the normal facing the ray that arrives
+    /// along `direction`.
+    pub fn facing(rec: &HitRecord, direction: Vec3) -> Self {
+        Frame::new(face_forward(rec.normal.unit(), direction), rec.dpdu)
+    }
+
+    pub fn local(&self, w: Vec3) -> Vec3 {
+        Vec3::new(w.dot(self.x), w.dot(self.y), w.dot(self.z))
+    }
+
+    pub fn world(&self, w: Vec3) -> Vec3 {
+        w.x() * self.x + w.y() * self.y + w.z() * self.z
+    }
+}
+
+/// The mirror image of `w` about `n`, both pointing away from the
+/// surface.
+#[inline(always)]
+pub fn reflect_about(w: Vec3, n: Vec3) -> Vec3 {
+    2.0 * w.dot(n) * n - w
+}
+
+/// The direction `w` on the side of `n` refracts into on the other side,
+/// where the index of refraction is `eta` times as large. None for total
+/// internal reflection.
+pub fn refract_about(w: Vec3, n: Vec3, eta: f32) -> Option<Vec3> {
+    let cos_i = w.dot(n);
+    let sin2_t = (1.0 - cos_i * cos_i).max(0.0) / (eta * eta);
+    if sin2_t >= 1.0 {
+        return None;
+    }
+    let cos_t = (1.0 - sin2_t).sqrt();
+    Some(-w / eta + (cos_i / eta - cos_t) * n)
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct TrowbridgeReitz {
+    pub alpha_x: f32,
+    pub alpha_y: f32,
+}
+
+impl TrowbridgeReitz {
+    /// Widths below `SMOOTH_ALPHA` are raised to it, so that a distribution
+    /// that is smooth along one axis only still has a finite density.
+    pub fn new(alpha_x: f32, alpha_y: f32) -> Self {
+        TrowbridgeReitz {
+            alpha_x: alpha_x.max(SMOOTH_ALPHA),
+            alpha_y: alpha_y.max(SMOOTH_ALPHA),
+        }
+    }
+
+    /// The widths of the distribution for a perceptual `roughness` in
+    /// [0, 1], squared as in the Disney model so that roughness varies
+    /// evenly in appearance.
+    pub fn from_roughness(u: f32, v: f32) -> Self {
+        TrowbridgeReitz::new(u * u, v * v)
+    }
+
+    pub fn effectively_smooth(&self) -> bool {
+        self.alpha_x.max(self.alpha_y) <= SMOOTH_ALPHA
+    }
+
+    /// The density of microfacet normals `wm` per projected area.
+    pub fn d(&self, wm: Vec3) -> f32 {
+        let cos2 = wm.z() * wm.z();
+        if cos2 <= 0.0 {
+            return 0.0;
+        }
+        let x = wm.x() / self.alpha_x;
+        let y = wm.y() / self.alpha_y;
+        let e = (x * x + y * y) / cos2;
+        1.0 / (PI
+            * self.alpha_x
+            * self.alpha_y
+            * cos2
+            * cos2
+            * (1.0 + e)
+            * (1.0 + e))
+    }
+
+    /// Smith's auxiliary function, the ratio of the area of backfacing to
+    /// visible microfacets seen from `w`.
+    pub fn lambda(&self, w: Vec3) -> f32 {
+        let cos2 = w.z() * w.z();
+        if cos2 <= 0.0 {
+            return 0.0;
+        }
+        let x = w.x() * self.alpha_x;
+        let y = w.y() * self.alpha_y;
+        let alpha2_tan2 = (x * x + y * y) / cos2;
+        0.5 * ((1.0 + alpha2_tan2).sqrt() - 1.0)
+    }
+
+    /// The fraction of microfacets visible from `w`.
+    pub fn g1(&self, w: Vec3) -> f32 {
+        1.0 / (1.0 + self.lambda(w))
+    }
+
+    /// The fraction of microfacets visible from both `wo` and `wi`.
+    pub fn g(&self, wo: Vec3, wi: Vec3) -> f32 {
+        1.0 / (1.0 + self.lambda(wo) + self.lambda(wi))
+    }
+
+    /// The transmission from `wo` through the microfacet `wm` into `wi`
+    /// across an interface of relative index `eta`, without the Fresnel
+    /// factor, and the change of variables from the density of `wm` to
+    /// that of `wi`.
+    pub fn transmission(
+        &self, wo: Vec3, wi: Vec3, wm: Vec3, eta: f32,
+    ) -> (f32, f32) {
+        let denom = wi.dot(wm) + wo.dot(wm) / eta;
+        let dwm_dwi = wi.dot(wm).abs() / (denom * denom);
+        // Radiance is compressed into the smaller solid angle on the
+        // denser side.
+        let f = self.d(wm) * self.g(wo, wi) * wo.dot(wm) * dwm_dwi
+            / (wo.z() * eta * eta);
+        (f, dwm_dwi)
+    }
+
+    /// The density of the normals `wm` visible from `w` per solid angle.
+    pub fn visible_d(&self, w: Vec3, wm: Vec3) -> f32 {
+        if w.z() == 0.0 {
+            return 0.0;
+        }
+        self.g1(w) / w.z().abs() * self.d(wm) * w.dot(wm).max(0.0)
+    }
+
+    /// Samples a normal visible from `w` with density `visible_d` from the
+    /// uniform numbers `u1` and `u2`. The distribution is stretched to the
+    /// isotropic one of unit width, where the visible normals project to a
+    /// disk and a half disk.
+    pub fn sample_wm(&self, w: Vec3, u1: f32, u2: f32) -> Vec3 {
+        let mut wh =
+            Vec3::new(self.alpha_x * w.x(), self.alpha_y * w.y(), w.z()).unit();
+        if wh.z() < 0.0 {
+            wh = -wh;
+        }
+        let t1 = if wh.z() < 0.99999 {
+            Vec3::new(0.0, 0.0, 1.0).cross(&wh).unit()
+        } else {
+            Vec3::new(1.0, 0.0, 0.0)
+        };
+        let t2 = wh.cross(&t1);
+        let r = u1.sqrt();
+        let phi = 2.0 * PI * u2;
+        let px = r * phi.cos();
+        let py = r * phi.sin();
+        let h = (1.0 - px * px).max(0.0).sqrt();
+        let s = 0.5 * (1.0 + wh.z());
+        let py = (1.0 - s) * h + s * py;
+        let pz = (1.0 - px * px - py * py).max(0.0).sqrt();
+        let nh = px * t1 + py * t2 + pz * wh;
+        Vec3::new(
+            self.alpha_x * nh.x(),
+            self.alpha_y * nh.y(),
+            nh.z().max(1e-6),
+        )
+        .unit()
+    }
+}
+
+#[cfg(test)]
+pub mod tests {
+    use super::*;
+    use ::std::assert;
+    use ::std::default::Default;
+
+    use crate::pbrt::RNG;
+
+    fn uniform_hemisphere(rng: &mut RNG) -> Vec3 {
+        let z = rng.rand();
+        let r = (1.0 - z * z).max(0.0).sqrt();
+        let phi = 2.0 * PI * rng.rand();
+        Vec3::new(r * phi.cos(), r * phi.sin(), z)
+    }
+
+    #[test]
+    fn test_normalization() {
+        // The projected microfacet area is the macro surface's, and the
+        // visible normals are a density over directions.
+        let mut rng = RNG::default();
+        let w = Vec3::new(0.6, -0.2, 0.5).unit();
+        for &(ax, ay) in &[(0.3, 0.3), (0.5, 0.2), (0.8, 0.6)] {
+            let tr = TrowbridgeReitz::new(ax, ay);
+            let n = 200000;
+            let (mut projected, mut visible) = (0.0, 0.0);
+            for _ in 0..n {
+                let wm = uniform_hemisphere(&mut rng);
+                projected += tr.d(wm) * wm.z() * 2.0 * PI;
+                visible += tr.visible_d(w, wm) * 2.0 * PI;
+            }
+            assert!((projected / n as f32 - 1.0).abs() < 0.03);
+            assert!((visible / n as f32 - 1.0).abs() < 0.03);
+        }
+    }
+
+    #[test]
+    fn test_sample_visible_normals() {
+        // The mean of sampled normals matches the one integrated over
+        // `visible_d`.
+        let mut rng = RNG::default();
+        let tr = TrowbridgeReitz::new(0.6, 0.25);
+        let w = Vec3::new(0.5, 0.4, 0.3).unit();
+        let n = 200000;
+        let mut sampled = Vec3::new(0.0, 0.0, 0.0);
+        let mut integrated = Vec3::new(0.0, 0.0, 0.0);
+        for _ in 0..n {
+            let wm = tr.sample_wm(w, rng.rand(), rng.rand());
+            assert!(wm.z() > 0.0 && w.dot(wm) >= 0.0);
+            sampled += wm;
+            let wm = uniform_hemisphere(&mut rng);
+            integrated += tr.visible_d(w, wm) * 2.0 * PI * wm;
+        }
+        let delta = (sampled - integrated) / n as f32;
+        assert!(delta.dot(delta).sqrt() < 0.02);
+    }
+
+    #[test]
+    fn test_smooth_along_one_axis() {
+        let tr = TrowbridgeReitz::new(0.0, 0.5);
+        assert!(!tr.effectively_smooth());
+        assert!(TrowbridgeReitz::new(0.0, 0.0).effectively_smooth());
+        let w = Vec3::new(0.3, 0.4, 0.5).unit();
+        for wm in [Vec3::new(0.0, 0.0, 1.0), Vec3::new(0.1, 0.2, 0.9).unit()] {
+            assert!(tr.d(wm).is_finite() && tr.visible_d(w, wm).is_finite());
+        }
+        assert!(tr.g(w, Vec3::new(-0.3, 0.1, 0.8).unit()).is_finite());
+        let wm = tr.sample_wm(w, 0.3, 0.7);
+        assert!(wm.z() > 0.0 && wm.length().is_finite());
+    }
+
+    #[test]
+    fn test_refract() {
+        let n = Vec3::new(0.0, 0.0, 1.0);
+        let w = Vec3::new(0.6, 0.0, 0.8);
+        let t = refract_about(w, n, 1.5).unwrap();
+        assert!((t.length() - 1.0).abs() < 1e-6);
+        assert!((1.5 * -t.x() - 0.6).abs() < 1e-6 && t.z() < 0.0);
+        // Back out again, and beyond the critical angle not at all.
+        let back = refract_about(t, -n, 1.0 / 1.5).unwrap();
+        assert!((back - w).length() < 1e-5);
+        let grazing = Vec3::new(0.8, 0.0, 0.6);
+        assert!(refract_about(grazing, n, 1.0 / 1.5).is_none());
+    }
+
+    #[test]
+    fn test_frame() {
+        let rec = HitRecord {
+            normal: Vec3::new(0.0, 2.0, 0.0),
+            dpdu: Vec3::new(3.0, 1.0, 0.0),
+            ..HitRecord::default()
+        };
+        let frame = Frame::facing(&rec, Vec3::new(0.0, -1.0, 0.0));
+        assert!((frame.x.x() - 1.0).abs() < 1e-6);
+        assert!((frame.z.y() - 1.0).abs() < 1e-6);
+        let w = Vec3::new(0.3, -0.5, 0.8);
+        let back = frame.world(frame.local(w));
+        assert!((back - w).dot(back - w) < 1e-10);
+        // Seen from below, the normal flips.
+        let frame = Frame::facing(&rec, Vec3::new(0.0, 1.0, 0.0));
+        assert!((frame.z.y() + 1.0).abs() < 1e-6);
+    }
+}
@@ -0,0 +1,739 @@
+//! Wavefront OBJ and MTL import.
+//!
+//! Every `o`/`g`/`usemtl` run of faces becomes one `TriangleMesh`, whose
+//! triangles are appended to the caller's `HitableList`. Materials from
+//! `mtllib` files are appended to the `MaterialLibrary`, with their
+//! `map_Kd` images as diffuse textures.
+
+use ::std::boxed::Box;
+use ::std::clone::Clone;
+use ::std::collections::HashMap;
+use ::std::convert::From;
+use ::std::default::Default;
+use ::std::fmt::{self, Display, Formatter};
+use ::std::fs;
+use ::std::io;
+use ::std::iter::{Extend, Iterator};
+use ::std::option::Option::{self, None, Some};
+use ::std::path::{Path, PathBuf};
+use ::std::result::Result::{self, Err, Ok};
+use ::std::str::FromStr;
+use ::std::string::{String, ToString};
+use ::std::sync::Arc;
+use ::std::vec::Vec;
+use ::std::{eprintln, format, matches, write};
+
+use ::math::{Point3, Vec3};
+
+use crate::material::{Dielectric, DiffuseLight, Lambertian, Metal};
+use crate::mesh::TriangleMesh;
+use crate::pbrt::{HitableList, Material, MaterialLibrary, Texture};
+use crate::principled::Principled;
+use crate::texture::{ConstTexture, FilterMode, ImageTexture, WrapMode};
+
+#[derive(Debug)]
+pub enum ObjError {
+    Io {
+        path: PathBuf,
+        error: io::Error,
+    },
+    Parse {
+        path: PathBuf,
+        line: usize,
+        message: String,
+    },
+}
+
+impl Display for ObjError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        match self {
+            ObjError::Io { path, error } => {
+                write!(f, "{}: {}", path.display(), error)
+            }
+            ObjError::Parse {
+                path,
+                line,
+                message,
+            } => write!(f, "{}:{}: {}", path.display(), line, message),
+        }
+    }
+}
+
+fn read_file(path: &Path) -> Result<String, ObjError> {
+    fs::read_to_string(path).map_err(|error| ObjError::Io {
+        path: path.to_path_buf(),
+        error,
+    })
+}
+
+/// Loads `path` and everything it references.
+pub fn load_obj(
+    path: &Path, hitables: &mut HitableList, matlib: &mut MaterialLibrary,
+) -> Result<(), ObjError> {
+    let src = read_file(path)?;
+    parse_obj(&src, path, hitables, matlib)
+}
+
+/// Parses OBJ source. `path` is used for error messages and to resolve
+/// `mtllib` statements.
+pub fn parse_obj(
+    src: &str, path: &Path, hitables: &mut HitableList,
+    matlib: &mut MaterialLibrary,
+) -> Result<(), ObjError> {
+    let mut parser = ObjParser {
+        path,
+        positions: Vec::new(),
+        normals: Vec::new(),
+        uvs: Vec::new(),
+        materials: HashMap::new(),
+        textures: HashMap::new(),
+        current_material: None,
+        default_material: None,
+        mesh: MeshBuilder::default(),
+        hitables,
+        matlib,
+    };
+    for (i, line) in src.lines().enumerate() {
+        parser.statement(i + 1, line)?;
+    }
+    parser.flush();
+    Ok(())
+}
+
+struct ObjParser<'a> {
+    path: &'a Path,
+    positions: Vec<Point3>,
+    normals: Vec<Vec3>,
+    uvs: Vec<(f32, f32)>,
+    materials: HashMap<String, usize>,
+    /// Texture images read so far, by path.
+    textures: HashMap<PathBuf, Arc<dyn Texture>>,
+    current_material: Option<usize>,
+    default_material: Option<usize>,
+    mesh: MeshBuilder,
+    hitables: &'a mut HitableList,
+    matlib: &'a mut MaterialLibrary,
+}
+
+impl<'a> ObjParser<'a> {
+    fn error(&self, line: usize, message: String) -> ObjError {
+        ObjError::Parse {
+            path: self.path.to_path_buf(),
+            line,
+            message,
+        }
+    }
+
+    fn warn(&self, line: usize, message: &str) {
+        eprintln!("{}:{}: warning: {}", self.path.display(), line, message);
+    }
+
+    fn statement(&mut self, line: usize, text: &str) -> Result<(), ObjError> {
+        let text = strip_comment(text);
+        let mut tokens = text.split_whitespace();
+        let keyword = match tokens.next() {
+            Some(keyword) => keyword,
+            None => return Ok(()),
+        };
+        let args: Vec<&str> = tokens.collect();
+        match keyword {
+            "v" => {
+                let [x, y, z] = self.floats::<3>(line, keyword, &args)?;
+                self.positions.push(Point3::new(x, y, z));
+            }
+            "vn" => {
+                let [x, y, z] = self.floats::<3>(line, keyword, &args)?;
+                self.normals.push(Vec3::new(x, y, z));
+            }
+            "vt" => {
+                // v is optional and defaults to 0, the optional third
+                // coordinate is ignored.
+                let [u] = self.floats::<1>(line, keyword, &args)?;
+                let v = match args.len() {
+                    1 => 0.0,
+                    _ => self.floats::<2>(line, keyword, &args)?[1],
+                };
+                self.uvs.push((u, v));
+            }
+            "f" => self.face(line, &args)?,
+            "o" | "g" => self.flush(),
+            "usemtl" => {
+                let name = self.name(line, keyword, &args)?;
+                self.flush();
+                self.current_material = self.materials.get(&name).cloned();
+                if self.current_material.is_none() {
+                    self.warn(
+                        line,
+                        &format!("unknown material '{}', using default", name),
+                    );
+                }
+            }
+            "mtllib" => {
+                let name = self.name(line, keyword, &args)?;
+                self.mtllib(line, &name)?;
+            }
+            "s" | "l" | "p" => {}
+            _ => self.warn(line, &format!("ignoring '{}'", keyword)),
+        }
+        Ok(())
+    }
+
+    fn floats<const N: usize>(
+        &self, line: usize, keyword: &str, args: &[&str],
+    ) -> Result<[f32; N], ObjError> {
+        if args.len() < N {
+            return Err(self.error(
+                line,
+                format!(
+                    "'{}' needs {} numbers, found {}",
+                    keyword,
+                    N,
+                    args.len()
+                ),
+            ));
+        }
+        let mut values = [0.0; N];
+        for (value, arg) in values.iter_mut().zip(args) {
+            *value = parse_number(arg)
+                .ok_or_else(|| self.error(line, expected_number(arg)))?;
+        }
+        Ok(values)
+    }
+
+    fn name(
+        &self, line: usize, keyword: &str, args: &[&str],
+    ) -> Result<String, ObjError> {
+        if args.is_empty() {
+            return Err(self.error(line, format!("'{}' needs a name", keyword)));
+        }
+        Ok(args.join(" "))
+    }
+
+    fn face(&mut self, line: usize, args: &[&str]) -> Result<(), ObjError> {
+        if args.len() < 3 {
+            return Err(self.error(
+                line,
+                format!("face needs at least 3 vertices, found {}", args.len()),
+            ));
+        }
+        let mut corners = Vec::with_capacity(args.len());
+        for arg in args {
+            let mut parts = arg.split('/');
+            let v = self.index(line, parts.next(), self.positions.len())?;
+            let vt = self.index(line, parts.next(), self.uvs.len())?;
+            let vn = self.index(line, parts.next(), self.normals.len())?;
+            let v = v.ok_or_else(|| {
+                self.error(line, format!("missing vertex index in '{}'", arg))
+            })?;
+            corners.push(self.mesh.vertex(
+                (v, vt, vn),
+                &self.positions,
+                &self.uvs,
+                &self.normals,
+            ));
+        }
+        // Polygons are triangulated as a fan around the first corner.
+        for i in 1..corners.len() - 1 {
+            self.mesh.indices.push(corners[0]);
+            self.mesh.indices.push(corners[i]);
+            self.mesh.indices.push(corners[i + 1]);
+        }
+        Ok(())
+    }
+
+    fn index(
+        &self, line: usize, part: Option<&str>, len: usize,
+    ) -> Result<Option<usize>, ObjError> {
+        let part = match part {
+            None | Some("") => return Ok(None),
+            Some(part) => part,
+        };
+        let i = i64::from_str(part).map_err(|_| {
+            self.error(line, format!("expected index, found '{}'", part))
+        })?;
+        // Negative indices count back from the last element.
+        let resolved = if i > 0 { i - 1 } else { len as i64 + i };
+        if i == 0 || resolved < 0 || resolved >= len as i64 {
+            return Err(self.error(
+                line,
+                format!("index {} out of range (have {})", i, len),
+            ));
+        }
+        Ok(Some(resolved as usize))
+    }
+
+    fn mtllib(&mut self, line: usize, name: &str) -> Result<(), ObjError> {
+        let path = match self.path.parent() {
+            Some(dir) => dir.join(name),
+            None => PathBuf::from(name),
+        };
+        let src = match fs::read_to_string(&path) {
+            Ok(src) => src,
+            Err(error) => {
+                self.warn(
+                    line,
+                    &format!("cannot read '{}': {}", path.display(), error),
+                );
+                return Ok(());
+            }
+        };
+        for (name, mtl) in parse_mtl(&src, &path)? {
+            let map_kd = match &mtl.map_kd {
+                Some(path) => self.texture(line, &name, path),
+                None => None,
+            };
+            self.matlib.lib.push(mtl.to_material(map_kd));
+            self.materials.insert(name, self.matlib.lib.len() - 1);
+        }
+        Ok(())
+    }
+
+    /// The texture of the image at `path`, or `None` after a warning if it
+    /// cannot be read.
+    fn texture(
+        &mut self, line: usize, material: &str, path: &Path,
+    ) -> Option<Arc<dyn Texture>> {
+        if let Some(texture) = self.textures.get(path) {
+            return Some(texture.clone());
+        }
+        let filter = FilterMode::Ewa;
+        match ImageTexture::read(path, None, WrapMode::Repeat, filter) {
+            Ok(texture) => {
+                let texture: Arc<dyn Texture> = Arc::new(texture);
+                self.textures.insert(path.to_path_buf(), texture.clone());
+                Some(texture)
+            }
+            Err(e) => {
+                self.warn(
+                    line,
+                    &format!("material '{}': {}, using Kd", material, e),
+                );
+                None
+            }
+        }
+    }
+
+    fn material(&mut self) -> usize {
+        if let Some(material) = self.current_material {
+            return material;
+        }
+        if let Some(material) = self.default_material {
+            return material;
+        }
+        self.matlib
+            .lib
+            .push(MtlMaterial::default().to_material(None));
+        self.default_material = Some(self.matlib.lib.len() - 1);
+        self.matlib.lib.len() - 1
+    }
+
+    fn flush(&mut self) {
+        if self.mesh.indices.is_empty() {
+            self.mesh = MeshBuilder::default();
+            return;
+        }
+        let material = self.material();
+        let mesh = ::std::mem::take(&mut self.mesh);
+        let triangles =
+            TriangleMesh::triangles(&Arc::new(mesh.build(material)));
+        self.hitables.list.extend(triangles.list);
+    }
+}
+
+/// Collects the vertices of one mesh, deduplicating `v/vt/vn` triples.
+#[derive(Default)]
+struct MeshBuilder {
+    positions: Vec<Point3>,
+    uvs: Vec<(f32, f32)>,
+    normals: Vec<Vec3>,
+    indices: Vec<usize>,
+    vertices: HashMap<(usize, Option<usize>, Option<usize>), usize>,
+    missing_uvs: bool,
+    missing_normals: bool,
+}
+
+impl MeshBuilder {
+    fn vertex(
+        &mut self, key: (usize, Option<usize>, Option<usize>),
+        positions: &[Point3], uvs: &[(f32, f32)], normals: &[Vec3],
+    ) -> usize {
+        if let Some(&index) = self.vertices.get(&key) {
+            return index;
+        }
+        let (v, vt, vn) = key;
+        self.positions.push(positions[v]);
+        self.uvs.push(vt.map_or((0.0, 0.0), |vt| uvs[vt]));
+        self.normals
+            .push(vn.map_or(Vec3::default(), |vn| normals[vn]));
+        self.missing_uvs |= vt.is_none();
+        self.missing_normals |= vn.is_none();
+        let index = self.positions.len() - 1;
+        self.vertices.insert(key, index);
+        index
+    }
+
+    fn build(mut self, material: usize) -> TriangleMesh {
+        // TriangleMesh wants per-vertex data for all vertices or none.
+        if self.missing_uvs {
+            self.uvs.clear();
+        }
+        if self.missing_normals {
+            self.normals.clear();
+        }
+        TriangleMesh::new(
+            self.positions,
+            self.indices,
+            self.normals,
+            self.uvs,
+            material,
+        )
+    }
+}
+
+/// The subset of an MTL material that maps onto our materials.
+#[derive(Debug, Clone)]
+pub struct MtlMaterial {
+    pub kd: Vec3,
+    pub ks: Vec3,
+    pub ke: Vec3,
+    pub ns: f32,
+    pub ni: f32,
+    pub dissolve: f32,
+    pub illum: u32,
+    pub map_kd: Option<PathBuf>,
+    /// The PBR extension's `Pr`, `Pm`, `Ps`, `Pc` and `Pcr`, which ask for
+    /// a principled material.
+    pub roughness: Option<f32>,
+    pub metallic: Option<f32>,
+    pub sheen: Option<f32>,
+    pub clearcoat: Option<f32>,
+    pub clearcoat_roughness: Option<f32>,
+}
+
+impl Default for MtlMaterial {
+    fn default() -> Self {
+        MtlMaterial {
+            kd: Vec3::new(0.8, 0.8, 0.8),
+            ks: Vec3::default(),
+            ke: Vec3::default(),
+            ns: 0.0,
+            ni: 1.0,
+            dissolve: 1.0,
+            illum: 2,
+            map_kd: None,
+            roughness: None,
+            metallic: None,
+            sheen: None,
+            clearcoat: None,
+            clearcoat_roughness: None,
+        }
+    }
+}
+
+fn max_component(v: Vec3) -> f32 {
+    v.x().max(v.y()).max(v.z())
+}
+
+impl MtlMaterial {
+    /// Emissive materials become `DiffuseLight`, ones with PBR parameters
+    /// `Principled`, transparent ones `Dielectric`, mirror-like ones
+    /// (`illum` 3/5/8 or no diffuse part) `Metal` and everything else
+    /// `Lambertian`. `map_kd` is the texture read from `map_kd`, which
+    /// replaces `kd` rather than scaling it.
+    pub fn to_material(
+        &self, map_kd: Option<Arc<dyn Texture>>,
+    ) -> Box<dyn Material> {
+        if max_component(self.ke) > 0.0 {
+            return Box::new(DiffuseLight {
+                emit: Arc::new(ConstTexture(self.ke)),
+            });
+        }
+        let has_diffuse = map_kd.is_some() || max_component(self.kd) > 0.0;
+        let diffuse: Arc<dyn Texture> = match map_kd {
+            Some(texture) => texture,
+            None => Arc::new(ConstTexture(self.kd)),
+        };
+        if self.is_pbr() {
+            return Box::new(self.principled(diffuse));
+        }
+        if self.dissolve < 1.0 || matches!(self.illum, 4 | 6 | 7 | 9) {
+            let ref_idx = if self.ni > 1.0 { self.ni } else { 1.5 };
+            return Box::new(Dielectric::new(ref_idx));
+        }
+        let mirror = matches!(self.illum, 3 | 5 | 8);
+        if mirror || (!has_diffuse && max_component(self.ks) > 0.0) {
+            // Map the Phong exponent to a perturbation radius.
+            let fuzz = (2.0 / (self.ns + 2.0)).sqrt();
+            return Box::new(Metal::new(self.ks, fuzz));
+        }
+        Box::new(Lambertian::new(diffuse))
+    }
+
+    fn is_pbr(&self) -> bool {
+        self.roughness.is_some()
+            || self.metallic.is_some()
+            || self.sheen.is_some()
+            || self.clearcoat.is_some()
+            || self.clearcoat_roughness.is_some()
+    }
+
+    /// The PBR parameters as exported by Blender and others, with the
+    /// specular from `Ni` and transmission for what `d` lets through.
+    fn principled(&self, base_color: Arc<dyn Texture>) -> Principled {
+        let gray = |v: f32| -> Arc<dyn Texture> {
+            let v = v.clamp(0.0, 1.0);
+            Arc::new(ConstTexture(Vec3::new(v, v, v)))
+        };
+        let specular = if self.ni > 1.0 {
+            ((self.ni - 1.0) / (self.ni + 1.0)).powi(2) / 0.08
+        } else {
+            0.5
+        };
+        Principled {
+            metallic: gray(self.metallic.unwrap_or(0.0)),
+            roughness: gray(self.roughness.unwrap_or(0.5)),
+            specular: gray(specular),
+            sheen: gray(self.sheen.unwrap_or(0.0)),
+            clearcoat: gray(self.clearcoat.unwrap_or(0.0)),
+            clearcoat_gloss: 1.0
+                - self.clearcoat_roughness.unwrap_or(0.0).clamp(0.0, 1.0),
+            transmission: gray(1.0 - self.dissolve),
+            ..Principled::new(base_color)
+        }
+    }
+}
+
+/// Parses MTL source into named materials, in file order.
+pub fn parse_mtl(
+    src: &str, path: &Path,
+) -> Result<Vec<(String, MtlMaterial)>, ObjError> {
+    let error = |line: usize, message: String| ObjError::Parse {
+        path: path.to_path_buf(),
+        line,
+        message,
+    };
+    let mut materials: Vec<(String, MtlMaterial)> = Vec::new();
+    for (i, text) in src.lines().enumerate() {
+        let line = i + 1;
+        let mut tokens = strip_comment(text).split_whitespace();
+        let keyword = match tokens.next() {
+            Some(keyword) => keyword,
+            None => continue,
+        };
+        let args: Vec<&str> = tokens.collect();
+        if keyword == "newmtl" {
+            if args.is_empty() {
+                return Err(error(line, "'newmtl' needs a name".to_string()));
+            }
+            materials.push((args.join(" "), MtlMaterial::default()));
+            continue;
+        }
+        let mtl = match materials.last_mut() {
+            Some((_, mtl)) => mtl,
+            None => {
+                return Err(error(
+                    line,
+                    format!("'{}' before 'newmtl'", keyword),
+                ))
+            }
+        };
+        let number = |i: usize| -> Result<f32, ObjError> {
+            let arg = args.get(i).ok_or_else(|| {
+                error(line, format!("'{}' needs more arguments", keyword))
+            })?;
+            parse_number(arg).ok_or_else(|| error(line, expected_number(arg)))
+        };
+        let color = || -> Result<Vec3, ObjError> {
+            let r = number(0)?;
+            // A single value means grey.
+            if args.len() == 1 {
+                return Ok(Vec3::new(r, r, r));
+            }
+            Ok(Vec3::new(r, number(1)?, number(2)?))
+        };
+        match keyword {
+            "Kd" => mtl.kd = color()?,
+            "Ks" => mtl.ks = color()?,
+            "Ke" => mtl.ke = color()?,
+            "Ns" => mtl.ns = number(0)?,
+            "Ni" => mtl.ni = number(0)?,
+            "d" => mtl.dissolve = number(0)?,
+            "Tr" => mtl.dissolve = 1.0 - number(0)?,
+            "illum" => mtl.illum = number(0)? as u32,
+            "Pr" => mtl.roughness = Some(number(0)?),
+            "Pm" => mtl.metallic = Some(number(0)?),
+            "Ps" => mtl.sheen = Some(number(0)?),
+            "Pc" => mtl.clearcoat = Some(number(0)?),
+            "Pcr" => mtl.clearcoat_roughness = Some(number(0)?),
+            "map_Kd" => {
+                // Options precede the file name, which comes last.
+                let name = args.last().ok_or_else(|| {
+                    error(line, "'map_Kd' needs a file name".to_string())
+                })?;
+                mtl.map_kd = Some(match path.parent() {
+                    Some(dir) => dir.join(name),
+                    None => PathBuf::from(name),
+                });
+            }
+            _ => {}
+        }
+    }
+    Ok(materials)
+}
+
+fn strip_comment(line: &str) -> &str {
+    match line.find('#') {
+        Some(i) => &line[..i],
+        None => line,
+    }
+}
+
+fn parse_number(s: &str) -> Option<f32> {
+    f32::from_str(s).ok()
+}
+
+fn expected_number(found: &str) -> String {
+    format!("expected number, found '{}'", found)
+}
+
+#[cfg(test)]
+pub mod tests {
+    use super::*;
+    use ::std::env;
+    use ::std::{assert, assert_eq, panic, vec};
+
+    use crate::image::{Image, WriteOptions};
+    use crate::pbrt::{HitRecord, Hitable, Ray};
+
+    fn parse(src: &str) -> Result<(HitableList, MaterialLibrary), ObjError> {
+        let mut hitables = HitableList::default();
+        let mut matlib = MaterialLibrary::default();
+        parse_obj(src, Path::new("test.obj"), &mut hitables, &mut matlib)?;
+        Ok((hitables, matlib))
+    }
+
+    #[test]
+    fn test_obj_quad() {
+        let (hitables, matlib) = parse(
+            "# a quad\n\
+             v 0 0 0\nv 1 0 0\nv 1 1 0\nv 0 1 0\n\
+             vt 0 0\nvt 1 0\nvt 1 1\nvt 0 1\n\
+             f 1/1 2/2 3/3 4/4\n",
+        )
+        .unwrap();
+        assert_eq!(hitables.list.len(), 2);
+        assert_eq!(matlib.lib.len(), 1);
+    }
+
+    #[test]
+    fn test_obj_short_uvs() {
+        // v defaults to 0 and a third coordinate is ignored.
+        let (hitables, _) = parse(
+            "v 0 0 0\nv 1 0 0\nv 0 1 0\n\
+             vt 0.5\nvt 1\nvt 0 1 0\n\
+             f 1/1 2/2 3/3\n",
+        )
+        .unwrap();
+        let r = Ray::new(
+            Point3::new(0.25, 0.25, 1.0),
+            Vec3::new(0.0, 0.0, -1.0),
+            0.0,
+            0,
+        );
+        let mut rec = HitRecord::default();
+        assert!(hitables.hit(&r, 0.001, f32::MAX, &mut rec));
+        assert!((rec.u - 0.5).abs() < 1e-5 && (rec.v - 0.25).abs() < 1e-5);
+        assert!(parse("vt\n").is_err());
+    }
+
+    #[test]
+    fn test_obj_negative_indices() {
+        let (hitables, _) =
+            parse("v 0 0 0\nv 1 0 0\nv 1 1 0\nf -3 -2 -1\n").unwrap();
+        assert_eq!(hitables.list.len(), 1);
+    }
+
+    #[test]
+    fn test_obj_errors() {
+        match parse("v 0 0 0\nv 1 0 0\nv 1 1 0\n\nf 1 2 4\n") {
+            Err(ObjError::Parse { line, .. }) => assert_eq!(line, 5),
+            _ => panic!("expected parse error"),
+        }
+        match parse("v 0 0\n") {
+            Err(ObjError::Parse { line, .. }) => assert_eq!(line, 1),
+            _ => panic!("expected parse error"),
+        }
+        match parse("v 0 0 0\nvn 0 x 1\n") {
+            Err(e) => assert_eq!(
+                e.to_string(),
+                "test.obj:2: expected number, found 'x'"
+            ),
+            _ => panic!("expected parse error"),
+        }
+    }
+
+    #[test]
+    fn test_mtl() {
+        let mtls = parse_mtl(
+            "newmtl red\nKd 1 0 0\n\nnewmtl glass\nNi 1.45\nd 0.2\n\
+             newmtl lamp\nKe 4 4 4\nnewmtl paint\nKd 0.5 0 0\nPr 0.3\nPc 1\n",
+            Path::new("test.mtl"),
+        )
+        .unwrap();
+        assert_eq!(mtls.len(), 4);
+        assert_eq!(mtls[0].0, "red");
+        assert_eq!(mtls[0].1.kd.x(), 1.0);
+        assert_eq!(mtls[1].1.ni, 1.45);
+        assert_eq!(mtls[2].1.ke.y(), 4.0);
+        assert!(!mtls[0].1.is_pbr());
+        assert_eq!(mtls[3].1.roughness, Some(0.3));
+        assert!(mtls[3].1.is_pbr() && mtls[3].1.clearcoat.is_some());
+        assert!(parse_mtl("Kd 1 1 1\n", Path::new("bad.mtl")).is_err());
+    }
+
+    #[test]
+    fn test_mtl_texture() {
+        let dir = env::temp_dir().join("raytracer_test_mtl_texture");
+        fs::create_dir_all(&dir).unwrap();
+        let mut image = Image::new(2, 2);
+        image.pixels = vec![Vec3::new(1.0, 0.0, 0.0); 4];
+        image
+            .write(&dir.join("red.png"), &WriteOptions::default())
+            .unwrap();
+        fs::write(
+            dir.join("test.mtl"),
+            "newmtl red\nKd 0.2 0.2 0.2\nmap_Kd -s 2 2 red.png\n\
+             newmtl missing\nKd 0 0 1\nmap_Kd missing.png\n",
+        )
+        .unwrap();
+        let mut hitables = HitableList::default();
+        let mut matlib = MaterialLibrary::default();
+        let result = parse_obj(
+            "mtllib test.mtl\n",
+            &dir.join("test.obj"),
+            &mut hitables,
+            &mut matlib,
+        );
+        let _ = fs::remove_dir_all(&dir);
+        result.unwrap();
+        assert_eq!(matlib.lib.len(), 2);
+        // The map replaces Kd; a map that cannot be read falls back to it.
+        let ray = Ray::new(
+            Point3::new(0.0, 0.0, 1.0),
+            Vec3::new(0.0, 0.0, -1.0),
+            0.0,
+            1,
+        );
+        let rec = HitRecord {
+            normal: Vec3::new(0.0, 0.0, 1.0),
+            u: 0.5,
+            v: 0.5,
+            ..HitRecord::default()
+        };
+        let up = Vec3::new(0.0, 0.0, 1.0);
+        let red = matlib.lib[0].eval(&ray, &rec, up);
+        assert!(red.x() > 0.3 && red.y() == 0.0, "{:?}", red);
+        let blue = matlib.lib[1].eval(&ray, &rec, up);
+        assert!(blue.z() > 0.3 && blue.x() == 0.0, "{:?}", blue);
+    }
+}
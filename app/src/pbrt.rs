@@ -1,15 +1,18 @@
 use ::std::boxed::Box;
 use ::std::clone::Clone;
-use ::std::cmp::Ordering;
+use ::std::cmp::{self, Ordering};
+use ::std::convert::From;
 use ::std::default::Default;
 use ::std::fmt::Debug;
 use ::std::iter::Iterator;
+use ::std::marker::{Send, Sync};
 use ::std::option::Option::{self, None, Some};
-use ::std::rc::Rc;
+use ::std::sync::Arc;
 use ::std::unreachable;
 use ::std::vec::Vec;
 
-use ::math::{Point3, Vec3};
+use ::math::{Point3, Transform, Vec3};
+use ::rand::rngs::SmallRng;
 use ::rand::Rng;
 use ::rand::SeedableRng;
 
@@ -21,20 +24,162 @@ pub struct HitRecord {
     pub material: usize,
     pub u: f32,
     pub v: f32,
+    /// The direction in which `u` increases along the surface, not
+    /// normalized. Zero for shapes that do not provide it.
+    pub dpdu: Vec3,
+    /// The same for `v`.
+    pub dpdv: Vec3,
+    /// How much `u` and `v` change from this pixel to the next one in x
+    /// and y, found by `set_differentials`. Zero when the ray carries no
+    /// differentials, which textures take as a point lookup.
+    pub dudx: f32,
+    pub dvdx: f32,
+    pub dudy: f32,
+    pub dvdy: f32,
 }
 
-pub trait Hitable: Debug {
+impl HitRecord {
+    /// Fills in the screen space derivatives of `u` and `v` from the
+    /// differentials of `ray`, the ray that found this hit. The offset rays
+    /// are intersected with the tangent plane at `p` and the offsets in `p`
+    /// expressed in `dpdu` and `dpdv` by least squares.
+    pub fn set_differentials(&mut self, ray: &Ray) {
+        self.dudx = 0.0;
+        self.dvdx = 0.0;
+        self.dudy = 0.0;
+        self.dvdy = 0.0;
+        let diff = match &ray.differentials {
+            Some(diff) => diff,
+            None => return,
+        };
+        let n = self.normal;
+        let offset = |origin: Point3, direction: Vec3| {
+            let cos = n.dot(direction);
+            if cos == 0.0 {
+                return None;
+            }
+            let t = n.dot(self.p - origin) / cos;
+            Some(origin + t * direction - self.p)
+        };
+        let (dpdx, dpdy) = match (
+            offset(diff.rx_origin, diff.rx_direction),
+            offset(diff.ry_origin, diff.ry_direction),
+        ) {
+            (Some(dpdx), Some(dpdy)) => (dpdx, dpdy),
+            _ => return,
+        };
+        let (uu, uv, vv) = (
+            self.dpdu.dot(self.dpdu),
+            self.dpdu.dot(self.dpdv),
+            self.dpdv.dot(self.dpdv),
+        );
+        let det = uu * vv - uv * uv;
+        if det.abs() < 1e-12 {
+            return;
+        }
+        let solve = |dp: Vec3| {
+            let (bu, bv) = (self.dpdu.dot(dp), self.dpdv.dot(dp));
+            let du = (vv * bu - uv * bv) / det;
+            let dv = (uu * bv - uv * bu) / det;
+            // Grazing angles give huge footprints; keep them finite.
+            let clamp = |x: f32| {
+                if x.is_finite() {
+                    x.clamp(-1e8, 1e8)
+                } else {
+                    0.0
+                }
+            };
+            (clamp(du), clamp(dv))
+        };
+        let (dudx, dvdx) = solve(dpdx);
+        let (dudy, dvdy) = solve(dpdy);
+        self.dudx = dudx;
+        self.dvdx = dvdx;
+        self.dudy = dudy;
+        self.dvdy = dvdy;
+    }
+}
+
+/// Where a ray enters a solid and where it leaves it again, both with
+/// outward normals.
+#[derive(Default, Clone)]
+pub struct Span {
+    pub enter: HitRecord,
+    pub exit: HitRecord,
+}
+
+/// A point picked on a shape by `Hitable::sample`.
+#[derive(Debug, Clone, Copy)]
+pub struct ShapeSample {
+    pub p: Point3,
+    /// Density of the direction towards `p`, with respect to solid angle
+    /// at the origin of the sample.
+    pub pdf: f32,
+}
+
+pub trait Hitable: Debug + Send + Sync {
     fn hit(&self, r: &Ray, t_min: f32, t_max: f32, rec: &mut HitRecord)
         -> bool;
     fn bounding_box(&self, t0: f32, t1: f32) -> Option<AABB>;
+
+    /// The material of a single shape. Aggregates have none.
+    fn material(&self) -> Option<usize> {
+        None
+    }
+
+    /// Picks a point on the shape as seen from `origin`, for sampling the
+    /// shape as a light. Shapes that cannot be sampled return `None`.
+    fn sample(
+        &self, _rng: &mut RNG, _origin: Point3, _time: f32,
+    ) -> Option<ShapeSample> {
+        None
+    }
+
+    /// The density with which `sample` picks the unit `direction` from
+    /// `origin`, with respect to solid angle. Zero if it never does.
+    fn pdf(&self, _origin: Point3, _direction: Vec3, _time: f32) -> f32 {
+        0.0
+    }
+
+    /// The fraction of light that gets through the media in the shape along
+    /// `r` between `t_min` and `t_max`. Shadow rays pass through media and
+    /// are attenuated by this instead. Surfaces let everything through
+    /// here; they stop shadow rays in `hit`.
+    fn transmittance(&self, _r: &Ray, _t_min: f32, _t_max: f32) -> f32 {
+        1.0
+    }
+
+    /// Appends the spans of the whole line through `r` that are inside the
+    /// shape, in order and without overlaps, for constructive solid
+    /// geometry. They can start at negative `t`. Shapes that do not enclose
+    /// a volume return false.
+    fn hit_all(&self, _r: &Ray, _spans: &mut Vec<Span>) -> bool {
+        false
+    }
 }
 
-#[derive(Default)]
+#[derive(Default, Clone)]
 pub struct Ray {
     pub origin: Point3,
     pub direction: Vec3,
     pub time: f32,
     pub max_depth: usize,
+    /// Whether the ray tests the visibility of a light. Media do not scatter
+    /// shadow rays; their `transmittance` is applied instead.
+    pub shadow: bool,
+    /// Rays through the neighbouring pixels, for filtering textures. Only
+    /// camera rays carry them; bounces do not.
+    pub differentials: Option<Differentials>,
+}
+
+/// The origins and directions of the rays one pixel over in x and in y
+/// from a camera ray.
+#[derive(Debug, Default, Clone, Copy)]
+pub struct Differentials {
+    pub rx_origin: Point3,
+    pub rx_direction: Vec3,
+    pub ry_origin: Point3,
+    pub ry_direction: Vec3,
 }
 
 impl Ray {
@@ -46,6 +191,15 @@ impl Ray {
             direction: direction.unit(),
             time,
             max_depth,
+            shadow: false,
+            differentials: None,
+        }
+    }
+
+    pub fn shadow(origin: Point3, direction: Vec3, time: f32) -> Self {
+        Ray {
+            shadow: true,
+            ..Ray::new(origin, direction, time, 0)
         }
     }
 
@@ -53,56 +207,162 @@ impl Ray {
         self.origin + t * self.direction
     }
 
+    /// Follows the path of this ray through `world` and returns the
+    /// radiance arriving along it.
+    ///
+    /// At every bounce off a material with non-delta lobes, one point on
+    /// `lights` is sampled and connected with a shadow ray. Emitters that
+    /// the path hits by chance are weighted against those light samples
+    /// with the power heuristic, so every light is counted once.
     pub fn trace(
-        &self, rng: &mut RNG, world: &dyn Hitable, matlib: &MaterialLibrary,
-        depth: usize,
+        &self, rng: &mut RNG, world: &dyn Hitable, lights: &HitableList,
+        matlib: &MaterialLibrary,
     ) -> Vec3 {
+        let mut radiance = Vec3::default();
+        let mut throughput = Vec3::new(1.0, 1.0, 1.0);
+        let mut ray = self.clone();
+        // Density of the material sample that produced `ray`. None for the
+        // camera ray and after delta lobes, which light sampling cannot
+        // reach.
+        let mut bsdf_pdf = None;
         let mut rec = HitRecord::default();
-        if world.hit(self, 0.001, f32::MAX, &mut rec) {
-            let mut scattered = Ray::default();
-            let mut attenuation = Vec3::default();
-            let mat = &matlib.lib[rec.material];
-            let emitted = mat.emitted(rec.u, rec.v, rec.p);
-            if depth < self.max_depth
-                && mat.scatter(
-                    rng,
-                    self,
-                    &mut rec,
-                    &mut attenuation,
-                    &mut scattered,
-                )
-            {
-                return emitted
-                    + attenuation
-                        * scattered.trace(rng, world, matlib, depth + 1);
+        while world.hit(&ray, 0.001, f32::MAX, &mut rec) {
+            rec.set_differentials(&ray);
+            let mat = &*matlib.lib[rec.material];
+            if mat.is_emissive() {
+                let emitted = mat.emitted(rec.u, rec.v, rec.p);
+                let weight = match bsdf_pdf {
+                    Some(pdf) if mat.is_light() => power_heuristic(
+                        pdf,
+                        lights.pdf(ray.origin, ray.direction, ray.time),
+                    ),
+                    _ => 1.0,
+                };
+                radiance += weight * throughput * emitted;
+            }
+            if ray.max_depth == 0 {
+                break;
+            }
+            if !mat.is_delta() && !lights.list.is_empty() {
+                radiance += throughput
+                    * sample_light(rng, world, lights, matlib, mat, &ray, &rec);
             }
-            return emitted;
+            let sample = match mat.sample(rng, &ray, &rec) {
+                Some(sample) if sample.pdf > 0.0 => sample,
+                _ => break,
+            };
+            bsdf_pdf = if sample.delta { None } else { Some(sample.pdf) };
+            throughput = throughput * (sample.f / sample.pdf);
+            ray =
+                Ray::new(rec.p, sample.direction, ray.time, ray.max_depth - 1);
         }
-        // let t = 0.5 * (self.direction.y() + 1.0);
-        // (1.0 - t) * Vec3::new(1.0, 1.0, 1.0) + t * Vec3::new(0.5, 0.7, 1.0)
+        radiance
+    }
+}
+
+/// Next-event estimation at `rec`: the radiance that `mat` reflects towards
+/// `ray`'s origin from one point sampled on `lights`, weighted against
+/// sampling the material.
+fn sample_light(
+    rng: &mut RNG, world: &dyn Hitable, lights: &HitableList,
+    matlib: &MaterialLibrary, mat: &dyn Material, ray: &Ray, rec: &HitRecord,
+) -> Vec3 {
+    let sample = match lights.sample(rng, rec.p, ray.time) {
+        Some(sample) if sample.pdf > 0.0 => sample,
+        _ => return Vec3::default(),
+    };
+    let to_light = sample.p - rec.p;
+    let distance = to_light.length();
+    if distance <= 0.0 {
+        return Vec3::default();
+    }
+    let direction = to_light / distance;
+    let bsdf_pdf = mat.pdf(ray, rec, direction);
+    if bsdf_pdf <= 0.0 {
         return Vec3::default();
     }
+    // The shadow ray has to reach the sampled point, not something in front
+    // of it. Whatever it hits there provides the emitted radiance.
+    let shadow = Ray::shadow(rec.p, direction, ray.time);
+    let mut light_rec = HitRecord::default();
+    if !world.hit(&shadow, 0.001, distance * 1.001, &mut light_rec)
+        || light_rec.t < distance * 0.999
+    {
+        return Vec3::default();
+    }
+    let transmittance = world.transmittance(&shadow, 0.001, light_rec.t);
+    if transmittance <= 0.0 {
+        return Vec3::default();
+    }
+    let light = &matlib.lib[light_rec.material];
+    let emitted = light.emitted(light_rec.u, light_rec.v, light_rec.p);
+    let weight = power_heuristic(sample.pdf, bsdf_pdf) / sample.pdf;
+    weight * transmittance * mat.eval(ray, rec, direction) * emitted
+}
+
+/// Two unit vectors that complete the unit vector `n` to an orthonormal
+/// basis (Duff et al., "Building an Orthonormal Basis, Revisited").
+pub fn orthonormal_basis(n: Vec3) -> (Vec3, Vec3) {
+    let sign = 1.0f32.copysign(n.z());
+    let a = -1.0 / (sign + n.z());
+    let b = n.x() * n.y() * a;
+    (
+        Vec3::new(1.0 + sign * n.x() * n.x() * a, sign * b, -sign * n.x()),
+        Vec3::new(b, sign + n.y() * n.y() * a, -n.y()),
+    )
+}
+
+/// Veach's power heuristic with an exponent of 2: the weight of a sample
+/// drawn with density `f` that could also have come from density `g`.
+pub fn power_heuristic(f: f32, g: f32) -> f32 {
+    let (f2, g2) = (f * f, g * g);
+    if f2 + g2 > 0.0 {
+        f2 / (f2 + g2)
+    } else {
+        0.0
+    }
 }
 
 pub struct RNG {
-    rng: Box<dyn ::rand::RngCore>,
+    rng: SmallRng,
 }
 
 impl RNG {
+    /// A generator for one of many independent streams, e.g. one per image
+    /// tile. The same `seed` and `stream` always give the same sequence.
+    pub fn new(seed: u64, stream: u64) -> Self {
+        RNG {
+            rng: SmallRng::seed_from_u64(
+                seed ^ stream.wrapping_mul(0x9e37_79b9_7f4a_7c15),
+            ),
+        }
+    }
+
+    /// A generator seeded with a hash of `words`. Hitables that make random
+    /// decisions seed one from the ray, so that testing the same ray again
+    /// gives the same answer.
+    pub fn hashed(words: &[u32]) -> Self {
+        // FNV-1a. seed_from_u64 scrambles the result further.
+        let mut hash: u64 = 0xcbf2_9ce4_8422_2325;
+        for &word in words {
+            hash = (hash ^ u64::from(word)).wrapping_mul(0x100_0000_01b3);
+        }
+        RNG {
+            rng: SmallRng::seed_from_u64(hash),
+        }
+    }
+
     pub fn rand(&mut self) -> f32 {
         self.rng.gen::<f32>()
         //::rand::random::<f32>()
     }
 
-    pub fn random_in_unit_sphere(&mut self) -> Vec3 {
-        loop {
-            let v = 2.0 * Vec3::new(self.rand(), self.rand(), self.rand())
-                - Vec3::new(1.0, 1.0, 1.0);
-            if v.dot(v) >= 1.0 {
-                return v;
-            }
-            // TODO: count misses
-        }
+    /// A uniformly distributed direction.
+    pub fn random_unit_vector(&mut self) -> Vec3 {
+        let z = 1.0 - 2.0 * self.rand();
+        let r = (1.0 - z * z).max(0.0).sqrt();
+        let phi = 2.0 * ::std::f32::consts::PI * self.rand();
+        Vec3::new(r * phi.cos(), r * phi.sin(), z)
     }
 
     pub fn random_in_unit_disk(&mut self) -> Vec3 {
@@ -120,12 +380,12 @@ impl RNG {
 impl Default for RNG {
     fn default() -> Self {
         RNG {
-            rng: Box::new(::rand::prelude::SmallRng::from_seed([
+            rng: SmallRng::from_seed([
                 4, 2, 3, 4, 5, 6, 7, 8, //br
                 9, 10, 11, 12, 13, 14, 15, 16, //br
                 17, 18, 19, 20, 21, 22, 23, 24, //br
                 25, 26, 27, 28, 29, 30, 31, 32,
-            ])),
+            ]),
         }
     }
 }
@@ -141,6 +401,14 @@ impl AABB {
         AABB { min, max }
     }
 
+    pub fn min(&self) -> Point3 {
+        self.min
+    }
+
+    pub fn max(&self) -> Point3 {
+        self.max
+    }
+
     pub fn surround(b0: AABB, b1: AABB) -> Self {
         AABB {
             min: b0.min.min(b1.min),
@@ -180,7 +448,7 @@ impl AABB {
 
 #[derive(Default, Clone, Debug)]
 pub struct HitableList {
-    pub list: Vec<Rc<dyn Hitable>>,
+    pub list: Vec<Arc<dyn Hitable>>,
 }
 
 impl HitableList {
@@ -198,6 +466,20 @@ impl HitableList {
         }
         (HitableList { list: left }, HitableList { list: right })
     }
+
+    /// The shapes whose material emits light.
+    pub fn emitters(&self, matlib: &MaterialLibrary) -> HitableList {
+        let list = self
+            .list
+            .iter()
+            .filter(|h| match h.material() {
+                Some(material) => matlib.lib[material].is_light(),
+                None => false,
+            })
+            .cloned()
+            .collect();
+        HitableList { list }
+    }
 }
 
 impl Hitable for HitableList {
@@ -234,16 +516,53 @@ impl Hitable for HitableList {
         }
         unreachable!()
     }
+
+    /// Picks one shape uniformly and samples it. The density is that of
+    /// the whole list, since other shapes may cover the same direction.
+    fn sample(
+        &self, rng: &mut RNG, origin: Point3, time: f32,
+    ) -> Option<ShapeSample> {
+        if self.list.is_empty() {
+            return None;
+        }
+        let n = self.list.len();
+        let i = cmp::min((rng.rand() * n as f32) as usize, n - 1);
+        let sample = self.list[i].sample(rng, origin, time)?;
+        let direction = (sample.p - origin).unit();
+        Some(ShapeSample {
+            p: sample.p,
+            pdf: self.pdf(origin, direction, time),
+        })
+    }
+
+    fn pdf(&self, origin: Point3, direction: Vec3, time: f32) -> f32 {
+        if self.list.is_empty() {
+            return 0.0;
+        }
+        let sum: f32 = self
+            .list
+            .iter()
+            .map(|h| h.pdf(origin, direction, time))
+            .sum();
+        sum / self.list.len() as f32
+    }
+
+    fn transmittance(&self, r: &Ray, t_min: f32, t_max: f32) -> f32 {
+        self.list
+            .iter()
+            .map(|h| h.transmittance(r, t_min, t_max))
+            .product()
+    }
 }
 
 #[derive(Debug)]
 pub struct BVH {
     bb: AABB,
-    left: Rc<dyn Hitable>,
-    right: Rc<dyn Hitable>,
+    left: Arc<dyn Hitable>,
+    right: Arc<dyn Hitable>,
 }
 
-fn box_x_compare(a: &Rc<dyn Hitable>, b: &Rc<dyn Hitable>) -> Ordering {
+fn box_x_compare(a: &Arc<dyn Hitable>, b: &Arc<dyn Hitable>) -> Ordering {
     if let Some(abox) = a.bounding_box(0.0, 0.0) {
         if let Some(bbox) = b.bounding_box(0.0, 0.0) {
             if (abox.min.x() - bbox.min.x()) < 0.0 {
@@ -255,7 +574,9 @@ fn box_x_compare(a: &Rc<dyn Hitable>, b: &Rc<dyn Hitable>) -> Ordering {
     unreachable!()
 }
 
-fn box_y_compare<'a, 'b>(a: &Rc<dyn Hitable>, b: &Rc<dyn Hitable>) -> Ordering {
+fn box_y_compare<'a, 'b>(
+    a: &Arc<dyn Hitable>, b: &Arc<dyn Hitable>,
+) -> Ordering {
     if let Some(abox) = a.bounding_box(0.0, 0.0) {
         if let Some(bbox) = b.bounding_box(0.0, 0.0) {
             if (abox.min.y() - bbox.min.y()) < 0.0 {
@@ -267,7 +588,7 @@ fn box_y_compare<'a, 'b>(a: &Rc<dyn Hitable>, b: &Rc<dyn Hitable>) -> Ordering {
     unreachable!()
 }
 
-fn box_z_compare(a: &Rc<dyn Hitable>, b: &Rc<dyn Hitable>) -> Ordering {
+fn box_z_compare(a: &Arc<dyn Hitable>, b: &Arc<dyn Hitable>) -> Ordering {
     if let Some(abox) = a.bounding_box(0.0, 0.0) {
         if let Some(bbox) = b.bounding_box(0.0, 0.0) {
             if (abox.min.z() - bbox.min.z()) < 0.0 {
@@ -305,8 +626,8 @@ impl BVH {
             _ => {
                 let index = list.list.len() / 2;
                 let (ll, lr) = list.partition(index);
-                left = Rc::new(BVH::new(ll, t0, t1, rng));
-                right = Rc::new(BVH::new(lr, t0, t1, rng));
+                left = Arc::new(BVH::new(ll, t0, t1, rng));
+                right = Arc::new(BVH::new(lr, t0, t1, rng));
             }
         };
         if let Some(abox) = left.bounding_box(t0, t1) {
@@ -353,6 +674,147 @@ impl Hitable for BVH {
     fn bounding_box(&self, _: f32, _: f32) -> Option<AABB> {
         Some(self.bb)
     }
+
+    fn transmittance(&self, r: &Ray, t_min: f32, t_max: f32) -> f32 {
+        if !self.bb.hit(r, t_min, t_max) {
+            return 1.0;
+        }
+        let left = self.left.transmittance(r, t_min, t_max);
+        // Nodes with a single child hold it on both sides.
+        if Arc::ptr_eq(&self.left, &self.right) {
+            return left;
+        }
+        left * self.right.transmittance(r, t_min, t_max)
+    }
+}
+
+/// Places `object`, defined in its own object space, into the world with an
+/// object to world `transform`. Rays are taken into object space and hits
+/// brought back, so many instances can share one mesh or BVH.
+#[derive(Debug)]
+pub struct TransformedHitable {
+    object: Arc<dyn Hitable>,
+    transform: Transform,
+}
+
+impl TransformedHitable {
+    pub fn new(object: Arc<dyn Hitable>, transform: Transform) -> Self {
+        TransformedHitable { object, transform }
+    }
+
+    /// Takes `r` into object space with a unit direction. Also returns the
+    /// object space units per world unit along the ray, which ray
+    /// parameters are scaled by.
+    fn object_ray(&self, r: &Ray) -> (Ray, f32) {
+        let inverse = self.transform.inverse_matrix();
+        let direction = inverse.vector(r.direction);
+        let scale = direction.length();
+        let ray = Ray {
+            origin: inverse.point(r.origin),
+            direction: direction / scale,
+            ..r.clone()
+        };
+        (ray, scale)
+    }
+
+    /// Takes a hit of the ray from `object_ray` back into world space.
+    fn world_record(&self, rec: &mut HitRecord, scale: f32) {
+        rec.t /= scale;
+        rec.p = self.transform.point(rec.p);
+        rec.normal = self.transform.normal(rec.normal).unit();
+        rec.dpdu = self.transform.vector(rec.dpdu);
+        rec.dpdv = self.transform.vector(rec.dpdv);
+    }
+
+    /// Maps the world direction `direction` into object space, along with
+    /// the factor that converts solid angle densities from object space to
+    /// the world: |det M^-1| / |M^-1 d|^3 for unit d.
+    fn object_direction(&self, direction: Vec3) -> (Vec3, f32) {
+        let inverse = self.transform.inverse_matrix();
+        let d = inverse.vector(direction);
+        let length = d.length();
+        let jacobian =
+            inverse.determinant3().abs() / (length * length * length);
+        (d / length, jacobian)
+    }
+}
+
+impl Hitable for TransformedHitable {
+    fn hit(
+        &self, r: &Ray, t_min: f32, t_max: f32, rec: &mut HitRecord,
+    ) -> bool {
+        let (ray, scale) = self.object_ray(r);
+        if !self.object.hit(&ray, t_min * scale, t_max * scale, rec) {
+            return false;
+        }
+        self.world_record(rec, scale);
+        true
+    }
+
+    fn bounding_box(&self, t0: f32, t1: f32) -> Option<AABB> {
+        let bb = self.object.bounding_box(t0, t1)?;
+        let corner = |i: usize| {
+            let pick = |bit: usize, axis: usize| {
+                if i & bit == 0 {
+                    bb.min[axis]
+                } else {
+                    bb.max[axis]
+                }
+            };
+            self.transform.point(Point3::new(
+                pick(1, 0),
+                pick(2, 1),
+                pick(4, 2),
+            ))
+        };
+        let first = corner(0);
+        let (min, max) = (1..8)
+            .map(corner)
+            .fold((first, first), |b, p| (b.0.min(p), b.1.max(p)));
+        Some(AABB::new(min, max))
+    }
+
+    fn material(&self) -> Option<usize> {
+        self.object.material()
+    }
+
+    fn sample(
+        &self, rng: &mut RNG, origin: Point3, time: f32,
+    ) -> Option<ShapeSample> {
+        let object_origin = self.transform.inverse_matrix().point(origin);
+        let sample = self.object.sample(rng, object_origin, time)?;
+        let p = self.transform.point(sample.p);
+        let (_, jacobian) = self.object_direction((p - origin).unit());
+        Some(ShapeSample {
+            p,
+            pdf: sample.pdf * jacobian,
+        })
+    }
+
+    fn pdf(&self, origin: Point3, direction: Vec3, time: f32) -> f32 {
+        let object_origin = self.transform.inverse_matrix().point(origin);
+        let (d, jacobian) = self.object_direction(direction);
+        self.object.pdf(object_origin, d, time) * jacobian
+    }
+
+    fn transmittance(&self, r: &Ray, t_min: f32, t_max: f32) -> f32 {
+        let (ray, scale) = self.object_ray(r);
+        self.object
+            .transmittance(&ray, t_min * scale, t_max * scale)
+    }
+
+    fn hit_all(&self, r: &Ray, spans: &mut Vec<Span>) -> bool {
+        let (ray, scale) = self.object_ray(r);
+        let start = spans.len();
+        if !self.object.hit_all(&ray, spans) {
+            return false;
+        }
+        for span in &mut spans[start..] {
+            self.world_record(&mut span.enter, scale);
+            self.world_record(&mut span.exit, scale);
+        }
+        true
+    }
 }
 
 pub struct Camera {
@@ -400,30 +862,90 @@ impl Camera {
         }
     }
 
-    pub fn get_ray(&self, rng: &mut RNG, u: f32, v: f32, depth: usize) -> Ray {
+    /// The ray through `(u, v)` on the film, with differentials through
+    /// `(u + pixel.0, v)` and `(u, v + pixel.1)` from the same point on the
+    /// lens.
+    pub fn get_ray(
+        &self, rng: &mut RNG, u: f32, v: f32, pixel: (f32, f32), depth: usize,
+    ) -> Ray {
         let rd = self.lens_radius * rng.random_in_unit_disk();
-        let offset = self.u * rd.x() + self.v * rd.y();
+        let origin = self.origin + self.u * rd.x() + self.v * rd.y();
         let t = self.t0 + rng.rand() * (self.t1 - self.t0);
-        Ray::new(
-            self.origin + offset,
+        let towards = |u: f32, v: f32| {
             self.lower_left_corner + u * self.horizontal + v * self.vertical
-                - self.origin
-                - offset,
-            t,
-            depth,
-        )
+                - origin
+        };
+        Ray {
+            differentials: Some(Differentials {
+                rx_origin: origin,
+                rx_direction: towards(u + pixel.0, v).unit(),
+                ry_origin: origin,
+                ry_direction: towards(u, v + pixel.1).unit(),
+            }),
+            ..Ray::new(origin, towards(u, v), t, depth)
+        }
     }
 }
 
-pub trait Material {
-    fn scatter(
-        &self, rng: &mut RNG, ray: &Ray, rec: &mut HitRecord,
-        attenuation: &mut Vec3, scattered: &mut Ray,
-    ) -> bool;
+/// A direction picked by `Material::sample`.
+#[derive(Debug, Clone, Copy)]
+pub struct BsdfSample {
+    /// The unit direction the path continues in.
+    pub direction: Vec3,
+    /// The BSDF times the cosine to the shading normal. For delta lobes
+    /// the delta function is left out, so `f / pdf` is still the path
+    /// weight.
+    pub f: Vec3,
+    /// Density of `direction` with respect to solid angle, or the
+    /// probability of choosing the lobe if it is a delta lobe.
+    pub pdf: f32,
+    /// Whether the direction came from a delta lobe, like a perfect mirror
+    /// or smooth glass. Such directions cannot be found by light sampling.
+    pub delta: bool,
+}
+
+/// The surface scattering of a shape. Directions passed in and returned
+/// all point away from the hit point; `ray` is the ray that hit it.
+pub trait Material: Send + Sync {
+    /// Picks a direction to continue the path in, or `None` if the path
+    /// ends here.
+    fn sample(
+        &self, rng: &mut RNG, ray: &Ray, rec: &HitRecord,
+    ) -> Option<BsdfSample>;
+
+    /// The BSDF times the cosine for scattering `ray` into `direction`.
+    /// Delta lobes do not contribute.
+    fn eval(&self, _ray: &Ray, _rec: &HitRecord, _direction: Vec3) -> Vec3 {
+        Vec3::default()
+    }
+
+    /// The density with which `sample` picks `direction`, with respect to
+    /// solid angle. Delta lobes do not contribute.
+    fn pdf(&self, _ray: &Ray, _rec: &HitRecord, _direction: Vec3) -> f32 {
+        0.0
+    }
+
+    /// Whether all lobes are delta lobes, so `eval` and `pdf` are always
+    /// zero and light sampling would be wasted.
+    fn is_delta(&self) -> bool {
+        false
+    }
 
     fn emitted(&self, _u: f32, _v: f32, _p: Point3) -> Vec3 {
         Vec3::default()
     }
+
+    /// Whether `emitted` can be non-zero.
+    fn is_emissive(&self) -> bool {
+        false
+    }
+
+    /// Whether shapes made of this material are sampled as lights. Light
+    /// that paths only find by chance, like the glow of a medium, is
+    /// counted in full when they do.
+    fn is_light(&self) -> bool {
+        self.is_emissive()
+    }
 }
 
 #[derive(Default)]
@@ -431,6 +953,70 @@ pub struct MaterialLibrary {
     pub lib: Vec<Box<dyn Material>>,
 }
 
-pub trait Texture: Debug {
+pub trait Texture: Debug + Send + Sync {
     fn value(&self, u: f32, v: f32, p: Point3) -> Vec3;
+
+    /// The value at `rec`, averaged over the footprint of a pixel that the
+    /// derivatives `rec.dudx` to `rec.dvdy` describe. Materials look up
+    /// their textures with this. Textures without detail finer than a
+    /// pixel need not filter.
+    fn filtered(&self, rec: &HitRecord) -> Vec3 {
+        self.value(rec.u, rec.v, rec.p)
+    }
+}
+
+#[cfg(test)]
+pub mod tests {
+    use super::*;
+    use ::std::assert;
+
+    use crate::shapes::Sphere;
+
+    #[test]
+    fn test_transformed_hitable() {
+        let sphere = Arc::new(Sphere::new(Point3::default(), 1.0, 0));
+        let t = Transform::translate(Vec3::new(0.0, 0.0, -4.0))
+            * Transform::rotate(90.0, Vec3::new(0.0, 0.0, 1.0))
+            * Transform::scale(2.0, 1.0, 1.0);
+        let ellipsoid = TransformedHitable::new(sphere, t);
+
+        let bb = ellipsoid.bounding_box(0.0, 0.0).unwrap();
+        let expected_min = Point3::new(-1.0, -2.0, -5.0);
+        let expected_max = Point3::new(1.0, 2.0, -3.0);
+        assert!((bb.min() - expected_min).length() < 1e-5);
+        assert!((bb.max() - expected_max).length() < 1e-5);
+
+        // x^2 + y^2 / 4 + (z + 4)^2 = 1 at y = 1.
+        let origin = Point3::new(0.0, 1.0, 0.0);
+        let r = Ray::new(origin, Vec3::new(0.0, 0.0, -1.0), 0.0, 0);
+        let mut rec = HitRecord::default();
+        assert!(ellipsoid.hit(&r, 0.001, f32::MAX, &mut rec));
+        let dz = 0.75f32.sqrt();
+        assert!((rec.t - (4.0 - dz)).abs() < 1e-4);
+        assert!((rec.p - Point3::new(0.0, 1.0, dz - 4.0)).length() < 1e-4);
+        // The gradient (2x, y / 2, 2 (z + 4)).
+        let normal = Vec3::new(0.0, 0.5, 2.0 * dz).unit();
+        assert!((rec.normal - normal).length() < 1e-4);
+
+        // Averaging 1/pdf of light samples gives the solid angle, which is
+        // also the fraction of uniform directions that hit.
+        let origin = Point3::default();
+        let mut rng = RNG::default();
+        let n = 20000;
+        let (mut inverse_pdfs, mut hits) = (0.0, 0);
+        for _ in 0..n {
+            let s = ellipsoid.sample(&mut rng, origin, 0.0).unwrap();
+            let direction = (s.p - origin).unit();
+            let pdf = ellipsoid.pdf(origin, direction, 0.0);
+            assert!((pdf - s.pdf).abs() < 1e-3 * s.pdf);
+            inverse_pdfs += 1.0 / s.pdf;
+            let r = Ray::new(origin, rng.random_unit_vector(), 0.0, 0);
+            if ellipsoid.hit(&r, 0.001, f32::MAX, &mut rec) {
+                hits += 1;
+            }
+        }
+        let estimate = inverse_pdfs / n as f32;
+        let expected = 4.0 * ::std::f32::consts::PI * hits as f32 / n as f32;
+        assert!((estimate - expected).abs() < 0.1 * expected);
+    }
 }
@@ -0,0 +1,2085 @@
+//! Reader for pbrt-v3 scene description files.
+//!
+//! Only the part of the format that maps onto this renderer is understood.
+//! Everything else is reported as a warning and skipped, so standard test
+//! scenes still render approximately.
+//!
+//! pbrt's world space is left-handed. All positions are mirrored along x on
+//! the way in, which keeps rendered images the right way round.
+
+use ::std::boxed::Box;
+use ::std::clone::Clone;
+use ::std::cmp;
+use ::std::collections::HashMap;
+use ::std::convert::From;
+use ::std::default::Default;
+use ::std::fmt::{self, Display, Formatter};
+use ::std::fs;
+use ::std::io;
+use ::std::iter::{Extend, Iterator};
+use ::std::option::Option::{self, None, Some};
+use ::std::path::{Path, PathBuf};
+use ::std::rc::Rc;
+use ::std::result::Result::{self, Err, Ok};
+use ::std::str::FromStr;
+use ::std::string::{String, ToString};
+use ::std::sync::Arc;
+use ::std::vec::Vec;
+use ::std::{eprintln, format, write};
+
+use ::math::{Matrix4, Point3, Transform, Vec3};
+
+use crate::curve::{Curve, CurveKind};
+use crate::film::FilterDesc;
+use crate::hair::{Hair, HairAbsorption};
+use crate::heightfield::Heightfield;
+use crate::layered::Mix;
+use crate::material::{
+    conductor_preset, Conductor, Dielectric, DiffuseLight, Lambertian, Metal,
+};
+use crate::mesh::TriangleMesh;
+use crate::microfacet::TrowbridgeReitz;
+use crate::pbrt::{
+    Camera, Hitable, HitableList, Material, MaterialLibrary, Texture,
+    TransformedHitable, BVH, RNG,
+};
+use crate::principled::Principled;
+use crate::quadric::Quadric;
+use crate::scene::{RenderSettings, Scene};
+use crate::shapes::Sphere;
+use crate::texture::{
+    CheckerTexture, ConstTexture, FilterMode, ImageTexture, WrapMode,
+};
+
+#[derive(Debug)]
+pub enum PbrtError {
+    Io {
+        path: PathBuf,
+        error: io::Error,
+    },
+    Parse {
+        path: PathBuf,
+        line: usize,
+        message: String,
+    },
+}
+
+impl Display for PbrtError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        match self {
+            PbrtError::Io { path, error } => {
+                write!(f, "{}: {}", path.display(), error)
+            }
+            PbrtError::Parse {
+                path,
+                line,
+                message,
+            } => write!(f, "{}:{}: {}", path.display(), line, message),
+        }
+    }
+}
+
+pub fn load_pbrt(path: &Path, rng: &mut RNG) -> Result<Scene, PbrtError> {
+    let src = fs::read_to_string(path).map_err(|error| PbrtError::Io {
+        path: path.to_path_buf(),
+        error,
+    })?;
+    parse_pbrt(&src, path, rng)
+}
+
+/// Parses pbrt source. `path` is used for messages and to resolve `Include`.
+pub fn parse_pbrt(
+    src: &str, path: &Path, rng: &mut RNG,
+) -> Result<Scene, PbrtError> {
+    let mut parser = Parser::default();
+    parser.lexers.push(Lexer::new(path, src));
+    parser.run()?;
+    parser.finish(rng)
+}
+
+/// Converts pbrt's left-handed world into our right-handed one.
+fn mirror_x() -> Matrix4 {
+    *Transform::scale(-1.0, 1.0, 1.0).matrix()
+}
+
+fn column_major(v: &[f32]) -> Matrix4 {
+    let mut m = [0.0; 16];
+    m.copy_from_slice(v);
+    Matrix4::from_column_major(&m)
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Token {
+    Ident(String),
+    Str(String),
+    Num(f32),
+    Open,
+    Close,
+}
+
+#[derive(Debug, Clone)]
+struct Location {
+    path: Rc<PathBuf>,
+    line: usize,
+}
+
+struct Lexer {
+    path: Rc<PathBuf>,
+    src: Vec<u8>,
+    pos: usize,
+    line: usize,
+}
+
+impl Lexer {
+    fn new(path: &Path, src: &str) -> Self {
+        Lexer {
+            path: Rc::new(path.to_path_buf()),
+            src: src.as_bytes().to_vec(),
+            pos: 0,
+            line: 1,
+        }
+    }
+
+    fn location(&self) -> Location {
+        Location {
+            path: self.path.clone(),
+            line: self.line,
+        }
+    }
+
+    fn next(&mut self) -> Result<Option<(Token, Location)>, PbrtError> {
+        // Skip whitespace and comments.
+        while self.pos < self.src.len() {
+            match self.src[self.pos] {
+                b'\n' => self.line += 1,
+                b'#' => {
+                    while self.pos < self.src.len()
+                        && self.src[self.pos] != b'\n'
+                    {
+                        self.pos += 1;
+                    }
+                    continue;
+                }
+                c if c.is_ascii_whitespace() => {}
+                _ => break,
+            }
+            self.pos += 1;
+        }
+        if self.pos == self.src.len() {
+            return Ok(None);
+        }
+        let loc = self.location();
+        let start = self.pos;
+        let token = match self.src[self.pos] {
+            b'[' => {
+                self.pos += 1;
+                Token::Open
+            }
+            b']' => {
+                self.pos += 1;
+                Token::Close
+            }
+            b'"' => {
+                self.pos += 1;
+                while self.pos < self.src.len() && self.src[self.pos] != b'"' {
+                    if self.src[self.pos] == b'\n' {
+                        return Err(parse_error(
+                            &loc,
+                            "unterminated string".to_string(),
+                        ));
+                    }
+                    self.pos += 1;
+                }
+                if self.pos == self.src.len() {
+                    return Err(parse_error(
+                        &loc,
+                        "unterminated string".to_string(),
+                    ));
+                }
+                self.pos += 1;
+                Token::Str(self.text(start + 1, self.pos - 1))
+            }
+            _ => {
+                while self.pos < self.src.len()
+                    && !self.src[self.pos].is_ascii_whitespace()
+                    && !b"[]\"#".contains(&self.src[self.pos])
+                {
+                    self.pos += 1;
+                }
+                let text = self.text(start, self.pos);
+                let first = text.as_bytes()[0];
+                if first.is_ascii_digit() || b"+-.".contains(&first) {
+                    let n = f32::from_str(&text).map_err(|_| {
+                        parse_error(
+                            &loc,
+                            format!("expected number, found '{}'", text),
+                        )
+                    })?;
+                    Token::Num(n)
+                } else {
+                    Token::Ident(text)
+                }
+            }
+        };
+        Ok(Some((token, loc)))
+    }
+
+    fn text(&self, start: usize, end: usize) -> String {
+        String::from_utf8_lossy(&self.src[start..end]).into_owned()
+    }
+}
+
+fn parse_error(loc: &Location, message: String) -> PbrtError {
+    PbrtError::Parse {
+        path: loc.path.to_path_buf(),
+        line: loc.line,
+        message,
+    }
+}
+
+/// An `imagemap` texture, or grey if its image cannot be read. pbrt's
+/// `black` wrap mode clamps instead, and `trilinear` picks trilinear over
+/// EWA filtering.
+fn imagemap(params: &ParamSet, loc: &Location) -> Arc<dyn Texture> {
+    let grey = || -> Arc<dyn Texture> {
+        Arc::new(ConstTexture(Vec3::new(0.5, 0.5, 0.5)))
+    };
+    let name = match params.string("filename") {
+        Some(name) => name,
+        None => {
+            warn(loc, "'imagemap' texture needs a filename, rendered grey");
+            return grey();
+        }
+    };
+    let path = match loc.path.parent() {
+        Some(dir) => dir.join(name),
+        None => PathBuf::from(name),
+    };
+    let wrap = match params.string("wrap").unwrap_or("repeat") {
+        "repeat" => WrapMode::Repeat,
+        "clamp" => WrapMode::Clamp,
+        wrap => {
+            warn(loc, &format!("wrap mode '{}' clamps", wrap));
+            WrapMode::Clamp
+        }
+    };
+    let filter = if params.bool("trilinear", false) {
+        FilterMode::Trilinear
+    } else {
+        FilterMode::Ewa
+    };
+    if params.float("scale", 1.0) != 1.0 {
+        warn(loc, "'imagemap' texture scale is ignored");
+    }
+    let srgb = params.has("gamma").then(|| params.bool("gamma", true));
+    match ImageTexture::read(&path, srgb, wrap, filter) {
+        Ok(mut texture) => {
+            texture.scale =
+                (params.float("uscale", 1.0), params.float("vscale", 1.0));
+            texture.offset =
+                (params.float("udelta", 0.0), params.float("vdelta", 0.0));
+            Arc::new(texture)
+        }
+        Err(e) => {
+            warn(loc, &format!("{}, rendered grey", e));
+            grey()
+        }
+    }
+}
+
+fn warn(loc: &Location, message: &str) {
+    eprintln!("{}:{}: warning: {}", loc.path.display(), loc.line, message);
+}
+
+struct Param {
+    ty: String,
+    name: String,
+    numbers: Vec<f32>,
+    strings: Vec<String>,
+    loc: Location,
+}
+
+#[derive(Default)]
+struct ParamSet {
+    params: Vec<Param>,
+}
+
+impl ParamSet {
+    fn find(&self, types: &[&str], name: &str) -> Option<&Param> {
+        self.params
+            .iter()
+            .find(|p| p.name == name && types.contains(&p.ty.as_str()))
+    }
+
+    fn has(&self, name: &str) -> bool {
+        self.params.iter().any(|p| p.name == name)
+    }
+
+    fn floats(&self, name: &str) -> Option<&[f32]> {
+        self.find(&["float"], name).map(|p| &p.numbers[..])
+    }
+
+    fn float(&self, name: &str, default: f32) -> f32 {
+        self.floats(name).map_or(default, |v| v[0])
+    }
+
+    fn integers(&self, name: &str) -> Option<&[f32]> {
+        self.find(&["integer"], name).map(|p| &p.numbers[..])
+    }
+
+    fn integer(&self, name: &str, default: usize) -> usize {
+        self.integers(name).map_or(default, |v| v[0] as usize)
+    }
+
+    fn string(&self, name: &str) -> Option<&str> {
+        self.find(&["string", "texture"], name)
+            .map(|p| p.strings[0].as_str())
+    }
+
+    fn bool(&self, name: &str, default: bool) -> bool {
+        self.find(&["bool"], name)
+            .map_or(default, |p| p.strings[0] == "true")
+    }
+
+    fn points(&self, types: &[&str], name: &str) -> Option<Vec<Vec3>> {
+        self.find(types, name).map(|p| {
+            p.numbers
+                .chunks(3)
+                .map(|c| Vec3::new(c[0], c[1], c[2]))
+                .collect()
+        })
+    }
+
+    /// RGB values directly, sampled spectra as their average. Named and
+    /// file spectra are not supported.
+    fn spectrum(&self, name: &str) -> Option<Vec3> {
+        let p = self.find(&["rgb", "color", "spectrum", "blackbody"], name)?;
+        match p.ty.as_str() {
+            "rgb" | "color" => {
+                Some(Vec3::new(p.numbers[0], p.numbers[1], p.numbers[2]))
+            }
+            "spectrum" if !p.numbers.is_empty() => {
+                let values = p.numbers.iter().skip(1).step_by(2);
+                let n = (p.numbers.len() / 2) as f32;
+                let avg = values.sum::<f32>() / n;
+                Some(Vec3::new(avg, avg, avg))
+            }
+            _ => {
+                warn(
+                    &p.loc,
+                    &format!(
+                        "'{} {}' is not supported, using white",
+                        p.ty, p.name
+                    ),
+                );
+                Some(Vec3::new(1.0, 1.0, 1.0))
+            }
+        }
+    }
+}
+
+#[derive(Clone)]
+struct GraphicsState {
+    ctm: Matrix4,
+    reverse_orientation: bool,
+    material: Option<usize>,
+    area_light: Option<usize>,
+}
+
+impl Default for GraphicsState {
+    fn default() -> Self {
+        GraphicsState {
+            ctm: Matrix4::identity(),
+            reverse_orientation: false,
+            material: None,
+            area_light: None,
+        }
+    }
+}
+
+struct CameraDesc {
+    world_to_camera: Matrix4,
+    params: ParamSet,
+    loc: Location,
+}
+
+/// pbrt's filters have separate x and y widths (really radii). Only the x
+/// radius is used.
+/// pbrt-v3's mapping from its perceptual roughness to the width of the
+/// microfacet distribution.
+fn roughness_to_alpha(roughness: f32) -> f32 {
+    let x = roughness.max(1e-3).ln();
+    1.62142
+        + 0.819955 * x
+        + 0.1734 * x * x
+        + 0.0171201 * x * x * x
+        + 0.000640711 * x * x * x * x
+}
+
+/// The microfacet distribution for pbrt's roughness parameters, which are
+/// remapped unless `remaproughness` is off. Zero roughness is smooth.
+fn distribution(params: &ParamSet, default: f32) -> TrowbridgeReitz {
+    let roughness = params.float("roughness", default);
+    let u = params.float("uroughness", roughness);
+    let v = params.float("vroughness", roughness);
+    if u == 0.0 && v == 0.0 {
+        TrowbridgeReitz::new(0.0, 0.0)
+    } else if params.bool("remaproughness", true) {
+        TrowbridgeReitz::new(roughness_to_alpha(u), roughness_to_alpha(v))
+    } else {
+        TrowbridgeReitz::new(u, v)
+    }
+}
+
+fn pixel_filter(ty: &str, params: &ParamSet, loc: &Location) -> FilterDesc {
+    let radius = |default| {
+        let x = params.float("xwidth", default);
+        if params.float("ywidth", x) != x {
+            warn(loc, "anisotropic filters are not supported, using xwidth");
+        }
+        x
+    };
+    match ty {
+        "box" => FilterDesc::Box {
+            radius: radius(0.5),
+        },
+        "triangle" => FilterDesc::Tent {
+            radius: radius(2.0),
+        },
+        "gaussian" => FilterDesc::Gaussian {
+            radius: radius(2.0),
+            alpha: params.float("alpha", 2.0),
+        },
+        "mitchell" => FilterDesc::Mitchell {
+            radius: radius(2.0),
+            b: params.float("B", 1.0 / 3.0),
+            c: params.float("C", 1.0 / 3.0),
+        },
+        "sinc" => FilterDesc::Lanczos {
+            radius: radius(4.0),
+            tau: params.float("tau", 3.0),
+        },
+        _ => {
+            warn(loc, &format!("unknown filter '{}', using box", ty));
+            FilterDesc::default()
+        }
+    }
+}
+
+#[derive(Default)]
+struct Parser {
+    lexers: Vec<Lexer>,
+    peeked: Option<(Token, Location)>,
+    last: Option<Location>,
+    state: GraphicsState,
+    attribute_stack: Vec<GraphicsState>,
+    transform_stack: Vec<Matrix4>,
+    named_coordinate_systems: HashMap<String, Matrix4>,
+    textures: HashMap<String, Arc<dyn Texture>>,
+    /// Float textures, kept as grays.
+    float_textures: HashMap<String, Arc<dyn Texture>>,
+    named_materials: HashMap<String, usize>,
+    /// How named materials were made, to make them again as parts of
+    /// others.
+    named_material_params: HashMap<String, (String, ParamSet)>,
+    default_material: Option<usize>,
+    camera: Option<CameraDesc>,
+    film: ParamSet,
+    sampler: ParamSet,
+    integrator: ParamSet,
+    filter: FilterDesc,
+    hitables: HitableList,
+    matlib: MaterialLibrary,
+    /// The object being defined between ObjectBegin and ObjectEnd, with
+    /// the shapes outside of it.
+    object: Option<(String, HitableList)>,
+    objects: HashMap<String, Arc<dyn Hitable>>,
+}
+
+impl Parser {
+    fn peek(&mut self) -> Result<Option<&(Token, Location)>, PbrtError> {
+        while self.peeked.is_none() {
+            let lexer = match self.lexers.last_mut() {
+                Some(lexer) => lexer,
+                None => return Ok(None),
+            };
+            match lexer.next()? {
+                Some(next) => self.peeked = Some(next),
+                None => {
+                    self.lexers.pop();
+                }
+            }
+        }
+        Ok(self.peeked.as_ref())
+    }
+
+    fn next(&mut self) -> Result<Option<(Token, Location)>, PbrtError> {
+        self.peek()?;
+        if let Some((_, loc)) = &self.peeked {
+            self.last = Some(loc.clone());
+        }
+        Ok(self.peeked.take())
+    }
+
+    fn expect_string(&mut self, loc: &Location) -> Result<String, PbrtError> {
+        match self.next()? {
+            Some((Token::Str(s), _)) => Ok(s),
+            Some((t, loc)) => Err(parse_error(
+                &loc,
+                format!("expected string, found {:?}", t),
+            )),
+            None => Err(parse_error(loc, "unexpected end of file".to_string())),
+        }
+    }
+
+    /// Reads `n` numbers, optionally enclosed in brackets.
+    fn numbers(
+        &mut self, n: usize, loc: &Location,
+    ) -> Result<Vec<f32>, PbrtError> {
+        let bracketed = matches_token(self.peek()?, &Token::Open);
+        if bracketed {
+            self.next()?;
+        }
+        let mut values = Vec::with_capacity(n);
+        for _ in 0..n {
+            match self.next()? {
+                Some((Token::Num(v), _)) => values.push(v),
+                Some((t, loc)) => {
+                    return Err(parse_error(
+                        &loc,
+                        format!("expected number, found {:?}", t),
+                    ))
+                }
+                None => {
+                    return Err(parse_error(
+                        loc,
+                        "unexpected end of file".to_string(),
+                    ))
+                }
+            }
+        }
+        if bracketed {
+            match self.next()? {
+                Some((Token::Close, _)) => {}
+                _ => {
+                    return Err(parse_error(
+                        loc,
+                        format!("expected {} numbers in brackets", n),
+                    ))
+                }
+            }
+        }
+        Ok(values)
+    }
+
+    fn params(&mut self) -> Result<ParamSet, PbrtError> {
+        let mut set = ParamSet::default();
+        while let Some((Token::Str(_), _)) = self.peek()? {
+            let (decl, loc) = match self.next()? {
+                Some((Token::Str(decl), loc)) => (decl, loc),
+                _ => ::std::unreachable!(),
+            };
+            let words: Vec<&str> = decl.split_whitespace().collect();
+            if words.len() != 2 {
+                return Err(parse_error(
+                    &loc,
+                    format!("bad parameter declaration '{}'", decl),
+                ));
+            }
+            let mut values = Vec::new();
+            match self.next()? {
+                Some((Token::Open, _)) => loop {
+                    match self.next()? {
+                        Some((Token::Close, _)) => break,
+                        Some((t @ Token::Num(_), _))
+                        | Some((t @ Token::Str(_), _))
+                        | Some((t @ Token::Ident(_), _)) => values.push(t),
+                        _ => {
+                            return Err(parse_error(
+                                &loc,
+                                format!("unterminated values for '{}'", decl),
+                            ))
+                        }
+                    }
+                },
+                Some((t @ Token::Num(_), _)) | Some((t @ Token::Str(_), _)) => {
+                    values.push(t)
+                }
+                _ => {
+                    return Err(parse_error(
+                        &loc,
+                        format!("missing value for '{}'", decl),
+                    ))
+                }
+            }
+            set.params
+                .push(make_param(words[0], words[1], values, loc)?);
+        }
+        Ok(set)
+    }
+
+    /// Skips the arguments of a directive we don't support.
+    fn skip_args(&mut self) -> Result<(), PbrtError> {
+        while let Some((token, _)) = self.peek()? {
+            if let Token::Ident(_) = token {
+                break;
+            }
+            self.next()?;
+        }
+        Ok(())
+    }
+
+    fn run(&mut self) -> Result<(), PbrtError> {
+        while let Some((token, loc)) = self.next()? {
+            match token {
+                Token::Ident(directive) => self.directive(&directive, loc)?,
+                t => {
+                    return Err(parse_error(
+                        &loc,
+                        format!("expected directive, found {:?}", t),
+                    ))
+                }
+            }
+        }
+        Ok(())
+    }
+
+    fn concat(&mut self, m: &Matrix4) {
+        self.state.ctm = self.state.ctm * *m;
+    }
+
+    fn directive(
+        &mut self, directive: &str, loc: Location,
+    ) -> Result<(), PbrtError> {
+        match directive {
+            "Identity" => self.state.ctm = Matrix4::identity(),
+            "Translate" => {
+                let v = self.numbers(3, &loc)?;
+                let d = Vec3::new(v[0], v[1], v[2]);
+                self.concat(Transform::translate(d).matrix());
+            }
+            "Scale" => {
+                let v = self.numbers(3, &loc)?;
+                self.concat(Transform::scale(v[0], v[1], v[2]).matrix());
+            }
+            "Rotate" => {
+                let v = self.numbers(4, &loc)?;
+                let axis = Vec3::new(v[1], v[2], v[3]);
+                if axis.length() == 0.0 {
+                    return Err(parse_error(
+                        &loc,
+                        "zero rotation axis".to_string(),
+                    ));
+                }
+                self.concat(Transform::rotate(v[0], axis).matrix());
+            }
+            "LookAt" => {
+                let v = self.numbers(9, &loc)?;
+                let t = Transform::look_at(
+                    Point3::new(v[0], v[1], v[2]),
+                    Point3::new(v[3], v[4], v[5]),
+                    Vec3::new(v[6], v[7], v[8]),
+                )
+                .ok_or_else(|| {
+                    parse_error(&loc, "degenerate LookAt".to_string())
+                })?;
+                self.concat(t.matrix());
+            }
+            "Transform" => {
+                let v = self.numbers(16, &loc)?;
+                self.state.ctm = column_major(&v);
+            }
+            "ConcatTransform" => {
+                let v = self.numbers(16, &loc)?;
+                self.concat(&column_major(&v));
+            }
+            "CoordinateSystem" => {
+                let name = self.expect_string(&loc)?;
+                self.named_coordinate_systems.insert(name, self.state.ctm);
+            }
+            "CoordSysTransform" => {
+                let name = self.expect_string(&loc)?;
+                match self.named_coordinate_systems.get(&name) {
+                    Some(&m) => self.state.ctm = m,
+                    None => warn(
+                        &loc,
+                        &format!("unknown coordinate system '{}'", name),
+                    ),
+                }
+            }
+            "ReverseOrientation" => {
+                self.state.reverse_orientation = !self.state.reverse_orientation
+            }
+            "Camera" => {
+                let ty = self.expect_string(&loc)?;
+                let params = self.params()?;
+                if ty != "perspective" {
+                    warn(
+                        &loc,
+                        &format!("'{}' camera rendered as perspective", ty),
+                    );
+                }
+                let world_to_camera = self.state.ctm;
+                if let Some(camera_to_world) = world_to_camera.inverse() {
+                    self.named_coordinate_systems
+                        .insert("camera".to_string(), camera_to_world);
+                }
+                self.camera = Some(CameraDesc {
+                    world_to_camera,
+                    params,
+                    loc,
+                });
+            }
+            "Film" | "Sampler" | "Integrator" => {
+                let ty = self.expect_string(&loc)?;
+                let params = self.params()?;
+                match directive {
+                    "Film" => self.film = params,
+                    "Sampler" => self.sampler = params,
+                    _ => {
+                        if ty != "path" {
+                            warn(
+                                &loc,
+                                &format!(
+                                    "'{}' integrator rendered as path",
+                                    ty
+                                ),
+                            );
+                        }
+                        self.integrator = params;
+                    }
+                }
+            }
+            "PixelFilter" => {
+                let ty = self.expect_string(&loc)?;
+                let params = self.params()?;
+                self.filter = pixel_filter(&ty, &params, &loc);
+            }
+            "Accelerator" => {
+                self.expect_string(&loc)?;
+                self.params()?;
+            }
+            "WorldBegin" => {
+                self.state.ctm = Matrix4::identity();
+                self.named_coordinate_systems
+                    .insert("world".to_string(), Matrix4::identity());
+            }
+            "WorldEnd" => {}
+            "AttributeBegin" => {
+                self.attribute_stack.push(self.state.clone());
+            }
+            "AttributeEnd" => match self.attribute_stack.pop() {
+                Some(state) => self.state = state,
+                None => warn(&loc, "unmatched AttributeEnd"),
+            },
+            "TransformBegin" => self.transform_stack.push(self.state.ctm),
+            "TransformEnd" => match self.transform_stack.pop() {
+                Some(ctm) => self.state.ctm = ctm,
+                None => warn(&loc, "unmatched TransformEnd"),
+            },
+            "Texture" => {
+                let name = self.expect_string(&loc)?;
+                let ty = self.expect_string(&loc)?;
+                let class = self.expect_string(&loc)?;
+                let params = self.params()?;
+                self.texture(name, &ty, &class, &params, &loc);
+            }
+            "Material" => {
+                let ty = self.expect_string(&loc)?;
+                let params = self.params()?;
+                self.state.material =
+                    self.material(&ty, &params, &loc).or(self.state.material);
+            }
+            "MakeNamedMaterial" => {
+                let name = self.expect_string(&loc)?;
+                let params = self.params()?;
+                let ty = params.string("type").unwrap_or("").to_string();
+                if let Some(m) = self.material(&ty, &params, &loc) {
+                    self.named_materials.insert(name.clone(), m);
+                    self.named_material_params.insert(name, (ty, params));
+                }
+            }
+            "NamedMaterial" => {
+                let name = self.expect_string(&loc)?;
+                match self.named_materials.get(&name) {
+                    Some(&m) => self.state.material = Some(m),
+                    None => warn(&loc, &format!("unknown material '{}'", name)),
+                }
+            }
+            "AreaLightSource" => {
+                let ty = self.expect_string(&loc)?;
+                let params = self.params()?;
+                if ty != "diffuse" {
+                    warn(&loc, &format!("'{}' area light as diffuse", ty));
+                }
+                let l =
+                    params.spectrum("L").unwrap_or(Vec3::new(1.0, 1.0, 1.0));
+                self.matlib.lib.push(Box::new(DiffuseLight {
+                    emit: Arc::new(ConstTexture(l)),
+                }));
+                self.state.area_light = Some(self.matlib.lib.len() - 1);
+            }
+            "LightSource" => {
+                let ty = self.expect_string(&loc)?;
+                let params = self.params()?;
+                self.light_source(&ty, &params, &loc);
+            }
+            "Shape" => {
+                let ty = self.expect_string(&loc)?;
+                let params = self.params()?;
+                self.shape(&ty, &params, &loc)?;
+            }
+            "Include" => {
+                let name = self.expect_string(&loc)?;
+                let path = match loc.path.parent() {
+                    Some(dir) => dir.join(&name),
+                    None => PathBuf::from(&name),
+                };
+                let src = fs::read_to_string(&path).map_err(|error| {
+                    PbrtError::Io {
+                        path: path.clone(),
+                        error,
+                    }
+                })?;
+                // The lexers are the files still open, so a path among them
+                // would include itself forever.
+                let canonical =
+                    |p: &Path| fs::canonicalize(p).unwrap_or(p.to_path_buf());
+                let file = canonical(&path);
+                if self.lexers.iter().any(|l| canonical(&l.path) == file) {
+                    return Err(parse_error(
+                        &loc,
+                        format!("'{}' includes itself", name),
+                    ));
+                }
+                self.lexers.push(Lexer::new(&path, &src));
+            }
+            "ObjectBegin" => {
+                let name = self.expect_string(&loc)?;
+                if self.object.is_some() {
+                    return Err(parse_error(
+                        &loc,
+                        "ObjectBegin inside an object definition".to_string(),
+                    ));
+                }
+                self.attribute_stack.push(self.state.clone());
+                let outer = ::std::mem::take(&mut self.hitables);
+                self.object = Some((name, outer));
+            }
+            "ObjectEnd" => self.end_object(&loc),
+            "ObjectInstance" => {
+                let name = self.expect_string(&loc)?;
+                self.object_instance(&name, &loc)?;
+            }
+            "MakeNamedMedium" | "MediumInterface" | "TransformTimes"
+            | "ActiveTransform" | "ColorSpace" | "Option" => {
+                warn(&loc, &format!("'{}' is not supported", directive));
+                self.skip_args()?;
+            }
+            _ => {
+                return Err(parse_error(
+                    &loc,
+                    format!("unknown directive '{}'", directive),
+                ))
+            }
+        }
+        Ok(())
+    }
+
+    fn spectrum_texture(
+        &self, params: &ParamSet, name: &str, default: Vec3,
+    ) -> Arc<dyn Texture> {
+        if let Some(p) = params.find(&["texture"], name) {
+            if let Some(t) = self.textures.get(&p.strings[0]) {
+                return t.clone();
+            }
+            warn(&p.loc, &format!("unknown texture '{}'", p.strings[0]));
+        }
+        Arc::new(ConstTexture(params.spectrum(name).unwrap_or(default)))
+    }
+
+    fn float_texture(
+        &self, params: &ParamSet, name: &str, default: f32,
+    ) -> Arc<dyn Texture> {
+        if let Some(p) = params.find(&["texture"], name) {
+            if let Some(t) = self.float_textures.get(&p.strings[0]) {
+                return t.clone();
+            }
+            warn(&p.loc, &format!("unknown texture '{}'", p.strings[0]));
+        }
+        let v = params.float(name, default);
+        Arc::new(ConstTexture(Vec3::new(v, v, v)))
+    }
+
+    fn texture(
+        &mut self, name: String, ty: &str, class: &str, params: &ParamSet,
+        loc: &Location,
+    ) {
+        let float = ty == "float";
+        let value = |name, default: f32| {
+            if float {
+                self.float_texture(params, name, default)
+            } else {
+                let default = Vec3::new(default, default, default);
+                self.spectrum_texture(params, name, default)
+            }
+        };
+        let texture: Arc<dyn Texture> = match class {
+            "constant" => value("value", 1.0),
+            "checkerboard" => Arc::new(CheckerTexture {
+                odd: value("tex1", 1.0),
+                even: value("tex2", 0.0),
+            }),
+            "scale" | "mix" => {
+                warn(loc, &format!("'{}' texture uses tex1 only", class));
+                value("tex1", 1.0)
+            }
+            "imagemap" => imagemap(params, loc),
+            _ => {
+                warn(loc, &format!("'{}' texture rendered grey", class));
+                Arc::new(ConstTexture(Vec3::new(0.5, 0.5, 0.5)))
+            }
+        };
+        if float {
+            self.float_textures.insert(name, texture);
+        } else {
+            self.textures.insert(name, texture);
+        }
+    }
+
+    /// Adds a material to the library and returns its index, or `None` for
+    /// materials that don't render anything.
+    fn material(
+        &mut self, ty: &str, params: &ParamSet, loc: &Location,
+    ) -> Option<usize> {
+        let material = self.build_material(ty, params, loc)?;
+        self.matlib.lib.push(material);
+        Some(self.matlib.lib.len() - 1)
+    }
+
+    fn build_material(
+        &self, ty: &str, params: &ParamSet, loc: &Location,
+    ) -> Option<Box<dyn Material>> {
+        let grey = Vec3::new(0.5, 0.5, 0.5);
+        let material: Box<dyn Material> = match ty {
+            "matte" => Box::new(Lambertian::new(
+                self.spectrum_texture(params, "Kd", grey),
+            )),
+            "plastic" | "uber" | "substrate" | "translucent"
+            | "kdsubsurface" => {
+                warn(loc, &format!("'{}' material rendered as matte", ty));
+                let kd = Vec3::new(0.25, 0.25, 0.25);
+                Box::new(Lambertian::new(
+                    self.spectrum_texture(params, "Kd", kd),
+                ))
+            }
+            "mirror" => Box::new(Metal::new(
+                params.spectrum("Kr").unwrap_or(Vec3::new(0.9, 0.9, 0.9)),
+                0.0,
+            )),
+            "metal" => {
+                // Copper, pbrt's default.
+                let (copper_eta, copper_k) =
+                    conductor_preset("copper").unwrap();
+                let eta = params.spectrum("eta").unwrap_or(copper_eta);
+                let k = params.spectrum("k").unwrap_or(copper_k);
+                Box::new(Conductor::new(eta, k, distribution(params, 0.01)))
+            }
+            "glass" => Box::new(Dielectric {
+                ref_idx: params.float("eta", params.float("index", 1.5)),
+                distribution: distribution(params, 0.0),
+                absorption: Vec3::default(),
+            }),
+            "disney" => Box::new(self.disney(params, loc)),
+            "hair" => {
+                let beta_n = params.float("beta_n", 0.3);
+                let absorption = if let Some(s) = params.spectrum("sigma_a") {
+                    HairAbsorption::SigmaA(s)
+                } else if let Some(c) = params.spectrum("color") {
+                    HairAbsorption::Color(c)
+                } else {
+                    HairAbsorption::Melanin {
+                        eumelanin: params.float("eumelanin", 1.3),
+                        pheomelanin: params.float("pheomelanin", 0.0),
+                    }
+                };
+                Box::new(Hair::new(
+                    absorption.sigma_a(beta_n),
+                    params.float("eta", 1.55),
+                    params.float("beta_m", 0.3),
+                    beta_n,
+                    params.float("alpha", 2.0),
+                ))
+            }
+            "mix" => {
+                // `amount` is the weight of the first material.
+                let part = |key| {
+                    let name = params.string(key).unwrap_or("");
+                    match self.named_material_params.get(name) {
+                        Some((ty, params)) => {
+                            self.build_material(ty, params, loc)
+                        }
+                        None => {
+                            warn(loc, &format!("unknown material '{}'", name));
+                            None
+                        }
+                    }
+                };
+                let half = Vec3::new(0.5, 0.5, 0.5);
+                Box::new(Mix {
+                    a: part("namedmaterial2")?,
+                    b: part("namedmaterial1")?,
+                    amount: self.spectrum_texture(params, "amount", half),
+                })
+            }
+            "" | "none" | "interface" => {
+                warn(loc, "shapes without material are not rendered");
+                return None;
+            }
+            _ => {
+                warn(loc, &format!("unknown material '{}', using matte", ty));
+                Box::new(Lambertian::new(Arc::new(ConstTexture(grey))))
+            }
+        };
+        Some(material)
+    }
+
+    /// pbrt's Disney material. Its specular comes from the index of
+    /// refraction, and the flatness of thin surfaces stands in for
+    /// subsurface scattering.
+    fn disney(&self, params: &ParamSet, loc: &Location) -> Principled {
+        for &(name, default) in &[("anisotropic", 0.0), ("difftrans", 1.0)] {
+            if params.float(name, default) != default {
+                warn(loc, &format!("disney '{}' is not supported", name));
+            }
+        }
+        if params.bool("thin", false) {
+            warn(loc, "thin disney material rendered as solid");
+        }
+        if params.has("scatterdistance") {
+            warn(loc, "disney 'scatterdistance' is not supported");
+        }
+        let eta = params.float("eta", 1.5);
+        let r0 = ((eta - 1.0) / (eta + 1.0)).powi(2);
+        let specular = (r0 / 0.08).min(1.0);
+        Principled {
+            base_color: self.spectrum_texture(
+                params,
+                "color",
+                Vec3::new(0.5, 0.5, 0.5),
+            ),
+            metallic: self.float_texture(params, "metallic", 0.0),
+            roughness: self.float_texture(params, "roughness", 0.5),
+            specular: Arc::new(ConstTexture(Vec3::new(
+                specular, specular, specular,
+            ))),
+            specular_tint: params.float("speculartint", 0.0),
+            sheen: self.float_texture(params, "sheen", 0.0),
+            sheen_tint: params.float("sheentint", 0.5),
+            clearcoat: self.float_texture(params, "clearcoat", 0.0),
+            clearcoat_gloss: params.float("clearcoatgloss", 1.0),
+            transmission: self.float_texture(params, "spectrans", 0.0),
+            subsurface: self.float_texture(params, "flatness", 0.0),
+        }
+    }
+
+    /// Stores the shapes since ObjectBegin as a named object. Large objects
+    /// get their own BVH, shared by all instances.
+    fn end_object(&mut self, loc: &Location) {
+        let (name, outer) = match self.object.take() {
+            Some(object) => object,
+            None => {
+                warn(loc, "unmatched ObjectEnd");
+                return;
+            }
+        };
+        let shapes = ::std::mem::replace(&mut self.hitables, outer);
+        if let Some(state) = self.attribute_stack.pop() {
+            self.state = state;
+        }
+        let object: Arc<dyn Hitable> = match shapes.list.len() {
+            0 => {
+                warn(loc, &format!("object '{}' is empty", name));
+                return;
+            }
+            1 => shapes.list[0].clone(),
+            // The BVH only draws a random split axis, any stream will do.
+            _ => Arc::new(BVH::new(shapes, 0.0, 1.0, &mut RNG::default())),
+        };
+        self.objects.insert(name, object);
+    }
+
+    fn object_instance(
+        &mut self, name: &str, loc: &Location,
+    ) -> Result<(), PbrtError> {
+        if self.object.is_some() {
+            return Err(parse_error(
+                loc,
+                "ObjectInstance inside an object definition".to_string(),
+            ));
+        }
+        let object = match self.objects.get(name) {
+            Some(object) => object.clone(),
+            None => {
+                warn(loc, &format!("unknown object '{}'", name));
+                return Ok(());
+            }
+        };
+        // The object's shapes are already mirrored, so mirror them back
+        // before applying the instance's transform.
+        let m = mirror_x() * self.state.ctm * mirror_x();
+        match Transform::new(m) {
+            Some(t) => self
+                .hitables
+                .list
+                .push(Arc::new(TransformedHitable::new(object, t))),
+            None => warn(loc, "singular instance transform, skipped"),
+        }
+        Ok(())
+    }
+
+    /// Places a shape that is defined in object space with `m`.
+    fn transformed(
+        &mut self, shape: Arc<dyn Hitable>, m: Matrix4, loc: &Location,
+    ) {
+        match Transform::new(m) {
+            Some(t) => self
+                .hitables
+                .list
+                .push(Arc::new(TransformedHitable::new(shape, t))),
+            None => warn(loc, "singular shape transform, skipped"),
+        }
+    }
+
+    fn current_material(&mut self) -> usize {
+        if let Some(light) = self.state.area_light {
+            return light;
+        }
+        if let Some(material) = self.state.material {
+            return material;
+        }
+        if let Some(material) = self.default_material {
+            return material;
+        }
+        let grey = Arc::new(ConstTexture(Vec3::new(0.5, 0.5, 0.5)));
+        self.matlib.lib.push(Box::new(Lambertian::new(grey)));
+        self.default_material = Some(self.matlib.lib.len() - 1);
+        self.matlib.lib.len() - 1
+    }
+
+    fn light_source(&mut self, ty: &str, params: &ParamSet, loc: &Location) {
+        match ty {
+            "point" | "spot" => {
+                // Approximated by a small emissive sphere of the same
+                // intensity.
+                const RADIUS: f32 = 0.1;
+                let i =
+                    params.spectrum("I").unwrap_or(Vec3::new(1.0, 1.0, 1.0));
+                let from = params
+                    .points(&["point", "point3"], "from")
+                    .map_or(Vec3::default(), |p| p[0]);
+                let m = mirror_x() * self.state.ctm;
+                let center = m.point(Point3::default() + from);
+                let pi = ::std::f32::consts::PI;
+                self.matlib.lib.push(Box::new(DiffuseLight {
+                    emit: Arc::new(ConstTexture(i / (pi * RADIUS * RADIUS))),
+                }));
+                let material = self.matlib.lib.len() - 1;
+                self.hitables
+                    .list
+                    .push(Arc::new(Sphere::new(center, RADIUS, material)));
+                warn(loc, &format!("'{}' light approximated by a sphere", ty));
+            }
+            _ => warn(loc, &format!("'{}' light is not supported", ty)),
+        }
+    }
+
+    fn shape(
+        &mut self, ty: &str, params: &ParamSet, loc: &Location,
+    ) -> Result<(), PbrtError> {
+        let m = mirror_x() * self.state.ctm;
+        match ty {
+            "sphere"
+                if ["zmin", "zmax", "phimax"].iter().any(|p| params.has(p)) =>
+            {
+                let radius = params.float("radius", 1.0);
+                let sphere = Quadric::sphere(
+                    radius,
+                    params.float("zmin", -radius),
+                    params.float("zmax", radius),
+                    params.float("phimax", 360.0),
+                    self.current_material(),
+                );
+                self.transformed(Arc::new(sphere), m, loc);
+            }
+            "sphere" => {
+                let det = m.determinant3().abs();
+                let scale = det.cbrt();
+                let sx = m.vector(Vec3::new(1.0, 0.0, 0.0)).length();
+                let sy = m.vector(Vec3::new(0.0, 1.0, 0.0)).length();
+                let radius = params.float("radius", 1.0);
+                let material = self.current_material();
+                if (sx - sy).abs() > 1e-3 * scale
+                    || (sx - scale).abs() > 1e-3 * scale
+                {
+                    // An ellipsoid: a sphere in object space.
+                    let sphere = Arc::new(Sphere::new(
+                        Point3::default(),
+                        radius,
+                        material,
+                    ));
+                    self.transformed(sphere, m, loc);
+                } else {
+                    let center = m.point(Point3::default());
+                    self.hitables.list.push(Arc::new(Sphere::new(
+                        center,
+                        radius * scale,
+                        material,
+                    )));
+                }
+            }
+            "cylinder" => {
+                let cylinder = Quadric::cylinder(
+                    params.float("radius", 1.0),
+                    params.float("zmin", -1.0),
+                    params.float("zmax", 1.0),
+                    params.float("phimax", 360.0),
+                    self.current_material(),
+                );
+                self.transformed(Arc::new(cylinder), m, loc);
+            }
+            "cone" => {
+                let cone = Quadric::cone(
+                    params.float("radius", 1.0),
+                    params.float("height", 1.0),
+                    params.float("phimax", 360.0),
+                    self.current_material(),
+                );
+                self.transformed(Arc::new(cone), m, loc);
+            }
+            "paraboloid" => {
+                let paraboloid = Quadric::paraboloid(
+                    params.float("radius", 1.0),
+                    params.float("zmin", 0.0),
+                    params.float("zmax", 1.0),
+                    params.float("phimax", 360.0),
+                    self.current_material(),
+                );
+                self.transformed(Arc::new(paraboloid), m, loc);
+            }
+            "hyperboloid" => {
+                let point = |name: &str, default: Point3| match params
+                    .points(&["point", "point3"], name)
+                {
+                    Some(p) if !p.is_empty() => Point3::default() + p[0],
+                    _ => default,
+                };
+                let p1 = point("p1", Point3::new(0.0, 0.0, 0.0));
+                let p2 = point("p2", Point3::new(1.0, 1.0, 1.0));
+                if p1.z() == p2.z() {
+                    warn(loc, "flat hyperboloid, skipped");
+                    return Ok(());
+                }
+                let hyperboloid = Quadric::hyperboloid(
+                    p1,
+                    p2,
+                    params.float("phimax", 360.0),
+                    self.current_material(),
+                );
+                self.transformed(Arc::new(hyperboloid), m, loc);
+            }
+            "curve" => self.curve(params, &m, loc)?,
+            "heightfield" => {
+                let (nu, nv) =
+                    (params.integer("nu", 0), params.integer("nv", 0));
+                let pz = params.floats("Pz").unwrap_or(&[]);
+                if nu < 2 || nv < 2 || pz.len() != nu * nv {
+                    return Err(parse_error(
+                        loc,
+                        "heightfield needs 'nu' x 'nv' heights 'Pz'"
+                            .to_string(),
+                    ));
+                }
+                let size = Vec3::new(1.0, 1.0, 1.0);
+                let material = self.current_material();
+                let field = Heightfield::new(nu, nv, pz, size, material);
+                // Our heights are along y, pbrt's along z.
+                let swap = Matrix4::new([
+                    [1.0, 0.0, 0.0, 0.0],
+                    [0.0, 0.0, 1.0, 0.0],
+                    [0.0, 1.0, 0.0, 0.0],
+                    [0.0, 0.0, 0.0, 1.0],
+                ]);
+                self.transformed(Arc::new(field), m * swap, loc);
+            }
+            "trianglemesh" => {
+                let mesh = self.triangle_mesh(params, &m, loc)?;
+                let triangles = TriangleMesh::triangles(&Arc::new(mesh));
+                self.hitables.list.extend(triangles.list);
+            }
+            _ => warn(loc, &format!("'{}' shape is not supported", ty)),
+        }
+        Ok(())
+    }
+
+    /// Adds a strand of cubic Bézier curves. Quadratic curves are raised
+    /// to cubics and B-splines converted to Bézier curves first.
+    fn curve(
+        &mut self, params: &ParamSet, m: &Matrix4, loc: &Location,
+    ) -> Result<(), PbrtError> {
+        let cp = params
+            .points(&["point", "point3"], "P")
+            .ok_or_else(|| parse_error(loc, "curve needs 'P'".to_string()))?;
+        let degree = params.integer("degree", 3);
+        let bspline = match params.string("basis").unwrap_or("bezier") {
+            "bezier" => false,
+            "bspline" => true,
+            basis => {
+                warn(loc, &format!("unknown curve basis '{}'", basis));
+                return Ok(());
+            }
+        };
+        if degree != 2 && degree != 3 {
+            warn(
+                loc,
+                &format!("curves of degree {} are not supported", degree),
+            );
+            return Ok(());
+        }
+        let valid = if bspline {
+            cp.len() > degree
+        } else {
+            cp.len() > degree && (cp.len() - 1) % degree == 0
+        };
+        if !valid {
+            return Err(parse_error(
+                loc,
+                format!(
+                    "{} control points don't make {} curves of degree {}",
+                    cp.len(),
+                    if bspline { "B-spline" } else { "Bézier" },
+                    degree
+                ),
+            ));
+        }
+        let lerp = |t: f32, a: Vec3, b: Vec3| a + t * (b - a);
+        // The Bézier control points of each curve, quadratic or cubic.
+        let mut curves: Vec<Vec<Vec3>> = Vec::new();
+        if !bspline {
+            for i in (0..cp.len() - 1).step_by(degree) {
+                curves.push(cp[i..=i + degree].to_vec());
+            }
+        } else if degree == 3 {
+            for w in cp.windows(4) {
+                let p122 = lerp(2.0 / 3.0, w[0], w[1]);
+                let p223 = lerp(1.0 / 3.0, w[1], w[2]);
+                let p233 = lerp(2.0 / 3.0, w[1], w[2]);
+                let p334 = lerp(1.0 / 3.0, w[2], w[3]);
+                curves.push(::std::vec![
+                    lerp(0.5, p122, p223),
+                    p223,
+                    p233,
+                    lerp(0.5, p233, p334),
+                ]);
+            }
+        } else {
+            for w in cp.windows(3) {
+                curves.push(::std::vec![
+                    lerp(0.5, w[0], w[1]),
+                    w[1],
+                    lerp(0.5, w[1], w[2]),
+                ]);
+            }
+        }
+        let mut points = Vec::with_capacity(3 * curves.len() + 1);
+        for (i, c) in curves.iter().enumerate() {
+            let c = match c.len() {
+                3 => [
+                    c[0],
+                    lerp(2.0 / 3.0, c[0], c[1]),
+                    lerp(2.0 / 3.0, c[2], c[1]),
+                    c[2],
+                ],
+                _ => [c[0], c[1], c[2], c[3]],
+            };
+            let first = if i == 0 { 0 } else { 1 };
+            for &p in &c[first..] {
+                points.push(m.point(Point3::default() + p));
+            }
+        }
+
+        let kind = match params.string("type").unwrap_or("flat") {
+            "flat" => CurveKind::Flat,
+            "cylinder" => CurveKind::Cylinder,
+            "ribbon" => CurveKind::Ribbon,
+            ty => {
+                warn(loc, &format!("unknown curve type '{}', using flat", ty));
+                CurveKind::Flat
+            }
+        };
+        let mut normals = params
+            .points(&["normal", "normal3"], "N")
+            .unwrap_or_default();
+        let kind = match kind {
+            CurveKind::Ribbon if normals.len() != curves.len() + 1 => {
+                warn(loc, "ribbon needs one 'N' per curve end, using flat");
+                CurveKind::Flat
+            }
+            kind => kind,
+        };
+        let normal_matrix =
+            m.inverse().unwrap_or_else(Matrix4::identity).transpose();
+        for n in &mut normals {
+            *n = normal_matrix.vector(*n);
+        }
+        let width = params.float("width", 1.0);
+        let scale = m.determinant3().abs().cbrt();
+        let widths = (
+            params.float("width0", width) * scale,
+            params.float("width1", width) * scale,
+        );
+        let split_depth = cmp::min(params.integer("splitdepth", 3), 10) as u32;
+        let strand = Curve::strand(
+            &points,
+            widths,
+            kind,
+            &normals,
+            split_depth,
+            self.current_material(),
+        );
+        self.hitables.list.extend(strand.list);
+        Ok(())
+    }
+
+    fn triangle_mesh(
+        &mut self, params: &ParamSet, m: &Matrix4, loc: &Location,
+    ) -> Result<TriangleMesh, PbrtError> {
+        let positions =
+            params.points(&["point", "point3"], "P").ok_or_else(|| {
+                parse_error(loc, "trianglemesh needs 'P'".to_string())
+            })?;
+        let mut indices: Vec<usize> = match params.integers("indices") {
+            Some(indices) => {
+                if let Some(&i) = indices.iter().find(|&&i| i < 0.0) {
+                    return Err(parse_error(
+                        loc,
+                        format!("negative index {}", i),
+                    ));
+                }
+                indices.iter().map(|&i| i as usize).collect()
+            }
+            None if positions.len() == 3 => ::std::vec![0, 1, 2],
+            None => {
+                return Err(parse_error(
+                    loc,
+                    "trianglemesh needs 'indices'".to_string(),
+                ))
+            }
+        };
+        if indices.len() % 3 != 0 {
+            return Err(parse_error(
+                loc,
+                format!(
+                    "'indices' has {} entries, not a multiple of 3",
+                    indices.len()
+                ),
+            ));
+        }
+        if let Some(&i) = indices.iter().find(|&&i| i >= positions.len()) {
+            return Err(parse_error(
+                loc,
+                format!("index {} out of range (have {})", i, positions.len()),
+            ));
+        }
+        let mut normals = params
+            .points(&["normal", "normal3"], "N")
+            .unwrap_or_default();
+        if !normals.is_empty() && normals.len() != positions.len() {
+            warn(loc, "'N' doesn't match 'P', ignored");
+            normals.clear();
+        }
+        let uvs: Vec<(f32, f32)> = match params
+            .find(&["float", "point2"], "uv")
+            .or_else(|| params.find(&["float", "point2"], "st"))
+        {
+            Some(p) if p.numbers.len() != 2 * positions.len() => {
+                return Err(parse_error(
+                    &p.loc,
+                    format!(
+                        "'{}' needs 2 values per point (have {} for {})",
+                        p.name,
+                        p.numbers.len(),
+                        positions.len()
+                    ),
+                ));
+            }
+            Some(p) => p.numbers.chunks(2).map(|c| (c[0], c[1])).collect(),
+            None => Vec::new(),
+        };
+
+        // Keep the winding, and so the normals, pointing outwards.
+        if (m.determinant3() < 0.0) != self.state.reverse_orientation {
+            for t in indices.chunks_mut(3) {
+                t.swap(1, 2);
+            }
+        }
+        let normal_matrix =
+            m.inverse().unwrap_or_else(Matrix4::identity).transpose();
+        let positions = positions
+            .iter()
+            .map(|&p| m.point(Point3::default() + p))
+            .collect();
+        let normals = normals
+            .iter()
+            .map(|&n| normal_matrix.vector(n).unit())
+            .collect();
+        let material = self.current_material();
+        Ok(TriangleMesh::new(
+            positions, indices, normals, uvs, material,
+        ))
+    }
+
+    fn finish(mut self, rng: &mut RNG) -> Result<Scene, PbrtError> {
+        if let Some((name, outer)) = self.object.take() {
+            if let Some(loc) = &self.last {
+                warn(loc, &format!("object '{}' has no ObjectEnd", name));
+            }
+            self.hitables = outer;
+        }
+        let width = self.film.integer("xresolution", 640);
+        let height = self.film.integer("yresolution", 480);
+        let samples = self.sampler.integer("pixelsamples", 16);
+        let max_depth = self.integrator.integer("maxdepth", 5);
+
+        let aspect = width as f32 / height as f32;
+        let (world_to_camera, params) = match self.camera.take() {
+            Some(desc) => {
+                if desc.world_to_camera.inverse().is_none() {
+                    return Err(parse_error(
+                        &desc.loc,
+                        "camera transform is singular".to_string(),
+                    ));
+                }
+                (desc.world_to_camera, desc.params)
+            }
+            None => (Matrix4::identity(), ParamSet::default()),
+        };
+        let camera_to_world = mirror_x() * world_to_camera.inverse().unwrap();
+        let look_from = camera_to_world.point(Point3::default());
+        let dir = camera_to_world.vector(Vec3::new(0.0, 0.0, 1.0));
+        let up = camera_to_world.vector(Vec3::new(0.0, 1.0, 0.0));
+        // pbrt's fov spans the shorter image axis.
+        let fov = params.float("fov", 90.0);
+        let vfov = if aspect >= 1.0 {
+            fov
+        } else {
+            2.0 * ((0.5 * fov).to_radians().tan() / aspect)
+                .atan()
+                .to_degrees()
+        };
+        let lens_radius = params.float("lensradius", 0.0);
+        let focus_dist = if lens_radius > 0.0 {
+            params.float("focaldistance", 1e6)
+        } else {
+            1.0
+        };
+        let camera = Camera::new(
+            look_from,
+            look_from + dir,
+            &up,
+            vfov,
+            aspect,
+            2.0 * lens_radius,
+            focus_dist,
+            params.float("shutteropen", 0.0),
+            params.float("shutterclose", 1.0),
+        );
+
+        if self.hitables.list.is_empty() {
+            let message = "scene has no shapes".to_string();
+            return Err(match &self.last {
+                Some(loc) => parse_error(loc, message),
+                None => PbrtError::Parse {
+                    path: PathBuf::new(),
+                    line: 0,
+                    message,
+                },
+            });
+        }
+        let settings = RenderSettings {
+            width,
+            height,
+            samples,
+            ray_depth: max_depth,
+            filter: self.filter,
+        };
+        Ok(Scene::new(
+            settings,
+            camera,
+            self.hitables,
+            self.matlib,
+            0.0,
+            1.0,
+            rng,
+        ))
+    }
+}
+
+fn matches_token(peeked: Option<&(Token, Location)>, token: &Token) -> bool {
+    match peeked {
+        Some((t, _)) => t == token,
+        None => false,
+    }
+}
+
+fn make_param(
+    ty: &str, name: &str, values: Vec<Token>, loc: Location,
+) -> Result<Param, PbrtError> {
+    let ty = match ty {
+        "point" => "point3",
+        "vector" => "vector3",
+        "normal" => "normal3",
+        "color" => "rgb",
+        ty => ty,
+    };
+    let mut numbers = Vec::new();
+    let mut strings = Vec::new();
+    for v in values {
+        match v {
+            Token::Num(n) => numbers.push(n),
+            Token::Str(s) | Token::Ident(s) => strings.push(s),
+            _ => {}
+        }
+    }
+    let stride = match ty {
+        "integer" | "float" | "spectrum" => 1,
+        "point2" | "vector2" | "blackbody" => 2,
+        "rgb" | "point3" | "vector3" | "normal3" => 3,
+        "string" | "texture" | "bool" => 0,
+        _ => {
+            return Err(parse_error(
+                &loc,
+                format!("unknown parameter type '{}'", ty),
+            ))
+        }
+    };
+    let ok = if stride == 0 {
+        numbers.is_empty() && !strings.is_empty()
+    } else if ty == "spectrum" {
+        // Either sampled (lambda, value) pairs or a named/file spectrum.
+        (numbers.len() >= 2 && numbers.len() % 2 == 0 && strings.is_empty())
+            || (numbers.is_empty() && strings.len() == 1)
+    } else {
+        strings.is_empty() && !numbers.is_empty() && numbers.len() % stride == 0
+    };
+    if !ok {
+        return Err(parse_error(
+            &loc,
+            format!("bad values for '{} {}'", ty, name),
+        ));
+    }
+    Ok(Param {
+        ty: ty.to_string(),
+        name: name.to_string(),
+        numbers,
+        strings,
+        loc,
+    })
+}
+
+#[cfg(test)]
+pub mod tests {
+    use super::*;
+    use ::std::env;
+    use ::std::{assert, assert_eq, panic, vec};
+
+    use crate::image::{Image, WriteOptions};
+    use crate::pbrt::{HitRecord, Ray};
+
+    fn parse(src: &str) -> Result<Scene, PbrtError> {
+        parse_pbrt(src, Path::new("test.pbrt"), &mut RNG::default())
+    }
+
+    #[test]
+    fn test_pbrt_scene() {
+        let scene = parse(
+            r#"
+            LookAt 0 0 5  0 0 0  0 1 0
+            Camera "perspective" "float fov" [ 45 ]
+            Film "image" "integer xresolution" [200]
+                "integer yresolution" [100] "string filename" "out.exr"
+            Sampler "halton" "integer pixelsamples" 8
+            WorldBegin
+            AttributeBegin
+              AreaLightSource "diffuse" "rgb L" [ 4 4 4 ]
+              Translate 0 4 0
+              Shape "sphere" "float radius" 1
+            AttributeEnd
+            Texture "checks" "spectrum" "checkerboard"
+                "rgb tex1" [1 0 0] "rgb tex2" [0 0 1]
+            Material "matte" "texture Kd" "checks"
+            Shape "trianglemesh" "integer indices" [0 1 2 0 2 3]
+                "point P" [-1 0 -1  1 0 -1  1 0 1  -1 0 1]
+            WorldEnd
+            "#,
+        )
+        .unwrap();
+        assert_eq!(scene.settings.width, 200);
+        assert_eq!(scene.settings.height, 100);
+        assert_eq!(scene.settings.samples, 8);
+        assert_eq!(scene.settings.ray_depth, 5);
+        assert_eq!(scene.matlib.lib.len(), 2);
+    }
+
+    #[test]
+    fn test_pbrt_microfacets() {
+        let scene = parse(
+            r#"
+            WorldBegin
+            Material "metal" "float roughness" 0.2
+            Shape "sphere"
+            Material "metal" "rgb eta" [0.2 0.9 1.1] "rgb k" [3.9 2.5 2.1]
+                "float uroughness" 0 "float vroughness" 0
+                "bool remaproughness" "false"
+            Shape "sphere"
+            Material "glass" "float index" 1.33
+            Shape "sphere"
+            Material "glass" "float uroughness" 0.3 "float vroughness" 0.1
+            Shape "sphere"
+            WorldEnd
+            "#,
+        )
+        .unwrap();
+        assert_eq!(scene.matlib.lib.len(), 4);
+        assert!(!scene.matlib.lib[0].is_delta());
+        assert!(scene.matlib.lib[1].is_delta());
+        assert!(scene.matlib.lib[2].is_delta());
+        assert!(!scene.matlib.lib[3].is_delta());
+    }
+
+    #[test]
+    fn test_pbrt_disney_and_mix() {
+        let scene = parse(
+            r#"
+            WorldBegin
+            Texture "rough" "float" "checkerboard" "float tex1" 0.2
+                "float tex2" 0.8
+            Material "disney" "rgb color" [0.8 0.1 0.1]
+                "float metallic" 1 "texture roughness" "rough"
+            Shape "sphere"
+            Material "disney" "float spectrans" 1 "float eta" 1.33
+            Shape "sphere"
+            MakeNamedMaterial "white" "string type" "matte"
+                "rgb Kd" [1 1 1]
+            MakeNamedMaterial "black" "string type" "matte"
+                "rgb Kd" [0 0 0]
+            Material "mix" "string namedmaterial1" "white"
+                "string namedmaterial2" "black" "rgb amount" [0.25 0.25 0.25]
+            Shape "sphere"
+            WorldEnd
+            "#,
+        )
+        .unwrap();
+        assert_eq!(scene.matlib.lib.len(), 5);
+        let ray = Ray::new(
+            Point3::new(0.0, 0.0, 2.0),
+            Vec3::new(0.0, 0.0, -1.0),
+            0.0,
+            1,
+        );
+        let rec = HitRecord {
+            normal: Vec3::new(0.0, 0.0, 1.0),
+            t: 1.0,
+            ..HitRecord::default()
+        };
+        let up = Vec3::new(0.0, 0.0, 1.0);
+        let f = scene.matlib.lib[0].eval(&ray, &rec, up);
+        assert!(f.x() > 5.0 * f.z());
+        let down = Vec3::new(0.0, 0.0, -1.0);
+        assert!(scene.matlib.lib[1].eval(&ray, &rec, down).y() > 0.0);
+        // A quarter of white.
+        let f = scene.matlib.lib[4].eval(&ray, &rec, up);
+        assert!((f.y() - 0.25 / ::std::f32::consts::PI).abs() < 1e-5);
+    }
+
+    #[test]
+    fn test_pbrt_imagemap() {
+        let dir = env::temp_dir().join("raytracer_test_pbrt_imagemap");
+        fs::create_dir_all(&dir).unwrap();
+        let mut image = Image::new(2, 1);
+        image.pixels = vec![Vec3::new(1.0, 0.0, 0.0), Vec3::new(0.0, 0.0, 1.0)];
+        image
+            .write(&dir.join("halves.png"), &WriteOptions::default())
+            .unwrap();
+        let result = parse_pbrt(
+            r#"
+            WorldBegin
+            Texture "halves" "spectrum" "imagemap"
+                "string filename" "halves.png" "string wrap" "clamp"
+                "float uscale" 2 "float udelta" -0.5
+            Material "matte" "texture Kd" "halves"
+            Shape "sphere"
+            Texture "missing" "spectrum" "imagemap"
+                "string filename" "missing.png"
+            Material "matte" "texture Kd" "missing"
+            Shape "sphere"
+            WorldEnd
+            "#,
+            &dir.join("test.pbrt"),
+            &mut RNG::default(),
+        );
+        let _ = fs::remove_dir_all(&dir);
+        let scene = result.unwrap();
+        let ray = Ray::new(
+            Point3::new(0.0, 0.0, 2.0),
+            Vec3::new(0.0, 0.0, -1.0),
+            0.0,
+            1,
+        );
+        let up = Vec3::new(0.0, 0.0, 1.0);
+        let eval = |material: usize, u: f32| {
+            let rec = HitRecord {
+                normal: Vec3::new(0.0, 0.0, 1.0),
+                u,
+                v: 0.5,
+                ..HitRecord::default()
+            };
+            scene.matlib.lib[material].eval(&ray, &rec, up)
+        };
+        // s = 2u - 0.5 puts the red texel at u = 0.375 and blue at 0.625.
+        let red = eval(0, 0.375);
+        assert!(red.x() > 0.3 && red.z() < 1e-4, "{:?}", red);
+        let blue = eval(0, 0.625);
+        assert!(blue.z() > 0.3 && blue.x() < 1e-4, "{:?}", blue);
+        let grey = eval(1, 0.5);
+        assert!(grey.x() > 0.0 && grey.x() == grey.z(), "{:?}", grey);
+    }
+
+    #[test]
+    fn test_pbrt_errors() {
+        match parse("WorldBegin\nShape \"sphere\" \"float radius\" [ 1 2\n") {
+            Err(PbrtError::Parse { line, .. }) => assert_eq!(line, 2),
+            _ => panic!("expected parse error"),
+        }
+        match parse("WorldBegin\n\nFoo 1 2 3\n") {
+            Err(e) => assert_eq!(
+                e.to_string(),
+                "test.pbrt:3: unknown directive 'Foo'"
+            ),
+            _ => panic!("expected parse error"),
+        }
+        match parse("Shape \"trianglemesh\" \"point P\" [0 0 0 1 0 0]") {
+            Err(PbrtError::Parse { line, .. }) => assert_eq!(line, 1),
+            _ => panic!("expected parse error"),
+        }
+        match parse(
+            "Shape \"trianglemesh\" \"integer indices\" [0 -1 2]\n\
+             \"point P\" [0 0 0 1 0 0 0 1 0]",
+        ) {
+            Err(e) => {
+                assert_eq!(e.to_string(), "test.pbrt:1: negative index -1")
+            }
+            _ => panic!("expected parse error"),
+        }
+        // One uv pair per point.
+        let mesh = "WorldBegin\nShape \"trianglemesh\" \"integer indices\" \
+                    [0 1 2] \"point P\" [0 0 0 1 0 0 0 1 0]\n";
+        assert!(parse(&format!("{}\"float uv\" [0 0 1 0 0 1]", mesh)).is_ok());
+        for uv in ["[0 0 1 0 0]", "[0 0 1 0]", "[0 0 1 0 0 1 1 1]"] {
+            match parse(&format!("{}\"float uv\" {}", mesh, uv)) {
+                Err(PbrtError::Parse { line, .. }) => assert_eq!(line, 3),
+                _ => panic!("expected parse error for {}", uv),
+            }
+        }
+    }
+
+    #[test]
+    fn test_pbrt_include() {
+        let dir = env::temp_dir().join("raytracer_test_pbrt_include");
+        fs::create_dir_all(&dir).unwrap();
+        let write = |name: &str, src: &str| {
+            fs::write(dir.join(name), src).unwrap();
+        };
+        write("sphere.pbrt", "Shape \"sphere\"\n");
+        write("self.pbrt", "Include \"self.pbrt\"\n");
+        write("a.pbrt", "Include \"b.pbrt\"\n");
+        write("b.pbrt", "Include \"./a.pbrt\"\n");
+        let parse_file = |src: &str| {
+            parse_pbrt(src, &dir.join("main.pbrt"), &mut RNG::default())
+        };
+        // The same file twice in a row is fine.
+        let twice = parse_file(
+            "WorldBegin\nInclude \"sphere.pbrt\"\nInclude \"sphere.pbrt\"\n",
+        );
+        let recursive = [
+            parse_file("Include \"self.pbrt\"\n"),
+            parse_file("Include \"a.pbrt\"\n"),
+        ];
+        let _ = fs::remove_dir_all(&dir);
+        assert_eq!(twice.unwrap().matlib.lib.len(), 1);
+        for result in recursive {
+            match result {
+                Err(e) => assert!(e.to_string().contains("includes itself")),
+                Ok(_) => panic!("expected recursive include error"),
+            }
+        }
+    }
+
+    #[test]
+    fn test_pbrt_quadrics() {
+        let scene = parse(
+            r#"
+            WorldBegin
+            Shape "cylinder" "float radius" 0.5 "float phimax" 180
+            Shape "cone" "float height" 2
+            Shape "paraboloid" "float zmax" 2
+            Shape "hyperboloid" "point p1" [1 0 0] "point p2" [1 0 0]
+            Translate 0 0 -5
+            Shape "sphere" "float zmin" 0
+            WorldEnd
+            "#,
+        )
+        .unwrap();
+        // Up the axis, past the lower half of the sphere that is cut away,
+        // onto the inside of its top. The flat hyperboloid was skipped.
+        let r = Ray::new(
+            Point3::new(0.0, 0.0, -10.0),
+            Vec3::new(0.0, 0.0, 1.0),
+            0.0,
+            0,
+        );
+        let mut rec = HitRecord::default();
+        assert!(scene.world.hit(&r, 0.001, f32::MAX, &mut rec));
+        assert!((rec.t - 6.0).abs() < 1e-4);
+        assert!(rec.normal.z() > 0.99);
+        // Then the tip of the paraboloid.
+        assert!(scene.world.hit(&r, 6.5, f32::MAX, &mut rec));
+        assert!((rec.t - 10.0).abs() < 1e-4);
+    }
+
+    #[test]
+    fn test_pbrt_curves() {
+        let scene = parse(
+            r#"
+            WorldBegin
+            Material "hair" "float eumelanin" 0.5 "float beta_m" 0.2
+            Shape "curve" "point P" [0 0 0  1 0 0  2 0 0  3 0 0]
+                "float width0" 0.2 "float width1" 0.1 "string type" "cylinder"
+            Translate 0 2 0
+            Scale 2 2 2
+            Shape "curve" "string basis" "bspline"
+                "point P" [-1 0 0  0 0 0  1 0 0  2 0 0  3 0 0]
+                "float width" 0.05
+            WorldEnd
+            "#,
+        )
+        .unwrap();
+        let down = |x: f32, y: f32| {
+            let r = Ray::new(
+                Point3::new(x, y, 5.0),
+                Vec3::new(0.0, 0.0, -1.0),
+                0.0,
+                0,
+            );
+            let mut rec = HitRecord::default();
+            scene.world.hit(&r, 0.001, f32::MAX, &mut rec)
+        };
+        // Mirrored along x, like all imported shapes.
+        assert!(down(-0.2, 0.09));
+        assert!(!down(-2.8, 0.09));
+        assert!(!down(0.2, 0.0));
+        // The B-spline runs between the averages of its ends, scaled.
+        assert!(down(-1.0, 2.04));
+        assert!(down(-3.9, 2.0));
+        assert!(!down(-4.1, 2.0));
+        assert!(!down(-1.0, 2.06));
+    }
+
+    #[test]
+    fn test_pbrt_heightfield() {
+        let scene = parse(
+            r#"
+            WorldBegin
+            Scale 4 2 1
+            Shape "heightfield" "integer nu" 3 "integer nv" 2
+                "float Pz" [0 1 0  0 1 0]
+            WorldEnd
+            "#,
+        )
+        .unwrap();
+        // The ridge runs along y at x = -2 after mirroring.
+        let r = Ray::new(
+            Point3::new(-2.0, 0.5, 5.0),
+            Vec3::new(0.0, 0.0, -1.0),
+            0.0,
+            0,
+        );
+        let mut rec = HitRecord::default();
+        assert!(scene.world.hit(&r, 0.001, f32::MAX, &mut rec));
+        assert!((rec.p.z() - 1.0).abs() < 1e-4);
+        assert!(rec.normal.z() > 0.4);
+        let beside = Ray::new(
+            Point3::new(-3.0, 0.5, 5.0),
+            Vec3::new(0.0, 0.0, -1.0),
+            0.0,
+            0,
+        );
+        assert!(scene.world.hit(&beside, 0.001, f32::MAX, &mut rec));
+        assert!((rec.p.z() - 0.5).abs() < 1e-4);
+        assert!(parse("Shape \"heightfield\" \"integer nu\" 2").is_err());
+    }
+
+    #[test]
+    fn test_pbrt_object_instance() {
+        let scene = parse(
+            r#"
+            WorldBegin
+            ObjectBegin "quad"
+              Shape "trianglemesh" "integer indices" [0 1 2 0 2 3]
+                  "point P" [-1 -1 0  1 -1 0  1 1 0  -1 1 0]
+            ObjectEnd
+            AttributeBegin
+              Translate 5 0 0
+              ObjectInstance "quad"
+            AttributeEnd
+            AttributeBegin
+              Translate 0 0 -2
+              Scale 2 2 2
+              ObjectInstance "quad"
+            AttributeEnd
+            WorldEnd
+            "#,
+        )
+        .unwrap();
+        let hit = |x: f32, y: f32| {
+            let r = Ray::new(
+                Point3::new(x, y, 5.0),
+                Vec3::new(0.0, 0.0, -1.0),
+                0.0,
+                0,
+            );
+            let mut rec = HitRecord::default();
+            if scene.world.hit(&r, 0.001, f32::MAX, &mut rec) {
+                Some(rec.t)
+            } else {
+                None
+            }
+        };
+        // pbrt's +x is our -x.
+        assert_eq!(hit(-5.0, 0.5), Some(5.0));
+        assert_eq!(hit(5.0, 0.5), None);
+        assert_eq!(hit(0.0, 1.5), Some(7.0));
+        assert_eq!(hit(0.0, 2.5), None);
+    }
+
+    #[test]
+    fn test_matrix_inverse() {
+        let m = *(Transform::translate(Vec3::new(1.0, 2.0, 3.0))
+            * Transform::rotate(30.0, Vec3::new(1.0, 1.0, 0.0))
+            * Transform::scale(2.0, 3.0, 4.0))
+        .matrix();
+        let p = Point3::new(0.5, -1.0, 2.0);
+        let q = m.inverse().unwrap().point(m.point(p));
+        assert!((q - p).length() < 1e-5);
+    }
+}
@@ -0,0 +1,481 @@
+//! A principled material after Burley, "Physically-Based Shading at
+//! Disney", 2012, with the transmission of the 2015 extension, as used by
+//! most content creation tools.
+//!
+//! A handful of artist-friendly parameters in [0, 1] blend a diffuse base
+//! with sheen and a fake subsurface look, a GGX specular layer that turns
+//! from dielectric to metal with `metallic`, a clearcoat on top and rough
+//! glass-like transmission. Every lobe is evaluated, and one is sampled at
+//! a time with a probability after its rough share of the reflected light.
+
+use ::std::default::Default;
+use ::std::iter::Iterator;
+use ::std::option::Option::{self, None, Some};
+use ::std::sync::Arc;
+
+use ::math::Vec3;
+
+use crate::material::fresnel_dielectric;
+use crate::microfacet::{reflect_about, refract_about, Frame, TrowbridgeReitz};
+use crate::pbrt::{BsdfSample, HitRecord, Material, Ray, Texture, RNG};
+
+const PI: f32 = ::std::f32::consts::PI;
+
+/// The smallest width of the specular lobes, sharper ones are not worth
+/// sampling as rough.
+const MIN_ALPHA: f32 = 1e-3;
+
+/// The roughness of the clearcoat's masking, fixed as in Disney's model.
+const CLEARCOAT_MASKING: f32 = 0.25;
+
+/// The parameters that are textures are read per hit, scalar ones as the
+/// mean of the texture's channels.
+pub struct Principled {
+    pub base_color: Arc<dyn Texture>,
+    pub metallic: Arc<dyn Texture>,
+    pub roughness: Arc<dyn Texture>,
+    /// The dielectric reflectance at normal incidence, scaled so that 0.5
+    /// is 4%, the index of refraction 1.5.
+    pub specular: Arc<dyn Texture>,
+    /// How much the dielectric specular takes on the base color.
+    pub specular_tint: f32,
+    /// A soft layer at grazing angles, for cloth.
+    pub sheen: Arc<dyn Texture>,
+    pub sheen_tint: f32,
+    pub clearcoat: Arc<dyn Texture>,
+    /// The smoothness of the clearcoat, 1 is a polished varnish.
+    pub clearcoat_gloss: f32,
+    /// How much of the dielectric base is glass rather than diffuse.
+    pub transmission: Arc<dyn Texture>,
+    /// Blends the diffuse lobe into a flatter one that looks like light
+    /// scattered under the surface.
+    pub subsurface: Arc<dyn Texture>,
+}
+
+impl Principled {
+    /// A plain grey dielectric with the model's defaults, to fill in with
+    /// struct update syntax.
+    pub fn new(base_color: Arc<dyn Texture>) -> Self {
+        let constant = |v: f32| -> Arc<dyn Texture> {
+            Arc::new(crate::texture::ConstTexture(Vec3::new(v, v, v)))
+        };
+        Principled {
+            base_color,
+            metallic: constant(0.0),
+            roughness: constant(0.5),
+            specular: constant(0.5),
+            specular_tint: 0.0,
+            sheen: constant(0.0),
+            sheen_tint: 0.5,
+            clearcoat: constant(0.0),
+            clearcoat_gloss: 1.0,
+            transmission: constant(0.0),
+            subsurface: constant(0.0),
+        }
+    }
+
+    fn lobes(&self, ray: &Ray, rec: &HitRecord) -> Lobes {
+        let scalar = |t: &Arc<dyn Texture>| {
+            let c = t.filtered(rec);
+            ((c.x() + c.y() + c.z()) / 3.0).clamp(0.0, 1.0)
+        };
+        let frame = Frame::facing(rec, ray.direction);
+        let wo = frame.local(-ray.direction);
+        let base = self.base_color.filtered(rec);
+        let metallic = scalar(&self.metallic);
+        let roughness = scalar(&self.roughness);
+        let transmission = scalar(&self.transmission);
+        let sheen = scalar(&self.sheen);
+        let clearcoat = scalar(&self.clearcoat);
+        let white = Vec3::new(1.0, 1.0, 1.0);
+        let lum = luminance(base);
+        let tint = if lum > 0.0 { base / lum } else { white };
+        let r0 = (0.08 * scalar(&self.specular)).sqrt();
+        let eta = ((1.0 + r0) / (1.0 - r0)).max(1.0 + 1e-4);
+        let eta = if ray.direction.dot(rec.normal) > 0.0 {
+            1.0 / eta
+        } else {
+            eta
+        };
+        let alpha = (roughness * roughness).max(MIN_ALPHA);
+        let gloss = self.clearcoat_gloss.clamp(0.0, 1.0);
+        let mut lobes = Lobes {
+            frame,
+            wo,
+            base,
+            metallic,
+            roughness,
+            subsurface: scalar(&self.subsurface),
+            eta,
+            specular_tint: lerp(self.specular_tint, white, tint),
+            sheen: sheen * lerp(self.sheen_tint, white, tint),
+            clearcoat,
+            clearcoat_alpha: 0.1 + (0.001 - 0.1) * gloss,
+            transmission,
+            distribution: TrowbridgeReitz::new(alpha, alpha),
+            weights: [0.0; 4],
+        };
+        let dielectric = (1.0 - metallic) * (1.0 - transmission);
+        let weights = [
+            dielectric * (lum + luminance(lobes.sheen)),
+            luminance(lobes.fresnel(wo.z())),
+            0.25 * clearcoat * schlick(0.04, wo.z()),
+            // Not weighted by the Fresnel transmittance, the rough facets
+            // still transmit beyond the critical angle of the macro surface.
+            (1.0 - metallic) * transmission * luminance(sqrt(base)),
+        ];
+        let total: f32 = weights.iter().sum();
+        if total > 0.0 {
+            for (w, &v) in lobes.weights.iter_mut().zip(weights.iter()) {
+                *w = v / total;
+            }
+        }
+        lobes
+    }
+}
+
+/// The parameters of the lobes at one hit, seen from `wo` in the local
+/// frame.
+struct Lobes {
+    frame: Frame,
+    wo: Vec3,
+    base: Vec3,
+    metallic: f32,
+    roughness: f32,
+    subsurface: f32,
+    /// The ratio of the indices of refraction across the surface from the
+    /// side of `wo`.
+    eta: f32,
+    specular_tint: Vec3,
+    sheen: Vec3,
+    clearcoat: f32,
+    clearcoat_alpha: f32,
+    transmission: f32,
+    distribution: TrowbridgeReitz,
+    /// The probabilities of sampling the diffuse, specular, clearcoat and
+    /// transmission lobes. All zero for black surfaces.
+    weights: [f32; 4],
+}
+
+impl Lobes {
+    /// The specular reflectance, a blend of the tinted dielectric one and
+    /// the metal's base color.
+    fn fresnel(&self, cos: f32) -> Vec3 {
+        let dielectric = fresnel_dielectric(cos, self.eta) * self.specular_tint;
+        let metal = schlick_color(self.base, cos);
+        (1.0 - self.metallic) * dielectric + self.metallic * metal
+    }
+
+    /// All lobes' BSDF times the cosine to `wi`, and the density of
+    /// sampling `wi`.
+    fn f_pdf(&self, wi: Vec3) -> (Vec3, f32) {
+        let wo = self.wo;
+        let mut f = Vec3::default();
+        let mut pdf = 0.0;
+        if wo.z() <= 0.0 || wi.z() == 0.0 {
+            return (f, pdf);
+        }
+        if wi.z() > 0.0 {
+            let wh = (wo + wi).unit();
+            let cos_d = wi.dot(wh);
+            let (fl, fv) = (schlick_weight(wi.z()), schlick_weight(wo.z()));
+
+            let dielectric = (1.0 - self.metallic) * (1.0 - self.transmission);
+            if dielectric > 0.0 {
+                // Retro-reflection at grazing angles on rough surfaces.
+                let fd90 = 0.5 + 2.0 * self.roughness * cos_d * cos_d;
+                let fd = (1.0 + (fd90 - 1.0) * fl) * (1.0 + (fd90 - 1.0) * fv);
+                // Hanrahan and Krueger's flattened subsurface look.
+                let fss90 = self.roughness * cos_d * cos_d;
+                let fss =
+                    (1.0 + (fss90 - 1.0) * fl) * (1.0 + (fss90 - 1.0) * fv);
+                let ss = 1.25 * (fss * (1.0 / (wi.z() + wo.z()) - 0.5) + 0.5);
+                let diffuse = fd + (ss - fd) * self.subsurface;
+                let sheen = schlick_weight(cos_d) * self.sheen;
+                f += dielectric * wi.z() * ((diffuse / PI) * self.base + sheen);
+            }
+            pdf += self.weights[0] * wi.z() / PI;
+
+            let tr = &self.distribution;
+            let cos_o = wo.dot(wh);
+            let specular = tr.d(wh) * tr.g(wo, wi) / (4.0 * wo.z());
+            f += specular * self.fresnel(cos_o);
+            pdf += self.weights[1] * tr.visible_d(wo, wh) / (4.0 * cos_o);
+
+            let d = gtr1(wh.z(), self.clearcoat_alpha);
+            if self.clearcoat > 0.0 {
+                let masking =
+                    TrowbridgeReitz::new(CLEARCOAT_MASKING, CLEARCOAT_MASKING);
+                let coat = 0.25 * self.clearcoat * d * masking.g(wo, wi)
+                    / (4.0 * wo.z())
+                    * schlick(0.04, cos_o);
+                f += Vec3::new(coat, coat, coat);
+            }
+            pdf += self.weights[2] * d * wh.z() / (4.0 * cos_o);
+        } else if let Some(wm) = self.refracting_normal(wi) {
+            let tr = &self.distribution;
+            let (ft, dwm_dwi) = tr.transmission(wo, wi, wm, self.eta);
+            let t = (1.0 - self.metallic)
+                * self.transmission
+                * (1.0 - fresnel_dielectric(wo.dot(wm), self.eta));
+            f = t * ft * sqrt(self.base);
+            pdf = self.weights[3] * tr.visible_d(wo, wm) * dwm_dwi;
+        }
+        (f, pdf)
+    }
+
+    /// The normal of the microfacet that refracts `wo` into `wi`, if it
+    /// faces both.
+    fn refracting_normal(&self, wi: Vec3) -> Option<Vec3> {
+        let wm = self.wo + self.eta * wi;
+        if wm.dot(wm) == 0.0 {
+            return None;
+        }
+        let wm = wm.unit();
+        let wm = if wm.z() < 0.0 { -wm } else { wm };
+        if wm.dot(self.wo) <= 0.0 || wm.dot(wi) >= 0.0 {
+            return None;
+        }
+        Some(wm)
+    }
+
+    fn sample_wi(&self, rng: &mut RNG) -> Option<Vec3> {
+        let wo = self.wo;
+        let mut u = rng.rand();
+        let mut lobe = 0;
+        while lobe < 3 && u >= self.weights[lobe] {
+            u -= self.weights[lobe];
+            lobe += 1;
+        }
+        let (u1, u2) = (rng.rand(), rng.rand());
+        let wi = match lobe {
+            0 => {
+                // Cosine weighted.
+                let r = u1.sqrt();
+                let phi = 2.0 * PI * u2;
+                let z = (1.0 - u1).max(0.0).sqrt();
+                Vec3::new(r * phi.cos(), r * phi.sin(), z)
+            }
+            1 => reflect_about(wo, self.distribution.sample_wm(wo, u1, u2)),
+            2 => {
+                let a2 = self.clearcoat_alpha * self.clearcoat_alpha;
+                let cos = ((1.0 - a2.powf(1.0 - u1)) / (1.0 - a2)).sqrt();
+                let sin = (1.0 - cos * cos).max(0.0).sqrt();
+                let phi = 2.0 * PI * u2;
+                let wh = Vec3::new(sin * phi.cos(), sin * phi.sin(), cos);
+                reflect_about(wo, wh)
+            }
+            _ => {
+                let wm = self.distribution.sample_wm(wo, u1, u2);
+                refract_about(wo, wm, self.eta)?
+            }
+        };
+        // A lobe that lands on the wrong side would be taken for another.
+        if (wi.z() > 0.0) == (lobe == 3) {
+            return None;
+        }
+        Some(wi)
+    }
+}
+
+impl Material for Principled {
+    fn sample(
+        &self, rng: &mut RNG, ray: &Ray, rec: &HitRecord,
+    ) -> Option<BsdfSample> {
+        let lobes = self.lobes(ray, rec);
+        if lobes.wo.z() <= 0.0 || lobes.weights.iter().all(|&w| w == 0.0) {
+            return None;
+        }
+        let wi = lobes.sample_wi(rng)?;
+        let (f, pdf) = lobes.f_pdf(wi);
+        if pdf <= 0.0 {
+            return None;
+        }
+        Some(BsdfSample {
+            direction: lobes.frame.world(wi).unit(),
+            f,
+            pdf,
+            delta: false,
+        })
+    }
+
+    fn eval(&self, ray: &Ray, rec: &HitRecord, direction: Vec3) -> Vec3 {
+        let lobes = self.lobes(ray, rec);
+        lobes.f_pdf(lobes.frame.local(direction)).0
+    }
+
+    fn pdf(&self, ray: &Ray, rec: &HitRecord, direction: Vec3) -> f32 {
+        let lobes = self.lobes(ray, rec);
+        lobes.f_pdf(lobes.frame.local(direction)).1
+    }
+}
+
+fn luminance(c: Vec3) -> f32 {
+    0.2126 * c.x() + 0.7152 * c.y() + 0.0722 * c.z()
+}
+
+fn lerp(t: f32, a: Vec3, b: Vec3) -> Vec3 {
+    (1.0 - t) * a + t * b
+}
+
+fn sqrt(c: Vec3) -> Vec3 {
+    Vec3::new(
+        c.x().max(0.0).sqrt(),
+        c.y().max(0.0).sqrt(),
+        c.z().max(0.0).sqrt(),
+    )
+}
+
+/// Schlick's weight of the grazing reflectance, (1 - cos)^5.
+fn schlick_weight(cos: f32) -> f32 {
+    let m = (1.0 - cos).clamp(0.0, 1.0);
+    let m2 = m * m;
+    m2 * m2 * m
+}
+
+fn schlick(r0: f32, cos: f32) -> f32 {
+    r0 + (1.0 - r0) * schlick_weight(cos)
+}
+
+fn schlick_color(r0: Vec3, cos: f32) -> Vec3 {
+    let w = schlick_weight(cos);
+    (1.0 - w) * r0 + Vec3::new(w, w, w)
+}
+
+/// The generalized Trowbridge-Reitz distribution with an exponent of 1,
+/// which has the long tails of the clearcoat's highlight.
+fn gtr1(cos: f32, alpha: f32) -> f32 {
+    if cos <= 0.0 {
+        return 0.0;
+    }
+    let a2 = alpha * alpha;
+    (a2 - 1.0) / (PI * a2.ln() * (1.0 + (a2 - 1.0) * cos * cos))
+}
+
+#[cfg(test)]
+pub mod tests {
+    use super::*;
+    use ::std::assert;
+    use ::std::clone::Clone;
+
+    use crate::texture::ConstTexture;
+    use ::math::Point3;
+
+    fn constant(v: f32) -> Arc<dyn Texture> {
+        Arc::new(ConstTexture(Vec3::new(v, v, v)))
+    }
+
+    /// Checks that samples agree with `eval` and `pdf` and with
+    /// integrating `eval` over uniform directions, and returns the
+    /// average path weight.
+    fn check_sampling(mat: &Principled, rec: &HitRecord) -> f32 {
+        let ray = Ray::new(
+            Point3::new(-1.0, 1.0, 0.0),
+            Vec3::new(1.0, -1.0, 0.0),
+            0.0,
+            1,
+        );
+        let mut rng = RNG::default();
+        let n = 100000;
+        let (mut sampled, mut uniform) = (0.0, 0.0);
+        for _ in 0..n {
+            if let Some(s) = mat.sample(&mut rng, &ray, rec) {
+                assert!(!s.delta);
+                let tolerance = 1e-3 * s.pdf.max(1.0);
+                let pdf = mat.pdf(&ray, rec, s.direction);
+                assert!((pdf - s.pdf).abs() < tolerance);
+                let f = mat.eval(&ray, rec, s.direction);
+                assert!((f.y() - s.f.y()).abs() < tolerance);
+                sampled += s.f.y() / s.pdf;
+            }
+            let w = rng.random_unit_vector();
+            uniform += mat.eval(&ray, rec, w).y() * 4.0 * PI;
+        }
+        let (sampled, uniform) = (sampled / n as f32, uniform / n as f32);
+        assert!((sampled - uniform).abs() < 0.06 * sampled.max(0.1));
+        sampled
+    }
+
+    fn outside() -> HitRecord {
+        HitRecord {
+            normal: Vec3::new(0.0, 1.0, 0.0),
+            t: 1.0,
+            ..HitRecord::default()
+        }
+    }
+
+    #[test]
+    fn test_principled_lobes() {
+        let white = constant(1.0);
+        let diffuse = Principled::new(white.clone());
+        let albedo = check_sampling(&diffuse, &outside());
+        assert!(albedo > 0.9 && albedo < 1.1);
+
+        let metal = Principled {
+            metallic: constant(1.0),
+            roughness: constant(0.5),
+            ..Principled::new(white.clone())
+        };
+        let albedo = check_sampling(&metal, &outside());
+        assert!(albedo > 0.8 && albedo <= 1.0);
+
+        let cloth = Principled {
+            roughness: constant(0.9),
+            sheen: constant(1.0),
+            subsurface: constant(1.0),
+            clearcoat: constant(1.0),
+            clearcoat_gloss: 0.5,
+            ..Principled::new(constant(0.3))
+        };
+        check_sampling(&cloth, &outside());
+
+        let glass = Principled {
+            transmission: constant(1.0),
+            roughness: constant(0.3),
+            ..Principled::new(white)
+        };
+        check_sampling(&glass, &outside());
+        let inside = HitRecord {
+            normal: Vec3::new(0.0, -1.0, 0.0),
+            ..outside()
+        };
+        check_sampling(&glass, &inside);
+    }
+
+    #[test]
+    fn test_principled_blend() {
+        // Black dielectrics only reflect their specular, and metals take
+        // on the base color.
+        let ray = Ray::new(
+            Point3::new(0.0, 1.0, 0.0),
+            Vec3::new(0.0, -1.0, 0.0),
+            0.0,
+            1,
+        );
+        let rec = outside();
+        let up = Vec3::new(0.0, 1.0, 0.0);
+        let black = Principled {
+            roughness: constant(0.2),
+            ..Principled::new(constant(0.0))
+        };
+        let red = Principled {
+            metallic: constant(1.0),
+            roughness: constant(0.2),
+            ..Principled::new(Arc::new(ConstTexture(Vec3::new(0.9, 0.1, 0.1))))
+        };
+        let f = black.eval(&ray, &rec, up);
+        let g = red.eval(&ray, &rec, up);
+        assert!((f.x() - f.z()).abs() < 1e-6);
+        assert!((g.x() / f.x() - 0.9 / 0.04).abs() < 0.5);
+        assert!((g.z() / f.z() - 0.1 / 0.04).abs() < 0.1);
+        // Transmission sees through, the opaque base does not.
+        let down = Vec3::new(0.0, -1.0, 0.0);
+        assert!(black.eval(&ray, &rec, down).y() == 0.0);
+        let clear = Principled {
+            transmission: constant(1.0),
+            roughness: constant(0.2),
+            ..Principled::new(constant(1.0))
+        };
+        assert!(clear.eval(&ray, &rec, down).y() > 0.0);
+    }
+}
@@ -0,0 +1,620 @@
+//! Analytic surfaces around the z axis in the style of pbrt: partial
+//! spheres, cylinders, cones, paraboloids, hyperboloids and tori.
+//!
+//! All of them can be cut to a sweep of `phimax` degrees around the axis,
+//! starting at the positive x axis, and all but the torus to a range of
+//! heights `zmin..zmax`. `u` runs along the sweep and `v` up the height,
+//! or around the tube of a torus. The shapes are placed in a scene with a
+//! transform.
+
+use ::std::default::Default;
+use ::std::iter::Iterator;
+use ::std::option::Option::{self, None, Some};
+use ::std::vec::Vec;
+
+use ::math::{Point3, Vec3};
+
+use crate::pbrt::{HitRecord, Hitable, Ray, Span, AABB};
+
+const PI: f64 = ::std::f64::consts::PI;
+
+/// Evaluates the polynomial with the coefficients `coeffs`, constant first.
+fn evaluate(coeffs: &[f64], x: f64) -> f64 {
+    coeffs.iter().rev().fold(0.0, |acc, &c| acc * x + c)
+}
+
+/// Pushes the roots of the polynomial `coeffs`, constant first, that lie in
+/// `lo..=hi` to `roots` in increasing order. Between two roots of the
+/// derivative the polynomial is monotonic, so each of those intervals holds
+/// at most one root, which bisection finds to full precision. Roots where
+/// the polynomial only touches zero are missed.
+fn polynomial_roots(coeffs: &[f64], lo: f64, hi: f64, roots: &mut Vec<f64>) {
+    let degree = match coeffs.iter().rposition(|&c| c != 0.0) {
+        Some(degree) => degree,
+        None => return,
+    };
+    let coeffs = &coeffs[..=degree];
+    match degree {
+        0 => return,
+        1 => {
+            let root = -coeffs[0] / coeffs[1];
+            if root >= lo && root <= hi {
+                roots.push(root);
+            }
+            return;
+        }
+        _ => {}
+    }
+    let derivative: Vec<f64> =
+        (1..=degree).map(|i| i as f64 * coeffs[i]).collect();
+    let mut bounds = Vec::with_capacity(degree + 1);
+    bounds.push(lo);
+    polynomial_roots(&derivative, lo, hi, &mut bounds);
+    bounds.push(hi);
+
+    let start = roots.len();
+    for pair in bounds.windows(2) {
+        let (mut x0, mut x1) = (pair[0], pair[1]);
+        let (f0, f1) = (evaluate(coeffs, x0), evaluate(coeffs, x1));
+        if f0 == 0.0 {
+            if roots.len() == start || roots[roots.len() - 1] < x0 {
+                roots.push(x0);
+            }
+            continue;
+        }
+        if f1 == 0.0 {
+            roots.push(x1);
+            continue;
+        }
+        if (f0 < 0.0) == (f1 < 0.0) {
+            continue;
+        }
+        let rising = f1 > f0;
+        // Stops once the midpoint no longer moves.
+        loop {
+            let mid = 0.5 * (x0 + x1);
+            if mid <= x0 || mid >= x1 {
+                break;
+            }
+            if (evaluate(coeffs, mid) < 0.0) == rising {
+                x0 = mid;
+            } else {
+                x1 = mid;
+            }
+        }
+        roots.push(0.5 * (x0 + x1));
+    }
+}
+
+/// The real roots of a x² + b x + c in increasing order, computed without
+/// cancellation. A linear equation has its root twice.
+fn solve_quadratic(a: f64, b: f64, c: f64) -> Option<(f64, f64)> {
+    if a == 0.0 {
+        if b == 0.0 {
+            return None;
+        }
+        return Some((-c / b, -c / b));
+    }
+    let discriminant = b * b - 4.0 * a * c;
+    if discriminant < 0.0 {
+        return None;
+    }
+    let q = -0.5 * (b + discriminant.sqrt().copysign(b));
+    if q == 0.0 {
+        return Some((0.0, 0.0));
+    }
+    let (t0, t1) = (q / a, c / q);
+    Some((t0.min(t1), t0.max(t1)))
+}
+
+/// The real roots in `lo..=hi` of the quartic `coeffs`, constant first, in
+/// increasing order.
+pub fn solve_quartic(coeffs: [f64; 5], lo: f64, hi: f64) -> Vec<f64> {
+    let mut roots = Vec::with_capacity(4);
+    polynomial_roots(&coeffs, lo, hi, &mut roots);
+    roots
+}
+
+/// `phimax` degrees in radians, at most all the way around.
+fn sweep(phimax: f32) -> f64 {
+    (phimax.clamp(0.0, 360.0) as f64).to_radians()
+}
+
+/// The angle of `p` around the z axis in `0..2π`.
+fn azimuth(p: Point3) -> f64 {
+    let phi = (p.y() as f64).atan2(p.x() as f64);
+    if phi < 0.0 {
+        phi + 2.0 * PI
+    } else {
+        phi
+    }
+}
+
+/// The bounds in x and y of the part of the ring between the radii `rmin`
+/// and `rmax` that is swept from the x axis to `phimax` radians: its
+/// corners and the points where it crosses an axis.
+fn sector_bounds(rmin: f32, rmax: f32, phimax: f64) -> (f32, f32, f32, f32) {
+    let (mut x0, mut x1, mut y0, mut y1) = (rmin, rmax, 0.0f32, 0.0f32);
+    let mut extend = |r: f32, phi: f64| {
+        let (x, y) = (r * phi.cos() as f32, r * phi.sin() as f32);
+        x0 = x0.min(x);
+        x1 = x1.max(x);
+        y0 = y0.min(y);
+        y1 = y1.max(y);
+    };
+    extend(rmin, phimax);
+    extend(rmax, phimax);
+    for k in 1..4 {
+        let phi = k as f64 * 0.5 * PI;
+        if phi <= phimax {
+            extend(rmax, phi);
+        }
+    }
+    (x0, x1, y0, y1)
+}
+
+/// A surface of revolution around the z axis whose squared radius is a
+/// quadratic in the height, x² + y² = a z² + b z + c, between `zmin` and
+/// `zmax` and swept by `phimax` degrees. The normals point away from the
+/// axis.
+#[derive(Debug)]
+pub struct Quadric {
+    a: f64,
+    b: f64,
+    c: f64,
+    zmin: f32,
+    zmax: f32,
+    phimax: f64,
+    material: usize,
+}
+
+impl Quadric {
+    fn new(
+        (a, b, c): (f64, f64, f64), zmin: f32, zmax: f32, phimax: f32,
+        material: usize,
+    ) -> Self {
+        Quadric {
+            a,
+            b,
+            c,
+            zmin: zmin.min(zmax),
+            zmax: zmin.max(zmax),
+            phimax: sweep(phimax),
+            material,
+        }
+    }
+
+    /// A sphere around the origin, cut to the heights `zmin..zmax`.
+    pub fn sphere(
+        radius: f32, zmin: f32, zmax: f32, phimax: f32, material: usize,
+    ) -> Self {
+        let (zmin, zmax) = (zmin.max(-radius), zmax.min(radius));
+        let r2 = radius as f64 * radius as f64;
+        Quadric::new((-1.0, 0.0, r2), zmin, zmax, phimax, material)
+    }
+
+    pub fn cylinder(
+        radius: f32, zmin: f32, zmax: f32, phimax: f32, material: usize,
+    ) -> Self {
+        let r2 = radius as f64 * radius as f64;
+        Quadric::new((0.0, 0.0, r2), zmin, zmax, phimax, material)
+    }
+
+    /// A cone with its base of `radius` at z = 0 and its apex at `height`.
+    pub fn cone(
+        radius: f32, height: f32, phimax: f32, material: usize,
+    ) -> Self {
+        let (r2, h) = (radius as f64 * radius as f64, height as f64);
+        let coeffs = (r2 / (h * h), -2.0 * r2 / h, r2);
+        Quadric::new(coeffs, 0.0, height, phimax, material)
+    }
+
+    /// A paraboloid with its tip at the origin and `radius` at `zmax`.
+    pub fn paraboloid(
+        radius: f32, zmin: f32, zmax: f32, phimax: f32, material: usize,
+    ) -> Self {
+        let r2 = radius as f64 * radius as f64;
+        let coeffs = (0.0, r2 / zmax as f64, 0.0);
+        Quadric::new(coeffs, zmin.max(0.0), zmax, phimax, material)
+    }
+
+    /// The surface that the line from `p1` to `p2` sweeps around the z
+    /// axis. The points need different heights.
+    pub fn hyperboloid(
+        p1: Point3, p2: Point3, phimax: f32, material: usize,
+    ) -> Self {
+        // The line is q + z d in x and y.
+        let dz = (p2.z() - p1.z()) as f64;
+        let (dx, dy) =
+            ((p2.x() - p1.x()) as f64 / dz, (p2.y() - p1.y()) as f64 / dz);
+        let z1 = p1.z() as f64;
+        let (qx, qy) = (p1.x() as f64 - z1 * dx, p1.y() as f64 - z1 * dy);
+        let coeffs = (
+            dx * dx + dy * dy,
+            2.0 * (qx * dx + qy * dy),
+            qx * qx + qy * qy,
+        );
+        Quadric::new(coeffs, p1.z(), p2.z(), phimax, material)
+    }
+
+    fn radius_squared(&self, z: f64) -> f64 {
+        (self.a * z + self.b) * z + self.c
+    }
+
+    /// Whether the point at `t` on `r` is on the cut surface; its record
+    /// if so.
+    fn record(&self, r: &Ray, t: f64, rec: &mut HitRecord) -> bool {
+        let p = r.point_at_param(t as f32);
+        if p.z() < self.zmin || p.z() > self.zmax {
+            return false;
+        }
+        let phi = azimuth(p);
+        if phi > self.phimax {
+            return false;
+        }
+        // The gradient of x² + y² - (a z² + b z + c), halved.
+        let dz = self.a * p.z() as f64 + 0.5 * self.b;
+        let gradient = Vec3::new(p.x(), p.y(), -dz as f32);
+        let length = gradient.length();
+        rec.t = t as f32;
+        rec.p = p;
+        // Only the apex of a cone has none.
+        rec.normal = if length > 0.0 {
+            gradient / length
+        } else {
+            Vec3::new(0.0, 0.0, 1.0)
+        };
+        rec.material = self.material;
+        rec.dpdu = self.phimax as f32 * Vec3::new(-p.y(), p.x(), 0.0);
+        // The radius changes by dz / radius per unit of height.
+        let rho2 = p.x() * p.x() + p.y() * p.y();
+        let spread = if rho2 > 0.0 { dz as f32 / rho2 } else { 0.0 };
+        rec.dpdv = (self.zmax - self.zmin)
+            * Vec3::new(p.x() * spread, p.y() * spread, 1.0);
+        rec.u = (phi / self.phimax) as f32;
+        rec.v = (p.z() - self.zmin) / (self.zmax - self.zmin);
+        true
+    }
+}
+
+impl Hitable for Quadric {
+    fn hit(
+        &self, r: &Ray, t_min: f32, t_max: f32, rec: &mut HitRecord,
+    ) -> bool {
+        let (ox, oy, oz) = (
+            r.origin.x() as f64,
+            r.origin.y() as f64,
+            r.origin.z() as f64,
+        );
+        let (dx, dy, dz) = (
+            r.direction.x() as f64,
+            r.direction.y() as f64,
+            r.direction.z() as f64,
+        );
+        let (a, b) = (self.a, self.b);
+        let (t0, t1) = match solve_quadratic(
+            dx * dx + dy * dy - a * dz * dz,
+            2.0 * (ox * dx + oy * dy - a * oz * dz) - b * dz,
+            ox * ox + oy * oy - self.radius_squared(oz),
+        ) {
+            Some(roots) => roots,
+            None => return false,
+        };
+        [t0, t1].iter().any(|&t| {
+            t > t_min as f64 && t < t_max as f64 && self.record(r, t, rec)
+        })
+    }
+
+    fn bounding_box(&self, _: f32, _: f32) -> Option<AABB> {
+        let (zmin, zmax) = (self.zmin as f64, self.zmax as f64);
+        let mut r2 = [self.radius_squared(zmin), self.radius_squared(zmax)];
+        // The widest or narrowest point of a hyperboloid or sphere.
+        if self.a != 0.0 {
+            let z = -0.5 * self.b / self.a;
+            if z > zmin && z < zmax {
+                let extreme = self.radius_squared(z);
+                r2[0] = r2[0].min(extreme);
+                r2[1] = r2[1].max(extreme);
+            }
+        }
+        let (rmin, rmax) = (
+            r2[0].min(r2[1]).max(0.0).sqrt() as f32,
+            r2[0].max(r2[1]).max(0.0).sqrt() as f32,
+        );
+        let (x0, x1, y0, y1) = sector_bounds(rmin, rmax, self.phimax);
+        Some(AABB::new(
+            Point3::new(x0, y0, self.zmin),
+            Point3::new(x1, y1, self.zmax),
+        ))
+    }
+
+    fn material(&self) -> Option<usize> {
+        Some(self.material)
+    }
+}
+
+/// A ring around the z axis with the tube of radius `minor` at `major`
+/// from the axis, swept by `phimax` degrees. `v` starts on the outside of
+/// the tube and runs up over the top.
+#[derive(Debug)]
+pub struct Torus {
+    major: f32,
+    minor: f32,
+    phimax: f64,
+    material: usize,
+}
+
+impl Torus {
+    pub fn new(major: f32, minor: f32, phimax: f32, material: usize) -> Self {
+        Torus {
+            major,
+            minor,
+            phimax: sweep(phimax),
+            material,
+        }
+    }
+
+    /// Whether the torus is closed all the way around and so encloses a
+    /// volume.
+    pub fn is_closed(&self) -> bool {
+        self.phimax >= 2.0 * PI
+    }
+
+    /// The intersections with the whole line through `r`, in order. The
+    /// quartic is set up around the point on the line that is nearest to
+    /// the center, which keeps it well conditioned for distant origins.
+    fn roots(&self, r: &Ray) -> Vec<f64> {
+        let d = r.direction;
+        let dd = d.dot(d) as f64;
+        let shift = -(r.origin - Point3::default()).dot(d) as f64 / dd;
+        let o = r.point_at_param(shift as f32);
+        let (ox, oy, oz) = (o.x() as f64, o.y() as f64, o.z() as f64);
+        let (dx, dy, dz) = (d.x() as f64, d.y() as f64, d.z() as f64);
+        let (major, minor) = (self.major as f64, self.minor as f64);
+        // Outside of the bounding sphere there is nothing to find.
+        let outer = major + minor;
+        let oo = ox * ox + oy * oy + oz * oz;
+        if oo > outer * outer {
+            return Vec::new();
+        }
+        let reach = ((outer * outer - oo) / dd).sqrt();
+
+        // (|o + s d|² + R² - r²)² = 4 R² ((ox + s dx)² + (oy + s dy)²)
+        let g = dd;
+        let h = 2.0 * (ox * dx + oy * dy + oz * dz);
+        let i = oo + major * major - minor * minor;
+        let k = 4.0 * major * major;
+        let coeffs = [
+            i * i - k * (ox * ox + oy * oy),
+            2.0 * h * i - 2.0 * k * (ox * dx + oy * dy),
+            h * h + 2.0 * g * i - k * (dx * dx + dy * dy),
+            2.0 * g * h,
+            g * g,
+        ];
+        let mut roots = solve_quartic(coeffs, -reach, reach);
+        for root in &mut roots {
+            *root += shift;
+        }
+        roots
+    }
+
+    fn record(&self, r: &Ray, t: f64, rec: &mut HitRecord) -> bool {
+        let p = r.point_at_param(t as f32);
+        let phi = azimuth(p);
+        if phi > self.phimax {
+            return false;
+        }
+        let rho = (p.x() as f64).hypot(p.y() as f64);
+        let (x, y) = if rho > 0.0 {
+            (p.x() as f64 / rho, p.y() as f64 / rho)
+        } else {
+            (1.0, 0.0)
+        };
+        let major = self.major as f64;
+        let center = Point3::new((major * x) as f32, (major * y) as f32, 0.0);
+        let theta = (p.z() as f64).atan2(rho - major);
+        let theta = if theta < 0.0 { theta + 2.0 * PI } else { theta };
+        rec.t = t as f32;
+        rec.p = p;
+        rec.normal = (p - center).unit();
+        rec.material = self.material;
+        rec.dpdu = self.phimax as f32 * Vec3::new(-p.y(), p.x(), 0.0);
+        // Turning the offset from the center a quarter turn in its plane.
+        let (x, y, tube) = (x as f32, y as f32, (rho - major) as f32);
+        rec.dpdv = 2.0
+            * ::std::f32::consts::PI
+            * Vec3::new(-p.z() * x, -p.z() * y, tube);
+        rec.u = (phi / self.phimax) as f32;
+        rec.v = (theta / (2.0 * PI)) as f32;
+        true
+    }
+}
+
+impl Hitable for Torus {
+    fn hit(
+        &self, r: &Ray, t_min: f32, t_max: f32, rec: &mut HitRecord,
+    ) -> bool {
+        self.roots(r).iter().any(|&t| {
+            t > t_min as f64 && t < t_max as f64 && self.record(r, t, rec)
+        })
+    }
+
+    /// Only a closed torus has an inside. The line alternately enters and
+    /// leaves it.
+    fn hit_all(&self, r: &Ray, spans: &mut Vec<Span>) -> bool {
+        if !self.is_closed() {
+            return false;
+        }
+        let roots = self.roots(r);
+        for pair in roots.chunks_exact(2) {
+            let mut span = Span::default();
+            self.record(r, pair[0], &mut span.enter);
+            self.record(r, pair[1], &mut span.exit);
+            spans.push(span);
+        }
+        true
+    }
+
+    fn bounding_box(&self, _: f32, _: f32) -> Option<AABB> {
+        let rmin = (self.major - self.minor).max(0.0);
+        let (x0, x1, y0, y1) =
+            sector_bounds(rmin, self.major + self.minor, self.phimax);
+        Some(AABB::new(
+            Point3::new(x0, y0, -self.minor),
+            Point3::new(x1, y1, self.minor),
+        ))
+    }
+
+    fn material(&self) -> Option<usize> {
+        Some(self.material)
+    }
+}
+
+#[cfg(test)]
+pub mod tests {
+    use super::*;
+    use ::std::{assert, assert_eq};
+
+    fn ray(origin: (f32, f32, f32), direction: (f32, f32, f32)) -> Ray {
+        Ray::new(
+            Point3::new(origin.0, origin.1, origin.2),
+            Vec3::new(direction.0, direction.1, direction.2),
+            0.0,
+            0,
+        )
+    }
+
+    fn hit(hitable: &dyn Hitable, r: &Ray) -> Option<HitRecord> {
+        let mut rec = HitRecord::default();
+        if hitable.hit(r, 0.001, f32::MAX, &mut rec) {
+            Some(rec)
+        } else {
+            None
+        }
+    }
+
+    fn near(a: Vec3, b: Vec3) -> bool {
+        (a - b).length() < 1e-4
+    }
+
+    #[test]
+    fn test_solve_quartic() {
+        // (x - 1)(x - 2)(x - 3)(x - 4)
+        let coeffs = [24.0, -50.0, 35.0, -10.0, 1.0];
+        let roots = solve_quartic(coeffs, -10.0, 10.0);
+        assert_eq!(roots.len(), 4);
+        for (i, root) in roots.iter().enumerate() {
+            assert!((root - (i + 1) as f64).abs() < 1e-12);
+        }
+        assert_eq!(solve_quartic(coeffs, 1.5, 3.5).len(), 2);
+        assert!(
+            solve_quartic([1.0, 0.0, 0.0, 0.0, 1.0], -10.0, 10.0).is_empty()
+        );
+        // (x - 0.001)(x - 1000)(x² + 1) has roots far apart.
+        let coeffs = [1.0, -1000.001, 2.0, -1000.001, 1.0];
+        let roots = solve_quartic(coeffs, -1e4, 1e4);
+        assert_eq!(roots.len(), 2);
+        assert!((roots[0] - 0.001).abs() < 1e-12);
+        assert!((roots[1] - 1000.0).abs() < 1e-9);
+    }
+
+    #[test]
+    fn test_quadrics() {
+        let cylinder = Quadric::cylinder(1.0, -1.0, 1.0, 360.0, 7);
+        let rec = hit(&cylinder, &ray((5.0, 0.0, 0.0), (-1.0, 0.0, 0.0)));
+        let rec = rec.unwrap();
+        assert!((rec.t - 4.0).abs() < 1e-5);
+        assert!(near(rec.normal, Vec3::new(1.0, 0.0, 0.0)));
+        assert_eq!((rec.material, rec.u, rec.v), (7, 0.0, 0.5));
+        assert!(
+            hit(&cylinder, &ray((0.0, 0.0, 5.0), (0.0, 0.0, -1.0))).is_none()
+        );
+        assert!(
+            hit(&cylinder, &ray((5.0, 0.0, 2.0), (-1.0, 0.0, 0.0))).is_none()
+        );
+
+        // A quarter of the cylinder is only hit from behind here.
+        let quarter = Quadric::cylinder(1.0, -1.0, 1.0, 90.0, 7);
+        let r = ray((-5.0, 0.0, 0.0), (1.0, 0.0, 0.0));
+        assert!((hit(&quarter, &r).unwrap().t - 6.0).abs() < 1e-5);
+        let bb = quarter.bounding_box(0.0, 0.0).unwrap();
+        assert!(near(
+            bb.min() - Point3::new(0.0, 0.0, -1.0),
+            Vec3::default()
+        ));
+        assert!(near(bb.max() - Point3::new(1.0, 1.0, 1.0), Vec3::default()));
+
+        // The radius of the cone is 0.75 at z = 0.5.
+        let cone = Quadric::cone(1.0, 2.0, 360.0, 0);
+        let rec = hit(&cone, &ray((5.0, 0.0, 0.5), (-1.0, 0.0, 0.0))).unwrap();
+        assert!((rec.t - 4.25).abs() < 1e-5);
+        assert!(near(rec.normal, Vec3::new(2.0, 0.0, 1.0).unit()));
+        assert!((rec.v - 0.25).abs() < 1e-6);
+
+        let paraboloid = Quadric::paraboloid(1.0, 0.0, 1.0, 360.0, 0);
+        let r = ray((0.5, 0.0, 5.0), (0.0, 0.0, -1.0));
+        let rec = hit(&paraboloid, &r).unwrap();
+        assert!((rec.t - 4.75).abs() < 1e-5);
+        assert!(near(rec.normal, Vec3::new(0.5, 0.0, -0.5).unit()));
+
+        // The line from (1, 0, 0) to (0, 1, 1) is nearest to the axis
+        // halfway, at a radius of sqrt(1/2).
+        let hyperboloid = Quadric::hyperboloid(
+            Point3::new(1.0, 0.0, 0.0),
+            Point3::new(0.0, 1.0, 1.0),
+            360.0,
+            0,
+        );
+        let r = ray((5.0, 0.0, 0.5), (-1.0, 0.0, 0.0));
+        let rec = hit(&hyperboloid, &r).unwrap();
+        assert!((rec.t - (5.0 - 0.5f32.sqrt())).abs() < 1e-5);
+        assert!(near(rec.normal, Vec3::new(1.0, 0.0, 0.0)));
+        let bb = hyperboloid.bounding_box(0.0, 0.0).unwrap();
+        assert!(near(bb.max() - Point3::new(1.0, 1.0, 1.0), Vec3::default()));
+
+        let cap = Quadric::sphere(1.0, 0.5, 2.0, 360.0, 0);
+        let r = ray((0.0, 0.0, 5.0), (0.0, 0.0, -1.0));
+        assert!((hit(&cap, &r).unwrap().t - 4.0).abs() < 1e-5);
+        let r = ray((5.0, 0.0, 0.0), (-1.0, 0.0, 0.0));
+        assert!(hit(&cap, &r).is_none());
+    }
+
+    #[test]
+    fn test_torus() {
+        let torus = Torus::new(2.0, 0.5, 360.0, 3);
+        let r = ray((10.0, 0.0, 0.0), (-1.0, 0.0, 0.0));
+        let rec = hit(&torus, &r).unwrap();
+        assert!((rec.t - 7.5).abs() < 1e-5);
+        assert!(near(rec.normal, Vec3::new(1.0, 0.0, 0.0)));
+        assert_eq!((rec.material, rec.u, rec.v), (3, 0.0, 0.0));
+        let mut spans = Vec::new();
+        assert!(torus.hit_all(&r, &mut spans));
+        let ts: Vec<(f32, f32)> =
+            spans.iter().map(|s| (s.enter.t, s.exit.t)).collect();
+        assert_eq!(ts.len(), 2);
+        assert!((ts[0].1 - 8.5).abs() < 1e-5 && (ts[1].0 - 11.5).abs() < 1e-5);
+
+        // Straight down onto the top of the tube.
+        let r = ray((0.0, 2.0, 10.0), (0.0, 0.0, -1.0));
+        let rec = hit(&torus, &r).unwrap();
+        assert!((rec.t - 9.5).abs() < 1e-5);
+        assert!((rec.u - 0.25).abs() < 1e-6 && (rec.v - 0.25).abs() < 1e-6);
+
+        // Accurate from far away, too.
+        let r = ray((1e4, 0.0, 0.1), (-1.0, 0.0, 0.0));
+        let expected = 1e4 - 2.0 - 0.24f32.sqrt();
+        assert!((hit(&torus, &r).unwrap().t - expected).abs() < 1e-2);
+
+        // The half torus on the positive y side is open.
+        let half = Torus::new(2.0, 0.5, 180.0, 3);
+        let r = ray((0.0, -10.0, 0.0), (0.0, 1.0, 0.0));
+        assert!((hit(&half, &r).unwrap().t - 11.5).abs() < 1e-5);
+        assert!(!half.hit_all(&r, &mut spans));
+        let bb = half.bounding_box(0.0, 0.0).unwrap();
+        assert!(near(
+            bb.min() - Point3::new(-2.5, 0.0, -0.5),
+            Vec3::default()
+        ));
+        assert!(near(bb.max() - Point3::new(2.5, 2.5, 0.5), Vec3::default()));
+    }
+}
@@ -0,0 +1,250 @@
+//! Renders an image in square tiles on a thread pool.
+//!
+//! Every tile has its own random stream, derived from the render seed and
+//! the tile's index. A tile's pixels therefore do not depend on which worker
+//! renders it or when, and the image is the same for any number of threads.
+
+use ::std::clone::Clone;
+use ::std::cmp;
+use ::std::io::{self, Write};
+use ::std::iter::{IntoIterator, Iterator};
+use ::std::result::Result::{self, Err, Ok};
+use ::std::vec::Vec;
+use ::std::{write, writeln};
+
+use crate::executor::{TaskError, ThreadPool};
+use crate::film::{Film, FilmTile, Filter};
+use crate::pbrt::RNG;
+use crate::scene::Scene;
+
+/// Edge length of a tile in pixels.
+pub const TILE_SIZE: usize = 32;
+
+/// A rectangle of pixels, `x0..x1` by `y0..y1`, rows counted from the top.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Tile {
+    pub x0: usize,
+    pub y0: usize,
+    pub x1: usize,
+    pub y1: usize,
+}
+
+/// Splits an image into tiles in row-major order.
+pub fn tiles(width: usize, height: usize) -> Vec<Tile> {
+    let mut tiles = Vec::new();
+    for y0 in (0..height).step_by(TILE_SIZE) {
+        for x0 in (0..width).step_by(TILE_SIZE) {
+            tiles.push(Tile {
+                x0,
+                y0,
+                x1: cmp::min(x0 + TILE_SIZE, width),
+                y1: cmp::min(y0 + TILE_SIZE, height),
+            });
+        }
+    }
+    tiles
+}
+
+/// Traces the samples of the pixels in `tile` into `film_tile`.
+fn render_tile(
+    scene: &Scene, filter: &dyn Filter, tile: Tile, film_tile: &mut FilmTile,
+    rng: &mut RNG,
+) {
+    let settings = &scene.settings;
+    let (width, height) = (settings.width as f32, settings.height as f32);
+    // With many samples per pixel, each sample only has to cover its share
+    // of the pixel, so textures can be sharper.
+    let scale = (1.0 / (settings.samples as f32).sqrt()).max(0.125);
+    let pixel = (scale / width, -scale / height);
+    for y in tile.y0..tile.y1 {
+        for x in tile.x0..tile.x1 {
+            for _ in 0..settings.samples {
+                let fx = x as f32 + rng.rand();
+                let fy = y as f32 + rng.rand();
+                // The camera's v points up.
+                let (u, v) = (fx / width, 1.0 - fy / height);
+                let r =
+                    scene.camera.get_ray(rng, u, v, pixel, settings.ray_depth);
+                let radiance =
+                    r.trace(rng, &scene.world, &scene.lights, &scene.matlib);
+                film_tile.add_sample(filter, fx, fy, radiance);
+            }
+        }
+    }
+}
+
+struct Progress {
+    current: usize,
+    total: usize,
+}
+
+impl Progress {
+    fn new(total: usize) -> Self {
+        Progress { current: 0, total }
+    }
+
+    fn increment(&mut self) {
+        self.current += 1
+    }
+
+    fn fraction(&self) -> f32 {
+        self.current as f32 / self.total as f32
+    }
+
+    fn report(&self) {
+        let mut stderr = io::stderr();
+        let _ = write!(stderr, "\rRendering {:3.0}%", 100.0 * self.fraction());
+        if self.current == self.total {
+            let _ = writeln!(stderr);
+        }
+        let _ = stderr.flush();
+    }
+}
+
+/// Renders `scene` with `threads` workers. One thread renders on the
+/// caller's thread without a pool. If a tile fails, the tiles that have not
+/// started yet are cancelled and the error is returned.
+pub fn render(
+    scene: &Scene, threads: usize, seed: u64,
+) -> Result<Film, TaskError> {
+    let settings = &scene.settings;
+    let filter = settings.filter.build();
+    let mut film = Film::new(settings.width, settings.height, &*filter);
+    let tiles = tiles(settings.width, settings.height);
+    let mut progress = Progress::new(tiles.len());
+    let mut store = |film: &mut Film, film_tile: FilmTile| {
+        film.merge(film_tile);
+        progress.increment();
+        progress.report();
+    };
+
+    if threads <= 1 {
+        for (i, &tile) in tiles.iter().enumerate() {
+            let mut film_tile = film.tile(tile);
+            let mut rng = RNG::new(seed, i as u64);
+            render_tile(scene, &*filter, tile, &mut film_tile, &mut rng);
+            store(&mut film, film_tile);
+        }
+        return Ok(film);
+    }
+
+    let pool = ThreadPool::new(threads);
+    let film_tiles: Vec<_> =
+        tiles.iter().map(|&tile| film.tile(tile)).collect();
+    let filter = &*filter;
+    pool.scope(|s| {
+        let handles: Vec<_> = tiles
+            .iter()
+            .zip(film_tiles)
+            .enumerate()
+            .map(|(i, (&tile, mut film_tile))| {
+                s.spawn(move || {
+                    let mut rng = RNG::new(seed, i as u64);
+                    render_tile(scene, filter, tile, &mut film_tile, &mut rng);
+                    film_tile
+                })
+            })
+            .collect();
+        // Merging in tile order keeps the sums independent of scheduling.
+        let mut handles = handles.into_iter();
+        for handle in &mut handles {
+            match handle.join() {
+                Ok(film_tile) => store(&mut film, film_tile),
+                Err(e) => {
+                    handles.for_each(|h| h.cancel());
+                    return Err(e);
+                }
+            }
+        }
+        Ok(film)
+    })
+}
+
+#[cfg(test)]
+pub mod tests {
+    use super::*;
+    use ::std::assert_eq;
+    use ::std::default::Default;
+    use ::std::path::Path;
+
+    use crate::image::Image;
+    use crate::scene::SceneDesc;
+
+    const SCENE: &str = r#"
+        version = 1
+
+        [render]
+        width = 70
+        height = 40
+        samples = 2
+        max_depth = 4
+        filter = { type = "gaussian" }
+
+        [camera]
+        look_from = [0, 1, 6]
+        look_at = [0, 0.5, 0]
+        fov = 40
+
+        [materials.ground]
+        type = "lambertian"
+        albedo = [0.5, 0.5, 0.5]
+
+        [materials.glass]
+        type = "dielectric"
+        ior = 1.5
+
+        [materials.sky]
+        type = "diffuse_light"
+        emit = [1, 1, 1]
+
+        [[objects]]
+        type = "sphere"
+        center = [0, -1000, 0]
+        radius = 1000
+        material = "ground"
+
+        [[objects]]
+        type = "sphere"
+        center = [0, 1, 0]
+        radius = 1
+        material = "glass"
+
+        [[objects]]
+        type = "sphere"
+        center = [0, 0, 0]
+        radius = 100
+        material = "sky"
+    "#;
+
+    #[test]
+    fn test_tiles() {
+        let tiles = tiles(70, 40);
+        assert_eq!(tiles.len(), 6);
+        assert_eq!(
+            tiles[5],
+            Tile {
+                x0: 64,
+                y0: 32,
+                x1: 70,
+                y1: 40
+            }
+        );
+    }
+
+    #[test]
+    fn test_render_is_deterministic() {
+        let path = Path::new("test.toml");
+        let desc = SceneDesc::from_toml(SCENE, path).unwrap();
+        let scene = desc.build(path, &mut RNG::default()).unwrap();
+        let single = render(&scene, 1, 7).unwrap().image(1.0);
+        let parallel = render(&scene, 4, 7).unwrap().image(1.0);
+        let bits = |image: &Image| -> Vec<[u32; 3]> {
+            image
+                .pixels
+                .iter()
+                .map(|c| [c.x().to_bits(), c.y().to_bits(), c.z().to_bits()])
+                .collect()
+        };
+        assert_eq!(bits(&single), bits(&parallel));
+    }
+}
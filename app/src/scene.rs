@@ -0,0 +1,4715 @@
+//! The native scene format.
+//!
+//! Scenes are TOML files. Textures and materials are declared in named
+//! tables and referenced by name, objects are an array of tables:
+//!
+//! ```toml
+//! version = 1
+//!
+//! [render]
+//! width = 400
+//! height = 200
+//!
+//! [camera]
+//! look_from = [13, 2, 3]
+//! look_at = [0, 0, 0]
+//! fov = 20
+//!
+//! [materials.glass]
+//! type = "dielectric"
+//! ior = 1.5
+//!
+//! [[objects]]
+//! type = "sphere"
+//! center = [0, 1, 0]
+//! radius = 1
+//! material = "glass"
+//! ```
+//!
+//! Any object can be placed with a transform, applied as scale, then
+//! rotation in degrees around an axis (+y by default), then translation:
+//!
+//! ```toml
+//! [[objects]]
+//! type = "obj"
+//! file = "bunny.obj"
+//! transform = { translate = [1, 0, 0], rotate = 90, scale = 2 }
+//! ```
+//!
+//! Fog and smoke are media inside a convex boundary object, with a phase
+//! function as their material:
+//!
+//! ```toml
+//! [materials.smoke]
+//! type = "henyey_greenstein"
+//! albedo = [0.8, 0.8, 0.8]
+//! g = 0.5
+//!
+//! [[objects]]
+//! type = "constant_medium"
+//! density = 0.2
+//! material = "smoke"
+//!
+//! [objects.boundary]
+//! type = "sphere"
+//! center = [0, 1, 0]
+//! radius = 1
+//! material = "smoke"
+//! ```
+//!
+//! Cylinders, cones, paraboloids, hyperboloids and tori stand on the z axis
+//! and are placed with a transform. They can be cut to a sweep of `phimax`
+//! degrees around it:
+//!
+//! ```toml
+//! [[objects]]
+//! type = "cylinder"
+//! radius = 0.5
+//! zmin = 0
+//! zmax = 2
+//! phimax = 270
+//! material = "steel"
+//! transform = { rotate = -90, axis = [1, 0, 0] }
+//! ```
+//!
+//! Signed distance fields describe shapes with a tree of primitives and
+//! operations, searched for inside the given bounds:
+//!
+//! ```toml
+//! [[objects]]
+//! type = "sdf"
+//! min = [-1, -1, -1]
+//! max = [1, 1, 1]
+//! material = "white"
+//!
+//! [objects.shape]
+//! type = "twist"
+//! rate = 90
+//!
+//! [objects.shape.shape]
+//! type = "box"
+//! half_size = [0.7, 1, 0.2]
+//! radius = 0.05
+//! ```
+//!
+//! Subdivision surfaces refine a control cage of polygons, optionally with
+//! creased edges, and Bézier patches are tessellated. Both become triangle
+//! meshes, and the Utah teapot is built in:
+//!
+//! ```toml
+//! [[objects]]
+//! type = "subdivision"
+//! positions = [[1, 1, 1], [1, -1, -1], [-1, 1, -1], [-1, -1, 1]]
+//! faces = [[0, 1, 2], [0, 3, 1], [0, 2, 3], [1, 3, 2]]
+//! creases = [{ edge = [0, 1], sharpness = 2 }]
+//! levels = 4
+//! material = "clay"
+//!
+//! [[objects]]
+//! type = "teapot"
+//! divisions = 8
+//! material = "white"
+//! transform = { rotate = -90, axis = [1, 0, 0] }
+//! ```
+//!
+//! Hair and fur are strands of cubic Bézier curves, 3n + 1 control points
+//! for n curves, that taper from one width to another. They are `flat`
+//! ribbons facing the ray, `cylinder` tubes or `ribbon`s turning between
+//! `normals` at the ends of the curves. The hair material absorbs by its
+//! `eumelanin` and `pheomelanin` concentrations, a `color` or `sigma_a`:
+//!
+//! ```toml
+//! [materials.auburn]
+//! type = "hair"
+//! eumelanin = 0.8
+//! pheomelanin = 0.6
+//! beta_m = 0.25
+//!
+//! [[objects]]
+//! type = "curve"
+//! points = [[0, 0, 0], [0, 1, 0], [0.2, 2, 0], [0.5, 3, 0]]
+//! width = [0.02, 0.005]
+//! kind = "cylinder"
+//! material = "auburn"
+//! ```
+//!
+//! Terrain comes from the heights in a gray 16-bit PNG or a PFM image,
+//! spanning x and z from 0 to the `size` with heights from 0 to `size.y`:
+//!
+//! ```toml
+//! [[objects]]
+//! type = "heightfield"
+//! file = "valley.png"
+//! size = [100, 12, 100]
+//! material = "grass"
+//! ```
+//!
+//! Metals reflect by the Fresnel equations of their complex index of
+//! refraction, either a `metal` preset (gold, copper, aluminium, silver or
+//! chrome) or `eta` and `k` for red, green and blue. `roughness` goes from
+//! a mirror at 0 to 1 and may be a pair, along the surface's u direction
+//! and across it, for brushed metal:
+//!
+//! ```toml
+//! [materials.brushed]
+//! type = "conductor"
+//! metal = "aluminium"
+//! roughness = [0.5, 0.1]
+//! ```
+//!
+//! Glass can be frosted with a `roughness` in the same way and tinted by
+//! absorption inside, given as the `color` that white light takes on after
+//! `distance` or directly as the `absorption` coefficient. Window panes
+//! are a `thin_dielectric`, which does not bend the light through it:
+//!
+//! ```toml
+//! [materials.bottle]
+//! type = "dielectric"
+//! ior = 1.5
+//! roughness = 0.2
+//! color = [0.4, 0.7, 0.4]
+//! distance = 0.5
+//! ```
+//!
+//! The `principled` material covers most surfaces with the parameters of
+//! Disney's model as other tools export them: `base_color`, `metallic`,
+//! `roughness`, `specular`, `specular_tint`, `sheen`, `sheen_tint`,
+//! `clearcoat`, `clearcoat_gloss`, `transmission` and `subsurface`. All
+//! are optional and all but the tints and the gloss may be textures:
+//!
+//! ```toml
+//! [materials.car_paint]
+//! type = "principled"
+//! base_color = [0.6, 0.05, 0.05]
+//! metallic = 0.3
+//! roughness = 0.4
+//! clearcoat = 1
+//! clearcoat_gloss = 0.9
+//! ```
+//!
+//! Materials can be made of other materials: a `mix` of `a` and `b` by the
+//! `amount` of `b`, a number or a texture; a `coated` `base` under a layer
+//! of glass with the `ior`, `roughness` and tint of a `dielectric` and a
+//! `thickness`, whose `color` is that after one crossing; and a
+//! `two_sided` surface with different materials on the `front`, where the
+//! normal points, and on the `back`:
+//!
+//! ```toml
+//! [materials.lacquered_wood]
+//! type = "coated"
+//! base = "wood"
+//! roughness = 0.05
+//! color = [0.9, 0.7, 0.4]
+//!
+//! [materials.worn]
+//! type = "mix"
+//! a = "lacquered_wood"
+//! b = "wood"
+//! amount = "scratches"
+//! ```
+//!
+//! Image textures read PNG, JPEG, PFM and Radiance HDR files. PNGs and
+//! JPEGs are taken to be sRGB encoded unless `srgb` is false. Outside the
+//! image, `wrap` repeats it (the default), `clamp`s to its edges or
+//! `mirror`s it. The `filter` over each pixel's footprint is `ewa` (the
+//! default), `trilinear` or `bilinear`, and `scale` repeats the image
+//! across (u, v):
+//!
+//! ```toml
+//! [textures.wood]
+//! type = "image"
+//! file = "wood.jpg"
+//! scale = [4, 4]
+//!
+//! [materials.floor]
+//! type = "lambertian"
+//! albedo = "wood"
+//! ```
+//!
+//! Spheres, boxes, closed tori and CSG objects are solids that can be combined with
+//! `union`, `intersection` or `difference`:
+//!
+//! ```toml
+//! [[objects]]
+//! type = "csg"
+//! operation = "difference"
+//!
+//! [objects.left]
+//! type = "box"
+//! min = [-1, 0, -1]
+//! max = [1, 2, 1]
+//! material = "white"
+//!
+//! [objects.right]
+//! type = "sphere"
+//! center = [0, 1, 0]
+//! radius = 1.3
+//! material = "red"
+//! ```
+//!
+//! `SceneDesc` is the in-memory form. It is built into a renderable `Scene`
+//! and can be written back out with `SceneDesc::to_toml`.
+
+use ::std::boxed::Box;
+use ::std::clone::Clone;
+use ::std::collections::{BTreeMap, HashMap};
+use ::std::convert::From;
+use ::std::default::Default;
+use ::std::fmt::{self, Display, Formatter};
+use ::std::fs;
+use ::std::io;
+use ::std::iter::{Extend, Iterator};
+use ::std::option::Option::{self, None, Some};
+use ::std::path::{Path, PathBuf};
+use ::std::result::Result::{self, Err, Ok};
+use ::std::str::FromStr;
+use ::std::string::{String, ToString};
+use ::std::sync::Arc;
+use ::std::vec::Vec;
+use ::std::{format, vec, write};
+
+use ::math::{Point3, Transform, Vec3};
+use ::toml::{Table, Value};
+
+use crate::bezier::{self, teapot};
+use crate::csg::{Csg, CsgOp};
+use crate::curve::{Curve, CurveKind};
+use crate::film::FilterDesc;
+use crate::hair::{Hair, HairAbsorption};
+use crate::heightfield::Heightfield;
+use crate::image::Image;
+use crate::layered::{Coated, Mix, TwoSided};
+use crate::material::{
+    conductor_preset, Conductor, Dielectric, DiffuseLight, HenyeyGreenstein,
+    Isotropic, Lambertian, Metal, ThinDielectric, CONDUCTOR_PRESETS,
+};
+use crate::medium::{load_grids, Blackbody, ConstantMedium, GridMedium};
+use crate::mesh::TriangleMesh;
+use crate::microfacet::TrowbridgeReitz;
+use crate::obj::{self, ObjError};
+use crate::pbrt::{Camera, Hitable, HitableList, Material, MaterialLibrary};
+use crate::pbrt::{Texture, TransformedHitable, AABB, BVH, RNG};
+use crate::principled::Principled;
+use crate::quadric::{Quadric, Torus};
+use crate::sdf::{Sdf, SdfShape};
+use crate::shapes::{
+    AxisRect, Cuboid, Disk, MovingSphere, Plane, Quad, Sphere,
+};
+use crate::subdiv::{Cage, Crease};
+use crate::texture::{
+    CheckerTexture, ConstTexture, FilterMode, ImageTexture, WrapMode,
+};
+
+pub const VERSION: i64 = 1;
+
+#[derive(Debug)]
+pub enum SceneError {
+    Io {
+        path: PathBuf,
+        error: io::Error,
+    },
+    Syntax {
+        path: PathBuf,
+        message: String,
+    },
+    Key {
+        path: PathBuf,
+        key: String,
+        message: String,
+    },
+    Obj(ObjError),
+}
+
+impl Display for SceneError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        match self {
+            SceneError::Io { path, error } => {
+                write!(f, "{}: {}", path.display(), error)
+            }
+            SceneError::Syntax { path, message } => {
+                write!(f, "{}: {}", path.display(), message)
+            }
+            SceneError::Key { path, key, message } => {
+                write!(f, "{}: {}: {}", path.display(), key, message)
+            }
+            SceneError::Obj(e) => e.fmt(f),
+        }
+    }
+}
+
+/// A key path and what is wrong with its value. Becomes a
+/// `SceneError::Key` once the file name is known.
+struct KeyError {
+    key: String,
+    message: String,
+}
+
+fn key_error<S: ToString>(key: &str, message: S) -> KeyError {
+    KeyError {
+        key: key.to_string(),
+        message: message.to_string(),
+    }
+}
+
+/// Image size and sampling parameters of a render.
+#[derive(Debug, Clone)]
+pub struct RenderSettings {
+    pub width: usize,
+    pub height: usize,
+    pub samples: usize,
+    pub ray_depth: usize,
+    pub filter: FilterDesc,
+}
+
+impl Default for RenderSettings {
+    fn default() -> Self {
+        RenderSettings {
+            width: 400,
+            height: 200,
+            samples: 100,
+            ray_depth: 50,
+            filter: FilterDesc::default(),
+        }
+    }
+}
+
+/// Everything needed to render an image.
+pub struct Scene {
+    pub settings: RenderSettings,
+    pub camera: Camera,
+    pub world: BVH,
+    /// The emissive shapes of `world`, sampled for direct lighting.
+    pub lights: HitableList,
+    pub matlib: MaterialLibrary,
+}
+
+impl Scene {
+    /// Builds the BVH over `hitables` for a shutter open from `t0` to `t1`
+    /// and collects the emissive ones as lights.
+    pub fn new(
+        settings: RenderSettings, camera: Camera, hitables: HitableList,
+        matlib: MaterialLibrary, t0: f32, t1: f32, rng: &mut RNG,
+    ) -> Self {
+        let lights = hitables.emitters(&matlib);
+        Scene {
+            settings,
+            camera,
+            world: BVH::new(hitables, t0, t1, rng),
+            lights,
+            matlib,
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct CameraDesc {
+    pub look_from: Point3,
+    pub look_at: Point3,
+    pub up: Vec3,
+    pub fov: f32,
+    pub aperture: f32,
+    pub focus_distance: f32,
+    pub shutter_open: f32,
+    pub shutter_close: f32,
+}
+
+impl Default for CameraDesc {
+    fn default() -> Self {
+        CameraDesc {
+            look_from: Point3::new(0.0, 0.0, 1.0),
+            look_at: Point3::default(),
+            up: Vec3::new(0.0, 1.0, 0.0),
+            fov: 40.0,
+            aperture: 0.0,
+            focus_distance: 1.0,
+            shutter_open: 0.0,
+            shutter_close: 1.0,
+        }
+    }
+}
+
+/// A color given inline or the name of a texture.
+#[derive(Debug, Clone)]
+pub enum ColorOrTexture {
+    Color(Vec3),
+    Texture(String),
+}
+
+#[derive(Debug, Clone)]
+pub enum TextureDesc {
+    Constant {
+        color: Vec3,
+    },
+    Checker {
+        odd: ColorOrTexture,
+        even: ColorOrTexture,
+    },
+    Image {
+        file: String,
+        wrap: WrapMode,
+        filter: FilterMode,
+        /// Whether to decode the values from sRGB; by the file type if
+        /// not given.
+        srgb: Option<bool>,
+        scale: (f32, f32),
+    },
+}
+
+#[derive(Debug, Clone)]
+pub enum MaterialDesc {
+    Lambertian {
+        albedo: ColorOrTexture,
+    },
+    Metal {
+        albedo: Vec3,
+        fuzz: f32,
+    },
+    /// Glass with `roughness` along `dpdu` and across it, absorbing by
+    /// `absorption` per unit length inside.
+    Dielectric {
+        ior: f32,
+        roughness: (f32, f32),
+        absorption: Vec3,
+    },
+    ThinDielectric {
+        ior: f32,
+    },
+    DiffuseLight {
+        emit: ColorOrTexture,
+    },
+    Isotropic {
+        albedo: ColorOrTexture,
+    },
+    HenyeyGreenstein {
+        albedo: ColorOrTexture,
+        g: f32,
+    },
+    /// Hair fibers, see `hair`. `alpha` is in degrees.
+    Hair {
+        absorption: HairAbsorption,
+        eta: f32,
+        beta_m: f32,
+        beta_n: f32,
+        alpha: f32,
+    },
+    /// A GGX conductor, see `material::Conductor`. `eta` and `k` come from
+    /// the preset `metal` if one is named.
+    Conductor {
+        metal: Option<String>,
+        eta: Vec3,
+        k: Vec3,
+        roughness: (f32, f32),
+    },
+    /// The principled material, see `principled::Principled`. All but the
+    /// base color are fractions, given as grays.
+    Principled {
+        base_color: ColorOrTexture,
+        metallic: ColorOrTexture,
+        roughness: ColorOrTexture,
+        specular: ColorOrTexture,
+        specular_tint: f32,
+        sheen: ColorOrTexture,
+        sheen_tint: f32,
+        clearcoat: ColorOrTexture,
+        clearcoat_gloss: f32,
+        transmission: ColorOrTexture,
+        subsurface: ColorOrTexture,
+    },
+    /// The materials named `a` and `b` blended by `amount` of `b`.
+    Mix {
+        a: String,
+        b: String,
+        amount: ColorOrTexture,
+    },
+    /// A dielectric coat like `Dielectric` over the material named `base`.
+    Coated {
+        base: String,
+        ior: f32,
+        roughness: (f32, f32),
+        absorption: Vec3,
+        thickness: f32,
+    },
+    TwoSided {
+        front: String,
+        back: String,
+    },
+}
+
+#[derive(Debug, Clone)]
+pub enum ObjectDesc {
+    Sphere {
+        center: Point3,
+        radius: f32,
+        material: String,
+    },
+    MovingSphere {
+        center0: Point3,
+        center1: Point3,
+        time0: f32,
+        time1: f32,
+        radius: f32,
+        material: String,
+    },
+    Mesh {
+        positions: Vec<Point3>,
+        indices: Vec<usize>,
+        normals: Vec<Vec3>,
+        uvs: Vec<(f32, f32)>,
+        material: String,
+    },
+    /// An axis-aligned rectangle: `a` and `b` are the ranges along the two
+    /// axes of `plane` and `k` is the position along the third.
+    Rect {
+        plane: Plane,
+        a: (f32, f32),
+        b: (f32, f32),
+        k: f32,
+        material: String,
+    },
+    /// A parallelogram with a corner and two edges.
+    Quad {
+        corner: Point3,
+        u: Vec3,
+        v: Vec3,
+        material: String,
+    },
+    Disk {
+        center: Point3,
+        normal: Vec3,
+        radius: f32,
+        material: String,
+    },
+    /// The quadrics and the torus stand on the z axis and are cut to a
+    /// sweep of `phimax` degrees around it, see `quadric`.
+    Cylinder {
+        radius: f32,
+        zmin: f32,
+        zmax: f32,
+        phimax: f32,
+        material: String,
+    },
+    /// A cone with its base at z = 0 and its apex at `height`.
+    Cone {
+        radius: f32,
+        height: f32,
+        phimax: f32,
+        material: String,
+    },
+    /// A paraboloid with its tip at the origin and `radius` at `zmax`.
+    Paraboloid {
+        radius: f32,
+        zmin: f32,
+        zmax: f32,
+        phimax: f32,
+        material: String,
+    },
+    /// The surface that the line from `p1` to `p2` sweeps around the z
+    /// axis.
+    Hyperboloid {
+        p1: Point3,
+        p2: Point3,
+        phimax: f32,
+        material: String,
+    },
+    Torus {
+        major_radius: f32,
+        minor_radius: f32,
+        phimax: f32,
+        material: String,
+    },
+    /// A Catmull-Clark subdivision surface of the control cage refined
+    /// `levels` times, see `subdiv`.
+    Subdivision {
+        cage: Cage,
+        levels: u32,
+        material: String,
+    },
+    /// Bicubic Bézier patches of 16 indices into `control_points` each,
+    /// tessellated into `divisions` × `divisions` quads.
+    Bezier {
+        control_points: Vec<Point3>,
+        patches: Vec<[usize; 16]>,
+        divisions: usize,
+        material: String,
+    },
+    /// The Utah teapot as Bézier patches, 3.15 tall and standing on the
+    /// z = 0 plane with its spout towards +x.
+    Teapot { divisions: usize, material: String },
+    /// A strand of cubic Bézier curves with 3n + 1 control `points`,
+    /// tapering from `widths.0` to `widths.1`, see `curve`. Ribbons have
+    /// n + 1 `normals`.
+    Curve {
+        points: Vec<Point3>,
+        widths: (f32, f32),
+        kind: CurveKind,
+        normals: Vec<Vec3>,
+        split_depth: u32,
+        material: String,
+    },
+    /// An axis-aligned box between two opposite corners.
+    Cuboid {
+        min: Point3,
+        max: Point3,
+        material: String,
+    },
+    /// A Wavefront OBJ file, relative to the scene file. It brings its own
+    /// materials.
+    Obj { file: PathBuf },
+    /// Terrain with the heights of a gray PNG or PFM image, relative to
+    /// the scene file, spanning x and z from 0 to `size` and scaled by
+    /// `size.y`, see `heightfield`.
+    Heightfield {
+        file: PathBuf,
+        size: Vec3,
+        material: String,
+    },
+    /// A volume of constant `density` inside the convex `boundary`, which
+    /// scatters with the phase function `material`. The boundary's own
+    /// material is not used.
+    ConstantMedium {
+        boundary: Box<ObjectDesc>,
+        density: f32,
+        material: String,
+    },
+    /// A medium with the density and temperature grids `density` and
+    /// `temperature` of a grid file. Collisions absorb with probability
+    /// `absorption` and glow with `emission` times the black body radiation
+    /// of the temperature; the others scatter with the phase function
+    /// `material`.
+    GridMedium {
+        file: PathBuf,
+        density: String,
+        temperature: Option<String>,
+        density_scale: f32,
+        absorption: f32,
+        emission: f32,
+        material: String,
+    },
+    /// The surface of a signed distance field, searched for inside the
+    /// bounds from `min` to `max`.
+    Sdf {
+        shape: Sdf,
+        min: Point3,
+        max: Point3,
+        material: String,
+    },
+    /// A boolean combination of two solids: spheres, boxes, closed tori or
+    /// other CSG objects, optionally transformed.
+    Csg {
+        operation: CsgOp,
+        left: Box<ObjectDesc>,
+        right: Box<ObjectDesc>,
+    },
+    /// Any other object placed with a transform, written as a `transform`
+    /// table on the object. Transformed OBJ files are loaded once and
+    /// shared by all objects that place them.
+    Transformed {
+        transform: TransformDesc,
+        object: Box<ObjectDesc>,
+    },
+}
+
+impl ObjectDesc {
+    /// Whether the object encloses a volume that CSG can combine.
+    pub fn is_solid(&self) -> bool {
+        match self {
+            ObjectDesc::Sphere { .. }
+            | ObjectDesc::Cuboid { .. }
+            | ObjectDesc::Csg { .. } => true,
+            ObjectDesc::Torus { phimax, .. } => *phimax >= 360.0,
+            ObjectDesc::Transformed { object, .. } => object.is_solid(),
+            _ => false,
+        }
+    }
+}
+
+/// Scales, then rotates by `rotate` degrees around `axis`, then translates.
+#[derive(Debug, Clone)]
+pub struct TransformDesc {
+    pub translate: Vec3,
+    pub rotate: f32,
+    pub axis: Vec3,
+    pub scale: Vec3,
+}
+
+impl Default for TransformDesc {
+    fn default() -> Self {
+        TransformDesc {
+            translate: Vec3::default(),
+            rotate: 0.0,
+            axis: Vec3::new(0.0, 1.0, 0.0),
+            scale: Vec3::new(1.0, 1.0, 1.0),
+        }
+    }
+}
+
+impl TransformDesc {
+    pub fn to_transform(&self) -> Transform {
+        Transform::translate(self.translate)
+            * Transform::rotate(self.rotate, self.axis)
+            * Transform::scale(self.scale.x(), self.scale.y(), self.scale.z())
+    }
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct SceneDesc {
+    pub settings: RenderSettings,
+    pub camera: CameraDesc,
+    pub textures: BTreeMap<String, TextureDesc>,
+    pub materials: BTreeMap<String, MaterialDesc>,
+    pub objects: Vec<ObjectDesc>,
+}
+
+impl SceneDesc {
+    pub fn load(path: &Path) -> Result<Self, SceneError> {
+        let src = fs::read_to_string(path).map_err(|error| SceneError::Io {
+            path: path.to_path_buf(),
+            error,
+        })?;
+        SceneDesc::from_toml(&src, path)
+    }
+
+    /// Parses scene source. `path` is only used for messages.
+    pub fn from_toml(src: &str, path: &Path) -> Result<Self, SceneError> {
+        let table = Table::from_str(src).map_err(|e| SceneError::Syntax {
+            path: path.to_path_buf(),
+            message: e.to_string(),
+        })?;
+        read_scene(&table).map_err(|e| SceneError::Key {
+            path: path.to_path_buf(),
+            key: e.key,
+            message: e.message,
+        })
+    }
+
+    pub fn to_toml(&self) -> String {
+        write_scene(self).to_string()
+    }
+
+    /// Creates textures, materials and geometry. `path` is the scene file;
+    /// it names the scene in messages and OBJ files are resolved relative
+    /// to it.
+    pub fn build(
+        &self, path: &Path, rng: &mut RNG,
+    ) -> Result<Scene, SceneError> {
+        let to_scene_error = |e: KeyError| SceneError::Key {
+            path: path.to_path_buf(),
+            key: e.key,
+            message: e.message,
+        };
+        let base_dir = path.parent().unwrap_or_else(|| Path::new(""));
+        let mut builder = Builder {
+            desc: self,
+            base_dir,
+            textures: HashMap::new(),
+            materials: HashMap::new(),
+            matlib: MaterialLibrary::default(),
+            instanced: HashMap::new(),
+        };
+        for (name, material) in &self.materials {
+            let key = format!("materials.{}", name);
+            let material = builder
+                .material(&key, material, 0)
+                .map_err(to_scene_error)?;
+            builder.matlib.lib.push(material);
+            builder
+                .materials
+                .insert(name.clone(), builder.matlib.lib.len() - 1);
+        }
+
+        let mut hitables = HitableList::default();
+        for (i, object) in self.objects.iter().enumerate() {
+            let key = format!("objects[{}]", i);
+            builder
+                .object(&key, object, base_dir, &mut hitables, rng)
+                .map_err(to_scene_error)?
+                .map_err(SceneError::Obj)?;
+        }
+        if hitables.list.is_empty() {
+            return Err(to_scene_error(key_error("objects", "scene is empty")));
+        }
+
+        let c = &self.camera;
+        let s = &self.settings;
+        let camera = Camera::new(
+            c.look_from,
+            c.look_at,
+            &c.up,
+            c.fov,
+            s.width as f32 / s.height as f32,
+            c.aperture,
+            c.focus_distance,
+            c.shutter_open,
+            c.shutter_close,
+        );
+        Ok(Scene::new(
+            self.settings.clone(),
+            camera,
+            hitables,
+            builder.matlib,
+            c.shutter_open,
+            c.shutter_close,
+            rng,
+        ))
+    }
+}
+
+struct Builder<'a> {
+    desc: &'a SceneDesc,
+    /// Where image files are resolved.
+    base_dir: &'a Path,
+    textures: HashMap<String, Arc<dyn Texture>>,
+    materials: HashMap<String, usize>,
+    matlib: MaterialLibrary,
+    /// OBJ files loaded for transformed objects, by path.
+    instanced: HashMap<PathBuf, Arc<dyn Hitable>>,
+}
+
+impl<'a> Builder<'a> {
+    fn texture(
+        &mut self, key: &str, value: &ColorOrTexture, depth: usize,
+    ) -> Result<Arc<dyn Texture>, KeyError> {
+        let name = match value {
+            ColorOrTexture::Color(c) => return Ok(Arc::new(ConstTexture(*c))),
+            ColorOrTexture::Texture(name) => name,
+        };
+        if let Some(t) = self.textures.get(name) {
+            return Ok(t.clone());
+        }
+        let desc = self.desc.textures.get(name).ok_or_else(|| {
+            key_error(key, format!("unknown texture '{}'", name))
+        })?;
+        if depth > self.desc.textures.len() {
+            return Err(key_error(key, "textures reference each other"));
+        }
+        let texture_key = format!("textures.{}", name);
+        let texture: Arc<dyn Texture> = match desc {
+            TextureDesc::Constant { color } => Arc::new(ConstTexture(*color)),
+            TextureDesc::Checker { odd, even } => Arc::new(CheckerTexture {
+                odd: self.texture(
+                    &format!("{}.odd", texture_key),
+                    odd,
+                    depth + 1,
+                )?,
+                even: self.texture(
+                    &format!("{}.even", texture_key),
+                    even,
+                    depth + 1,
+                )?,
+            }),
+            TextureDesc::Image {
+                file,
+                wrap,
+                filter,
+                srgb,
+                scale,
+            } => {
+                let path = self.base_dir.join(file);
+                let mut texture = ImageTexture::read(
+                    &path, *srgb, *wrap, *filter,
+                )
+                .map_err(|e| key_error(&format!("{}.file", texture_key), e))?;
+                texture.scale = *scale;
+                Arc::new(texture)
+            }
+        };
+        self.textures.insert(name.clone(), texture.clone());
+        Ok(texture)
+    }
+
+    /// A new instance of the material `name` for one that is made of it.
+    fn part(
+        &mut self, key: &str, name: &str, depth: usize,
+    ) -> Result<Box<dyn Material>, KeyError> {
+        let desc = self.desc.materials.get(name).ok_or_else(|| {
+            key_error(key, format!("unknown material '{}'", name))
+        })?;
+        if depth > self.desc.materials.len() {
+            return Err(key_error(key, "materials are made of each other"));
+        }
+        self.material(&format!("materials.{}", name), desc, depth + 1)
+    }
+
+    fn material(
+        &mut self, key: &str, desc: &MaterialDesc, depth: usize,
+    ) -> Result<Box<dyn Material>, KeyError> {
+        Ok(match desc {
+            MaterialDesc::Lambertian { albedo } => Box::new(Lambertian::new(
+                self.texture(&format!("{}.albedo", key), albedo, 0)?,
+            )),
+            MaterialDesc::Metal { albedo, fuzz } => {
+                Box::new(Metal::new(*albedo, *fuzz))
+            }
+            MaterialDesc::Dielectric {
+                ior,
+                roughness,
+                absorption,
+            } => Box::new(Dielectric {
+                ref_idx: *ior,
+                distribution: TrowbridgeReitz::from_roughness(
+                    roughness.0,
+                    roughness.1,
+                ),
+                absorption: *absorption,
+            }),
+            MaterialDesc::ThinDielectric { ior } => {
+                Box::new(ThinDielectric { ref_idx: *ior })
+            }
+            MaterialDesc::DiffuseLight { emit } => Box::new(DiffuseLight {
+                emit: self.texture(&format!("{}.emit", key), emit, 0)?,
+            }),
+            MaterialDesc::Isotropic { albedo } => Box::new(Isotropic {
+                albedo: self.texture(&format!("{}.albedo", key), albedo, 0)?,
+            }),
+            MaterialDesc::HenyeyGreenstein { albedo, g } => {
+                Box::new(HenyeyGreenstein {
+                    albedo: self.texture(
+                        &format!("{}.albedo", key),
+                        albedo,
+                        0,
+                    )?,
+                    g: *g,
+                })
+            }
+            MaterialDesc::Hair {
+                absorption,
+                eta,
+                beta_m,
+                beta_n,
+                alpha,
+            } => Box::new(Hair::new(
+                absorption.sigma_a(*beta_n),
+                *eta,
+                *beta_m,
+                *beta_n,
+                *alpha,
+            )),
+            MaterialDesc::Conductor {
+                eta, k, roughness, ..
+            } => Box::new(Conductor::new(
+                *eta,
+                *k,
+                TrowbridgeReitz::from_roughness(roughness.0, roughness.1),
+            )),
+            MaterialDesc::Principled {
+                base_color,
+                metallic,
+                roughness,
+                specular,
+                specular_tint,
+                sheen,
+                sheen_tint,
+                clearcoat,
+                clearcoat_gloss,
+                transmission,
+                subsurface,
+            } => Box::new(Principled {
+                base_color: self.texture(
+                    &format!("{}.base_color", key),
+                    base_color,
+                    0,
+                )?,
+                metallic: self.texture(
+                    &format!("{}.metallic", key),
+                    metallic,
+                    0,
+                )?,
+                roughness: self.texture(
+                    &format!("{}.roughness", key),
+                    roughness,
+                    0,
+                )?,
+                specular: self.texture(
+                    &format!("{}.specular", key),
+                    specular,
+                    0,
+                )?,
+                specular_tint: *specular_tint,
+                sheen: self.texture(&format!("{}.sheen", key), sheen, 0)?,
+                sheen_tint: *sheen_tint,
+                clearcoat: self.texture(
+                    &format!("{}.clearcoat", key),
+                    clearcoat,
+                    0,
+                )?,
+                clearcoat_gloss: *clearcoat_gloss,
+                transmission: self.texture(
+                    &format!("{}.transmission", key),
+                    transmission,
+                    0,
+                )?,
+                subsurface: self.texture(
+                    &format!("{}.subsurface", key),
+                    subsurface,
+                    0,
+                )?,
+            }),
+            MaterialDesc::Mix { a, b, amount } => Box::new(Mix {
+                a: self.part(&format!("{}.a", key), a, depth)?,
+                b: self.part(&format!("{}.b", key), b, depth)?,
+                amount: self.texture(&format!("{}.amount", key), amount, 0)?,
+            }),
+            MaterialDesc::Coated {
+                base,
+                ior,
+                roughness,
+                absorption,
+                thickness,
+            } => Box::new(Coated {
+                coat: Dielectric {
+                    ref_idx: *ior,
+                    distribution: TrowbridgeReitz::from_roughness(
+                        roughness.0,
+                        roughness.1,
+                    ),
+                    absorption: *absorption,
+                },
+                base: self.part(&format!("{}.base", key), base, depth)?,
+                thickness: *thickness,
+            }),
+            MaterialDesc::TwoSided { front, back } => Box::new(TwoSided {
+                front: self.part(&format!("{}.front", key), front, depth)?,
+                back: self.part(&format!("{}.back", key), back, depth)?,
+            }),
+        })
+    }
+
+    fn material_index(&self, key: &str, name: &str) -> Result<usize, KeyError> {
+        self.materials.get(name).cloned().ok_or_else(|| {
+            key_error(
+                &format!("{}.material", key),
+                format!("unknown material '{}'", name),
+            )
+        })
+    }
+
+    /// The outer error names a bad key, the inner one comes from loading
+    /// an OBJ file.
+    fn object(
+        &mut self, key: &str, desc: &ObjectDesc, base_dir: &Path,
+        hitables: &mut HitableList, rng: &mut RNG,
+    ) -> Result<Result<(), ObjError>, KeyError> {
+        match desc {
+            ObjectDesc::Sphere {
+                center,
+                radius,
+                material,
+            } => {
+                let material = self.material_index(key, material)?;
+                hitables
+                    .list
+                    .push(Arc::new(Sphere::new(*center, *radius, material)));
+            }
+            ObjectDesc::MovingSphere {
+                center0,
+                center1,
+                time0,
+                time1,
+                radius,
+                material,
+            } => {
+                let material = self.material_index(key, material)?;
+                hitables.list.push(Arc::new(MovingSphere::new(
+                    *center0, *center1, *time0, *time1, *radius, material,
+                )));
+            }
+            ObjectDesc::Mesh {
+                positions,
+                indices,
+                normals,
+                uvs,
+                material,
+            } => {
+                let material = self.material_index(key, material)?;
+                check_mesh(key, positions, indices, normals, uvs)?;
+                let mesh = TriangleMesh::new(
+                    positions.clone(),
+                    indices.clone(),
+                    normals.clone(),
+                    uvs.clone(),
+                    material,
+                );
+                let triangles = TriangleMesh::triangles(&Arc::new(mesh));
+                hitables.list.extend(triangles.list);
+            }
+            ObjectDesc::Rect {
+                plane,
+                a,
+                b,
+                k,
+                material,
+            } => {
+                let material = self.material_index(key, material)?;
+                hitables.list.push(Arc::new(AxisRect::new(
+                    *plane, *a, *b, *k, material,
+                )));
+            }
+            ObjectDesc::Quad {
+                corner,
+                u,
+                v,
+                material,
+            } => {
+                let material = self.material_index(key, material)?;
+                if u.cross(v).length() == 0.0 {
+                    return Err(key_error(
+                        &format!("{}.v", key),
+                        "must not be parallel to u",
+                    ));
+                }
+                hitables
+                    .list
+                    .push(Arc::new(Quad::new(*corner, *u, *v, material)));
+            }
+            ObjectDesc::Disk {
+                center,
+                normal,
+                radius,
+                material,
+            } => {
+                let material = self.material_index(key, material)?;
+                if normal.length() == 0.0 {
+                    return Err(key_error(
+                        &format!("{}.normal", key),
+                        "must not be zero",
+                    ));
+                }
+                hitables.list.push(Arc::new(Disk::new(
+                    *center, *normal, *radius, material,
+                )));
+            }
+            ObjectDesc::Cylinder {
+                radius,
+                zmin,
+                zmax,
+                phimax,
+                material,
+            } => {
+                let material = self.material_index(key, material)?;
+                hitables.list.push(Arc::new(Quadric::cylinder(
+                    *radius, *zmin, *zmax, *phimax, material,
+                )));
+            }
+            ObjectDesc::Cone {
+                radius,
+                height,
+                phimax,
+                material,
+            } => {
+                let material = self.material_index(key, material)?;
+                hitables.list.push(Arc::new(Quadric::cone(
+                    *radius, *height, *phimax, material,
+                )));
+            }
+            ObjectDesc::Paraboloid {
+                radius,
+                zmin,
+                zmax,
+                phimax,
+                material,
+            } => {
+                let material = self.material_index(key, material)?;
+                hitables.list.push(Arc::new(Quadric::paraboloid(
+                    *radius, *zmin, *zmax, *phimax, material,
+                )));
+            }
+            ObjectDesc::Hyperboloid {
+                p1,
+                p2,
+                phimax,
+                material,
+            } => {
+                let material = self.material_index(key, material)?;
+                hitables.list.push(Arc::new(Quadric::hyperboloid(
+                    *p1, *p2, *phimax, material,
+                )));
+            }
+            ObjectDesc::Torus {
+                major_radius,
+                minor_radius,
+                phimax,
+                material,
+            } => {
+                let material = self.material_index(key, material)?;
+                hitables.list.push(Arc::new(Torus::new(
+                    *major_radius,
+                    *minor_radius,
+                    *phimax,
+                    material,
+                )));
+            }
+            ObjectDesc::Subdivision {
+                cage,
+                levels,
+                material,
+            } => {
+                let material = self.material_index(key, material)?;
+                let mesh = cage.subdivide(*levels).to_mesh(material);
+                let triangles = TriangleMesh::triangles(&Arc::new(mesh));
+                hitables.list.extend(triangles.list);
+            }
+            ObjectDesc::Bezier {
+                control_points,
+                patches,
+                divisions,
+                material,
+            } => {
+                let material = self.material_index(key, material)?;
+                let mesh = bezier::tessellate(
+                    control_points,
+                    patches,
+                    *divisions,
+                    material,
+                );
+                let triangles = TriangleMesh::triangles(&Arc::new(mesh));
+                hitables.list.extend(triangles.list);
+            }
+            ObjectDesc::Teapot {
+                divisions,
+                material,
+            } => {
+                let material = self.material_index(key, material)?;
+                let (points, patches) = teapot();
+                let mesh =
+                    bezier::tessellate(&points, &patches, *divisions, material);
+                let triangles = TriangleMesh::triangles(&Arc::new(mesh));
+                hitables.list.extend(triangles.list);
+            }
+            ObjectDesc::Curve {
+                points,
+                widths,
+                kind,
+                normals,
+                split_depth,
+                material,
+            } => {
+                let material = self.material_index(key, material)?;
+                let strand = Curve::strand(
+                    points,
+                    *widths,
+                    *kind,
+                    normals,
+                    *split_depth,
+                    material,
+                );
+                hitables.list.extend(strand.list);
+            }
+            ObjectDesc::Cuboid { min, max, material } => {
+                let material = self.material_index(key, material)?;
+                hitables
+                    .list
+                    .push(Arc::new(Cuboid::new(*min, *max, material)));
+            }
+            ObjectDesc::Obj { file } => {
+                return Ok(obj::load_obj(
+                    &base_dir.join(file),
+                    hitables,
+                    &mut self.matlib,
+                ));
+            }
+            ObjectDesc::Heightfield {
+                file,
+                size,
+                material,
+            } => {
+                let material = self.material_index(key, material)?;
+                let image = Image::read(&base_dir.join(file))
+                    .map_err(|e| key_error(&format!("{}.file", key), e))?;
+                if image.width < 2 || image.height < 2 {
+                    return Err(key_error(
+                        &format!("{}.file", key),
+                        "needs at least 2 x 2 heights",
+                    ));
+                }
+                hitables.list.push(Arc::new(Heightfield::from_image(
+                    &image, *size, material,
+                )));
+            }
+            ObjectDesc::Transformed { transform, object } => {
+                let s = transform.scale;
+                if s.x() == 0.0 || s.y() == 0.0 || s.z() == 0.0 {
+                    return Err(key_error(
+                        &format!("{}.transform.scale", key),
+                        "must not be zero",
+                    ));
+                }
+                if transform.axis.length() == 0.0 {
+                    return Err(key_error(
+                        &format!("{}.transform.axis", key),
+                        "must not be zero",
+                    ));
+                }
+                let shared = match &**object {
+                    ObjectDesc::Obj { file } => Some(base_dir.join(file)),
+                    _ => None,
+                };
+                let cached =
+                    shared.as_ref().and_then(|p| self.instanced.get(p));
+                let prototype = match cached {
+                    Some(prototype) => prototype.clone(),
+                    None => {
+                        let prototype =
+                            match self.single(key, object, base_dir, rng)? {
+                                Ok(Some(prototype)) => prototype,
+                                Ok(None) => return Ok(Ok(())),
+                                Err(e) => return Ok(Err(e)),
+                            };
+                        if let Some(path) = shared {
+                            self.instanced.insert(path, prototype.clone());
+                        }
+                        prototype
+                    }
+                };
+                hitables.list.push(Arc::new(TransformedHitable::new(
+                    prototype,
+                    transform.to_transform(),
+                )));
+            }
+            ObjectDesc::ConstantMedium {
+                boundary,
+                density,
+                material,
+            } => {
+                let material = self.material_index(key, material)?;
+                let key = format!("{}.boundary", key);
+                let boundary =
+                    match self.single(&key, boundary, base_dir, rng)? {
+                        Ok(Some(boundary)) => boundary,
+                        Ok(None) => return Ok(Ok(())),
+                        Err(e) => return Ok(Err(e)),
+                    };
+                hitables.list.push(Arc::new(ConstantMedium::new(
+                    boundary, *density, material,
+                )));
+            }
+            ObjectDesc::Sdf {
+                shape,
+                min,
+                max,
+                material,
+            } => {
+                let material = self.material_index(key, material)?;
+                hitables.list.push(Arc::new(SdfShape::new(
+                    shape.clone(),
+                    AABB::new(*min, *max),
+                    material,
+                )));
+            }
+            ObjectDesc::Csg {
+                operation,
+                left,
+                right,
+            } => {
+                let mut operand = |side: &str, desc: &ObjectDesc| {
+                    let key = format!("{}.{}", key, side);
+                    self.single(&key, desc, base_dir, rng)
+                };
+                let left = match operand("left", left)? {
+                    Ok(Some(left)) => left,
+                    Ok(None) => return Ok(Ok(())),
+                    Err(e) => return Ok(Err(e)),
+                };
+                let right = match operand("right", right)? {
+                    Ok(Some(right)) => right,
+                    Ok(None) => return Ok(Ok(())),
+                    Err(e) => return Ok(Err(e)),
+                };
+                hitables
+                    .list
+                    .push(Arc::new(Csg::new(*operation, left, right)));
+            }
+            ObjectDesc::GridMedium {
+                file,
+                density,
+                temperature,
+                density_scale,
+                absorption,
+                emission,
+                material,
+            } => {
+                let phase = self.material_index(key, material)?;
+                let grids = load_grids(&base_dir.join(file))
+                    .map_err(|e| key_error(&format!("{}.file", key), e))?;
+                let grid = |name: &str, field: &str| match grids
+                    .iter()
+                    .find(|(n, _)| n == name)
+                {
+                    Some((_, grid)) => Ok(Arc::new(grid.clone())),
+                    None => Err(key_error(
+                        &format!("{}.{}", key, field),
+                        format!("no grid named '{}'", name),
+                    )),
+                };
+                let density = grid(density, "density")?;
+                let temperature = match temperature {
+                    Some(name) => Some(grid(name, "temperature")?),
+                    None => None,
+                };
+                self.matlib.lib.push(Box::new(Blackbody::new(*emission)));
+                let absorber = self.matlib.lib.len() - 1;
+                hitables.list.push(Arc::new(GridMedium::new(
+                    density,
+                    temperature,
+                    *density_scale,
+                    *absorption,
+                    phase,
+                    absorber,
+                )));
+            }
+        }
+        Ok(Ok(()))
+    }
+
+    /// Builds `desc` on its own into its only shape or a BVH over all of
+    /// them. `None` if it has no shapes.
+    fn single(
+        &mut self, key: &str, desc: &ObjectDesc, base_dir: &Path, rng: &mut RNG,
+    ) -> Result<Result<Option<Arc<dyn Hitable>>, ObjError>, KeyError> {
+        let mut shapes = HitableList::default();
+        if let Err(e) = self.object(key, desc, base_dir, &mut shapes, rng)? {
+            return Ok(Err(e));
+        }
+        let c = &self.desc.camera;
+        Ok(Ok(match shapes.list.len() {
+            0 => None,
+            1 => Some(shapes.list[0].clone()),
+            _ => Some(Arc::new(BVH::new(
+                shapes,
+                c.shutter_open,
+                c.shutter_close,
+                rng,
+            ))),
+        }))
+    }
+}
+
+fn check_mesh(
+    key: &str, positions: &[Point3], indices: &[usize], normals: &[Vec3],
+    uvs: &[(f32, f32)],
+) -> Result<(), KeyError> {
+    if indices.is_empty() || !indices.len().is_multiple_of(3) {
+        return Err(key_error(
+            &format!("{}.indices", key),
+            "needs a non-empty multiple of 3 entries",
+        ));
+    }
+    if let Some(&i) = indices.iter().find(|&&i| i >= positions.len()) {
+        return Err(key_error(
+            &format!("{}.indices", key),
+            format!("index {} out of range (have {})", i, positions.len()),
+        ));
+    }
+    if !normals.is_empty() && normals.len() != positions.len() {
+        return Err(key_error(
+            &format!("{}.normals", key),
+            "needs one entry per position",
+        ));
+    }
+    if !uvs.is_empty() && uvs.len() != positions.len() {
+        return Err(key_error(
+            &format!("{}.uvs", key),
+            "needs one entry per position",
+        ));
+    }
+    Ok(())
+}
+
+/// Reads the keys of one table and complains about the ones nobody asked
+/// for.
+struct Fields<'a> {
+    key: String,
+    table: &'a Table,
+    known: Vec<&'static str>,
+}
+
+impl<'a> Fields<'a> {
+    fn new(key: String, value: &'a Value) -> Result<Self, KeyError> {
+        match value {
+            Value::Table(table) => Ok(Fields {
+                key,
+                table,
+                known: Vec::new(),
+            }),
+            _ => Err(key_error(&key, "expected a table")),
+        }
+    }
+
+    fn key(&self, name: &str) -> String {
+        if self.key.is_empty() {
+            name.to_string()
+        } else {
+            format!("{}.{}", self.key, name)
+        }
+    }
+
+    fn get(&mut self, name: &'static str) -> Option<&'a Value> {
+        self.known.push(name);
+        self.table.get(name)
+    }
+
+    fn required(&mut self, name: &'static str) -> Result<&'a Value, KeyError> {
+        let key = self.key(name);
+        self.get(name)
+            .ok_or_else(|| key_error(&key, "missing required key"))
+    }
+
+    fn number(&mut self, name: &'static str) -> Result<f32, KeyError> {
+        let value = self.required(name)?;
+        as_number(&self.key(name), value)
+    }
+
+    fn positive(&mut self, name: &'static str) -> Result<f32, KeyError> {
+        let number = self.number(name)?;
+        if number.is_nan() || number <= 0.0 {
+            return Err(key_error(&self.key(name), "must be positive"));
+        }
+        Ok(number)
+    }
+
+    fn positive_or(
+        &mut self, name: &'static str, default: f32,
+    ) -> Result<f32, KeyError> {
+        let number = self.number_or(name, default)?;
+        if number.is_nan() || number <= 0.0 {
+            return Err(key_error(&self.key(name), "must be positive"));
+        }
+        Ok(number)
+    }
+
+    fn number_or(
+        &mut self, name: &'static str, default: f32,
+    ) -> Result<f32, KeyError> {
+        match self.get(name) {
+            Some(value) => as_number(&self.key(name), value),
+            None => Ok(default),
+        }
+    }
+
+    fn count_or(
+        &mut self, name: &'static str, default: usize,
+    ) -> Result<usize, KeyError> {
+        match self.get(name) {
+            Some(value) => as_count(&self.key(name), value),
+            None => Ok(default),
+        }
+    }
+
+    fn string(&mut self, name: &'static str) -> Result<&'a str, KeyError> {
+        let value = self.required(name)?;
+        value
+            .as_str()
+            .ok_or_else(|| key_error(&self.key(name), "expected a string"))
+    }
+
+    fn vec3(&mut self, name: &'static str) -> Result<Vec3, KeyError> {
+        let value = self.required(name)?;
+        as_vec3(&self.key(name), value)
+    }
+
+    fn vec3_or(
+        &mut self, name: &'static str, default: Vec3,
+    ) -> Result<Vec3, KeyError> {
+        match self.get(name) {
+            Some(value) => as_vec3(&self.key(name), value),
+            None => Ok(default),
+        }
+    }
+
+    fn pair(&mut self, name: &'static str) -> Result<(f32, f32), KeyError> {
+        let value = self.required(name)?;
+        as_pair(&self.key(name), value)
+    }
+
+    fn point3(&mut self, name: &'static str) -> Result<Point3, KeyError> {
+        Ok(Point3::default() + self.vec3(name)?)
+    }
+
+    fn points(&mut self, name: &'static str) -> Result<Vec<Point3>, KeyError> {
+        let key = self.key(name);
+        self.array(name)?
+            .ok_or_else(|| key_error(&key, "missing required key"))?
+            .iter()
+            .enumerate()
+            .map(|(i, v)| {
+                Ok(Point3::default() + as_vec3(&format!("{}[{}]", key, i), v)?)
+            })
+            .collect()
+    }
+
+    fn color_or_texture(
+        &mut self, name: &'static str,
+    ) -> Result<ColorOrTexture, KeyError> {
+        let key = self.key(name);
+        match self.required(name)? {
+            Value::String(s) => Ok(ColorOrTexture::Texture(s.clone())),
+            value => Ok(ColorOrTexture::Color(as_vec3(&key, value)?)),
+        }
+    }
+
+    /// A number from 0 to 1, kept as a gray, or the name of a texture.
+    fn fraction_or_texture(
+        &mut self, name: &'static str, default: f32,
+    ) -> Result<ColorOrTexture, KeyError> {
+        match self.get(name) {
+            Some(Value::String(s)) => Ok(ColorOrTexture::Texture(s.clone())),
+            Some(_) => {
+                let v = self.fraction_or(name, default)?;
+                Ok(ColorOrTexture::Color(Vec3::new(v, v, v)))
+            }
+            None => {
+                Ok(ColorOrTexture::Color(Vec3::new(default, default, default)))
+            }
+        }
+    }
+
+    fn fraction_or(
+        &mut self, name: &'static str, default: f32,
+    ) -> Result<f32, KeyError> {
+        let v = self.number_or(name, default)?;
+        if !(0.0..=1.0).contains(&v) {
+            return Err(key_error(&self.key(name), "must be between 0 and 1"));
+        }
+        Ok(v)
+    }
+
+    fn array(
+        &mut self, name: &'static str,
+    ) -> Result<Option<&'a Vec<Value>>, KeyError> {
+        match self.get(name) {
+            Some(Value::Array(array)) => Ok(Some(array)),
+            Some(_) => Err(key_error(&self.key(name), "expected an array")),
+            None => Ok(None),
+        }
+    }
+
+    fn finish(self) -> Result<(), KeyError> {
+        for name in self.table.keys() {
+            if !self.known.contains(&name.as_str()) {
+                return Err(key_error(&self.key(name), "unknown key"));
+            }
+        }
+        Ok(())
+    }
+}
+
+fn as_number(key: &str, value: &Value) -> Result<f32, KeyError> {
+    match value {
+        Value::Float(f) => Ok(*f as f32),
+        Value::Integer(i) => Ok(*i as f32),
+        _ => Err(key_error(key, "expected a number")),
+    }
+}
+
+fn as_count(key: &str, value: &Value) -> Result<usize, KeyError> {
+    match value {
+        Value::Integer(i) if *i >= 0 => Ok(*i as usize),
+        _ => Err(key_error(key, "expected a non-negative integer")),
+    }
+}
+
+fn as_indices(key: &str, value: &Value) -> Result<Vec<usize>, KeyError> {
+    match value {
+        Value::Array(a) => a
+            .iter()
+            .enumerate()
+            .map(|(i, v)| as_count(&format!("{}[{}]", key, i), v))
+            .collect(),
+        _ => Err(key_error(key, "expected an array of indices")),
+    }
+}
+
+fn as_pair(key: &str, value: &Value) -> Result<(f32, f32), KeyError> {
+    match value {
+        Value::Array(a) if a.len() == 2 => Ok((
+            as_number(&format!("{}[0]", key), &a[0])?,
+            as_number(&format!("{}[1]", key), &a[1])?,
+        )),
+        _ => Err(key_error(key, "expected an array of 2 numbers")),
+    }
+}
+
+fn as_vec3(key: &str, value: &Value) -> Result<Vec3, KeyError> {
+    match value {
+        Value::Array(a) if a.len() == 3 => Ok(Vec3::new(
+            as_number(&format!("{}[0]", key), &a[0])?,
+            as_number(&format!("{}[1]", key), &a[1])?,
+            as_number(&format!("{}[2]", key), &a[2])?,
+        )),
+        _ => Err(key_error(key, "expected an array of 3 numbers")),
+    }
+}
+
+fn read_scene(table: &Table) -> Result<SceneDesc, KeyError> {
+    let root = Value::Table(table.clone());
+    let mut fields = Fields::new(String::new(), &root)?;
+    let version = match fields.required("version")? {
+        Value::Integer(v) => *v,
+        _ => return Err(key_error("version", "expected an integer")),
+    };
+    if version != VERSION {
+        return Err(key_error(
+            "version",
+            format!("unsupported version {}, expected {}", version, VERSION),
+        ));
+    }
+
+    let mut desc = SceneDesc::default();
+    if let Some(render) = fields.get("render") {
+        let mut f = Fields::new("render".to_string(), render)?;
+        let d = RenderSettings::default();
+        desc.settings = RenderSettings {
+            width: f.count_or("width", d.width)?,
+            height: f.count_or("height", d.height)?,
+            samples: f.count_or("samples", d.samples)?,
+            ray_depth: f.count_or("max_depth", d.ray_depth)?,
+            filter: match f.get("filter") {
+                Some(filter) => {
+                    read_filter(Fields::new(f.key("filter"), filter)?)?
+                }
+                None => d.filter,
+            },
+        };
+        if desc.settings.width == 0 || desc.settings.height == 0 {
+            return Err(key_error("render", "image size must not be zero"));
+        }
+        f.finish()?;
+    }
+    let camera = fields.required("camera")?;
+    desc.camera = read_camera(Fields::new("camera".to_string(), camera)?)?;
+
+    if let Some(textures) = fields.get("textures") {
+        let f = Fields::new("textures".to_string(), textures)?;
+        for (name, value) in f.table {
+            let t = read_texture(Fields::new(f.key(name), value)?)?;
+            desc.textures.insert(name.clone(), t);
+        }
+    }
+    if let Some(materials) = fields.get("materials") {
+        let f = Fields::new("materials".to_string(), materials)?;
+        for (name, value) in f.table {
+            let m = read_material(Fields::new(f.key(name), value)?)?;
+            desc.materials.insert(name.clone(), m);
+        }
+    }
+    if let Some(objects) = fields.array("objects")? {
+        for (i, value) in objects.iter().enumerate() {
+            let key = format!("objects[{}]", i);
+            desc.objects.push(read_object(Fields::new(key, value)?)?);
+        }
+    }
+    fields.finish()?;
+    Ok(desc)
+}
+
+fn read_filter(mut f: Fields) -> Result<FilterDesc, KeyError> {
+    let filter = match f.string("type")? {
+        "box" => FilterDesc::Box {
+            radius: f.number_or("radius", 0.5)?,
+        },
+        "tent" => FilterDesc::Tent {
+            radius: f.number_or("radius", 1.0)?,
+        },
+        "gaussian" => FilterDesc::Gaussian {
+            radius: f.number_or("radius", 1.5)?,
+            alpha: f.number_or("alpha", 2.0)?,
+        },
+        "mitchell" => FilterDesc::Mitchell {
+            radius: f.number_or("radius", 2.0)?,
+            b: f.number_or("b", 1.0 / 3.0)?,
+            c: f.number_or("c", 1.0 / 3.0)?,
+        },
+        "lanczos" => FilterDesc::Lanczos {
+            radius: f.number_or("radius", 3.0)?,
+            tau: f.number_or("tau", 3.0)?,
+        },
+        ty => {
+            return Err(key_error(
+                &f.key("type"),
+                format!("unknown filter type '{}'", ty),
+            ))
+        }
+    };
+    let radius = match filter {
+        FilterDesc::Box { radius }
+        | FilterDesc::Tent { radius }
+        | FilterDesc::Gaussian { radius, .. }
+        | FilterDesc::Mitchell { radius, .. }
+        | FilterDesc::Lanczos { radius, .. } => radius,
+    };
+    if radius.is_nan() || radius <= 0.0 {
+        return Err(key_error(&f.key("radius"), "must be positive"));
+    }
+    f.finish()?;
+    Ok(filter)
+}
+
+fn read_camera(mut f: Fields) -> Result<CameraDesc, KeyError> {
+    let d = CameraDesc::default();
+    let look_from = f.point3("look_from")?;
+    let look_at = f.point3("look_at")?;
+    let camera = CameraDesc {
+        look_from,
+        look_at,
+        up: f.vec3_or("up", d.up)?,
+        fov: f.number_or("fov", d.fov)?,
+        aperture: f.number_or("aperture", d.aperture)?,
+        focus_distance: f
+            .number_or("focus_distance", (look_from - look_at).length())?,
+        shutter_open: f.number_or("shutter_open", d.shutter_open)?,
+        shutter_close: f.number_or("shutter_close", d.shutter_close)?,
+    };
+    f.finish()?;
+    Ok(camera)
+}
+
+fn read_texture(mut f: Fields) -> Result<TextureDesc, KeyError> {
+    let texture = match f.string("type")? {
+        "constant" => TextureDesc::Constant {
+            color: f.vec3("color")?,
+        },
+        "checker" => TextureDesc::Checker {
+            odd: f.color_or_texture("odd")?,
+            even: f.color_or_texture("even")?,
+        },
+        "image" => read_image_texture(&mut f)?,
+        ty => {
+            return Err(key_error(
+                &f.key("type"),
+                format!("unknown texture type '{}'", ty),
+            ))
+        }
+    };
+    f.finish()?;
+    Ok(texture)
+}
+
+/// Hair absorbs as given by `sigma_a`, to look like `color`, or by the
+/// concentrations of melanin, which default to brown hair.
+fn read_image_texture(f: &mut Fields) -> Result<TextureDesc, KeyError> {
+    let file = f.string("file")?.to_string();
+    let wrap = match f.get("wrap") {
+        None => WrapMode::Repeat,
+        Some(_) => match f.string("wrap")? {
+            "repeat" => WrapMode::Repeat,
+            "clamp" => WrapMode::Clamp,
+            "mirror" => WrapMode::Mirror,
+            wrap => {
+                return Err(key_error(
+                    &f.key("wrap"),
+                    format!("unknown wrap mode '{}'", wrap),
+                ))
+            }
+        },
+    };
+    let filter = match f.get("filter") {
+        None => FilterMode::Ewa,
+        Some(_) => match f.string("filter")? {
+            "bilinear" => FilterMode::Bilinear,
+            "trilinear" => FilterMode::Trilinear,
+            "ewa" => FilterMode::Ewa,
+            filter => {
+                return Err(key_error(
+                    &f.key("filter"),
+                    format!("unknown texture filter '{}'", filter),
+                ))
+            }
+        },
+    };
+    let srgb = match f.get("srgb") {
+        None => None,
+        Some(Value::Boolean(srgb)) => Some(*srgb),
+        Some(_) => return Err(key_error(&f.key("srgb"), "expected a boolean")),
+    };
+    let scale = match f.get("scale") {
+        None => (1.0, 1.0),
+        Some(_) => f.pair("scale")?,
+    };
+    if !(scale.0.is_finite() && scale.1.is_finite()) {
+        return Err(key_error(&f.key("scale"), "must be finite"));
+    }
+    Ok(TextureDesc::Image {
+        file,
+        wrap,
+        filter,
+        srgb,
+        scale,
+    })
+}
+
+fn read_hair_absorption(f: &mut Fields) -> Result<HairAbsorption, KeyError> {
+    let given: Vec<_> = ["sigma_a", "color", "eumelanin", "pheomelanin"]
+        .iter()
+        .filter(|&&name| f.table.contains_key(name))
+        .collect();
+    if given.len() > 1 && !given.iter().all(|&&name| name.ends_with("melanin"))
+    {
+        return Err(key_error(
+            &f.key(given[0]),
+            format!("cannot be given with {}", given[1]),
+        ));
+    }
+    if f.get("sigma_a").is_some() {
+        return Ok(HairAbsorption::SigmaA(f.vec3("sigma_a")?));
+    }
+    if f.get("color").is_some() {
+        let color = f.vec3("color")?;
+        if (0..3).any(|i| !(0.0..=1.0).contains(&color[i])) {
+            return Err(key_error(&f.key("color"), "must be between 0 and 1"));
+        }
+        return Ok(HairAbsorption::Color(color));
+    }
+    let eumelanin = f.number_or("eumelanin", 1.3)?;
+    let pheomelanin = f.number_or("pheomelanin", 0.0)?;
+    for &(name, c) in &[("eumelanin", eumelanin), ("pheomelanin", pheomelanin)]
+    {
+        if c.is_nan() || c < 0.0 {
+            return Err(key_error(&f.key(name), "must not be negative"));
+        }
+    }
+    Ok(HairAbsorption::Melanin {
+        eumelanin,
+        pheomelanin,
+    })
+}
+
+/// A conductor is a `metal` preset or has its `eta` and `k` given, and is
+/// isotropic unless `roughness` is a pair.
+fn read_conductor(f: &mut Fields) -> Result<MaterialDesc, KeyError> {
+    let metal = match f.get("metal") {
+        Some(_) => {
+            let name = f.string("metal")?;
+            if let Some(other) =
+                ["eta", "k"].iter().find(|&&n| f.table.contains_key(n))
+            {
+                return Err(key_error(
+                    &f.key("metal"),
+                    format!("cannot be given with {}", other),
+                ));
+            }
+            Some(name.to_string())
+        }
+        None => None,
+    };
+    let (eta, k) = match &metal {
+        Some(name) => conductor_preset(name).ok_or_else(|| {
+            key_error(
+                &f.key("metal"),
+                format!(
+                    "unknown metal '{}', expected one of {}",
+                    name,
+                    CONDUCTOR_PRESETS.join(", ")
+                ),
+            )
+        })?,
+        None => (f.vec3("eta")?, f.vec3("k")?),
+    };
+    Ok(MaterialDesc::Conductor {
+        metal,
+        eta,
+        k,
+        roughness: read_roughness(f)?,
+    })
+}
+
+/// Microfacet roughness from 0 to 1, a pair for different roughness along
+/// `dpdu` and across it. Smooth by default.
+fn read_roughness(f: &mut Fields) -> Result<(f32, f32), KeyError> {
+    let key = f.key("roughness");
+    let roughness = match f.get("roughness") {
+        Some(value @ Value::Array(_)) => as_pair(&key, value)?,
+        Some(value) => {
+            let roughness = as_number(&key, value)?;
+            (roughness, roughness)
+        }
+        None => (0.0, 0.0),
+    };
+    if !(0.0..=1.0).contains(&roughness.0)
+        || !(0.0..=1.0).contains(&roughness.1)
+    {
+        return Err(key_error(&key, "must be between 0 and 1"));
+    }
+    Ok(roughness)
+}
+
+/// The absorption coefficient inside glass, given directly or as the
+/// `color` that white light takes on after `distance` inside, by default
+/// `default_distance`.
+fn read_absorption(
+    f: &mut Fields, default_distance: f32,
+) -> Result<Vec3, KeyError> {
+    if f.get("absorption").is_some() {
+        if f.table.contains_key("color") {
+            return Err(key_error(
+                &f.key("absorption"),
+                "cannot be given with color",
+            ));
+        }
+        let absorption = f.vec3("absorption")?;
+        if (0..3).any(|i| absorption[i].is_nan() || absorption[i] < 0.0) {
+            return Err(key_error(
+                &f.key("absorption"),
+                "must not be negative",
+            ));
+        }
+        return Ok(absorption);
+    }
+    let color = f.vec3_or("color", Vec3::new(1.0, 1.0, 1.0))?;
+    if (0..3).any(|i| !(color[i] > 0.0 && color[i] <= 1.0)) {
+        return Err(key_error(
+            &f.key("color"),
+            "must be greater than 0 and at most 1",
+        ));
+    }
+    let distance = f.number_or("distance", default_distance)?;
+    if distance.is_nan() || distance <= 0.0 {
+        return Err(key_error(&f.key("distance"), "must be positive"));
+    }
+    Ok(Vec3::new(
+        -color.x().ln() / distance,
+        -color.y().ln() / distance,
+        -color.z().ln() / distance,
+    ))
+}
+
+/// Every parameter of the principled material is optional, with the
+/// defaults of Disney's model.
+fn read_principled(f: &mut Fields) -> Result<MaterialDesc, KeyError> {
+    let base_color = if f.get("base_color").is_some() {
+        f.color_or_texture("base_color")?
+    } else {
+        ColorOrTexture::Color(Vec3::new(0.8, 0.8, 0.8))
+    };
+    Ok(MaterialDesc::Principled {
+        base_color,
+        metallic: f.fraction_or_texture("metallic", 0.0)?,
+        roughness: f.fraction_or_texture("roughness", 0.5)?,
+        specular: f.fraction_or_texture("specular", 0.5)?,
+        specular_tint: f.fraction_or("specular_tint", 0.0)?,
+        sheen: f.fraction_or_texture("sheen", 0.0)?,
+        sheen_tint: f.fraction_or("sheen_tint", 0.5)?,
+        clearcoat: f.fraction_or_texture("clearcoat", 0.0)?,
+        clearcoat_gloss: f.fraction_or("clearcoat_gloss", 1.0)?,
+        transmission: f.fraction_or_texture("transmission", 0.0)?,
+        subsurface: f.fraction_or_texture("subsurface", 0.0)?,
+    })
+}
+
+fn read_material(mut f: Fields) -> Result<MaterialDesc, KeyError> {
+    let material = match f.string("type")? {
+        "lambertian" => MaterialDesc::Lambertian {
+            albedo: f.color_or_texture("albedo")?,
+        },
+        "metal" => MaterialDesc::Metal {
+            albedo: f.vec3("albedo")?,
+            fuzz: f.number_or("fuzz", 0.0)?,
+        },
+        "dielectric" => MaterialDesc::Dielectric {
+            ior: f.positive("ior")?,
+            roughness: read_roughness(&mut f)?,
+            absorption: read_absorption(&mut f, 1.0)?,
+        },
+        "thin_dielectric" => MaterialDesc::ThinDielectric {
+            ior: f.positive("ior")?,
+        },
+        "diffuse_light" => MaterialDesc::DiffuseLight {
+            emit: f.color_or_texture("emit")?,
+        },
+        "isotropic" => MaterialDesc::Isotropic {
+            albedo: f.color_or_texture("albedo")?,
+        },
+        "henyey_greenstein" => {
+            let albedo = f.color_or_texture("albedo")?;
+            let g = f.number("g")?;
+            if g.is_nan() || g <= -1.0 || g >= 1.0 {
+                return Err(key_error(&f.key("g"), "must be between -1 and 1"));
+            }
+            MaterialDesc::HenyeyGreenstein { albedo, g }
+        }
+        "hair" => {
+            let absorption = read_hair_absorption(&mut f)?;
+            let eta = f.number_or("eta", 1.55)?;
+            if eta.is_nan() || eta <= 0.0 {
+                return Err(key_error(&f.key("eta"), "must be positive"));
+            }
+            let mut roughness = |name| {
+                let beta = f.number_or(name, 0.3)?;
+                if !(0.0..=1.0).contains(&beta) {
+                    return Err(key_error(
+                        &f.key(name),
+                        "must be between 0 and 1",
+                    ));
+                }
+                Ok(beta)
+            };
+            MaterialDesc::Hair {
+                absorption,
+                eta,
+                beta_m: roughness("beta_m")?,
+                beta_n: roughness("beta_n")?,
+                alpha: f.number_or("alpha", 2.0)?,
+            }
+        }
+        "conductor" => read_conductor(&mut f)?,
+        "principled" => read_principled(&mut f)?,
+        "mix" => MaterialDesc::Mix {
+            a: f.string("a")?.to_string(),
+            b: f.string("b")?.to_string(),
+            amount: f.fraction_or_texture("amount", 0.5)?,
+        },
+        "coated" => {
+            let thickness = f.positive_or("thickness", 0.01)?;
+            MaterialDesc::Coated {
+                base: f.string("base")?.to_string(),
+                ior: f.positive_or("ior", 1.5)?,
+                roughness: read_roughness(&mut f)?,
+                // The color is what the coat leaves after one crossing.
+                absorption: read_absorption(&mut f, thickness)?,
+                thickness,
+            }
+        }
+        "two_sided" => MaterialDesc::TwoSided {
+            front: f.string("front")?.to_string(),
+            back: f.string("back")?.to_string(),
+        },
+        ty => {
+            return Err(key_error(
+                &f.key("type"),
+                format!("unknown material type '{}'", ty),
+            ))
+        }
+    };
+    f.finish()?;
+    Ok(material)
+}
+
+fn read_object(mut f: Fields) -> Result<ObjectDesc, KeyError> {
+    let object = match f.string("type")? {
+        "sphere" => ObjectDesc::Sphere {
+            center: f.point3("center")?,
+            radius: f.number("radius")?,
+            material: f.string("material")?.to_string(),
+        },
+        "moving_sphere" => ObjectDesc::MovingSphere {
+            center0: f.point3("center0")?,
+            center1: f.point3("center1")?,
+            time0: f.number("time0")?,
+            time1: f.number("time1")?,
+            radius: f.number("radius")?,
+            material: f.string("material")?.to_string(),
+        },
+        "mesh" => {
+            let positions = f.points("positions")?;
+            let key = f.key("indices");
+            let indices = f
+                .array("indices")?
+                .ok_or_else(|| key_error(&key, "missing required key"))?
+                .iter()
+                .enumerate()
+                .map(|(i, v)| as_count(&format!("{}[{}]", key, i), v))
+                .collect::<Result<Vec<_>, _>>()?;
+            let key = f.key("normals");
+            let normals = f
+                .array("normals")?
+                .map_or(&[][..], |a| &a[..])
+                .iter()
+                .enumerate()
+                .map(|(i, v)| as_vec3(&format!("{}[{}]", key, i), v))
+                .collect::<Result<Vec<_>, _>>()?;
+            let key = f.key("uvs");
+            let uvs = f
+                .array("uvs")?
+                .map_or(&[][..], |a| &a[..])
+                .iter()
+                .enumerate()
+                .map(|(i, v)| as_pair(&format!("{}[{}]", key, i), v))
+                .collect::<Result<Vec<_>, _>>()?;
+            ObjectDesc::Mesh {
+                positions,
+                indices,
+                normals,
+                uvs,
+                material: f.string("material")?.to_string(),
+            }
+        }
+        ty @ ("xy_rect" | "xz_rect" | "yz_rect") => {
+            let plane = match ty {
+                "xy_rect" => Plane::XY,
+                "xz_rect" => Plane::XZ,
+                _ => Plane::YZ,
+            };
+            let (a, b, k) = rect_keys(plane);
+            ObjectDesc::Rect {
+                plane,
+                a: f.pair(a)?,
+                b: f.pair(b)?,
+                k: f.number(k)?,
+                material: f.string("material")?.to_string(),
+            }
+        }
+        "quad" => ObjectDesc::Quad {
+            corner: f.point3("corner")?,
+            u: f.vec3("u")?,
+            v: f.vec3("v")?,
+            material: f.string("material")?.to_string(),
+        },
+        "disk" => ObjectDesc::Disk {
+            center: f.point3("center")?,
+            normal: f.vec3("normal")?,
+            radius: f.number("radius")?,
+            material: f.string("material")?.to_string(),
+        },
+        "cylinder" => {
+            let (zmin, zmax) = read_heights(&mut f)?;
+            ObjectDesc::Cylinder {
+                radius: f.positive("radius")?,
+                zmin,
+                zmax,
+                phimax: read_phimax(&mut f)?,
+                material: f.string("material")?.to_string(),
+            }
+        }
+        "cone" => {
+            let height = f.number("height")?;
+            if height.is_nan() || height == 0.0 {
+                return Err(key_error(&f.key("height"), "must not be zero"));
+            }
+            ObjectDesc::Cone {
+                radius: f.positive("radius")?,
+                height,
+                phimax: read_phimax(&mut f)?,
+                material: f.string("material")?.to_string(),
+            }
+        }
+        "paraboloid" => {
+            let (zmin, zmax) = read_heights(&mut f)?;
+            if zmax <= 0.0 {
+                return Err(key_error(&f.key("zmax"), "must be positive"));
+            }
+            ObjectDesc::Paraboloid {
+                radius: f.positive("radius")?,
+                zmin,
+                zmax,
+                phimax: read_phimax(&mut f)?,
+                material: f.string("material")?.to_string(),
+            }
+        }
+        "hyperboloid" => {
+            let (p1, p2) = (f.point3("p1")?, f.point3("p2")?);
+            if p1.z() == p2.z() {
+                return Err(key_error(
+                    &f.key("p2"),
+                    "must differ from p1 in z",
+                ));
+            }
+            ObjectDesc::Hyperboloid {
+                p1,
+                p2,
+                phimax: read_phimax(&mut f)?,
+                material: f.string("material")?.to_string(),
+            }
+        }
+        "torus" => ObjectDesc::Torus {
+            major_radius: f.positive("major_radius")?,
+            minor_radius: f.positive("minor_radius")?,
+            phimax: read_phimax(&mut f)?,
+            material: f.string("material")?.to_string(),
+        },
+        "subdivision" => ObjectDesc::Subdivision {
+            cage: read_cage(&mut f)?,
+            levels: match f.count_or("levels", 3)? {
+                levels if levels <= MAX_SUBDIVISION_LEVELS => levels as u32,
+                _ => {
+                    return Err(key_error(
+                        &f.key("levels"),
+                        format!("must be at most {}", MAX_SUBDIVISION_LEVELS),
+                    ))
+                }
+            },
+            material: f.string("material")?.to_string(),
+        },
+        "bezier" => {
+            let control_points = f.points("control_points")?;
+            let key = f.key("patches");
+            let patches = f
+                .array("patches")?
+                .ok_or_else(|| key_error(&key, "missing required key"))?
+                .iter()
+                .enumerate()
+                .map(|(i, v)| {
+                    let key = format!("{}[{}]", key, i);
+                    let indices = as_indices(&key, v)?;
+                    let mut patch = [0; 16];
+                    if indices.len() != 16 {
+                        return Err(key_error(&key, "needs 16 indices"));
+                    }
+                    patch.copy_from_slice(&indices);
+                    if let Some(&i) =
+                        patch.iter().find(|&&i| i >= control_points.len())
+                    {
+                        return Err(key_error(
+                            &key,
+                            format!(
+                                "index {} out of range (have {})",
+                                i,
+                                control_points.len()
+                            ),
+                        ));
+                    }
+                    Ok(patch)
+                })
+                .collect::<Result<Vec<_>, _>>()?;
+            ObjectDesc::Bezier {
+                control_points,
+                patches,
+                divisions: read_divisions(&mut f)?,
+                material: f.string("material")?.to_string(),
+            }
+        }
+        "teapot" => ObjectDesc::Teapot {
+            divisions: read_divisions(&mut f)?,
+            material: f.string("material")?.to_string(),
+        },
+        "curve" => read_curve(&mut f)?,
+        "box" => ObjectDesc::Cuboid {
+            min: f.point3("min")?,
+            max: f.point3("max")?,
+            material: f.string("material")?.to_string(),
+        },
+        "obj" => ObjectDesc::Obj {
+            file: PathBuf::from(f.string("file")?),
+        },
+        "heightfield" => {
+            let size = f.vec3_or("size", Vec3::new(1.0, 1.0, 1.0))?;
+            if !(size.x() > 0.0 && size.z() > 0.0) || size.y().is_nan() {
+                return Err(key_error(
+                    &f.key("size"),
+                    "needs a positive width and depth",
+                ));
+            }
+            ObjectDesc::Heightfield {
+                file: PathBuf::from(f.string("file")?),
+                size,
+                material: f.string("material")?.to_string(),
+            }
+        }
+        "grid_medium" => {
+            let density_scale = f.number_or("density_scale", 1.0)?;
+            if density_scale.is_nan() || density_scale < 0.0 {
+                return Err(key_error(
+                    &f.key("density_scale"),
+                    "must not be negative",
+                ));
+            }
+            let absorption = f.number_or("absorption", 0.0)?;
+            if !(0.0..=1.0).contains(&absorption) {
+                return Err(key_error(
+                    &f.key("absorption"),
+                    "must be between 0 and 1",
+                ));
+            }
+            let emission = f.number_or("emission", 1.0)?;
+            if emission.is_nan() || emission < 0.0 {
+                return Err(key_error(
+                    &f.key("emission"),
+                    "must not be negative",
+                ));
+            }
+            ObjectDesc::GridMedium {
+                file: PathBuf::from(f.string("file")?),
+                density: match f.get("density") {
+                    Some(_) => f.string("density")?.to_string(),
+                    None => "density".to_string(),
+                },
+                temperature: match f.get("temperature") {
+                    Some(_) => Some(f.string("temperature")?.to_string()),
+                    None => None,
+                },
+                density_scale,
+                absorption,
+                emission,
+                material: f.string("material")?.to_string(),
+            }
+        }
+        "sdf" => {
+            let (min, max) = (f.point3("min")?, f.point3("max")?);
+            if (0..3)
+                .any(|i| min[i].is_nan() || max[i].is_nan() || min[i] >= max[i])
+            {
+                return Err(key_error(
+                    &f.key("max"),
+                    "must be greater than min",
+                ));
+            }
+            let shape = f.required("shape")?;
+            ObjectDesc::Sdf {
+                shape: read_sdf(Fields::new(f.key("shape"), shape)?)?,
+                min,
+                max,
+                material: f.string("material")?.to_string(),
+            }
+        }
+        "csg" => {
+            let operation = match f.string("operation")? {
+                "union" => CsgOp::Union,
+                "intersection" => CsgOp::Intersection,
+                "difference" => CsgOp::Difference,
+                op => {
+                    return Err(key_error(
+                        &f.key("operation"),
+                        format!("unknown operation '{}'", op),
+                    ))
+                }
+            };
+            let mut operand = |side: &'static str| {
+                let value = f.required(side)?;
+                let operand = read_object(Fields::new(f.key(side), value)?)?;
+                if !operand.is_solid() {
+                    return Err(key_error(
+                        &f.key(side),
+                        "must be a sphere, box, torus or csg object",
+                    ));
+                }
+                Ok(Box::new(operand))
+            };
+            ObjectDesc::Csg {
+                operation,
+                left: operand("left")?,
+                right: operand("right")?,
+            }
+        }
+        "constant_medium" => {
+            let boundary = f.required("boundary")?;
+            let boundary =
+                read_object(Fields::new(f.key("boundary"), boundary)?)?;
+            let density = f.number("density")?;
+            if density.is_nan() || density <= 0.0 {
+                return Err(key_error(&f.key("density"), "must be positive"));
+            }
+            ObjectDesc::ConstantMedium {
+                boundary: Box::new(boundary),
+                density,
+                material: f.string("material")?.to_string(),
+            }
+        }
+        ty => {
+            return Err(key_error(
+                &f.key("type"),
+                format!("unknown object type '{}'", ty),
+            ))
+        }
+    };
+    let object = match f.get("transform") {
+        Some(t) => ObjectDesc::Transformed {
+            transform: read_transform(Fields::new(f.key("transform"), t)?)?,
+            object: Box::new(object),
+        },
+        None => object,
+    };
+    f.finish()?;
+    Ok(object)
+}
+
+fn read_sdf(mut f: Fields) -> Result<Sdf, KeyError> {
+    let child = |f: &mut Fields, name: &'static str| {
+        let value = f.required(name)?;
+        Ok(Box::new(read_sdf(Fields::new(f.key(name), value)?)?))
+    };
+    let sdf = match f.string("type")? {
+        "sphere" => Sdf::Sphere {
+            radius: f.positive("radius")?,
+        },
+        "box" => {
+            let half_size = f.vec3("half_size")?;
+            let smallest = half_size.x().min(half_size.y()).min(half_size.z());
+            if smallest.is_nan() || smallest <= 0.0 {
+                return Err(key_error(&f.key("half_size"), "must be positive"));
+            }
+            let radius = f.number_or("radius", 0.0)?;
+            if radius.is_nan() || radius < 0.0 || radius > smallest {
+                return Err(key_error(
+                    &f.key("radius"),
+                    "must be between 0 and the smallest half size",
+                ));
+            }
+            Sdf::Box { half_size, radius }
+        }
+        "torus" => Sdf::Torus {
+            major_radius: f.positive("major_radius")?,
+            minor_radius: f.positive("minor_radius")?,
+        },
+        "cylinder" => Sdf::Cylinder {
+            radius: f.positive("radius")?,
+            height: f.positive("height")?,
+        },
+        "mandelbulb" => {
+            let power = f.number_or("power", 8.0)?;
+            if power.is_nan() || power <= 1.0 {
+                return Err(key_error(&f.key("power"), "must be above 1"));
+            }
+            Sdf::Mandelbulb {
+                power,
+                iterations: f.count_or("iterations", 12)?,
+            }
+        }
+        "union" => Sdf::Union(child(&mut f, "left")?, child(&mut f, "right")?),
+        "intersection" => {
+            Sdf::Intersection(child(&mut f, "left")?, child(&mut f, "right")?)
+        }
+        "difference" => {
+            Sdf::Difference(child(&mut f, "left")?, child(&mut f, "right")?)
+        }
+        "smooth_union" => Sdf::SmoothUnion {
+            left: child(&mut f, "left")?,
+            right: child(&mut f, "right")?,
+            k: f.positive("k")?,
+        },
+        "translate" => Sdf::Translate {
+            offset: f.vec3("offset")?,
+            shape: child(&mut f, "shape")?,
+        },
+        "scale" => Sdf::Scale {
+            factor: f.positive("factor")?,
+            shape: child(&mut f, "shape")?,
+        },
+        "twist" => Sdf::Twist {
+            rate: f.number("rate")?,
+            shape: child(&mut f, "shape")?,
+        },
+        "repeat" => {
+            let period = f.vec3("period")?;
+            if (0..3).any(|i| period[i].is_nan() || period[i] < 0.0) {
+                return Err(key_error(
+                    &f.key("period"),
+                    "must not be negative",
+                ));
+            }
+            Sdf::Repeat {
+                period,
+                shape: child(&mut f, "shape")?,
+            }
+        }
+        ty => {
+            return Err(key_error(
+                &f.key("type"),
+                format!("unknown sdf type '{}'", ty),
+            ))
+        }
+    };
+    f.finish()?;
+    Ok(sdf)
+}
+
+/// The heights `zmin` and `zmax` that a quadric is cut to.
+fn read_heights(f: &mut Fields) -> Result<(f32, f32), KeyError> {
+    let (zmin, zmax) = (f.number("zmin")?, f.number("zmax")?);
+    if zmin.is_nan() || zmax.is_nan() || zmax <= zmin {
+        return Err(key_error(&f.key("zmax"), "must be greater than zmin"));
+    }
+    Ok((zmin, zmax))
+}
+
+/// The sweep of a quadric in degrees, all the way around by default.
+fn read_phimax(f: &mut Fields) -> Result<f32, KeyError> {
+    let phimax = f.number_or("phimax", 360.0)?;
+    if phimax.is_nan() || phimax <= 0.0 || phimax > 360.0 {
+        return Err(key_error(
+            &f.key("phimax"),
+            "must be between 0 and 360 degrees",
+        ));
+    }
+    Ok(phimax)
+}
+
+/// Every level of subdivision quadruples the number of faces.
+const MAX_SUBDIVISION_LEVELS: usize = 8;
+
+/// The control cage of a subdivision surface: `positions`, `faces` of at
+/// least three of them and optional `creases`, each an `edge` between two
+/// positions with a `sharpness`, infinite by default.
+fn read_cage(f: &mut Fields) -> Result<Cage, KeyError> {
+    let positions = f.points("positions")?;
+    let in_range = |key: &str, indices: &[usize]| match indices
+        .iter()
+        .find(|&&i| i >= positions.len())
+    {
+        Some(&i) => Err(key_error(
+            key,
+            format!("index {} out of range (have {})", i, positions.len()),
+        )),
+        None => Ok(()),
+    };
+    let key = f.key("faces");
+    let faces = f
+        .array("faces")?
+        .ok_or_else(|| key_error(&key, "missing required key"))?
+        .iter()
+        .enumerate()
+        .map(|(i, v)| {
+            let key = format!("{}[{}]", key, i);
+            let face = as_indices(&key, v)?;
+            if face.len() < 3 {
+                return Err(key_error(&key, "needs at least 3 indices"));
+            }
+            in_range(&key, &face)?;
+            Ok(face)
+        })
+        .collect::<Result<Vec<_>, _>>()?;
+    if faces.is_empty() {
+        return Err(key_error(&key, "must not be empty"));
+    }
+    let key = f.key("creases");
+    let creases = f
+        .array("creases")?
+        .map_or(&[][..], |a| &a[..])
+        .iter()
+        .enumerate()
+        .map(|(i, v)| {
+            let mut c = Fields::new(format!("{}[{}]", key, i), v)?;
+            let edge_key = c.key("edge");
+            let edge = as_indices(&edge_key, c.required("edge")?)?;
+            if edge.len() != 2 {
+                return Err(key_error(&edge_key, "needs 2 indices"));
+            }
+            in_range(&edge_key, &edge)?;
+            let sharpness = c.number_or("sharpness", f32::INFINITY)?;
+            if sharpness.is_nan() || sharpness < 0.0 {
+                return Err(key_error(
+                    &c.key("sharpness"),
+                    "must not be negative",
+                ));
+            }
+            c.finish()?;
+            Ok(Crease {
+                edge: (edge[0], edge[1]),
+                sharpness,
+            })
+        })
+        .collect::<Result<Vec<_>, _>>()?;
+    Ok(Cage {
+        positions,
+        faces,
+        creases,
+    })
+}
+
+/// The number of quads along each side of a tessellated Bézier patch.
+fn read_divisions(f: &mut Fields) -> Result<usize, KeyError> {
+    match f.count_or("divisions", 8)? {
+        0 => Err(key_error(&f.key("divisions"), "must be positive")),
+        divisions => Ok(divisions),
+    }
+}
+
+/// The most segments a curve is split into for the BVH is 2 to this.
+const MAX_SPLIT_DEPTH: u32 = 10;
+
+fn read_curve(f: &mut Fields) -> Result<ObjectDesc, KeyError> {
+    let points = f.points("points")?;
+    if points.len() < 4 || (points.len() - 1) % 3 != 0 {
+        return Err(key_error(
+            &f.key("points"),
+            "needs 3n + 1 control points for n curves",
+        ));
+    }
+    let n = (points.len() - 1) / 3;
+    let key = f.key("width");
+    let widths = match f.required("width")? {
+        value @ Value::Array(_) => as_pair(&key, value)?,
+        value => {
+            let width = as_number(&key, value)?;
+            (width, width)
+        }
+    };
+    if widths.0.is_nan()
+        || widths.1.is_nan()
+        || widths.0 < 0.0
+        || widths.1 < 0.0
+        || widths.0 + widths.1 <= 0.0
+    {
+        return Err(key_error(&key, "must be positive"));
+    }
+    let kind = match f.get("kind") {
+        None => CurveKind::Flat,
+        Some(_) => match f.string("kind")? {
+            "flat" => CurveKind::Flat,
+            "cylinder" => CurveKind::Cylinder,
+            "ribbon" => CurveKind::Ribbon,
+            kind => {
+                return Err(key_error(
+                    &f.key("kind"),
+                    format!("unknown curve kind '{}'", kind),
+                ))
+            }
+        },
+    };
+    let key = f.key("normals");
+    let normals = match f.array("normals")? {
+        Some(normals) => normals
+            .iter()
+            .enumerate()
+            .map(|(i, v)| as_vec3(&format!("{}[{}]", key, i), v))
+            .collect::<Result<Vec<_>, _>>()?,
+        None => Vec::new(),
+    };
+    match kind {
+        CurveKind::Ribbon if normals.len() != n + 1 => {
+            return Err(key_error(
+                &key,
+                format!("needs {} normals for {} curves", n + 1, n),
+            ))
+        }
+        CurveKind::Ribbon => {
+            if let Some(i) = normals.iter().position(|n| n.length() == 0.0) {
+                return Err(key_error(
+                    &format!("{}[{}]", key, i),
+                    "must not be zero",
+                ));
+            }
+        }
+        _ if !normals.is_empty() => {
+            return Err(key_error(&key, "only ribbons have normals"))
+        }
+        _ => {}
+    }
+    Ok(ObjectDesc::Curve {
+        points,
+        widths,
+        kind,
+        normals,
+        split_depth: match f.count_or("split_depth", 3)? as u32 {
+            depth if depth <= MAX_SPLIT_DEPTH => depth,
+            _ => {
+                return Err(key_error(
+                    &f.key("split_depth"),
+                    format!("must be at most {}", MAX_SPLIT_DEPTH),
+                ))
+            }
+        },
+        material: f.string("material")?.to_string(),
+    })
+}
+
+/// The keys of the two ranges and of the position of a rectangle in
+/// `plane`.
+fn rect_keys(plane: Plane) -> (&'static str, &'static str, &'static str) {
+    match plane {
+        Plane::XY => ("x", "y", "z"),
+        Plane::XZ => ("x", "z", "y"),
+        Plane::YZ => ("y", "z", "x"),
+    }
+}
+
+fn read_transform(mut f: Fields) -> Result<TransformDesc, KeyError> {
+    let d = TransformDesc::default();
+    let scale = match f.get("scale") {
+        Some(value @ Value::Array(_)) => as_vec3(&f.key("scale"), value)?,
+        Some(value) => {
+            let s = as_number(&f.key("scale"), value)?;
+            Vec3::new(s, s, s)
+        }
+        None => d.scale,
+    };
+    let transform = TransformDesc {
+        translate: f.vec3_or("translate", d.translate)?,
+        rotate: f.number_or("rotate", d.rotate)?,
+        axis: f.vec3_or("axis", d.axis)?,
+        scale,
+    };
+    f.finish()?;
+    Ok(transform)
+}
+
+/// f32 -> f64 through the shortest decimal form, so that 0.3 is written as
+/// 0.3 and not as 0.30000001192092896.
+fn number(x: f32) -> Value {
+    Value::Float(f64::from_str(&x.to_string()).unwrap())
+}
+
+fn vec3(v: Vec3) -> Value {
+    Value::Array(vec![number(v.x()), number(v.y()), number(v.z())])
+}
+
+fn point3(p: Point3) -> Value {
+    vec3(Vec3::from(p))
+}
+
+fn string(s: &str) -> Value {
+    Value::String(s.to_string())
+}
+
+fn pair((a, b): (f32, f32)) -> Value {
+    Value::Array(vec![number(a), number(b)])
+}
+
+/// One number for isotropic roughness, a pair otherwise.
+fn roughness_value(roughness: (f32, f32)) -> Value {
+    if roughness.0 == roughness.1 {
+        number(roughness.0)
+    } else {
+        pair(roughness)
+    }
+}
+
+fn count(n: usize) -> Value {
+    Value::Integer(n as i64)
+}
+
+fn color_or_texture(c: &ColorOrTexture) -> Value {
+    match c {
+        ColorOrTexture::Color(c) => vec3(*c),
+        ColorOrTexture::Texture(name) => string(name),
+    }
+}
+
+/// A fraction kept as a gray is written as the number.
+fn fraction_or_texture(c: &ColorOrTexture) -> Value {
+    match c {
+        ColorOrTexture::Color(c) => number(c.x()),
+        ColorOrTexture::Texture(name) => string(name),
+    }
+}
+
+fn table(entries: Vec<(&str, Value)>) -> Value {
+    let mut t = Table::new();
+    for (k, v) in entries {
+        t.insert(k.to_string(), v);
+    }
+    Value::Table(t)
+}
+
+fn write_filter(filter: &FilterDesc) -> Value {
+    match *filter {
+        FilterDesc::Box { radius } => {
+            table(vec![("type", string("box")), ("radius", number(radius))])
+        }
+        FilterDesc::Tent { radius } => {
+            table(vec![("type", string("tent")), ("radius", number(radius))])
+        }
+        FilterDesc::Gaussian { radius, alpha } => table(vec![
+            ("type", string("gaussian")),
+            ("radius", number(radius)),
+            ("alpha", number(alpha)),
+        ]),
+        FilterDesc::Mitchell { radius, b, c } => table(vec![
+            ("type", string("mitchell")),
+            ("radius", number(radius)),
+            ("b", number(b)),
+            ("c", number(c)),
+        ]),
+        FilterDesc::Lanczos { radius, tau } => table(vec![
+            ("type", string("lanczos")),
+            ("radius", number(radius)),
+            ("tau", number(tau)),
+        ]),
+    }
+}
+
+fn write_scene(desc: &SceneDesc) -> Table {
+    let s = &desc.settings;
+    let c = &desc.camera;
+    let mut root = Table::new();
+    root.insert("version".to_string(), Value::Integer(VERSION));
+    root.insert(
+        "render".to_string(),
+        table(vec![
+            ("width", count(s.width)),
+            ("height", count(s.height)),
+            ("samples", count(s.samples)),
+            ("max_depth", count(s.ray_depth)),
+            ("filter", write_filter(&s.filter)),
+        ]),
+    );
+    root.insert(
+        "camera".to_string(),
+        table(vec![
+            ("look_from", point3(c.look_from)),
+            ("look_at", point3(c.look_at)),
+            ("up", vec3(c.up)),
+            ("fov", number(c.fov)),
+            ("aperture", number(c.aperture)),
+            ("focus_distance", number(c.focus_distance)),
+            ("shutter_open", number(c.shutter_open)),
+            ("shutter_close", number(c.shutter_close)),
+        ]),
+    );
+
+    let mut textures = Table::new();
+    for (name, t) in &desc.textures {
+        let t = match t {
+            TextureDesc::Constant { color } => table(vec![
+                ("type", string("constant")),
+                ("color", vec3(*color)),
+            ]),
+            TextureDesc::Checker { odd, even } => table(vec![
+                ("type", string("checker")),
+                ("odd", color_or_texture(odd)),
+                ("even", color_or_texture(even)),
+            ]),
+            TextureDesc::Image {
+                file,
+                wrap,
+                filter,
+                srgb,
+                scale,
+            } => {
+                let wrap = match wrap {
+                    WrapMode::Repeat => "repeat",
+                    WrapMode::Clamp => "clamp",
+                    WrapMode::Mirror => "mirror",
+                };
+                let filter = match filter {
+                    FilterMode::Bilinear => "bilinear",
+                    FilterMode::Trilinear => "trilinear",
+                    FilterMode::Ewa => "ewa",
+                };
+                let mut entries = vec![
+                    ("type", string("image")),
+                    ("file", string(file)),
+                    ("wrap", string(wrap)),
+                    ("filter", string(filter)),
+                    ("scale", pair(*scale)),
+                ];
+                if let Some(srgb) = srgb {
+                    entries.push(("srgb", Value::Boolean(*srgb)));
+                }
+                table(entries)
+            }
+        };
+        textures.insert(name.clone(), t);
+    }
+    if !textures.is_empty() {
+        root.insert("textures".to_string(), Value::Table(textures));
+    }
+
+    let mut materials = Table::new();
+    for (name, m) in &desc.materials {
+        let m = match m {
+            MaterialDesc::Lambertian { albedo } => table(vec![
+                ("type", string("lambertian")),
+                ("albedo", color_or_texture(albedo)),
+            ]),
+            MaterialDesc::Metal { albedo, fuzz } => table(vec![
+                ("type", string("metal")),
+                ("albedo", vec3(*albedo)),
+                ("fuzz", number(*fuzz)),
+            ]),
+            MaterialDesc::Dielectric {
+                ior,
+                roughness,
+                absorption,
+            } => table(vec![
+                ("type", string("dielectric")),
+                ("ior", number(*ior)),
+                ("roughness", roughness_value(*roughness)),
+                ("absorption", vec3(*absorption)),
+            ]),
+            MaterialDesc::ThinDielectric { ior } => table(vec![
+                ("type", string("thin_dielectric")),
+                ("ior", number(*ior)),
+            ]),
+            MaterialDesc::DiffuseLight { emit } => table(vec![
+                ("type", string("diffuse_light")),
+                ("emit", color_or_texture(emit)),
+            ]),
+            MaterialDesc::Isotropic { albedo } => table(vec![
+                ("type", string("isotropic")),
+                ("albedo", color_or_texture(albedo)),
+            ]),
+            MaterialDesc::HenyeyGreenstein { albedo, g } => table(vec![
+                ("type", string("henyey_greenstein")),
+                ("albedo", color_or_texture(albedo)),
+                ("g", number(*g)),
+            ]),
+            MaterialDesc::Hair {
+                absorption,
+                eta,
+                beta_m,
+                beta_n,
+                alpha,
+            } => {
+                let mut entries = vec![("type", string("hair"))];
+                match absorption {
+                    HairAbsorption::SigmaA(sigma_a) => {
+                        entries.push(("sigma_a", vec3(*sigma_a)))
+                    }
+                    HairAbsorption::Color(color) => {
+                        entries.push(("color", vec3(*color)))
+                    }
+                    HairAbsorption::Melanin {
+                        eumelanin,
+                        pheomelanin,
+                    } => {
+                        entries.push(("eumelanin", number(*eumelanin)));
+                        entries.push(("pheomelanin", number(*pheomelanin)));
+                    }
+                }
+                entries.push(("eta", number(*eta)));
+                entries.push(("beta_m", number(*beta_m)));
+                entries.push(("beta_n", number(*beta_n)));
+                entries.push(("alpha", number(*alpha)));
+                table(entries)
+            }
+            MaterialDesc::Conductor {
+                metal,
+                eta,
+                k,
+                roughness,
+            } => {
+                let mut entries = vec![("type", string("conductor"))];
+                match metal {
+                    Some(name) => entries.push(("metal", string(name))),
+                    None => {
+                        entries.push(("eta", vec3(*eta)));
+                        entries.push(("k", vec3(*k)));
+                    }
+                }
+                entries.push(("roughness", roughness_value(*roughness)));
+                table(entries)
+            }
+            MaterialDesc::Principled {
+                base_color,
+                metallic,
+                roughness,
+                specular,
+                specular_tint,
+                sheen,
+                sheen_tint,
+                clearcoat,
+                clearcoat_gloss,
+                transmission,
+                subsurface,
+            } => table(vec![
+                ("type", string("principled")),
+                ("base_color", color_or_texture(base_color)),
+                ("metallic", fraction_or_texture(metallic)),
+                ("roughness", fraction_or_texture(roughness)),
+                ("specular", fraction_or_texture(specular)),
+                ("specular_tint", number(*specular_tint)),
+                ("sheen", fraction_or_texture(sheen)),
+                ("sheen_tint", number(*sheen_tint)),
+                ("clearcoat", fraction_or_texture(clearcoat)),
+                ("clearcoat_gloss", number(*clearcoat_gloss)),
+                ("transmission", fraction_or_texture(transmission)),
+                ("subsurface", fraction_or_texture(subsurface)),
+            ]),
+            MaterialDesc::Mix { a, b, amount } => table(vec![
+                ("type", string("mix")),
+                ("a", string(a)),
+                ("b", string(b)),
+                ("amount", fraction_or_texture(amount)),
+            ]),
+            MaterialDesc::Coated {
+                base,
+                ior,
+                roughness,
+                absorption,
+                thickness,
+            } => table(vec![
+                ("type", string("coated")),
+                ("base", string(base)),
+                ("ior", number(*ior)),
+                ("roughness", roughness_value(*roughness)),
+                ("absorption", vec3(*absorption)),
+                ("thickness", number(*thickness)),
+            ]),
+            MaterialDesc::TwoSided { front, back } => table(vec![
+                ("type", string("two_sided")),
+                ("front", string(front)),
+                ("back", string(back)),
+            ]),
+        };
+        materials.insert(name.clone(), m);
+    }
+    root.insert("materials".to_string(), Value::Table(materials));
+
+    let objects = desc.objects.iter().map(write_object).collect();
+    root.insert("objects".to_string(), Value::Array(objects));
+    root
+}
+
+fn write_sdf(sdf: &Sdf) -> Value {
+    let pair = |ty: &str, left: &Sdf, right: &Sdf| {
+        table(vec![
+            ("type", string(ty)),
+            ("left", write_sdf(left)),
+            ("right", write_sdf(right)),
+        ])
+    };
+    match sdf {
+        Sdf::Sphere { radius } => table(vec![
+            ("type", string("sphere")),
+            ("radius", number(*radius)),
+        ]),
+        Sdf::Box { half_size, radius } => table(vec![
+            ("type", string("box")),
+            ("half_size", vec3(*half_size)),
+            ("radius", number(*radius)),
+        ]),
+        Sdf::Torus {
+            major_radius,
+            minor_radius,
+        } => table(vec![
+            ("type", string("torus")),
+            ("major_radius", number(*major_radius)),
+            ("minor_radius", number(*minor_radius)),
+        ]),
+        Sdf::Cylinder { radius, height } => table(vec![
+            ("type", string("cylinder")),
+            ("radius", number(*radius)),
+            ("height", number(*height)),
+        ]),
+        Sdf::Mandelbulb { power, iterations } => table(vec![
+            ("type", string("mandelbulb")),
+            ("power", number(*power)),
+            ("iterations", count(*iterations)),
+        ]),
+        Sdf::Union(left, right) => pair("union", left, right),
+        Sdf::Intersection(left, right) => pair("intersection", left, right),
+        Sdf::Difference(left, right) => pair("difference", left, right),
+        Sdf::SmoothUnion { left, right, k } => {
+            let mut value = pair("smooth_union", left, right);
+            if let Value::Table(t) = &mut value {
+                t.insert("k".to_string(), number(*k));
+            }
+            value
+        }
+        Sdf::Translate { offset, shape } => table(vec![
+            ("type", string("translate")),
+            ("offset", vec3(*offset)),
+            ("shape", write_sdf(shape)),
+        ]),
+        Sdf::Scale { factor, shape } => table(vec![
+            ("type", string("scale")),
+            ("factor", number(*factor)),
+            ("shape", write_sdf(shape)),
+        ]),
+        Sdf::Twist { rate, shape } => table(vec![
+            ("type", string("twist")),
+            ("rate", number(*rate)),
+            ("shape", write_sdf(shape)),
+        ]),
+        Sdf::Repeat { period, shape } => table(vec![
+            ("type", string("repeat")),
+            ("period", vec3(*period)),
+            ("shape", write_sdf(shape)),
+        ]),
+    }
+}
+
+fn write_object(object: &ObjectDesc) -> Value {
+    match object {
+        ObjectDesc::Sphere {
+            center,
+            radius,
+            material,
+        } => table(vec![
+            ("type", string("sphere")),
+            ("center", point3(*center)),
+            ("radius", number(*radius)),
+            ("material", string(material)),
+        ]),
+        ObjectDesc::MovingSphere {
+            center0,
+            center1,
+            time0,
+            time1,
+            radius,
+            material,
+        } => table(vec![
+            ("type", string("moving_sphere")),
+            ("center0", point3(*center0)),
+            ("center1", point3(*center1)),
+            ("time0", number(*time0)),
+            ("time1", number(*time1)),
+            ("radius", number(*radius)),
+            ("material", string(material)),
+        ]),
+        ObjectDesc::Mesh {
+            positions,
+            indices,
+            normals,
+            uvs,
+            material,
+        } => {
+            let mut entries = vec![
+                ("type", string("mesh")),
+                (
+                    "positions",
+                    Value::Array(
+                        positions.iter().map(|&p| point3(p)).collect(),
+                    ),
+                ),
+                (
+                    "indices",
+                    Value::Array(indices.iter().map(|&i| count(i)).collect()),
+                ),
+                ("material", string(material)),
+            ];
+            if !normals.is_empty() {
+                entries.push((
+                    "normals",
+                    Value::Array(normals.iter().map(|&n| vec3(n)).collect()),
+                ));
+            }
+            if !uvs.is_empty() {
+                entries.push((
+                    "uvs",
+                    Value::Array(uvs.iter().map(|&uv| pair(uv)).collect()),
+                ));
+            }
+            table(entries)
+        }
+        ObjectDesc::Rect {
+            plane,
+            a,
+            b,
+            k,
+            material,
+        } => {
+            let ty = match plane {
+                Plane::XY => "xy_rect",
+                Plane::XZ => "xz_rect",
+                Plane::YZ => "yz_rect",
+            };
+            let (a_key, b_key, k_key) = rect_keys(*plane);
+            table(vec![
+                ("type", string(ty)),
+                (a_key, pair(*a)),
+                (b_key, pair(*b)),
+                (k_key, number(*k)),
+                ("material", string(material)),
+            ])
+        }
+        ObjectDesc::Quad {
+            corner,
+            u,
+            v,
+            material,
+        } => table(vec![
+            ("type", string("quad")),
+            ("corner", point3(*corner)),
+            ("u", vec3(*u)),
+            ("v", vec3(*v)),
+            ("material", string(material)),
+        ]),
+        ObjectDesc::Disk {
+            center,
+            normal,
+            radius,
+            material,
+        } => table(vec![
+            ("type", string("disk")),
+            ("center", point3(*center)),
+            ("normal", vec3(*normal)),
+            ("radius", number(*radius)),
+            ("material", string(material)),
+        ]),
+        ObjectDesc::Cylinder {
+            radius,
+            zmin,
+            zmax,
+            phimax,
+            material,
+        } => table(vec![
+            ("type", string("cylinder")),
+            ("radius", number(*radius)),
+            ("zmin", number(*zmin)),
+            ("zmax", number(*zmax)),
+            ("phimax", number(*phimax)),
+            ("material", string(material)),
+        ]),
+        ObjectDesc::Cone {
+            radius,
+            height,
+            phimax,
+            material,
+        } => table(vec![
+            ("type", string("cone")),
+            ("radius", number(*radius)),
+            ("height", number(*height)),
+            ("phimax", number(*phimax)),
+            ("material", string(material)),
+        ]),
+        ObjectDesc::Paraboloid {
+            radius,
+            zmin,
+            zmax,
+            phimax,
+            material,
+        } => table(vec![
+            ("type", string("paraboloid")),
+            ("radius", number(*radius)),
+            ("zmin", number(*zmin)),
+            ("zmax", number(*zmax)),
+            ("phimax", number(*phimax)),
+            ("material", string(material)),
+        ]),
+        ObjectDesc::Hyperboloid {
+            p1,
+            p2,
+            phimax,
+            material,
+        } => table(vec![
+            ("type", string("hyperboloid")),
+            ("p1", point3(*p1)),
+            ("p2", point3(*p2)),
+            ("phimax", number(*phimax)),
+            ("material", string(material)),
+        ]),
+        ObjectDesc::Torus {
+            major_radius,
+            minor_radius,
+            phimax,
+            material,
+        } => table(vec![
+            ("type", string("torus")),
+            ("major_radius", number(*major_radius)),
+            ("minor_radius", number(*minor_radius)),
+            ("phimax", number(*phimax)),
+            ("material", string(material)),
+        ]),
+        ObjectDesc::Subdivision {
+            cage,
+            levels,
+            material,
+        } => {
+            let indices = |i: &[usize]| {
+                Value::Array(i.iter().map(|&i| count(i)).collect())
+            };
+            let mut entries = vec![
+                ("type", string("subdivision")),
+                (
+                    "positions",
+                    Value::Array(
+                        cage.positions.iter().map(|&p| point3(p)).collect(),
+                    ),
+                ),
+                (
+                    "faces",
+                    Value::Array(
+                        cage.faces.iter().map(|f| indices(f)).collect(),
+                    ),
+                ),
+                ("levels", count(*levels as usize)),
+                ("material", string(material)),
+            ];
+            if !cage.creases.is_empty() {
+                let creases = cage.creases.iter().map(|c| {
+                    table(vec![
+                        ("edge", indices(&[c.edge.0, c.edge.1])),
+                        ("sharpness", number(c.sharpness)),
+                    ])
+                });
+                entries.push(("creases", Value::Array(creases.collect())));
+            }
+            table(entries)
+        }
+        ObjectDesc::Bezier {
+            control_points,
+            patches,
+            divisions,
+            material,
+        } => table(vec![
+            ("type", string("bezier")),
+            (
+                "control_points",
+                Value::Array(
+                    control_points.iter().map(|&p| point3(p)).collect(),
+                ),
+            ),
+            (
+                "patches",
+                Value::Array(
+                    patches
+                        .iter()
+                        .map(|p| {
+                            Value::Array(p.iter().map(|&i| count(i)).collect())
+                        })
+                        .collect(),
+                ),
+            ),
+            ("divisions", count(*divisions)),
+            ("material", string(material)),
+        ]),
+        ObjectDesc::Teapot {
+            divisions,
+            material,
+        } => table(vec![
+            ("type", string("teapot")),
+            ("divisions", count(*divisions)),
+            ("material", string(material)),
+        ]),
+        ObjectDesc::Curve {
+            points,
+            widths,
+            kind,
+            normals,
+            split_depth,
+            material,
+        } => {
+            let mut entries = vec![
+                ("type", string("curve")),
+                (
+                    "points",
+                    Value::Array(points.iter().map(|&p| point3(p)).collect()),
+                ),
+            ];
+            if widths.0 == widths.1 {
+                entries.push(("width", number(widths.0)));
+            } else {
+                entries.push(("width", pair(*widths)));
+            }
+            let kind = match kind {
+                CurveKind::Flat => "flat",
+                CurveKind::Cylinder => "cylinder",
+                CurveKind::Ribbon => "ribbon",
+            };
+            entries.push(("kind", string(kind)));
+            if !normals.is_empty() {
+                let normals = normals.iter().map(|&n| vec3(n)).collect();
+                entries.push(("normals", Value::Array(normals)));
+            }
+            entries.push(("split_depth", count(*split_depth as usize)));
+            entries.push(("material", string(material)));
+            table(entries)
+        }
+        ObjectDesc::Cuboid { min, max, material } => table(vec![
+            ("type", string("box")),
+            ("min", point3(*min)),
+            ("max", point3(*max)),
+            ("material", string(material)),
+        ]),
+        ObjectDesc::Obj { file } => table(vec![
+            ("type", string("obj")),
+            ("file", string(&file.to_string_lossy())),
+        ]),
+        ObjectDesc::Heightfield {
+            file,
+            size,
+            material,
+        } => table(vec![
+            ("type", string("heightfield")),
+            ("file", string(&file.to_string_lossy())),
+            ("size", vec3(*size)),
+            ("material", string(material)),
+        ]),
+        ObjectDesc::ConstantMedium {
+            boundary,
+            density,
+            material,
+        } => table(vec![
+            ("type", string("constant_medium")),
+            ("boundary", write_object(boundary)),
+            ("density", number(*density)),
+            ("material", string(material)),
+        ]),
+        ObjectDesc::GridMedium {
+            file,
+            density,
+            temperature,
+            density_scale,
+            absorption,
+            emission,
+            material,
+        } => {
+            let mut entries = vec![
+                ("type", string("grid_medium")),
+                ("file", string(&file.to_string_lossy())),
+                ("density", string(density)),
+                ("density_scale", number(*density_scale)),
+                ("absorption", number(*absorption)),
+                ("emission", number(*emission)),
+                ("material", string(material)),
+            ];
+            if let Some(temperature) = temperature {
+                entries.push(("temperature", string(temperature)));
+            }
+            table(entries)
+        }
+        ObjectDesc::Sdf {
+            shape,
+            min,
+            max,
+            material,
+        } => table(vec![
+            ("type", string("sdf")),
+            ("min", point3(*min)),
+            ("max", point3(*max)),
+            ("material", string(material)),
+            ("shape", write_sdf(shape)),
+        ]),
+        ObjectDesc::Csg {
+            operation,
+            left,
+            right,
+        } => table(vec![
+            ("type", string("csg")),
+            (
+                "operation",
+                string(match operation {
+                    CsgOp::Union => "union",
+                    CsgOp::Intersection => "intersection",
+                    CsgOp::Difference => "difference",
+                }),
+            ),
+            ("left", write_object(left)),
+            ("right", write_object(right)),
+        ]),
+        ObjectDesc::Transformed { transform, object } => {
+            let mut value = write_object(object);
+            if let Value::Table(t) = &mut value {
+                let transform = table(vec![
+                    ("translate", vec3(transform.translate)),
+                    ("rotate", number(transform.rotate)),
+                    ("axis", vec3(transform.axis)),
+                    ("scale", vec3(transform.scale)),
+                ]);
+                t.insert("transform".to_string(), transform);
+            }
+            value
+        }
+    }
+}
+
+#[cfg(test)]
+pub mod tests {
+    use super::*;
+    use ::std::env;
+    use ::std::f32::consts::LN_2;
+    use ::std::{assert, assert_eq, panic};
+
+    use crate::image::WriteOptions;
+    use crate::pbrt::{HitRecord, Ray};
+
+    const SCENE: &str = r#"
+        version = 1
+
+        [render]
+        width = 64
+        height = 32
+        samples = 4
+        filter = { type = "mitchell", radius = 1.5 }
+
+        [camera]
+        look_from = [0, 1, 5]
+        look_at = [0, 0.5, 0]
+        fov = 30
+
+        [textures.checks]
+        type = "checker"
+        odd = [0.2, 0.3, 0.1]
+        even = [0.9, 0.9, 0.9]
+
+        [materials.ground]
+        type = "lambertian"
+        albedo = "checks"
+
+        [materials.lamp]
+        type = "diffuse_light"
+        emit = [4, 4, 4]
+
+        [[objects]]
+        type = "sphere"
+        center = [0, -1000, 0]
+        radius = 1000
+        material = "ground"
+
+        [[objects]]
+        type = "mesh"
+        positions = [[-1, 2, 0], [1, 2, 0], [0, 2, 1]]
+        indices = [0, 1, 2]
+        material = "lamp"
+    "#;
+
+    fn parse(src: &str) -> Result<SceneDesc, SceneError> {
+        SceneDesc::from_toml(src, Path::new("test.toml"))
+    }
+
+    fn expect_key_error(src: &str, expected: &str) {
+        match parse(src) {
+            Err(SceneError::Key { key, .. }) => assert_eq!(key, expected),
+            Err(e) => panic!("unexpected error: {}", e),
+            Ok(_) => panic!("expected error for {}", expected),
+        }
+    }
+
+    #[test]
+    fn test_scene_parse_and_build() {
+        let desc = parse(SCENE).unwrap();
+        assert_eq!(desc.settings.width, 64);
+        assert_eq!(desc.settings.ray_depth, 50);
+        assert_eq!(desc.objects.len(), 2);
+        let scene = desc
+            .build(Path::new("test.toml"), &mut RNG::default())
+            .unwrap();
+        assert_eq!(scene.matlib.lib.len(), 2);
+    }
+
+    #[test]
+    fn test_scene_transform() {
+        let src = r#"
+            version = 1
+            [camera]
+            look_from = [0, 0, 5]
+            look_at = [0, 0, 0]
+            [materials.grey]
+            type = "lambertian"
+            albedo = [0.5, 0.5, 0.5]
+            [[objects]]
+            type = "mesh"
+            positions = [[-1, -1, 0], [1, -1, 0], [1, 1, 0], [-1, 1, 0]]
+            indices = [0, 1, 2, 0, 2, 3]
+            material = "grey"
+            transform = { translate = [0, 0, -2], rotate = 90, scale = 2 }
+        "#;
+        let desc = parse(src).unwrap();
+        let exported = desc.to_toml();
+        assert_eq!(parse(&exported).unwrap().to_toml(), exported);
+        let scene = desc
+            .build(Path::new("test.toml"), &mut RNG::default())
+            .unwrap();
+        // Turned to face +x, 4 wide and centered at z = -2.
+        let r = Ray::new(
+            Point3::new(5.0, 1.5, -3.5),
+            Vec3::new(-1.0, 0.0, 0.0),
+            0.0,
+            0,
+        );
+        let mut rec = HitRecord::default();
+        assert!(scene.world.hit(&r, 0.001, f32::MAX, &mut rec));
+        assert!((rec.t - 5.0).abs() < 1e-4);
+        assert!((rec.normal.x().abs() - 1.0).abs() < 1e-4);
+
+        let flat = parse(&src.replace("scale = 2", "scale = [1, 0, 1]"));
+        match flat
+            .unwrap()
+            .build(Path::new("test.toml"), &mut RNG::default())
+        {
+            Err(SceneError::Key { key, .. }) => {
+                assert_eq!(key, "objects[0].transform.scale")
+            }
+            _ => panic!("expected an error for a zero scale"),
+        }
+    }
+
+    #[test]
+    fn test_scene_planar_shapes() {
+        let src = r#"
+            version = 1
+            [camera]
+            look_from = [0, 1, 5]
+            look_at = [0, 1, 0]
+            [materials.white]
+            type = "lambertian"
+            albedo = [0.7, 0.7, 0.7]
+            [materials.lamp]
+            type = "diffuse_light"
+            emit = [4, 4, 4]
+            [[objects]]
+            type = "xz_rect"
+            x = [-2, 2]
+            z = [-2, 2]
+            y = 0
+            material = "white"
+            [[objects]]
+            type = "quad"
+            corner = [-2, 0, -2]
+            u = [4, 0, 0]
+            v = [0, 2, 0]
+            material = "white"
+            [[objects]]
+            type = "disk"
+            center = [0, 2, 0]
+            normal = [0, -1, 0]
+            radius = 0.5
+            material = "lamp"
+            [[objects]]
+            type = "box"
+            min = [-0.5, 0, -0.5]
+            max = [0.5, 1, 0.5]
+            material = "white"
+            transform = { rotate = 30 }
+        "#;
+        let desc = parse(src).unwrap();
+        let exported = desc.to_toml();
+        assert_eq!(parse(&exported).unwrap().to_toml(), exported);
+        assert!(exported.contains("type = \"xz_rect\""));
+        let scene = desc
+            .build(Path::new("test.toml"), &mut RNG::default())
+            .unwrap();
+        assert_eq!(scene.lights.list.len(), 1);
+        let r = Ray::new(
+            Point3::new(0.0, 0.5, 5.0),
+            Vec3::new(0.0, 0.0, -1.0),
+            0.0,
+            0,
+        );
+        let mut rec = HitRecord::default();
+        assert!(scene.world.hit(&r, 0.001, f32::MAX, &mut rec));
+        // The front face of the turned box crosses x = 0 at z = 0.5 / cos 30°.
+        let z = 0.5 / 30.0f32.to_radians().cos();
+        assert!((rec.t - (5.0 - z)).abs() < 1e-3);
+
+        expect_key_error(&src.replace("y = 0\n", ""), "objects[0].y");
+        let parallel = parse(&src.replace("v = [0, 2, 0]", "v = [1, 0, 0]"));
+        match parallel
+            .unwrap()
+            .build(Path::new("test.toml"), &mut RNG::default())
+        {
+            Err(SceneError::Key { key, .. }) => assert_eq!(key, "objects[1].v"),
+            _ => panic!("expected an error for parallel edges"),
+        }
+    }
+
+    #[test]
+    fn test_scene_medium() {
+        let src = r#"
+            version = 1
+            [camera]
+            look_from = [0, 0, 5]
+            look_at = [0, 0, 0]
+            [materials.fog]
+            type = "henyey_greenstein"
+            albedo = [0.9, 0.9, 0.9]
+            g = -0.3
+            [[objects]]
+            type = "constant_medium"
+            density = 100
+            material = "fog"
+            transform = { translate = [0, 0, -1] }
+            [objects.boundary]
+            type = "sphere"
+            center = [0, 0, 0]
+            radius = 1
+            material = "fog"
+        "#;
+        let desc = parse(src).unwrap();
+        let exported = desc.to_toml();
+        assert_eq!(parse(&exported).unwrap().to_toml(), exported);
+        let scene = desc
+            .build(Path::new("test.toml"), &mut RNG::default())
+            .unwrap();
+        // So dense that rays scatter right after entering.
+        let r = Ray::new(
+            Point3::new(0.0, 0.0, 5.0),
+            Vec3::new(0.0, 0.0, -1.0),
+            0.0,
+            0,
+        );
+        let mut rec = HitRecord::default();
+        assert!(scene.world.hit(&r, 0.001, f32::MAX, &mut rec));
+        assert!(rec.t > 5.0 && rec.t < 5.2);
+
+        expect_key_error(&src.replace("100", "0"), "objects[0].density");
+        expect_key_error(&src.replace("-0.3", "1"), "materials.fog.g");
+    }
+
+    #[test]
+    fn test_scene_grid_medium() {
+        let bytes = crate::medium::tests::grid_file(&[(
+            "density",
+            [1, 1, 1],
+            [-1.0, -1.0, -1.0, 1.0, 1.0, 1.0],
+            &[100.0],
+        )]);
+        let dir = env::temp_dir();
+        fs::write(dir.join("raytracer_test_scene.grid"), bytes).unwrap();
+        let src = r#"
+            version = 1
+            [camera]
+            look_from = [0, 0, 5]
+            look_at = [0, 0, 0]
+            [materials.smoke]
+            type = "isotropic"
+            albedo = [0.5, 0.5, 0.5]
+            [[objects]]
+            type = "grid_medium"
+            file = "raytracer_test_scene.grid"
+            density_scale = 2
+            emission = 0.5
+            material = "smoke"
+        "#;
+        let desc = parse(src).unwrap();
+        let exported = desc.to_toml();
+        assert_eq!(parse(&exported).unwrap().to_toml(), exported);
+        assert!(!exported.contains("temperature"));
+        let path = dir.join("test.toml");
+        let scene = desc.build(&path, &mut RNG::default()).unwrap();
+        assert_eq!(scene.matlib.lib.len(), 2);
+        let r = Ray::new(
+            Point3::new(0.0, 0.0, 5.0),
+            Vec3::new(0.0, 0.0, -1.0),
+            0.0,
+            0,
+        );
+        let mut rec = HitRecord::default();
+        assert!(scene.world.hit(&r, 0.001, f32::MAX, &mut rec));
+        assert!(rec.t > 4.0 && rec.t < 4.1);
+
+        let missing =
+            src.replace("material =", "temperature = \"t\"\nmaterial =");
+        match parse(&missing).unwrap().build(&path, &mut RNG::default()) {
+            Err(SceneError::Key { key, .. }) => {
+                assert_eq!(key, "objects[0].temperature")
+            }
+            _ => panic!("expected a missing grid"),
+        }
+        expect_key_error(
+            &src.replace("= 2", "= -2"),
+            "objects[0].density_scale",
+        );
+        expect_key_error(
+            &src.replace("= 0.5", "= -0.5"),
+            "objects[0].emission",
+        );
+    }
+
+    #[test]
+    fn test_scene_heightfield() {
+        // A ridge along z.
+        let mut image = Image::new(3, 2);
+        for z in 0..2 {
+            image.set_pixel(1, z, Vec3::new(1.0, 1.0, 1.0));
+        }
+        let dir = env::temp_dir();
+        let file = dir.join("raytracer_test_scene_heights.pfm");
+        image.write(&file, &WriteOptions::default()).unwrap();
+        let src = r#"
+            version = 1
+            [camera]
+            look_from = [0, 5, 0]
+            look_at = [0, 0, 0]
+            [materials.grass]
+            type = "lambertian"
+            albedo = [0.3, 0.6, 0.2]
+            [[objects]]
+            type = "heightfield"
+            file = "raytracer_test_scene_heights.pfm"
+            size = [4, 2, 1]
+            material = "grass"
+        "#;
+        let desc = parse(src).unwrap();
+        let exported = desc.to_toml();
+        assert_eq!(parse(&exported).unwrap().to_toml(), exported);
+        let path = dir.join("test.toml");
+        let scene = desc.build(&path, &mut RNG::default()).unwrap();
+        let _ = fs::remove_file(&file);
+        let r = Ray::new(
+            Point3::new(1.0, 5.0, 0.5),
+            Vec3::new(0.0, -1.0, 0.0),
+            0.0,
+            0,
+        );
+        let mut rec = HitRecord::default();
+        assert!(scene.world.hit(&r, 0.001, f32::MAX, &mut rec));
+        assert!((rec.p.y() - 1.0).abs() < 1e-4);
+        assert!(rec.normal.x() < -0.3);
+
+        match parse(src).unwrap().build(&path, &mut RNG::default()) {
+            Err(SceneError::Key { key, .. }) => {
+                assert_eq!(key, "objects[0].file")
+            }
+            _ => panic!("expected a missing file"),
+        }
+        expect_key_error(
+            &src.replace("[4, 2, 1]", "[4, 2, 0]"),
+            "objects[0].size",
+        );
+    }
+
+    #[test]
+    fn test_scene_csg() {
+        let src = r#"
+            version = 1
+            [camera]
+            look_from = [0, 0, 5]
+            look_at = [0, 0, 0]
+            [materials.white]
+            type = "lambertian"
+            albedo = [0.8, 0.8, 0.8]
+            [materials.red]
+            type = "lambertian"
+            albedo = [0.8, 0.1, 0.1]
+            [[objects]]
+            type = "csg"
+            operation = "difference"
+            [objects.left]
+            type = "box"
+            min = [-1, -1, -1]
+            max = [1, 1, 1]
+            material = "white"
+            [objects.right]
+            type = "sphere"
+            center = [0, 0, 0]
+            radius = 0.5
+            material = "red"
+            transform = { translate = [0, 0, 1] }
+        "#;
+        let desc = parse(src).unwrap();
+        let exported = desc.to_toml();
+        assert_eq!(parse(&exported).unwrap().to_toml(), exported);
+        let scene = desc
+            .build(Path::new("test.toml"), &mut RNG::default())
+            .unwrap();
+        // Straight into the dent that the sphere leaves in the front face.
+        let r = Ray::new(
+            Point3::new(0.0, 0.0, 5.0),
+            Vec3::new(0.0, 0.0, -1.0),
+            0.0,
+            0,
+        );
+        let mut rec = HitRecord::default();
+        assert!(scene.world.hit(&r, 0.001, f32::MAX, &mut rec));
+        assert!((rec.t - 4.5).abs() < 1e-4);
+        // Materials are numbered by name, so red comes first.
+        assert_eq!(rec.material, 0);
+        assert!(rec.normal.z() > 0.99);
+
+        expect_key_error(
+            &src.replace("\"difference\"", "\"xor\""),
+            "objects[0].operation",
+        );
+        expect_key_error(
+            &src.replace("\"sphere\"", "\"disk\"\nnormal = [0, 0, 1]"),
+            "objects[0].right",
+        );
+    }
+
+    #[test]
+    fn test_scene_quadrics() {
+        let src = r#"
+            version = 1
+            [camera]
+            look_from = [0, 0, 5]
+            look_at = [0, 0, 0]
+            [materials.steel]
+            type = "metal"
+            albedo = [0.8, 0.8, 0.8]
+            fuzz = 0.1
+            [[objects]]
+            type = "cylinder"
+            radius = 0.5
+            zmin = 0
+            zmax = 2
+            phimax = 270
+            material = "steel"
+            [[objects]]
+            type = "cone"
+            radius = 1
+            height = 2
+            material = "steel"
+            [[objects]]
+            type = "paraboloid"
+            radius = 1
+            zmin = 0
+            zmax = 1
+            material = "steel"
+            [[objects]]
+            type = "hyperboloid"
+            p1 = [1, 0, 0]
+            p2 = [0, 1, 1]
+            material = "steel"
+            [[objects]]
+            type = "csg"
+            operation = "union"
+            [objects.left]
+            type = "torus"
+            major_radius = 2
+            minor_radius = 0.5
+            material = "steel"
+            [objects.right]
+            type = "sphere"
+            center = [0, 0, 0]
+            radius = 0.5
+            material = "steel"
+        "#;
+        let desc = parse(src).unwrap();
+        let exported = desc.to_toml();
+        assert_eq!(parse(&exported).unwrap().to_toml(), exported);
+        let scene = desc
+            .build(Path::new("test.toml"), &mut RNG::default())
+            .unwrap();
+        // Onto the outside of the torus.
+        let r = Ray::new(
+            Point3::new(10.0, 0.0, 0.0),
+            Vec3::new(-1.0, 0.0, 0.0),
+            0.0,
+            0,
+        );
+        let mut rec = HitRecord::default();
+        assert!(scene.world.hit(&r, 0.001, f32::MAX, &mut rec));
+        assert!((rec.t - 7.5).abs() < 1e-4);
+
+        expect_key_error(&src.replace("270", "400"), "objects[0].phimax");
+        expect_key_error(
+            &src.replace("zmax = 2", "zmax = -1"),
+            "objects[0].zmax",
+        );
+        expect_key_error(
+            &src.replace("p2 = [0, 1, 1]", "p2 = [0, 1, 0]"),
+            "objects[3].p2",
+        );
+        expect_key_error(
+            &src.replace(
+                "minor_radius = 0.5\n",
+                "minor_radius = 0.5\nphimax = 90\n",
+            ),
+            "objects[4].left",
+        );
+    }
+
+    #[test]
+    fn test_scene_sdf() {
+        let src = r#"
+            version = 1
+            [camera]
+            look_from = [0, 0, 5]
+            look_at = [0, 0, 0]
+            [materials.white]
+            type = "lambertian"
+            albedo = [0.8, 0.8, 0.8]
+            [[objects]]
+            type = "sdf"
+            min = [-2, -1, -1]
+            max = [2, 1, 1]
+            material = "white"
+            [objects.shape]
+            type = "smooth_union"
+            k = 0.2
+            [objects.shape.left]
+            type = "translate"
+            offset = [-1, 0, 0]
+            [objects.shape.left.shape]
+            type = "box"
+            half_size = [0.5, 0.5, 0.5]
+            radius = 0.1
+            [objects.shape.right]
+            type = "repeat"
+            period = [0.5, 0, 0]
+            [objects.shape.right.shape]
+            type = "scale"
+            factor = 0.2
+            [objects.shape.right.shape.shape]
+            type = "twist"
+            rate = 45
+            [objects.shape.right.shape.shape.shape]
+            type = "mandelbulb"
+        "#;
+        let desc = parse(src).unwrap();
+        let exported = desc.to_toml();
+        assert_eq!(parse(&exported).unwrap().to_toml(), exported);
+        assert!(exported.contains("iterations = 12"));
+        let scene = desc
+            .build(Path::new("test.toml"), &mut RNG::default())
+            .unwrap();
+        // The front of the box.
+        let r = Ray::new(
+            Point3::new(-1.0, 0.0, 5.0),
+            Vec3::new(0.0, 0.0, -1.0),
+            0.0,
+            0,
+        );
+        let mut rec = HitRecord::default();
+        assert!(scene.world.hit(&r, 0.001, f32::MAX, &mut rec));
+        assert!((rec.t - 4.5).abs() < 1e-3);
+
+        expect_key_error(
+            &src.replace("radius = 0.1", "radius = 1"),
+            "objects[0].shape.left.shape.radius",
+        );
+        expect_key_error(
+            &src.replace("\"mandelbulb\"", "\"menger\""),
+            "objects[0].shape.right.shape.shape.shape.type",
+        );
+        expect_key_error(
+            &src.replace("max = [2", "max = [-3"),
+            "objects[0].max",
+        );
+    }
+
+    #[test]
+    fn test_scene_subdivision() {
+        let src = r#"
+            version = 1
+            [camera]
+            look_from = [0, 0, 5]
+            look_at = [0, 0, 0]
+            [materials.clay]
+            type = "lambertian"
+            albedo = [0.8, 0.6, 0.5]
+            [[objects]]
+            type = "subdivision"
+            positions = [[1, 1, 1], [1, -1, -1], [-1, 1, -1], [-1, -1, 1]]
+            faces = [[0, 1, 2], [0, 3, 1], [0, 2, 3], [1, 3, 2]]
+            creases = [{ edge = [0, 1], sharpness = 2 }, { edge = [2, 3] }]
+            levels = 2
+            material = "clay"
+            [[objects]]
+            type = "bezier"
+            control_points = [
+                [3, 0, -2], [4, 0, -2], [5, 0, -2], [6, 0, -2],
+                [3, 1, -2], [4, 1, -2], [5, 1, -2], [6, 1, -2],
+                [3, 2, -2], [4, 2, -2], [5, 2, -2], [6, 2, -2],
+                [3, 3, -2], [4, 3, -2], [5, 3, -2], [6, 3, -2],
+            ]
+            patches = [[0, 1, 2, 3, 4, 5, 6, 7, 8, 9, 10, 11, 12, 13, 14, 15]]
+            divisions = 2
+            material = "clay"
+            [[objects]]
+            type = "teapot"
+            divisions = 4
+            material = "clay"
+            transform = { translate = [-10, 0, 0] }
+        "#;
+        let desc = parse(src).unwrap();
+        let exported = desc.to_toml();
+        assert_eq!(parse(&exported).unwrap().to_toml(), exported);
+        let scene = desc
+            .build(Path::new("test.toml"), &mut RNG::default())
+            .unwrap();
+        let down = |x: f32, y: f32| {
+            let r = Ray::new(
+                Point3::new(x, y, 5.0),
+                Vec3::new(0.0, 0.0, -1.0),
+                0.0,
+                0,
+            );
+            let mut rec = HitRecord::default();
+            assert!(scene.world.hit(&r, 0.001, f32::MAX, &mut rec));
+            rec
+        };
+        // The tetrahedron shrinks towards its center.
+        let rec = down(0.0, 0.0);
+        assert!(rec.p.z() > 0.0 && rec.p.z() < 1.0);
+        assert!(rec.normal.z() > 0.0);
+        assert!((down(4.5, 0.5).t - 7.0).abs() < 1e-5);
+        let rec = down(-9.7, 0.1);
+        assert!(rec.p.z() > 3.0 && rec.p.z() < 3.15);
+
+        expect_key_error(
+            &src.replace("[0, 3, 1]", "[0, 4, 1]"),
+            "objects[0].faces[1]",
+        );
+        expect_key_error(
+            &src.replace("[0, 3, 1]", "[0, 3]"),
+            "objects[0].faces[1]",
+        );
+        expect_key_error(
+            &src.replace(
+                "{ edge = [2, 3] }",
+                "{ edge = [2, 3], sharpness = -1 }",
+            ),
+            "objects[0].creases[1].sharpness",
+        );
+        expect_key_error(
+            &src.replace("levels = 2", "levels = 9"),
+            "objects[0].levels",
+        );
+        expect_key_error(
+            &src.replace("14, 15]", "14]"),
+            "objects[1].patches[0]",
+        );
+        expect_key_error(
+            &src.replace("14, 15]", "14, 16]"),
+            "objects[1].patches[0]",
+        );
+        expect_key_error(
+            &src.replace("divisions = 4", "divisions = 0"),
+            "objects[2].divisions",
+        );
+    }
+
+    #[test]
+    fn test_scene_curves() {
+        let src = r#"
+            version = 1
+            [camera]
+            look_from = [0, 0, 5]
+            look_at = [0, 0, 0]
+            [materials.auburn]
+            type = "hair"
+            eumelanin = 0.8
+            pheomelanin = 0.6
+            beta_m = 0.25
+            [materials.white]
+            type = "hair"
+            color = [0.9, 0.9, 0.9]
+            [[objects]]
+            type = "curve"
+            points = [
+                [0, 0, 0], [1, 0, 0], [2, 0, 0],
+                [3, 0, 0], [4, 0, 0], [5, 0, 0], [6, 0, 0],
+            ]
+            width = [0.2, 0.1]
+            kind = "cylinder"
+            material = "auburn"
+            [[objects]]
+            type = "curve"
+            points = [[0, 2, 0], [1, 2, 0], [2, 2, 0], [3, 2, 0]]
+            width = 0.1
+            kind = "ribbon"
+            normals = [[0, 0, 1], [0, 1, 1]]
+            split_depth = 2
+            material = "white"
+        "#;
+        let desc = parse(src).unwrap();
+        let exported = desc.to_toml();
+        assert_eq!(parse(&exported).unwrap().to_toml(), exported);
+        let scene = desc
+            .build(Path::new("test.toml"), &mut RNG::default())
+            .unwrap();
+        let hit = |x: f32, y: f32| {
+            let r = Ray::new(
+                Point3::new(x, y, 5.0),
+                Vec3::new(0.0, 0.0, -1.0),
+                0.0,
+                0,
+            );
+            let mut rec = HitRecord::default();
+            if scene.world.hit(&r, 0.001, f32::MAX, &mut rec) {
+                Some(rec)
+            } else {
+                None
+            }
+        };
+        // The strand tapers from 0.2 to 0.1 over both curves.
+        let rec = hit(1.0, 0.08).unwrap();
+        assert!((rec.t - 5.0).abs() < 1e-4);
+        assert!((rec.u - 1.0 / 3.0).abs() < 0.01);
+        assert!(rec.dpdu.x() > 0.0);
+        assert!(hit(5.0, 0.08).is_none());
+        assert!(hit(5.0, 0.05).is_some());
+        // The ribbon tilts away from the ray towards its end.
+        assert!(hit(0.1, 2.045).is_some());
+        assert!(hit(2.9, 2.045).is_none());
+
+        expect_key_error(&src.replace("[6, 0, 0],", ""), "objects[0].points");
+        expect_key_error(
+            &src.replace("width = [0.2, 0.1]", "width = -1"),
+            "objects[0].width",
+        );
+        expect_key_error(
+            &src.replace("\"cylinder\"", "\"tube\""),
+            "objects[0].kind",
+        );
+        expect_key_error(
+            &src.replace(", [0, 1, 1]]", "]"),
+            "objects[1].normals",
+        );
+        expect_key_error(
+            &src.replace("kind = \"ribbon\"", ""),
+            "objects[1].normals",
+        );
+        expect_key_error(
+            &src.replace("split_depth = 2", "split_depth = 11"),
+            "objects[1].split_depth",
+        );
+        expect_key_error(
+            &src.replace("beta_m = 0.25", "beta_m = 2"),
+            "materials.auburn.beta_m",
+        );
+        expect_key_error(
+            &src.replace("beta_m = 0.25", "color = [0.5, 0.5, 0.5]"),
+            "materials.auburn.color",
+        );
+        expect_key_error(
+            &src.replace("[0.9, 0.9, 0.9]", "[0.9, 1.5, 0.9]"),
+            "materials.white.color",
+        );
+    }
+
+    #[test]
+    fn test_scene_conductor() {
+        let src = r#"
+            version = 1
+            [camera]
+            look_from = [0, 0, 5]
+            look_at = [0, 0, 0]
+            [materials.brushed]
+            type = "conductor"
+            metal = "aluminium"
+            roughness = [0.5, 0.1]
+            [materials.custom]
+            type = "conductor"
+            eta = [0.2, 0.9, 1.1]
+            k = [3.9, 2.5, 2.1]
+            roughness = 0.3
+            [materials.mirror]
+            type = "conductor"
+            metal = "silver"
+            [[objects]]
+            type = "sphere"
+            center = [0, 0, 0]
+            radius = 1
+            material = "brushed"
+        "#;
+        let desc = parse(src).unwrap();
+        let exported = desc.to_toml();
+        assert_eq!(parse(&exported).unwrap().to_toml(), exported);
+        assert!(exported.contains("metal = \"aluminium\""));
+        assert!(exported.contains("roughness = [0.5, 0.1]"));
+        match &desc.materials["custom"] {
+            MaterialDesc::Conductor {
+                metal, roughness, ..
+            } => {
+                assert!(metal.is_none());
+                assert_eq!(*roughness, (0.3, 0.3));
+            }
+            m => panic!("unexpected material {:?}", m),
+        }
+        let scene = desc
+            .build(Path::new("test.toml"), &mut RNG::default())
+            .unwrap();
+        // Materials are built in name order, and silver without roughness
+        // is a mirror.
+        assert_eq!(scene.matlib.lib.len(), 3);
+        assert!(!scene.matlib.lib[0].is_delta());
+        assert!(scene.matlib.lib[2].is_delta());
+
+        expect_key_error(
+            &src.replace("\"aluminium\"", "\"brass\""),
+            "materials.brushed.metal",
+        );
+        expect_key_error(
+            &src.replace("metal = \"silver\"", "metal = \"silver\"\nk = 1"),
+            "materials.mirror.metal",
+        );
+        expect_key_error(
+            &src.replace("k = [3.9, 2.5, 2.1]", ""),
+            "materials.custom.k",
+        );
+        expect_key_error(
+            &src.replace("roughness = 0.3", "roughness = 1.5"),
+            "materials.custom.roughness",
+        );
+    }
+
+    #[test]
+    fn test_scene_layered() {
+        let src = r#"
+            version = 1
+            [camera]
+            look_from = [0, 0, 5]
+            look_at = [0, 0, 0]
+            [materials.wood]
+            type = "lambertian"
+            albedo = [0.5, 0.3, 0.1]
+            [materials.lacquer]
+            type = "coated"
+            base = "wood"
+            roughness = 0.05
+            color = [0.9, 0.7, 0.4]
+            [materials.worn]
+            type = "mix"
+            a = "lacquer"
+            b = "wood"
+            amount = 0.3
+            [materials.sheet]
+            type = "two_sided"
+            front = "worn"
+            back = "wood"
+            [[objects]]
+            type = "sphere"
+            center = [0, 0, 0]
+            radius = 1
+            material = "sheet"
+        "#;
+        let desc = parse(src).unwrap();
+        let exported = desc.to_toml();
+        assert_eq!(parse(&exported).unwrap().to_toml(), exported);
+        match &desc.materials["lacquer"] {
+            MaterialDesc::Coated {
+                absorption,
+                thickness,
+                ior,
+                ..
+            } => {
+                // The color is reached after crossing the default coat.
+                assert_eq!(*thickness, 0.01);
+                assert_eq!(*ior, 1.5);
+                assert!((absorption.x() * 0.01 + 0.9f32.ln()).abs() < 1e-5);
+            }
+            m => panic!("unexpected material {:?}", m),
+        }
+        let scene = desc
+            .build(Path::new("test.toml"), &mut RNG::default())
+            .unwrap();
+        assert_eq!(scene.matlib.lib.len(), 4);
+
+        let build_error = |src: &str| match parse(src)
+            .unwrap()
+            .build(Path::new("test.toml"), &mut RNG::default())
+        {
+            Err(SceneError::Key { key, .. }) => key,
+            _ => panic!("expected an error"),
+        };
+        assert_eq!(
+            build_error(&src.replace("b = \"wood\"", "b = \"oak\"")),
+            "materials.worn.b"
+        );
+        // Made of itself, through the mix.
+        assert_eq!(
+            build_error(&src.replace("base = \"wood\"", "base = \"worn\"")),
+            "materials.worn.a"
+        );
+        expect_key_error(
+            &src.replace("amount = 0.3", "amount = 2"),
+            "materials.worn.amount",
+        );
+        expect_key_error(
+            &src.replace("front = \"worn\"", ""),
+            "materials.sheet.front",
+        );
+    }
+
+    #[test]
+    fn test_scene_principled() {
+        let src = r#"
+            version = 1
+            [camera]
+            look_from = [0, 0, 5]
+            look_at = [0, 0, 0]
+            [textures.rust]
+            type = "checker"
+            odd = [0, 0, 0]
+            even = [1, 1, 1]
+            [materials.paint]
+            type = "principled"
+            base_color = [0.6, 0.05, 0.05]
+            metallic = "rust"
+            roughness = 0.4
+            clearcoat = 1
+            clearcoat_gloss = 0.9
+            [materials.plain]
+            type = "principled"
+            [[objects]]
+            type = "sphere"
+            center = [0, 0, 0]
+            radius = 1
+            material = "paint"
+        "#;
+        let desc = parse(src).unwrap();
+        let exported = desc.to_toml();
+        assert_eq!(parse(&exported).unwrap().to_toml(), exported);
+        assert!(exported.contains("metallic = \"rust\""));
+        assert!(exported.contains("roughness = 0.4"));
+        match &desc.materials["plain"] {
+            MaterialDesc::Principled {
+                base_color: ColorOrTexture::Color(c),
+                roughness: ColorOrTexture::Color(r),
+                sheen_tint,
+                ..
+            } => {
+                assert_eq!(c.y(), 0.8);
+                assert_eq!(r.x(), 0.5);
+                assert_eq!(*sheen_tint, 0.5);
+            }
+            m => panic!("unexpected material {:?}", m),
+        }
+        let scene = desc
+            .build(Path::new("test.toml"), &mut RNG::default())
+            .unwrap();
+        assert_eq!(scene.matlib.lib.len(), 2);
+        assert!(!scene.matlib.lib[0].is_delta());
+
+        expect_key_error(
+            &src.replace("roughness = 0.4", "roughness = 1.4"),
+            "materials.paint.roughness",
+        );
+        expect_key_error(
+            &src.replace("clearcoat_gloss = 0.9", "clearcoat_gloss = -1"),
+            "materials.paint.clearcoat_gloss",
+        );
+        let missing = parse(&src.replace("\"rust\"\n", "\"rusty\"\n"));
+        match missing
+            .unwrap()
+            .build(Path::new("test.toml"), &mut RNG::default())
+        {
+            Err(SceneError::Key { key, .. }) => {
+                assert_eq!(key, "materials.paint.metallic")
+            }
+            _ => panic!("expected an error for an unknown texture"),
+        }
+    }
+
+    #[test]
+    fn test_scene_glass() {
+        let src = r#"
+            version = 1
+            [camera]
+            look_from = [0, 0, 5]
+            look_at = [0, 0, 0]
+            [materials.frosted]
+            type = "dielectric"
+            ior = 1.5
+            roughness = 0.3
+            [materials.green]
+            type = "dielectric"
+            ior = 1.5
+            color = [0.5, 0.8, 0.5]
+            distance = 2
+            [materials.window]
+            type = "thin_dielectric"
+            ior = 1.5
+            [[objects]]
+            type = "sphere"
+            center = [0, 0, 0]
+            radius = 1
+            material = "frosted"
+        "#;
+        let desc = parse(src).unwrap();
+        let exported = desc.to_toml();
+        assert_eq!(parse(&exported).unwrap().to_toml(), exported);
+        match &desc.materials["green"] {
+            MaterialDesc::Dielectric {
+                roughness,
+                absorption,
+                ..
+            } => {
+                assert_eq!(*roughness, (0.0, 0.0));
+                // Half the red light is left after 2 units.
+                assert!((absorption.x() * 2.0 - LN_2).abs() < 1e-6);
+            }
+            m => panic!("unexpected material {:?}", m),
+        }
+        let scene = desc
+            .build(Path::new("test.toml"), &mut RNG::default())
+            .unwrap();
+        assert!(!scene.matlib.lib[0].is_delta());
+        assert!(scene.matlib.lib[1].is_delta());
+        assert!(scene.matlib.lib[2].is_delta());
+
+        expect_key_error(
+            &src.replace("distance = 2", "absorption = [1, 1, 1]"),
+            "materials.green.absorption",
+        );
+        expect_key_error(
+            &src.replace("[0.5, 0.8, 0.5]", "[0.5, 0, 0.5]"),
+            "materials.green.color",
+        );
+        expect_key_error(
+            &src.replace("distance = 2", "distance = 0"),
+            "materials.green.distance",
+        );
+        expect_key_error(
+            &src.replace("roughness = 0.3", "roughness = [0.3]"),
+            "materials.frosted.roughness",
+        );
+    }
+
+    #[test]
+    fn test_scene_image_texture() {
+        let mut image = Image::new(2, 1);
+        image.set_pixel(0, 0, Vec3::new(1.0, 0.0, 0.0));
+        image.set_pixel(1, 0, Vec3::new(0.0, 0.0, 1.0));
+        let dir = env::temp_dir();
+        let file = dir.join("raytracer_test_scene_halves.png");
+        image.write(&file, &WriteOptions::default()).unwrap();
+        let src = r#"
+            version = 1
+            [camera]
+            look_from = [0, 0, 5]
+            look_at = [0, 0, 0]
+            [textures.halves]
+            type = "image"
+            file = "raytracer_test_scene_halves.png"
+            wrap = "clamp"
+            filter = "bilinear"
+            srgb = false
+            [materials.floor]
+            type = "lambertian"
+            albedo = "halves"
+            [[objects]]
+            type = "sphere"
+            center = [0, 0, 0]
+            radius = 1
+            material = "floor"
+        "#;
+        let desc = parse(src).unwrap();
+        let exported = desc.to_toml();
+        assert_eq!(parse(&exported).unwrap().to_toml(), exported);
+        let path = dir.join("test.toml");
+        let scene = desc.build(&path, &mut RNG::default()).unwrap();
+        let _ = fs::remove_file(&file);
+        let ray = Ray::new(
+            Point3::new(0.0, 0.0, 2.0),
+            Vec3::new(0.0, 0.0, -1.0),
+            0.0,
+            1,
+        );
+        let up = Vec3::new(0.0, 0.0, 1.0);
+        let albedo = |u: f32| {
+            let rec = HitRecord {
+                normal: up,
+                u,
+                v: 0.5,
+                ..HitRecord::default()
+            };
+            scene.matlib.lib[0].eval(&ray, &rec, up)
+        };
+        let red = albedo(0.25);
+        assert!(red.x() > 0.3 && red.z() == 0.0, "{:?}", red);
+        let blue = albedo(0.75);
+        assert!(blue.z() > 0.3 && blue.x() == 0.0, "{:?}", blue);
+
+        match parse(src).unwrap().build(&path, &mut RNG::default()) {
+            Err(SceneError::Key { key, .. }) => {
+                assert_eq!(key, "textures.halves.file")
+            }
+            _ => panic!("expected a missing file"),
+        }
+        expect_key_error(
+            &src.replace("\"clamp\"", "\"tile\""),
+            "textures.halves.wrap",
+        );
+        expect_key_error(
+            &src.replace("\"bilinear\"", "\"box\""),
+            "textures.halves.filter",
+        );
+        expect_key_error(
+            &src.replace("srgb = false", "srgb = 0"),
+            "textures.halves.srgb",
+        );
+    }
+
+    #[test]
+    fn test_scene_round_trip() {
+        let desc = parse(SCENE).unwrap();
+        let exported = desc.to_toml();
+        let again = parse(&exported).unwrap();
+        assert_eq!(again.to_toml(), exported);
+        assert!(exported.contains("look_at = [0.0, 0.5, 0.0]"));
+    }
+
+    #[test]
+    fn test_scene_errors() {
+        expect_key_error("version = 2\n", "version");
+        expect_key_error(
+            "version = 1\n[camera]\nlook_from = [0, 0]\nlook_at = [0, 0, 0]\n",
+            "camera.look_from",
+        );
+        expect_key_error(
+            "version = 1\n[camera]\nlook_from = [0, 0, 1]\n\
+             look_at = [0, 0, 0]\nfvo = 30\n",
+            "camera.fvo",
+        );
+        expect_key_error(
+            "version = 1\n[camera]\nlook_from = [0, 0, 1]\n\
+             look_at = [0, 0, 0]\n[materials.glass]\ntype = \"dielectric\"\n\
+             ior = \"high\"\n",
+            "materials.glass.ior",
+        );
+        let desc = parse(
+            "version = 1\n[camera]\nlook_from = [0, 0, 1]\n\
+             look_at = [0, 0, 0]\n[[objects]]\ntype = \"sphere\"\n\
+             center = [0, 0, 0]\nradius = 1\nmaterial = \"nope\"\n",
+        )
+        .unwrap();
+        match desc.build(Path::new("test.toml"), &mut RNG::default()) {
+            Err(SceneError::Key { key, .. }) => {
+                assert_eq!(key, "objects[0].material")
+            }
+            _ => panic!("expected unknown material"),
+        }
+    }
+}
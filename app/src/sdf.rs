@@ -0,0 +1,529 @@
+//! Shapes given by signed distance fields and rendered by sphere tracing.
+//!
+//! An `Sdf` is an expression tree of primitives, boolean and smooth
+//! combinations and domain operations like twists and repetition. It gives
+//! the distance from a point to the surface, negative inside. `SdfShape`
+//! finds hits by stepping along the ray by that distance, which can never
+//! overshoot the surface, and estimates normals from the gradient. The
+//! field is only searched inside user-supplied bounds, which are also what
+//! the BVH sees.
+//!
+//! Not every operation keeps the distance exact. Twists stretch space, so
+//! `Sdf::lipschitz` bounds how much faster than the true distance a field
+//! can change, and the steps are shortened by it. The Mandelbulb's
+//! distance is an estimate.
+//!
+//! Primitives are centered on the origin with y up.
+
+use ::std::boxed::Box;
+use ::std::clone::Clone;
+use ::std::convert::From;
+use ::std::default::Default;
+use ::std::iter::Iterator;
+use ::std::option::Option::{self, None, Some};
+
+use ::math::{Point3, Vec3};
+
+use crate::pbrt::{HitRecord, Hitable, Ray, AABB};
+
+#[derive(Debug, Clone)]
+pub enum Sdf {
+    Sphere {
+        radius: f32,
+    },
+    /// A box with the corners rounded off by `radius`, inside the same
+    /// bounds as the sharp one.
+    Box {
+        half_size: Vec3,
+        radius: f32,
+    },
+    /// A ring in the xz plane around the y axis.
+    Torus {
+        major_radius: f32,
+        minor_radius: f32,
+    },
+    /// A capped cylinder along the y axis.
+    Cylinder {
+        radius: f32,
+        height: f32,
+    },
+    /// The power 8 Mandelbulb fits in a sphere of radius 1.2.
+    Mandelbulb {
+        power: f32,
+        iterations: usize,
+    },
+    Union(Box<Sdf>, Box<Sdf>),
+    Intersection(Box<Sdf>, Box<Sdf>),
+    /// The first shape with the second one cut out of it.
+    Difference(Box<Sdf>, Box<Sdf>),
+    /// A union that blends the shapes where they are closer than `k`.
+    SmoothUnion {
+        left: Box<Sdf>,
+        right: Box<Sdf>,
+        k: f32,
+    },
+    Translate {
+        offset: Vec3,
+        shape: Box<Sdf>,
+    },
+    Scale {
+        factor: f32,
+        shape: Box<Sdf>,
+    },
+    /// Rotates the shape around the y axis by `rate` degrees per unit of
+    /// height.
+    Twist {
+        rate: f32,
+        shape: Box<Sdf>,
+    },
+    /// Repeats the shape every `period` along each axis. Axes with a period
+    /// of zero are not repeated. The shape should fit in one period.
+    Repeat {
+        period: Vec3,
+        shape: Box<Sdf>,
+    },
+}
+
+fn abs(v: Vec3) -> Vec3 {
+    Vec3::new(v.x().abs(), v.y().abs(), v.z().abs())
+}
+
+fn max_zero(v: Vec3) -> Vec3 {
+    Vec3::new(v.x().max(0.0), v.y().max(0.0), v.z().max(0.0))
+}
+
+fn max_component(v: Vec3) -> f32 {
+    v.x().max(v.y()).max(v.z())
+}
+
+/// `x` folded into the cell of `period` around zero.
+fn fold(x: f32, period: f32) -> f32 {
+    if period > 0.0 {
+        x - period * (x / period).round()
+    } else {
+        x
+    }
+}
+
+impl Sdf {
+    /// The signed distance from `p` to the surface.
+    pub fn distance(&self, p: Point3) -> f32 {
+        self.eval(Vec3::from(p))
+    }
+
+    fn eval(&self, p: Vec3) -> f32 {
+        match self {
+            Sdf::Sphere { radius } => p.length() - radius,
+            Sdf::Box { half_size, radius } => {
+                let r = Vec3::new(*radius, *radius, *radius);
+                let q = abs(p) - (*half_size - r);
+                max_zero(q).length() + max_component(q).min(0.0) - radius
+            }
+            Sdf::Torus {
+                major_radius,
+                minor_radius,
+            } => {
+                let ring = p.x().hypot(p.z()) - major_radius;
+                ring.hypot(p.y()) - minor_radius
+            }
+            Sdf::Cylinder { radius, height } => {
+                let dx = p.x().hypot(p.z()) - radius;
+                let dy = p.y().abs() - 0.5 * height;
+                dx.max(dy).min(0.0) + dx.max(0.0).hypot(dy.max(0.0))
+            }
+            Sdf::Mandelbulb { power, iterations } => {
+                mandelbulb(p, *power, *iterations)
+            }
+            Sdf::Union(a, b) => a.eval(p).min(b.eval(p)),
+            Sdf::Intersection(a, b) => a.eval(p).max(b.eval(p)),
+            Sdf::Difference(a, b) => a.eval(p).max(-b.eval(p)),
+            Sdf::SmoothUnion { left, right, k } => {
+                let (a, b) = (left.eval(p), right.eval(p));
+                let h = (0.5 + 0.5 * (b - a) / k).clamp(0.0, 1.0);
+                b + h * (a - b) - k * h * (1.0 - h)
+            }
+            Sdf::Translate { offset, shape } => shape.eval(p - *offset),
+            Sdf::Scale { factor, shape } => shape.eval(p / *factor) * factor,
+            Sdf::Twist { rate, shape } => {
+                let (sin, cos) = (-rate.to_radians() * p.y()).sin_cos();
+                shape.eval(Vec3::new(
+                    cos * p.x() - sin * p.z(),
+                    p.y(),
+                    sin * p.x() + cos * p.z(),
+                ))
+            }
+            Sdf::Repeat { period, shape } => shape.eval(Vec3::new(
+                fold(p.x(), period.x()),
+                fold(p.y(), period.y()),
+                fold(p.z(), period.z()),
+            )),
+        }
+    }
+
+    /// A bound on how fast the field changes per unit of distance at points
+    /// up to `radius` from the origin. Exact distances change at rate 1.
+    pub fn lipschitz(&self, radius: f32) -> f32 {
+        match self {
+            Sdf::Sphere { .. }
+            | Sdf::Box { .. }
+            | Sdf::Torus { .. }
+            | Sdf::Cylinder { .. }
+            | Sdf::Mandelbulb { .. } => 1.0,
+            Sdf::Union(a, b)
+            | Sdf::Intersection(a, b)
+            | Sdf::Difference(a, b)
+            | Sdf::SmoothUnion {
+                left: a, right: b, ..
+            } => a.lipschitz(radius).max(b.lipschitz(radius)),
+            Sdf::Translate { offset, shape } => {
+                shape.lipschitz(radius + offset.length())
+            }
+            Sdf::Scale { factor, shape } => shape.lipschitz(radius / factor),
+            // Points at `radius` from the axis move sideways by up to
+            // rate * radius per unit of height.
+            Sdf::Twist { rate, shape } => {
+                let stretch = rate.to_radians() * radius;
+                shape.lipschitz(radius) * (1.0 + stretch * stretch).sqrt()
+            }
+            Sdf::Repeat { period, shape } => {
+                let cell = |x: f32| if x > 0.0 { 0.5 * x } else { radius };
+                let cell = Vec3::new(
+                    cell(period.x()),
+                    cell(period.y()),
+                    cell(period.z()),
+                );
+                shape.lipschitz(cell.length().min(radius))
+            }
+        }
+    }
+}
+
+/// The distance estimate of the Mandelbulb from the derivative of its
+/// iteration, with the poles on the y axis.
+fn mandelbulb(p: Vec3, power: f32, iterations: usize) -> f32 {
+    let mut z = p;
+    let mut dr = 1.0;
+    let mut r = z.length();
+    for _ in 0..iterations {
+        if r > 2.0 || r == 0.0 {
+            break;
+        }
+        let theta = (z.y() / r).acos() * power;
+        let phi = z.z().atan2(z.x()) * power;
+        dr = power * r.powf(power - 1.0) * dr + 1.0;
+        let zr = r.powf(power);
+        z =
+            zr * Vec3::new(
+                theta.sin() * phi.cos(),
+                theta.cos(),
+                theta.sin() * phi.sin(),
+            ) + p;
+        r = z.length();
+    }
+    if r == 0.0 {
+        return 0.0;
+    }
+    0.5 * r.ln() * r / dr
+}
+
+/// The most steps a ray takes through the bounds before it gives up.
+const MAX_STEPS: usize = 1000;
+
+/// The surface of an `Sdf` inside `bounds`.
+#[derive(Debug)]
+pub struct SdfShape {
+    sdf: Sdf,
+    bounds: AABB,
+    lipschitz: f32,
+    /// How close to the surface counts as a hit, relative to the size of
+    /// the bounds.
+    epsilon: f32,
+    material: usize,
+}
+
+impl SdfShape {
+    pub fn new(sdf: Sdf, bounds: AABB, material: usize) -> Self {
+        let (min, max) = (Vec3::from(bounds.min()), Vec3::from(bounds.max()));
+        let radius = (0..8)
+            .map(|i| {
+                let pick = |bit: usize, axis: usize| {
+                    if i & bit == 0 {
+                        min[axis]
+                    } else {
+                        max[axis]
+                    }
+                };
+                Vec3::new(pick(1, 0), pick(2, 1), pick(4, 2)).length()
+            })
+            .fold(0.0, f32::max);
+        let lipschitz = sdf.lipschitz(radius).max(1.0);
+        SdfShape {
+            sdf,
+            bounds,
+            lipschitz,
+            epsilon: ((max - min).length() * 1e-5).max(1e-6),
+            material,
+        }
+    }
+
+    /// The part of `r` inside the bounds.
+    fn clip(&self, r: &Ray, t_min: f32, t_max: f32) -> Option<(f32, f32)> {
+        let (mut t0, mut t1) = (t_min, t_max);
+        for axis in 0..3 {
+            let inv = 1.0 / r.direction[axis];
+            let near = (self.bounds.min()[axis] - r.origin[axis]) * inv;
+            let far = (self.bounds.max()[axis] - r.origin[axis]) * inv;
+            let (near, far) = if inv < 0.0 { (far, near) } else { (near, far) };
+            // Written so that the NaN of a ray in the plane of a face keeps
+            // the current limits.
+            if near > t0 {
+                t0 = near;
+            }
+            if far < t1 {
+                t1 = far;
+            }
+            if t0 > t1 {
+                return None;
+            }
+        }
+        Some((t0, t1))
+    }
+
+    /// The gradient of the field at `p` from four samples at the corners
+    /// of a tetrahedron.
+    fn normal(&self, p: Point3) -> Vec3 {
+        let h = self.epsilon;
+        let gradient = [
+            (1.0, -1.0, -1.0),
+            (-1.0, -1.0, 1.0),
+            (-1.0, 1.0, -1.0),
+            (1.0, 1.0, 1.0),
+        ]
+        .iter()
+        .map(|&(x, y, z)| {
+            let k = Vec3::new(x, y, z);
+            self.sdf.distance(p + h * k) * k
+        })
+        .fold(Vec3::default(), |sum, v| sum + v);
+        if gradient.length() > 0.0 {
+            gradient.unit()
+        } else {
+            Vec3::new(0.0, 1.0, 0.0)
+        }
+    }
+}
+
+impl Hitable for SdfShape {
+    fn hit(
+        &self, r: &Ray, t_min: f32, t_max: f32, rec: &mut HitRecord,
+    ) -> bool {
+        let (mut t, t1) = match self.clip(r, t_min, t_max) {
+            Some(span) => span,
+            None => return false,
+        };
+        let speed = r.direction.length() * self.lipschitz;
+        let eps = self.epsilon;
+        // Rays march on the side of the surface they start on. Rays that
+        // leave a surface start right next to it, so the side is the one
+        // they head to, and hits only count once they got away from it.
+        let start = self.sdf.distance(r.point_at_param(t));
+        let side = if start.abs() > eps {
+            start.signum()
+        } else {
+            self.normal(r.point_at_param(t)).dot(r.direction).signum()
+        };
+        let mut away = side * start > eps;
+        for _ in 0..MAX_STEPS {
+            let p = r.point_at_param(t);
+            let distance = side * self.sdf.distance(p);
+            if distance < eps {
+                if away {
+                    rec.t = t;
+                    rec.p = p;
+                    rec.normal = self.normal(p);
+                    rec.material = self.material;
+                    rec.u = 0.0;
+                    rec.v = 0.0;
+                    return true;
+                }
+            } else {
+                away = true;
+            }
+            t += distance.max(eps) / speed;
+            if t > t1 {
+                break;
+            }
+        }
+        false
+    }
+
+    fn bounding_box(&self, _: f32, _: f32) -> Option<AABB> {
+        Some(self.bounds)
+    }
+
+    fn material(&self) -> Option<usize> {
+        Some(self.material)
+    }
+}
+
+#[cfg(test)]
+pub mod tests {
+    use super::*;
+    use ::std::assert;
+
+    fn boxed(sdf: Sdf) -> Box<Sdf> {
+        Box::new(sdf)
+    }
+
+    fn bounds(size: f32) -> AABB {
+        AABB::new(
+            Point3::new(-size, -size, -size),
+            Point3::new(size, size, size),
+        )
+    }
+
+    fn ray(origin: Point3, direction: Vec3) -> Ray {
+        Ray::new(origin, direction, 0.0, 0)
+    }
+
+    #[test]
+    fn test_sdf_distances() {
+        let at = |sdf: &Sdf, x: f32, y: f32, z: f32| {
+            sdf.distance(Point3::new(x, y, z))
+        };
+        let close = |a: f32, b: f32| (a - b).abs() < 1e-5;
+        let sphere = Sdf::Sphere { radius: 1.0 };
+        assert!(close(at(&sphere, 0.0, 3.0, 0.0), 2.0));
+        assert!(close(at(&sphere, 0.0, 0.0, 0.0), -1.0));
+
+        let rounded = Sdf::Box {
+            half_size: Vec3::new(1.0, 1.0, 1.0),
+            radius: 0.25,
+        };
+        assert!(close(at(&rounded, 2.0, 0.0, 0.0), 1.0));
+        // The corner is rounded off around (0.75, 0.75, 0.75).
+        let corner = 3.0f32.sqrt() * 0.25 - 0.25;
+        assert!(close(at(&rounded, 1.0, 1.0, 1.0), corner));
+
+        let torus = Sdf::Torus {
+            major_radius: 2.0,
+            minor_radius: 0.5,
+        };
+        assert!(close(at(&torus, 0.0, 0.0, 2.0), -0.5));
+        assert!(close(at(&torus, 0.0, 1.0, -2.0), 0.5));
+        let cylinder = Sdf::Cylinder {
+            radius: 1.0,
+            height: 2.0,
+        };
+        assert!(close(at(&cylinder, 0.0, 3.0, 0.0), 2.0));
+        assert!(close(at(&cylinder, 4.0, 5.0, 0.0), 5.0));
+
+        // Blending pulls the surface out between the shapes.
+        let pair = |smooth: bool| {
+            let left = boxed(Sdf::Translate {
+                offset: Vec3::new(-1.0, 0.0, 0.0),
+                shape: boxed(sphere.clone()),
+            });
+            let right = boxed(Sdf::Translate {
+                offset: Vec3::new(1.2, 0.0, 0.0),
+                shape: boxed(sphere.clone()),
+            });
+            if smooth {
+                Sdf::SmoothUnion {
+                    left,
+                    right,
+                    k: 0.5,
+                }
+            } else {
+                Sdf::Union(left, right)
+            }
+        };
+        assert!(close(at(&pair(false), 0.1, 0.0, 0.0), 0.1));
+        assert!(at(&pair(true), 0.1, 0.0, 0.0) < 0.0);
+
+        let row = Sdf::Repeat {
+            period: Vec3::new(4.0, 0.0, 0.0),
+            shape: boxed(sphere.clone()),
+        };
+        assert!(close(at(&row, 40.0, 0.0, 0.0), -1.0));
+        assert!(close(at(&row, 38.0, 0.0, 0.0), 1.0));
+        assert!(close(at(&row, 40.0, 3.0, 0.0), 2.0));
+
+        // Twisting by 90 degrees over one unit turns x into z.
+        let twisted = Sdf::Twist {
+            rate: 90.0,
+            shape: boxed(Sdf::Box {
+                half_size: Vec3::new(2.0, 5.0, 0.5),
+                radius: 0.0,
+            }),
+        };
+        assert!(close(at(&twisted, 1.5, 0.0, 0.0), -0.5));
+        assert!(close(at(&twisted, 0.0, 1.0, 1.5), -0.5));
+        assert!(close(at(&twisted, 1.5, 1.0, 0.0), 1.0));
+        assert!(twisted.lipschitz(1.0) > 1.8);
+        assert!(close(sphere.lipschitz(10.0), 1.0));
+    }
+
+    #[test]
+    fn test_sdf_hit() {
+        let sphere = SdfShape::new(Sdf::Sphere { radius: 1.0 }, bounds(1.1), 4);
+        let mut rec = HitRecord::default();
+        let r = ray(Point3::new(0.0, 0.0, 5.0), Vec3::new(0.0, 0.0, -1.0));
+        assert!(sphere.hit(&r, 0.001, f32::MAX, &mut rec));
+        assert!((rec.t - 4.0).abs() < 1e-3);
+        assert!((rec.normal - Vec3::new(0.0, 0.0, 1.0)).length() < 1e-3);
+        assert!(rec.material == 4);
+        assert!(!sphere.hit(&r, 0.001, 3.9, &mut rec));
+
+        // From the center, and from the surface into the sphere, the ray
+        // finds the far side. Leaving the surface it finds nothing.
+        let r = ray(Point3::default(), Vec3::new(1.0, 0.0, 0.0));
+        assert!(sphere.hit(&r, 0.001, f32::MAX, &mut rec));
+        assert!((rec.t - 1.0).abs() < 1e-3);
+        let top = Point3::new(0.0, 1.0, 0.0);
+        let r = ray(top, Vec3::new(0.0, -1.0, 0.0));
+        assert!(sphere.hit(&r, 0.001, f32::MAX, &mut rec));
+        assert!((rec.t - 2.0).abs() < 1e-3);
+        let r = ray(top, Vec3::new(1.0, 1.0, 0.0));
+        assert!(!sphere.hit(&r, 0.001, f32::MAX, &mut rec));
+        let r = ray(Point3::new(0.0, 5.0, 0.0), Vec3::new(1.0, 0.0, 0.0));
+        assert!(!sphere.hit(&r, 0.001, f32::MAX, &mut rec));
+    }
+
+    #[test]
+    fn test_sdf_no_overshoot() {
+        // A strongly twisted slab and a Mandelbulb, hit from the side. The
+        // hit is on the surface and nothing in front of it is inside.
+        let twisted = Sdf::Twist {
+            rate: 120.0,
+            shape: boxed(Sdf::Box {
+                half_size: Vec3::new(1.5, 1.0, 0.2),
+                radius: 0.05,
+            }),
+        };
+        let bulb = Sdf::Mandelbulb {
+            power: 8.0,
+            iterations: 12,
+        };
+        for sdf in &[twisted, bulb] {
+            let shape = SdfShape::new(sdf.clone(), bounds(1.6), 0);
+            let mut hits = 0;
+            for i in 0..20 {
+                let y = -0.9 + 0.09 * i as f32;
+                let origin = Point3::new(-4.0, y, 3.0);
+                let r = ray(origin, Vec3::new(4.0, -y, -3.0));
+                let mut rec = HitRecord::default();
+                if !shape.hit(&r, 0.001, f32::MAX, &mut rec) {
+                    continue;
+                }
+                hits += 1;
+                assert!(sdf.distance(rec.p).abs() < 1e-3);
+                for k in 0..200 {
+                    let t = rec.t * k as f32 / 200.0;
+                    assert!(sdf.distance(r.point_at_param(t)) > 0.0);
+                }
+            }
+            assert!(hits > 10);
+        }
+    }
+}
@@ -0,0 +1,906 @@
+use ::std::clone::Clone;
+use ::std::cmp;
+use ::std::default::Default;
+use ::std::iter::Iterator;
+use ::std::option::Option::{self, None, Some};
+use ::std::vec::Vec;
+
+use ::math::{Point3, Vec3};
+
+use crate::pbrt::{
+    orthonormal_basis, HitRecord, Hitable, Ray, ShapeSample, Span, AABB, RNG,
+};
+
+#[derive(Debug)]
+pub struct Sphere {
+    center: Point3,
+    radius: f32,
+    radius_squared: f32,
+    material: usize,
+}
+
+impl Sphere {
+    pub fn new(center: Point3, radius: f32, material: usize) -> Self {
+        Sphere {
+            center,
+            radius,
+            radius_squared: radius * radius,
+            material,
+        }
+    }
+}
+
+const PI: f32 = ::std::f32::consts::PI;
+
+/// One minus the cosine of the half angle of the cone that a sphere
+/// subtends from `origin`, or `None` if `origin` is inside it.
+fn cone_height(origin: Point3, center: Point3, radius: f32) -> Option<f32> {
+    let to_center = center - origin;
+    let sin2_max = radius * radius / to_center.dot(to_center);
+    if sin2_max >= 1.0 {
+        return None;
+    }
+    // Small cones lose all precision in 1 - cos.
+    Some(sin2_max / (1.0 + (1.0 - sin2_max).sqrt()))
+}
+
+/// Samples a point on the part of a sphere that is visible from `origin`,
+/// uniformly in the cone of directions towards it.
+fn sample_sphere(
+    rng: &mut RNG, origin: Point3, center: Point3, radius: f32,
+) -> Option<ShapeSample> {
+    let height = cone_height(origin, center, radius)?;
+    let to_center = center - origin;
+    let distance = to_center.length();
+    let w = to_center / distance;
+    let (u, v) = orthonormal_basis(w);
+    let cos_theta = 1.0 - rng.rand() * height;
+    let sin2_theta = (1.0 - cos_theta * cos_theta).max(0.0);
+    let sin_theta = sin2_theta.sqrt();
+    let phi = 2.0 * PI * rng.rand();
+    let direction =
+        sin_theta * phi.cos() * u + sin_theta * phi.sin() * v + cos_theta * w;
+    // The nearer of the two intersections along `direction`.
+    let t = distance * cos_theta
+        - (radius * radius - distance * distance * sin2_theta)
+            .max(0.0)
+            .sqrt();
+    Some(ShapeSample {
+        p: origin + t * direction,
+        pdf: 1.0 / (2.0 * PI * height),
+    })
+}
+
+fn sphere_pdf(
+    hitable: &dyn Hitable, origin: Point3, direction: Vec3, time: f32,
+    center: Point3, radius: f32,
+) -> f32 {
+    let height = match cone_height(origin, center, radius) {
+        Some(height) => height,
+        None => return 0.0,
+    };
+    let r = Ray::new(origin, direction, time, 0);
+    if !hitable.hit(&r, 0.001, f32::MAX, &mut HitRecord::default()) {
+        return 0.0;
+    }
+    1.0 / (2.0 * PI * height)
+}
+
+impl Sphere {
+    /// The parameters of the two points where the line through `r` meets
+    /// the sphere, nearest first.
+    fn roots(&self, r: &Ray) -> Option<(f32, f32)> {
+        let oc = r.origin - self.center;
+        let a = r.direction.dot(r.direction);
+        let b = oc.dot(r.direction);
+        let c = oc.dot(oc) - self.radius_squared;
+        let discriminat = b * b - a * c;
+        if discriminat <= 0.0 {
+            return None;
+        }
+        let dsqrt = discriminat.sqrt();
+        Some(((-b - dsqrt) / a, (-b + dsqrt) / a))
+    }
+
+    fn record(&self, r: &Ray, t: f32, rec: &mut HitRecord) {
+        sphere_record(r, t, self.center, self.radius, rec);
+        rec.material = self.material;
+    }
+}
+
+/// Fills in the hit at `t` on a sphere. u runs westward around the y axis
+/// and v from the south pole to the north pole.
+fn sphere_record(
+    r: &Ray, t: f32, center: Point3, radius: f32, rec: &mut HitRecord,
+) {
+    let p = r.point_at_param(t);
+    let normal = (p - center) / radius;
+    let phi = normal.z().atan2(normal.x());
+    let theta = normal.y().clamp(-1.0, 1.0).asin();
+    rec.t = t;
+    rec.p = p;
+    rec.normal = normal;
+    rec.dpdu = 2.0 * PI * radius * Vec3::new(normal.z(), 0.0, -normal.x());
+    // Towards the north pole along the meridian; none at the poles.
+    let cos_theta = normal.x().hypot(normal.z());
+    rec.dpdv = if cos_theta > 0.0 {
+        let s = -normal.y() / cos_theta;
+        PI * radius * Vec3::new(s * normal.x(), cos_theta, s * normal.z())
+    } else {
+        Vec3::default()
+    };
+    rec.u = 1.0 - (phi + PI) / (2.0 * PI);
+    rec.v = (theta + PI / 2.0) / PI;
+}
+
+impl Hitable for Sphere {
+    fn hit(
+        &self, r: &Ray, t_min: f32, t_max: f32, rec: &mut HitRecord,
+    ) -> bool {
+        let (near, far) = match self.roots(r) {
+            Some(roots) => roots,
+            None => return false,
+        };
+        for &t in &[near, far] {
+            if t < t_max && t > t_min {
+                self.record(r, t, rec);
+                return true;
+            }
+        }
+        false
+    }
+
+    fn hit_all(&self, r: &Ray, spans: &mut Vec<Span>) -> bool {
+        if let Some((near, far)) = self.roots(r) {
+            let mut span = Span::default();
+            self.record(r, near, &mut span.enter);
+            self.record(r, far, &mut span.exit);
+            spans.push(span);
+        }
+        true
+    }
+
+    fn bounding_box(&self, _: f32, _: f32) -> Option<AABB> {
+        let rv = Vec3::new(self.radius, self.radius, self.radius);
+        Some(AABB::new(self.center - rv, self.center + rv))
+    }
+
+    fn material(&self) -> Option<usize> {
+        Some(self.material)
+    }
+
+    fn sample(
+        &self, rng: &mut RNG, origin: Point3, _time: f32,
+    ) -> Option<ShapeSample> {
+        sample_sphere(rng, origin, self.center, self.radius)
+    }
+
+    fn pdf(&self, origin: Point3, direction: Vec3, time: f32) -> f32 {
+        sphere_pdf(self, origin, direction, time, self.center, self.radius)
+    }
+}
+
+#[derive(Debug)]
+pub struct MovingSphere {
+    center0: Point3,
+    center1: Point3,
+    t0: f32,
+    t1: f32,
+    radius: f32,
+    radius_squared: f32,
+    material: usize,
+}
+
+impl MovingSphere {
+    pub fn new(
+        center0: Point3, center1: Point3, t0: f32, t1: f32, radius: f32,
+        material: usize,
+    ) -> Self {
+        MovingSphere {
+            center0,
+            center1,
+            t0,
+            t1,
+            radius,
+            radius_squared: radius * radius,
+            material,
+        }
+    }
+
+    fn center(&self, t: f32) -> Point3 {
+        self.center0
+            + ((t - self.t0) / (self.t1 - self.t0))
+                * (self.center1 - self.center0)
+    }
+}
+
+impl Hitable for MovingSphere {
+    fn hit(
+        &self, r: &Ray, t_min: f32, t_max: f32, rec: &mut HitRecord,
+    ) -> bool {
+        let oc = r.origin - self.center(r.time);
+        let a = r.direction.dot(r.direction);
+        let b = oc.dot(r.direction);
+        let c = oc.dot(oc) - self.radius_squared;
+        let discriminat = b * b - a * c;
+        if discriminat > 0.0 {
+            let dsqrt = discriminat.sqrt();
+            let temp = (-b - dsqrt) / a;
+            if temp < t_max && temp > t_min {
+                sphere_record(r, temp, self.center(r.time), self.radius, rec);
+                rec.material = self.material;
+                return true;
+            }
+            let temp = (-b + dsqrt) / a;
+            if temp < t_max && temp > t_min {
+                sphere_record(r, temp, self.center(r.time), self.radius, rec);
+                rec.material = self.material;
+                return true;
+            }
+        }
+        false
+    }
+
+    fn bounding_box(&self, _: f32, _: f32) -> Option<AABB> {
+        let rv = Vec3::new(self.radius, self.radius, self.radius);
+        let b0 = AABB::new(self.center0 - rv, self.center0 + rv);
+        let b1 = AABB::new(self.center1 - rv, self.center1 + rv);
+        Some(AABB::surround(b0, b1))
+    }
+
+    fn material(&self) -> Option<usize> {
+        Some(self.material)
+    }
+
+    fn sample(
+        &self, rng: &mut RNG, origin: Point3, time: f32,
+    ) -> Option<ShapeSample> {
+        sample_sphere(rng, origin, self.center(time), self.radius)
+    }
+
+    fn pdf(&self, origin: Point3, direction: Vec3, time: f32) -> f32 {
+        let center = self.center(time);
+        sphere_pdf(self, origin, direction, time, center, self.radius)
+    }
+}
+
+// Planar shapes have no extent along their normal, which would give them a
+// flat bounding box.
+const BBOX_PADDING: f32 = 0.0001;
+
+/// Converts the area density `1 / area` of the point `p` on a plane with
+/// the unit `normal` into solid angle density at `origin`.
+fn planar_pdf(origin: Point3, p: Point3, normal: Vec3, area: f32) -> f32 {
+    let to_p = p - origin;
+    let distance2 = to_p.dot(to_p);
+    let cosine = normal.dot(to_p).abs() / distance2.sqrt();
+    if cosine < 1e-6 {
+        return 0.0;
+    }
+    distance2 / (cosine * area)
+}
+
+/// A point picked uniformly by area on a planar shape, or `None` if it is
+/// seen edge-on from `origin`.
+fn planar_sample(
+    origin: Point3, p: Point3, normal: Vec3, area: f32,
+) -> Option<ShapeSample> {
+    let pdf = planar_pdf(origin, p, normal, area);
+    if pdf <= 0.0 || !pdf.is_finite() {
+        return None;
+    }
+    Some(ShapeSample { p, pdf })
+}
+
+fn planar_hit_pdf(
+    hitable: &dyn Hitable, origin: Point3, direction: Vec3, time: f32,
+    normal: Vec3, area: f32,
+) -> f32 {
+    let r = Ray::new(origin, direction, time, 0);
+    let mut rec = HitRecord::default();
+    if !hitable.hit(&r, 0.001, f32::MAX, &mut rec) {
+        return 0.0;
+    }
+    planar_pdf(origin, rec.p, normal, area)
+}
+
+fn axis_vec(axis: usize, value: f32) -> Vec3 {
+    let mut v = [0.0; 3];
+    v[axis] = value;
+    Vec3::new(v[0], v[1], v[2])
+}
+
+/// The plane that an `AxisRect` lies in.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Plane {
+    XY,
+    XZ,
+    YZ,
+}
+
+impl Plane {
+    /// The two axes spanning the plane, then the axis along its normal.
+    pub fn axes(self) -> (usize, usize, usize) {
+        match self {
+            Plane::XY => (0, 1, 2),
+            Plane::XZ => (0, 2, 1),
+            Plane::YZ => (1, 2, 0),
+        }
+    }
+}
+
+/// An axis-aligned rectangle. For `Plane::XZ`, `a` is the range of x, `b`
+/// the range of z and `k` the height y of the rectangle. The normal points
+/// along the positive third axis unless the rectangle is flipped.
+#[derive(Debug, Clone, Copy)]
+pub struct AxisRect {
+    plane: Plane,
+    a: (f32, f32),
+    b: (f32, f32),
+    k: f32,
+    sign: f32,
+    material: usize,
+}
+
+impl AxisRect {
+    pub fn new(
+        plane: Plane, a: (f32, f32), b: (f32, f32), k: f32, material: usize,
+    ) -> Self {
+        AxisRect {
+            plane,
+            a: (a.0.min(a.1), a.0.max(a.1)),
+            b: (b.0.min(b.1), b.0.max(b.1)),
+            k,
+            sign: 1.0,
+            material,
+        }
+    }
+
+    /// The same rectangle with its normal pointing the other way.
+    pub fn flip(self) -> Self {
+        AxisRect {
+            sign: -self.sign,
+            ..self
+        }
+    }
+
+    fn normal(&self) -> Vec3 {
+        axis_vec(self.plane.axes().2, self.sign)
+    }
+
+    fn area(&self) -> f32 {
+        (self.a.1 - self.a.0) * (self.b.1 - self.b.0)
+    }
+
+    fn point(&self, a: f32, b: f32, k: f32) -> Point3 {
+        let (ia, ib, ik) = self.plane.axes();
+        let p = axis_vec(ia, a) + axis_vec(ib, b) + axis_vec(ik, k);
+        Point3::default() + p
+    }
+
+    /// Whether `origin` is on the side that the normal points to.
+    fn faces(&self, origin: Point3) -> bool {
+        (origin[self.plane.axes().2] - self.k) * self.sign > 0.0
+    }
+}
+
+impl Hitable for AxisRect {
+    fn hit(
+        &self, r: &Ray, t_min: f32, t_max: f32, rec: &mut HitRecord,
+    ) -> bool {
+        let (ia, ib, ik) = self.plane.axes();
+        let t = (self.k - r.origin[ik]) / r.direction[ik];
+        // Also rejects the NaN of rays parallel to the plane.
+        if !(t > t_min && t < t_max) {
+            return false;
+        }
+        let a = r.origin[ia] + t * r.direction[ia];
+        let b = r.origin[ib] + t * r.direction[ib];
+        if a < self.a.0 || a > self.a.1 || b < self.b.0 || b > self.b.1 {
+            return false;
+        }
+        rec.t = t;
+        rec.p = self.point(a, b, self.k);
+        rec.normal = self.normal();
+        rec.material = self.material;
+        rec.dpdu = axis_vec(ia, self.a.1 - self.a.0);
+        rec.dpdv = axis_vec(ib, self.b.1 - self.b.0);
+        rec.u = (a - self.a.0) / (self.a.1 - self.a.0);
+        rec.v = (b - self.b.0) / (self.b.1 - self.b.0);
+        true
+    }
+
+    fn bounding_box(&self, _: f32, _: f32) -> Option<AABB> {
+        Some(AABB::new(
+            self.point(self.a.0, self.b.0, self.k - BBOX_PADDING),
+            self.point(self.a.1, self.b.1, self.k + BBOX_PADDING),
+        ))
+    }
+
+    fn material(&self) -> Option<usize> {
+        Some(self.material)
+    }
+
+    fn sample(
+        &self, rng: &mut RNG, origin: Point3, _time: f32,
+    ) -> Option<ShapeSample> {
+        let a = self.a.0 + rng.rand() * (self.a.1 - self.a.0);
+        let b = self.b.0 + rng.rand() * (self.b.1 - self.b.0);
+        let p = self.point(a, b, self.k);
+        planar_sample(origin, p, self.normal(), self.area())
+    }
+
+    fn pdf(&self, origin: Point3, direction: Vec3, time: f32) -> f32 {
+        let (normal, area) = (self.normal(), self.area());
+        planar_hit_pdf(self, origin, direction, time, normal, area)
+    }
+}
+
+/// The parallelogram with a corner at `q` and the edges `u` and `v`. The
+/// normal is `u × v`; the texture coordinates run along the edges.
+#[derive(Debug)]
+pub struct Quad {
+    q: Point3,
+    u: Vec3,
+    v: Vec3,
+    normal: Vec3,
+    // Maps points in the plane to their coordinates along the edges.
+    w: Vec3,
+    area: f32,
+    material: usize,
+}
+
+impl Quad {
+    pub fn new(q: Point3, u: Vec3, v: Vec3, material: usize) -> Self {
+        let n = u.cross(&v);
+        Quad {
+            q,
+            u,
+            v,
+            normal: n.unit(),
+            w: n / n.dot(n),
+            area: n.length(),
+            material,
+        }
+    }
+}
+
+impl Hitable for Quad {
+    fn hit(
+        &self, r: &Ray, t_min: f32, t_max: f32, rec: &mut HitRecord,
+    ) -> bool {
+        let denom = self.normal.dot(r.direction);
+        if denom.abs() < 1e-8 {
+            return false;
+        }
+        let t = self.normal.dot(self.q - r.origin) / denom;
+        if !(t > t_min && t < t_max) {
+            return false;
+        }
+        let p = r.point_at_param(t);
+        let planar = p - self.q;
+        let alpha = self.w.dot(planar.cross(&self.v));
+        let beta = self.w.dot(self.u.cross(&planar));
+        if !(0.0..=1.0).contains(&alpha) || !(0.0..=1.0).contains(&beta) {
+            return false;
+        }
+        rec.t = t;
+        rec.p = p;
+        rec.normal = self.normal;
+        rec.material = self.material;
+        rec.dpdu = self.u;
+        rec.dpdv = self.v;
+        rec.u = alpha;
+        rec.v = beta;
+        true
+    }
+
+    fn bounding_box(&self, _: f32, _: f32) -> Option<AABB> {
+        let (q, u, v) = (self.q, self.u, self.v);
+        let pad = Vec3::new(BBOX_PADDING, BBOX_PADDING, BBOX_PADDING);
+        Some(AABB::new(
+            q.min(q + u).min(q + v).min(q + u + v) - pad,
+            q.max(q + u).max(q + v).max(q + u + v) + pad,
+        ))
+    }
+
+    fn material(&self) -> Option<usize> {
+        Some(self.material)
+    }
+
+    fn sample(
+        &self, rng: &mut RNG, origin: Point3, _time: f32,
+    ) -> Option<ShapeSample> {
+        let p = self.q + rng.rand() * self.u + rng.rand() * self.v;
+        planar_sample(origin, p, self.normal, self.area)
+    }
+
+    fn pdf(&self, origin: Point3, direction: Vec3, time: f32) -> f32 {
+        planar_hit_pdf(self, origin, direction, time, self.normal, self.area)
+    }
+}
+
+/// A disk of `radius` around `center`, facing along `normal`. The texture
+/// coordinates are the angle around the center, as a fraction of a turn,
+/// and the distance from it, as a fraction of the radius.
+#[derive(Debug)]
+pub struct Disk {
+    center: Point3,
+    normal: Vec3,
+    tangent: Vec3,
+    bitangent: Vec3,
+    radius: f32,
+    material: usize,
+}
+
+impl Disk {
+    pub fn new(
+        center: Point3, normal: Vec3, radius: f32, material: usize,
+    ) -> Self {
+        let normal = normal.unit();
+        let (tangent, bitangent) = orthonormal_basis(normal);
+        Disk {
+            center,
+            normal,
+            tangent,
+            bitangent,
+            radius,
+            material,
+        }
+    }
+
+    fn area(&self) -> f32 {
+        PI * self.radius * self.radius
+    }
+}
+
+impl Hitable for Disk {
+    fn hit(
+        &self, r: &Ray, t_min: f32, t_max: f32, rec: &mut HitRecord,
+    ) -> bool {
+        let denom = self.normal.dot(r.direction);
+        if denom.abs() < 1e-8 {
+            return false;
+        }
+        let t = self.normal.dot(self.center - r.origin) / denom;
+        if !(t > t_min && t < t_max) {
+            return false;
+        }
+        let p = r.point_at_param(t);
+        let d = p - self.center;
+        let distance2 = d.dot(d);
+        if distance2 > self.radius * self.radius {
+            return false;
+        }
+        let phi = d.dot(self.bitangent).atan2(d.dot(self.tangent));
+        rec.t = t;
+        rec.p = p;
+        rec.normal = self.normal;
+        rec.material = self.material;
+        rec.dpdu = 2.0
+            * PI
+            * (d.dot(self.tangent) * self.bitangent
+                - d.dot(self.bitangent) * self.tangent);
+        rec.u = if phi < 0.0 { phi + 2.0 * PI } else { phi } / (2.0 * PI);
+        let distance = distance2.sqrt();
+        rec.dpdv = if distance > 0.0 {
+            d * (self.radius / distance)
+        } else {
+            Vec3::default()
+        };
+        rec.v = distance / self.radius;
+        true
+    }
+
+    fn bounding_box(&self, _: f32, _: f32) -> Option<AABB> {
+        let n = self.normal;
+        let extent =
+            |c: f32| self.radius * (1.0 - c * c).max(0.0).sqrt() + BBOX_PADDING;
+        let e = Vec3::new(extent(n.x()), extent(n.y()), extent(n.z()));
+        Some(AABB::new(self.center - e, self.center + e))
+    }
+
+    fn material(&self) -> Option<usize> {
+        Some(self.material)
+    }
+
+    fn sample(
+        &self, rng: &mut RNG, origin: Point3, _time: f32,
+    ) -> Option<ShapeSample> {
+        let r = self.radius * rng.rand().sqrt();
+        let phi = 2.0 * PI * rng.rand();
+        let p = self.center
+            + r * phi.cos() * self.tangent
+            + r * phi.sin() * self.bitangent;
+        planar_sample(origin, p, self.normal, self.area())
+    }
+
+    fn pdf(&self, origin: Point3, direction: Vec3, time: f32) -> f32 {
+        let (normal, area) = (self.normal, self.area());
+        planar_hit_pdf(self, origin, direction, time, normal, area)
+    }
+}
+
+/// An axis-aligned box from `min` to `max`, made of six rectangles with
+/// outward normals.
+#[derive(Debug)]
+pub struct Cuboid {
+    faces: [AxisRect; 6],
+    min: Point3,
+    max: Point3,
+    material: usize,
+}
+
+impl Cuboid {
+    pub fn new(p0: Point3, p1: Point3, material: usize) -> Self {
+        let (min, max) = (p0.min(p1), p0.max(p1));
+        let (x, y, z) =
+            ((min.x(), max.x()), (min.y(), max.y()), (min.z(), max.z()));
+        let faces = [
+            AxisRect::new(Plane::XY, x, y, max.z(), material),
+            AxisRect::new(Plane::XY, x, y, min.z(), material).flip(),
+            AxisRect::new(Plane::XZ, x, z, max.y(), material),
+            AxisRect::new(Plane::XZ, x, z, min.y(), material).flip(),
+            AxisRect::new(Plane::YZ, y, z, max.x(), material),
+            AxisRect::new(Plane::YZ, y, z, min.x(), material).flip(),
+        ];
+        Cuboid {
+            faces,
+            min,
+            max,
+            material,
+        }
+    }
+
+    /// Which faces light samples are taken on: the ones turned towards
+    /// `origin`, or all of them from inside. Every direction from `origin`
+    /// towards the box first hits exactly one of them.
+    fn visible_faces(&self, origin: Point3) -> ([bool; 6], usize) {
+        let mut visible = [false; 6];
+        for (v, face) in visible.iter_mut().zip(&self.faces) {
+            *v = face.faces(origin);
+        }
+        match visible.iter().filter(|&&v| v).count() {
+            0 => ([true; 6], 6),
+            n => (visible, n),
+        }
+    }
+
+    /// The index of the nearest face that `r` hits.
+    fn hit_face(
+        &self, r: &Ray, t_min: f32, t_max: f32, rec: &mut HitRecord,
+    ) -> Option<usize> {
+        let mut closest = None;
+        let mut closest_t = t_max;
+        for (i, face) in self.faces.iter().enumerate() {
+            if face.hit(r, t_min, closest_t, rec) {
+                closest = Some(i);
+                closest_t = rec.t;
+            }
+        }
+        closest
+    }
+}
+
+impl Hitable for Cuboid {
+    fn hit(
+        &self, r: &Ray, t_min: f32, t_max: f32, rec: &mut HitRecord,
+    ) -> bool {
+        self.hit_face(r, t_min, t_max, rec).is_some()
+    }
+
+    /// The line enters at the first face it meets and leaves at the last.
+    /// Going through an edge meets more than two.
+    fn hit_all(&self, r: &Ray, spans: &mut Vec<Span>) -> bool {
+        let mut span: Option<Span> = None;
+        let mut rec = HitRecord::default();
+        for face in &self.faces {
+            if !face.hit(r, -f32::MAX, f32::MAX, &mut rec) {
+                continue;
+            }
+            match &mut span {
+                None => {
+                    span = Some(Span {
+                        enter: rec.clone(),
+                        exit: rec.clone(),
+                    })
+                }
+                Some(span) if rec.t < span.enter.t => span.enter = rec.clone(),
+                Some(span) if rec.t > span.exit.t => span.exit = rec.clone(),
+                Some(_) => {}
+            }
+        }
+        if let Some(span) = span {
+            if span.enter.t < span.exit.t {
+                spans.push(span);
+            }
+        }
+        true
+    }
+
+    fn bounding_box(&self, _: f32, _: f32) -> Option<AABB> {
+        Some(AABB::new(self.min, self.max))
+    }
+
+    fn material(&self) -> Option<usize> {
+        Some(self.material)
+    }
+
+    /// Picks one of the faces that can be seen from `origin` uniformly,
+    /// then a point on it by area.
+    fn sample(
+        &self, rng: &mut RNG, origin: Point3, time: f32,
+    ) -> Option<ShapeSample> {
+        let (visible, n) = self.visible_faces(origin);
+        let k = cmp::min((rng.rand() * n as f32) as usize, n - 1);
+        let (face, _) =
+            self.faces.iter().zip(&visible).filter(|(_, &v)| v).nth(k)?;
+        let sample = face.sample(rng, origin, time)?;
+        Some(ShapeSample {
+            p: sample.p,
+            pdf: sample.pdf / n as f32,
+        })
+    }
+
+    fn pdf(&self, origin: Point3, direction: Vec3, time: f32) -> f32 {
+        let r = Ray::new(origin, direction, time, 0);
+        let mut rec = HitRecord::default();
+        let (visible, n) = self.visible_faces(origin);
+        match self.hit_face(&r, 0.001, f32::MAX, &mut rec) {
+            Some(i) if visible[i] => {
+                let face = &self.faces[i];
+                planar_pdf(origin, rec.p, face.normal(), face.area()) / n as f32
+            }
+            _ => 0.0,
+        }
+    }
+}
+
+#[cfg(test)]
+pub mod tests {
+    use super::*;
+    use ::std::{assert, assert_eq};
+
+    #[test]
+    fn test_sphere_sample() {
+        let sphere = Sphere::new(Point3::new(0.0, 0.0, -5.0), 1.0, 3);
+        let origin = Point3::new(0.0, 0.0, 0.0);
+        let mut rng = RNG::default();
+        // The cone has a half angle of asin(1/5).
+        let height = 1.0 - (24.0f32 / 25.0).sqrt();
+        let expected = 1.0 / (2.0 * PI * height);
+        for _ in 0..100 {
+            let s = sphere.sample(&mut rng, origin, 0.0).unwrap();
+            assert!(((s.p - sphere.center).length() - 1.0).abs() < 1e-4);
+            assert!((s.pdf - expected).abs() < 1e-3 * expected);
+            let direction = (s.p - origin).unit();
+            let pdf = sphere.pdf(origin, direction, 0.0);
+            assert!((pdf - s.pdf).abs() < 1e-3 * expected);
+        }
+        let away = Vec3::new(0.0, 0.0, 1.0);
+        assert!(sphere.pdf(origin, away, 0.0) == 0.0);
+        let inside = Point3::new(0.0, 0.0, -5.5);
+        assert!(sphere.sample(&mut rng, inside, 0.0).is_none());
+    }
+
+    /// Checks that `sample` and `pdf` agree and that 1/pdf averages to the
+    /// solid angle of the shape.
+    fn check_light(shape: &dyn Hitable, origin: Point3, solid_angle: f32) {
+        let mut rng = RNG::default();
+        let n = 10000;
+        let mut sum = 0.0;
+        for _ in 0..n {
+            let s = shape.sample(&mut rng, origin, 0.0).unwrap();
+            let direction = (s.p - origin).unit();
+            let pdf = shape.pdf(origin, direction, 0.0);
+            assert!((pdf - s.pdf).abs() < 1e-3 * s.pdf);
+            sum += 1.0 / s.pdf;
+        }
+        assert!((sum / n as f32 - solid_angle).abs() < 0.02 * solid_angle);
+    }
+
+    #[test]
+    fn test_axis_rect() {
+        let rect = AxisRect::new(Plane::XZ, (1.0, -1.0), (-1.0, 1.0), 2.0, 4);
+        let r = Ray::new(
+            Point3::new(0.5, 0.0, -0.5),
+            Vec3::new(0.0, 1.0, 0.0),
+            0.0,
+            0,
+        );
+        let mut rec = HitRecord::default();
+        assert!(rect.hit(&r, 0.001, f32::MAX, &mut rec));
+        assert_eq!(rec.t, 2.0);
+        assert_eq!(rec.material, 4);
+        assert_eq!(rec.normal.y(), 1.0);
+        assert_eq!((rec.u, rec.v), (0.75, 0.25));
+        assert_eq!(rec.dpdu.x(), 2.0);
+        assert_eq!(rect.flip().normal().y(), -1.0);
+        let bb = rect.bounding_box(0.0, 0.0).unwrap();
+        assert_eq!((bb.min().x(), bb.max().z()), (-1.0, 1.0));
+        assert!(bb.min().y() < 2.0 && bb.max().y() > 2.0);
+        // A square of side 2 seen from 2 below its center.
+        let expected = 4.0 * (1.0f32 / 5.0).asin();
+        check_light(&rect, Point3::default(), expected);
+    }
+
+    #[test]
+    fn test_quad_and_disk() {
+        let quad = Quad::new(
+            Point3::new(-1.0, -1.0, -2.0),
+            Vec3::new(2.0, 0.0, 0.0),
+            Vec3::new(0.0, 2.0, 0.0),
+            0,
+        );
+        let r = Ray::new(
+            Point3::new(0.5, 0.0, 0.0),
+            Vec3::new(0.0, 0.0, -1.0),
+            0.0,
+            0,
+        );
+        let mut rec = HitRecord::default();
+        assert!(quad.hit(&r, 0.001, f32::MAX, &mut rec));
+        assert!((rec.u - 0.75).abs() < 1e-6 && (rec.v - 0.5).abs() < 1e-6);
+        assert_eq!(rec.normal.z(), 1.0);
+        check_light(&quad, Point3::default(), 4.0 * (1.0f32 / 5.0).asin());
+
+        let disk = Disk::new(
+            Point3::new(0.0, 0.0, -2.0),
+            Vec3::new(0.0, 0.0, 1.0),
+            1.0,
+            0,
+        );
+        assert!(disk.hit(&r, 0.001, f32::MAX, &mut rec));
+        assert!((rec.v - 0.5).abs() < 1e-6);
+        let miss = Ray::new(
+            Point3::new(0.8, 0.8, 0.0),
+            Vec3::new(0.0, 0.0, -1.0),
+            0.0,
+            0,
+        );
+        assert!(!disk.hit(&miss, 0.001, f32::MAX, &mut rec));
+        let bb = disk.bounding_box(0.0, 0.0).unwrap();
+        assert!((bb.max().x() - 1.0).abs() < 1e-3);
+        assert!((bb.max().z() + 2.0).abs() < 1e-3);
+        let expected = 2.0 * PI * (1.0 - 2.0 / 5.0f32.sqrt());
+        check_light(&disk, Point3::default(), expected);
+    }
+
+    #[test]
+    fn test_cuboid() {
+        let cuboid = Cuboid::new(
+            Point3::new(1.0, 1.0, -1.0),
+            Point3::new(-1.0, -1.0, -3.0),
+            2,
+        );
+        // Normals point out, whichever side the ray comes from.
+        let r = Ray::new(
+            Point3::new(0.0, 0.0, 0.0),
+            Vec3::new(0.0, 0.0, -1.0),
+            0.0,
+            0,
+        );
+        let mut rec = HitRecord::default();
+        assert!(cuboid.hit(&r, 0.001, f32::MAX, &mut rec));
+        assert_eq!((rec.t, rec.normal.z()), (1.0, 1.0));
+        assert!(cuboid.hit(&r, 1.5, f32::MAX, &mut rec));
+        assert_eq!((rec.t, rec.normal.z()), (3.0, -1.0));
+        let bb = cuboid.bounding_box(0.0, 0.0).unwrap();
+        assert_eq!((bb.min().z(), bb.max().z()), (-3.0, -1.0));
+        // Only the front face can be seen from straight ahead.
+        let expected = 4.0 * (1.0f32 / 2.0).asin();
+        check_light(&cuboid, Point3::default(), expected);
+        // From off to the side two faces are sampled, and from inside all.
+        let mut rng = RNG::default();
+        for &origin in
+            &[Point3::new(3.0, 0.5, 0.0), Point3::new(0.0, 0.0, -2.0)]
+        {
+            for _ in 0..100 {
+                let s = cuboid.sample(&mut rng, origin, 0.0).unwrap();
+                let direction = (s.p - origin).unit();
+                let pdf = cuboid.pdf(origin, direction, 0.0);
+                assert!((pdf - s.pdf).abs() < 1e-3 * s.pdf);
+            }
+        }
+    }
+}
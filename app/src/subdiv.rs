@@ -0,0 +1,536 @@
+//! Catmull-Clark subdivision surfaces.
+//!
+//! A control cage of polygons is refined a fixed number of times when the
+//! scene is built and the resulting quads go down the triangle mesh path.
+//! Edges can be tagged as creases: a crease of sharpness `s` is refined with
+//! the sharp rules for `s` levels and smoothly after that, and fractional
+//! sharpness blends the two (DeRose, Kass and Truong, "Subdivision Surfaces
+//! in Character Animation", 1998). Cage boundaries are infinitely sharp
+//! creases, and boundary vertices of a single face are kept as corners.
+
+use ::std::clone::Clone;
+use ::std::collections::HashMap;
+use ::std::default::Default;
+use ::std::iter::Iterator;
+use ::std::option::Option::{self, None, Some};
+use ::std::vec::Vec;
+
+use ::math::{Point3, Vec3};
+
+use crate::mesh::TriangleMesh;
+
+/// An edge of the cage between two vertices, in either order, refined with
+/// the sharp rules for `sharpness` levels.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Crease {
+    pub edge: (usize, usize),
+    pub sharpness: f32,
+}
+
+/// A polygon mesh with creases. Faces list their vertices in
+/// counter-clockwise order seen from outside.
+#[derive(Debug, Clone, Default)]
+pub struct Cage {
+    pub positions: Vec<Point3>,
+    pub faces: Vec<Vec<usize>>,
+    pub creases: Vec<Crease>,
+}
+
+fn edge_key(a: usize, b: usize) -> (usize, usize) {
+    if a < b {
+        (a, b)
+    } else {
+        (b, a)
+    }
+}
+
+fn lerp(a: Point3, b: Point3, t: f32) -> Point3 {
+    a + t * (b - a)
+}
+
+fn average(points: impl Iterator<Item = Point3>) -> Point3 {
+    let mut sum = Vec3::default();
+    let mut n = 0;
+    for p in points {
+        sum += p - Point3::default();
+        n += 1;
+    }
+    Point3::default() + sum / n as f32
+}
+
+struct Edge {
+    ends: (usize, usize),
+    faces: Vec<usize>,
+    /// The face corners at `ends.0` and `ends.1` of every face, numbered
+    /// through all faces in order.
+    corners: Vec<(usize, usize)>,
+    /// Infinite on boundaries and edges of more than two faces.
+    sharpness: f32,
+}
+
+/// Edges and vertex neighbourhoods of a cage.
+struct Topology {
+    edges: Vec<Edge>,
+    /// The edge from every face corner to the next one.
+    corner_edges: Vec<usize>,
+    vertex_edges: Vec<Vec<usize>>,
+    vertex_faces: Vec<Vec<usize>>,
+}
+
+impl Topology {
+    fn new(cage: &Cage) -> Self {
+        let mut creases = HashMap::new();
+        for crease in &cage.creases {
+            let key = edge_key(crease.edge.0, crease.edge.1);
+            let s = creases.entry(key).or_insert(0.0f32);
+            *s = s.max(crease.sharpness);
+        }
+        let mut edges: Vec<Edge> = Vec::new();
+        let mut index = HashMap::new();
+        let mut corner_edges = Vec::new();
+        let mut vertex_edges = ::std::vec![Vec::new(); cage.positions.len()];
+        let mut vertex_faces = ::std::vec![Vec::new(); cage.positions.len()];
+        for (f, face) in cage.faces.iter().enumerate() {
+            let first = corner_edges.len();
+            for (i, &a) in face.iter().enumerate() {
+                let next = (i + 1) % face.len();
+                let b = face[next];
+                let key = edge_key(a, b);
+                let e = *index.entry(key).or_insert_with(|| {
+                    edges.push(Edge {
+                        ends: key,
+                        faces: Vec::new(),
+                        corners: Vec::new(),
+                        sharpness: creases.get(&key).cloned().unwrap_or(0.0),
+                    });
+                    vertex_edges[a].push(edges.len() - 1);
+                    vertex_edges[b].push(edges.len() - 1);
+                    edges.len() - 1
+                });
+                let (ca, cb) = (first + i, first + next);
+                edges[e].faces.push(f);
+                edges[e].corners.push(if a == key.0 {
+                    (ca, cb)
+                } else {
+                    (cb, ca)
+                });
+                corner_edges.push(e);
+                vertex_faces[a].push(f);
+            }
+        }
+        for edge in &mut edges {
+            if edge.faces.len() != 2 {
+                edge.sharpness = f32::INFINITY;
+            }
+        }
+        Topology {
+            edges,
+            corner_edges,
+            vertex_edges,
+            vertex_faces,
+        }
+    }
+}
+
+impl Cage {
+    /// One level of Catmull-Clark refinement. Every face with n vertices
+    /// becomes n quads.
+    pub fn refine(&self) -> Cage {
+        let topo = Topology::new(self);
+        let p = &self.positions;
+        let face_points: Vec<Point3> = self
+            .faces
+            .iter()
+            .map(|face| average(face.iter().map(|&v| p[v])))
+            .collect();
+
+        let edge_points: Vec<Point3> = topo
+            .edges
+            .iter()
+            .map(|e| {
+                let mid = lerp(p[e.ends.0], p[e.ends.1], 0.5);
+                if e.sharpness >= 1.0 {
+                    return mid;
+                }
+                let smooth = average(
+                    [p[e.ends.0], p[e.ends.1]]
+                        .iter()
+                        .cloned()
+                        .chain(e.faces.iter().map(|&f| face_points[f])),
+                );
+                lerp(smooth, mid, e.sharpness)
+            })
+            .collect();
+
+        let vertex_points = p.iter().enumerate().map(|(v, &pv)| {
+            let edges = &topo.vertex_edges[v];
+            let faces = &topo.vertex_faces[v];
+            if edges.is_empty() {
+                return pv;
+            }
+            let other = |e: &Edge| {
+                if e.ends.0 == v {
+                    p[e.ends.1]
+                } else {
+                    p[e.ends.0]
+                }
+            };
+            let creases: Vec<&Edge> = edges
+                .iter()
+                .map(|&e| &topo.edges[e])
+                .filter(|e| e.sharpness > 0.0)
+                .collect();
+            let sharp = match creases.len() {
+                0 | 1 => return self.smooth_vertex(v, &topo, &face_points),
+                // The cubic B-spline rule (a + 6v + b) / 8 along the crease.
+                2 if faces.len() > 1 => lerp(
+                    pv,
+                    lerp(other(creases[0]), other(creases[1]), 0.5),
+                    0.25,
+                ),
+                _ => pv,
+            };
+            let sharpness = creases.iter().map(|e| e.sharpness).sum::<f32>()
+                / creases.len() as f32;
+            if sharpness >= 1.0 {
+                sharp
+            } else {
+                lerp(
+                    self.smooth_vertex(v, &topo, &face_points),
+                    sharp,
+                    sharpness,
+                )
+            }
+        });
+
+        let mut positions: Vec<Point3> = vertex_points.collect();
+        let face_base = positions.len();
+        positions.extend_from_slice(&face_points);
+        let edge_base = positions.len();
+        positions.extend_from_slice(&edge_points);
+
+        let mut faces = Vec::new();
+        let mut corner = 0;
+        for (f, face) in self.faces.iter().enumerate() {
+            let n = face.len();
+            for (i, &v) in face.iter().enumerate() {
+                let prev = topo.corner_edges[corner + (i + n - 1) % n];
+                let next = topo.corner_edges[corner + i];
+                faces.push(::std::vec![
+                    v,
+                    edge_base + next,
+                    face_base + f,
+                    edge_base + prev,
+                ]);
+            }
+            corner += n;
+        }
+
+        let mut creases = Vec::new();
+        for (e, edge) in topo.edges.iter().enumerate() {
+            if edge.faces.len() == 2 && edge.sharpness > 1.0 {
+                for &end in &[edge.ends.0, edge.ends.1] {
+                    creases.push(Crease {
+                        edge: (end, edge_base + e),
+                        sharpness: edge.sharpness - 1.0,
+                    });
+                }
+            }
+        }
+        Cage {
+            positions,
+            faces,
+            creases,
+        }
+    }
+
+    /// The smooth rule for an interior vertex of valence n:
+    /// (F + 2R + (n - 3)V) / n with the average face point F and the
+    /// average edge midpoint R.
+    fn smooth_vertex(
+        &self, v: usize, topo: &Topology, face_points: &[Point3],
+    ) -> Point3 {
+        let p = &self.positions;
+        let edges = &topo.vertex_edges[v];
+        let n = edges.len() as f32;
+        let f = average(topo.vertex_faces[v].iter().map(|&f| face_points[f]));
+        let r = average(edges.iter().map(|&e| {
+            let ends = topo.edges[e].ends;
+            lerp(p[ends.0], p[ends.1], 0.5)
+        }));
+        let origin = Point3::default();
+        origin
+            + ((f - origin) + 2.0 * (r - origin) + (n - 3.0) * (p[v] - origin))
+                / n
+    }
+
+    /// Refines the cage `levels` times.
+    pub fn subdivide(&self, levels: u32) -> Cage {
+        let mut cage = self.clone();
+        for _ in 0..levels {
+            cage = cage.refine();
+        }
+        cage
+    }
+
+    /// Triangulates the faces with normals averaged over the faces around
+    /// each vertex. Creases that are still sharp split the normals, so they
+    /// stay visible as edges.
+    pub fn to_mesh(&self, material: usize) -> TriangleMesh {
+        let topo = Topology::new(self);
+        // Face corners around a vertex share a normal unless a sharp edge
+        // lies between them.
+        let mut groups: Vec<usize> = (0..topo.corner_edges.len()).collect();
+        fn find(groups: &mut [usize], mut i: usize) -> usize {
+            while groups[i] != i {
+                groups[i] = groups[groups[i]];
+                i = groups[i];
+            }
+            i
+        }
+        for edge in &topo.edges {
+            if edge.sharpness > 0.0 {
+                continue;
+            }
+            let (a, b) = (edge.corners[0], edge.corners[1]);
+            for &(x, y) in &[(a.0, b.0), (a.1, b.1)] {
+                let (x, y) = (find(&mut groups, x), find(&mut groups, y));
+                groups[x] = y;
+            }
+        }
+
+        // Newell's normal, twice the area of the face long.
+        let origin = Point3::default();
+        let face_normals = self.faces.iter().map(|face| {
+            let mut n = Vec3::default();
+            for (i, &a) in face.iter().enumerate() {
+                let b = face[(i + 1) % face.len()];
+                n += (self.positions[a] - origin)
+                    .cross(&(self.positions[b] - origin));
+            }
+            n
+        });
+
+        let mut vertices: Vec<Option<usize>> = ::std::vec![None; groups.len()];
+        let mut positions = Vec::new();
+        let mut normals: Vec<Vec3> = Vec::new();
+        let mut corner_vertices = Vec::with_capacity(groups.len());
+        let mut corner = 0;
+        for (face, n) in self.faces.iter().zip(face_normals) {
+            for (i, &v) in face.iter().enumerate() {
+                let group = find(&mut groups, corner + i);
+                let vertex = match vertices[group] {
+                    Some(vertex) => vertex,
+                    None => {
+                        positions.push(self.positions[v]);
+                        normals.push(Vec3::default());
+                        vertices[group] = Some(positions.len() - 1);
+                        positions.len() - 1
+                    }
+                };
+                normals[vertex] += n;
+                corner_vertices.push(vertex);
+            }
+            corner += face.len();
+        }
+        for n in &mut normals {
+            *n = if n.length() > 0.0 {
+                n.unit()
+            } else {
+                Vec3::new(0.0, 0.0, 1.0)
+            };
+        }
+
+        let mut indices = Vec::new();
+        let mut corner = 0;
+        for face in &self.faces {
+            for i in 1..face.len() - 1 {
+                indices.extend_from_slice(&[
+                    corner_vertices[corner],
+                    corner_vertices[corner + i],
+                    corner_vertices[corner + i + 1],
+                ]);
+            }
+            corner += face.len();
+        }
+        TriangleMesh::new(positions, indices, normals, Vec::new(), material)
+    }
+}
+
+#[cfg(test)]
+pub mod tests {
+    use super::*;
+    use ::std::{assert, assert_eq, vec};
+
+    use crate::pbrt::{HitRecord, Hitable, Ray};
+
+    /// The unit cube around the origin.
+    pub fn cube() -> Cage {
+        let positions = (0..8)
+            .map(|i| {
+                let c = |bit: usize| if i & bit != 0 { 0.5 } else { -0.5 };
+                Point3::new(c(1), c(2), c(4))
+            })
+            .collect();
+        Cage {
+            positions,
+            faces: vec![
+                vec![0, 2, 3, 1],
+                vec![4, 5, 7, 6],
+                vec![0, 1, 5, 4],
+                vec![2, 6, 7, 3],
+                vec![0, 4, 6, 2],
+                vec![1, 3, 7, 5],
+            ],
+            creases: Vec::new(),
+        }
+    }
+
+    fn max_radius(cage: &Cage) -> f32 {
+        cage.positions
+            .iter()
+            .map(|&p| (p - Point3::default()).length())
+            .fold(0.0, f32::max)
+    }
+
+    #[test]
+    fn test_refine_cube() {
+        let cube = cube();
+        let once = cube.refine();
+        // 8 vertices, 6 face points and 12 edge points.
+        assert_eq!(once.positions.len(), 26);
+        assert_eq!(once.faces.len(), 24);
+        assert!(once.faces.iter().all(|f| f.len() == 4));
+        // A corner of valence 3: (F + 2R + 0V) / 3 with F a third and R two
+        // thirds of the way out to the corner.
+        let c = 5.0 / 18.0;
+        assert!((once.positions[7] - Point3::new(c, c, c)).length() < 1e-6);
+        // Face points are face centers, edge points pulled inwards.
+        assert_eq!(once.positions[9], Point3::new(0.0, 0.0, 0.5));
+        assert!(once.positions[14..]
+            .iter()
+            .all(|&p| { (p - Point3::default()).length() < 0.5f32.sqrt() }));
+
+        // The limit surface is a rounded box inside the cube that keeps
+        // its symmetry.
+        let fine = cube.subdivide(4);
+        assert_eq!(fine.faces.len(), 6 * 256);
+        let r = max_radius(&fine);
+        assert!(r < 0.5 && r > 0.4, "{}", r);
+        let sum = fine
+            .positions
+            .iter()
+            .fold(Vec3::default(), |s, &p| s + (p - Point3::default()));
+        assert!(sum.length() < 1e-3);
+    }
+
+    #[test]
+    fn test_creases() {
+        // Infinitely sharp creases on all edges keep the cube.
+        let mut sharp = cube();
+        for face in sharp.faces.clone() {
+            for i in 0..4 {
+                sharp.creases.push(Crease {
+                    edge: (face[i], face[(i + 1) % 4]),
+                    sharpness: f32::INFINITY,
+                });
+            }
+        }
+        let fine = sharp.subdivide(3);
+        assert!(fine.positions.iter().all(|p| {
+            [p.x(), p.y(), p.z()]
+                .iter()
+                .any(|c| (c.abs() - 0.5).abs() < 1e-6)
+        }));
+        assert!((max_radius(&fine) - 0.75f32.sqrt()).abs() < 1e-6);
+
+        // A semi-sharp crease rounds off less than a smooth edge and more
+        // than a sharp one.
+        let corner = |sharpness: f32| {
+            let mut cage = cube();
+            for face in cage.faces.clone() {
+                for i in 0..4 {
+                    cage.creases.push(Crease {
+                        edge: (face[i], face[(i + 1) % 4]),
+                        sharpness,
+                    });
+                }
+            }
+            (cage.subdivide(4).positions[7] - Point3::default()).length()
+        };
+        let (smooth, half, two) = (corner(0.0), corner(0.5), corner(2.0));
+        assert!(smooth < half && half < two && two < 0.75f32.sqrt());
+    }
+
+    #[test]
+    fn test_boundary() {
+        // An open grid of 2 x 2 quads: the corners stay, boundary edges
+        // stay on their lines, the center vertex is smoothed.
+        let positions = (0..9)
+            .map(|i| Point3::new((i % 3) as f32, (i / 3) as f32, 0.0))
+            .collect();
+        let mut grid = Cage {
+            positions,
+            faces: vec![
+                vec![0, 1, 4, 3],
+                vec![1, 2, 5, 4],
+                vec![3, 4, 7, 6],
+                vec![4, 5, 8, 7],
+            ],
+            creases: Vec::new(),
+        };
+        grid.positions[4] = Point3::new(1.0, 1.0, 1.0);
+        let fine = grid.subdivide(2);
+        for &i in &[0, 2, 6, 8] {
+            assert_eq!(fine.positions[i], grid.positions[i]);
+        }
+        // Boundary vertex 1 follows the cubic B-spline along y = 0.
+        assert_eq!(fine.positions[1], Point3::new(1.0, 0.0, 0.0));
+        assert!(fine.positions[4].z() > 0.0 && fine.positions[4].z() < 1.0);
+    }
+
+    #[test]
+    fn test_mesh() {
+        let mesh = cube().subdivide(3).to_mesh(3);
+        assert_eq!(mesh.num_triangles(), 6 * 64 * 2);
+        // Smooth everywhere, so every vertex is shared by its faces and
+        // normals point away from the center.
+        assert_eq!(mesh.positions.len(), 6 * 64 + 2);
+        for (p, n) in mesh.positions.iter().zip(&mesh.normals) {
+            assert!(n.dot(*p - Point3::default()) > 0.0);
+        }
+        let tris = TriangleMesh::triangles(&::std::sync::Arc::new(mesh));
+        let r = Ray::new(
+            Point3::new(0.0, 0.0, 5.0),
+            Vec3::new(0.0, 0.0, -1.0),
+            0.0,
+            0,
+        );
+        let mut rec = HitRecord::default();
+        assert!(tris.hit(&r, 0.001, f32::MAX, &mut rec));
+        assert_eq!(rec.material, 3);
+        assert!(rec.t > 4.5 && rec.t < 4.6);
+        assert!((rec.normal - Vec3::new(0.0, 0.0, 1.0)).length() < 1e-3);
+
+        // Sharp edges give every face of a cube its own vertices.
+        let mut sharp = cube();
+        for face in sharp.faces.clone() {
+            for i in 0..4 {
+                sharp.creases.push(Crease {
+                    edge: (face[i], face[(i + 1) % 4]),
+                    sharpness: f32::INFINITY,
+                });
+            }
+        }
+        let mesh = sharp.to_mesh(0);
+        assert_eq!(mesh.positions.len(), 24);
+        assert_eq!(mesh.num_triangles(), 12);
+        assert!(mesh.normals.iter().all(|n| {
+            [n.x(), n.y(), n.z()]
+                .iter()
+                .filter(|c| c.abs() == 1.0)
+                .count()
+                == 1
+        }));
+    }
+}
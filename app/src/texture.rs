@@ -0,0 +1,491 @@
+//! Textures: constants, a procedural checker and filtered images.
+//!
+//! Image textures keep a MIP pyramid of box filtered copies, each half the
+//! size of the one before. Materials look them up through
+//! `Texture::filtered`, with the screen space derivatives of (u, v) from
+//! the camera ray's differentials, so that a texture seen from afar or at
+//! a grazing angle is averaged over the pixel instead of aliasing. The
+//! filters are bilinear (no pyramid), trilinear (Williams, "Pyramidal
+//! Parametrics", 1983) and elliptical weighted average (Heckbert,
+//! "Fundamentals of Texture Mapping and Image Warping", 1989), as in pbrt.
+
+use ::std::clone::Clone;
+use ::std::cmp;
+use ::std::convert::From;
+use ::std::option::Option::{self, Some};
+use ::std::path::Path;
+use ::std::result::Result::{self, Err, Ok};
+use ::std::string::ToString;
+use ::std::sync::Arc;
+use ::std::vec::Vec;
+use ::std::{assert, matches};
+
+use ::math::{Point3, Vec3};
+
+use crate::image::{Image, ImageError, ImageFormat};
+use crate::pbrt::{HitRecord, Texture};
+
+#[derive(Debug, Clone, Copy)]
+pub struct ConstTexture(pub Vec3);
+
+impl Texture for ConstTexture {
+    fn value(&self, _: f32, _: f32, _: Point3) -> Vec3 {
+        self.0
+    }
+}
+
+#[derive(Debug)]
+pub struct CheckerTexture {
+    pub odd: Arc<dyn Texture>,
+    pub even: Arc<dyn Texture>,
+}
+
+impl CheckerTexture {
+    fn is_odd(p: Point3) -> bool {
+        let sines: Vec3 = Vec3::from(p) * 10.0;
+        sines[0].sin() * sines[1].sin() * sines[2].sin() < 0.0
+    }
+}
+
+impl Texture for CheckerTexture {
+    fn value(&self, u: f32, v: f32, p: Point3) -> Vec3 {
+        if CheckerTexture::is_odd(p) {
+            self.odd.value(u, v, p)
+        } else {
+            self.even.value(u, v, p)
+        }
+    }
+
+    fn filtered(&self, rec: &HitRecord) -> Vec3 {
+        if CheckerTexture::is_odd(rec.p) {
+            self.odd.filtered(rec)
+        } else {
+            self.even.filtered(rec)
+        }
+    }
+}
+
+/// Where lookups outside the image find their texels.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum WrapMode {
+    /// Tiles the image.
+    Repeat,
+    /// Extends the edge texels.
+    Clamp,
+    /// Tiles the image, flipped in every other tile.
+    Mirror,
+}
+
+impl WrapMode {
+    /// The texel that stands in for `i` in a row of `n`.
+    fn texel(self, i: i64, n: usize) -> usize {
+        let n = n as i64;
+        let i = match self {
+            WrapMode::Repeat => i.rem_euclid(n),
+            WrapMode::Clamp => cmp::Ord::clamp(i, 0, n - 1),
+            WrapMode::Mirror => {
+                let m = i.rem_euclid(2 * n);
+                if m < n {
+                    m
+                } else {
+                    2 * n - 1 - m
+                }
+            }
+        };
+        i as usize
+    }
+}
+
+/// How an image texture averages over the footprint of a pixel.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum FilterMode {
+    /// Interpolates the four nearest texels of the full image and ignores
+    /// the footprint.
+    Bilinear,
+    /// Blends bilinear lookups in the two levels of the pyramid whose
+    /// texels are nearest the footprint's width. Blurs footprints that
+    /// are longer than wide.
+    Trilinear,
+    /// Weighs the texels under the elliptical footprint with a Gaussian,
+    /// in the level where its minor axis is about one texel wide.
+    Ewa,
+}
+
+/// How much longer than wide EWA footprints may be. Longer ones are
+/// widened, which blurs them, to bound the texels they cover.
+const MAX_ANISOTROPY: f32 = 8.0;
+
+/// An image and its MIP pyramid, addressed with (s, t) in [0, 1] from the
+/// top left corner.
+#[derive(Debug)]
+pub struct MipMap {
+    levels: Vec<Image>,
+    wrap: WrapMode,
+}
+
+impl MipMap {
+    /// Builds the pyramid down to a single texel. Each texel of a level
+    /// is the mean of the two by two texels under it; the last row or
+    /// column of an odd sized level is counted twice. The image must not be
+    /// empty.
+    pub fn new(image: Image, wrap: WrapMode) -> Self {
+        assert!(image.width > 0 && image.height > 0, "empty MIP map image");
+        let mut levels = Vec::new();
+        let mut image = image;
+        while image.width > 1 || image.height > 1 {
+            let (width, height) =
+                (image.width.div_ceil(2), image.height.div_ceil(2));
+            let mut next = Image::new(width, height);
+            for y in 0..height {
+                for x in 0..width {
+                    let texel = |dx: usize, dy: usize| {
+                        image.pixel(
+                            cmp::min(2 * x + dx, image.width - 1),
+                            cmp::min(2 * y + dy, image.height - 1),
+                        )
+                    };
+                    let sum =
+                        texel(0, 0) + texel(1, 0) + texel(0, 1) + texel(1, 1);
+                    next.set_pixel(x, y, 0.25 * sum);
+                }
+            }
+            levels.push(image);
+            image = next;
+        }
+        levels.push(image);
+        MipMap { levels, wrap }
+    }
+
+    fn texel(&self, level: usize, x: i64, y: i64) -> Vec3 {
+        let image = &self.levels[level];
+        image.pixel(
+            self.wrap.texel(x, image.width),
+            self.wrap.texel(y, image.height),
+        )
+    }
+
+    /// The level, with a fraction, whose texels are `width` wide in (s, t).
+    fn level(&self, width: f32) -> f32 {
+        let image = &self.levels[0];
+        let size = cmp::max(image.width, image.height) as f32;
+        (width * size).max(1e-8).log2().max(0.0)
+    }
+
+    /// Interpolates between the four texels nearest (s, t) in `level`.
+    pub fn bilinear(&self, level: usize, s: f32, t: f32) -> Vec3 {
+        let level = cmp::min(level, self.levels.len() - 1);
+        let image = &self.levels[level];
+        let x = s * image.width as f32 - 0.5;
+        let y = t * image.height as f32 - 0.5;
+        let (x0, y0) = (x.floor(), y.floor());
+        let (fx, fy) = (x - x0, y - y0);
+        let (x0, y0) = (x0 as i64, y0 as i64);
+        (1.0 - fx) * (1.0 - fy) * self.texel(level, x0, y0)
+            + fx * (1.0 - fy) * self.texel(level, x0 + 1, y0)
+            + (1.0 - fx) * fy * self.texel(level, x0, y0 + 1)
+            + fx * fy * self.texel(level, x0 + 1, y0 + 1)
+    }
+
+    /// Blends the two levels whose texels are nearest `width` in size.
+    pub fn trilinear(&self, s: f32, t: f32, width: f32) -> Vec3 {
+        let level = self.level(width);
+        let last = (self.levels.len() - 1) as f32;
+        if level >= last {
+            return self.bilinear(self.levels.len() - 1, s, t);
+        }
+        let (i, f) = (level.floor(), level.fract());
+        let i = i as usize;
+        (1.0 - f) * self.bilinear(i, s, t) + f * self.bilinear(i + 1, s, t)
+    }
+
+    /// Averages over the ellipse with the axes `dst0` and `dst1` around
+    /// (s, t), both in units of the whole image.
+    pub fn ewa(
+        &self, s: f32, t: f32, dst0: (f32, f32), dst1: (f32, f32),
+    ) -> Vec3 {
+        let length2 = |d: (f32, f32)| d.0 * d.0 + d.1 * d.1;
+        let (major, mut minor) = if length2(dst0) < length2(dst1) {
+            (dst1, dst0)
+        } else {
+            (dst0, dst1)
+        };
+        let (major_length, minor_length) =
+            (length2(major).sqrt(), length2(minor).sqrt());
+        if minor_length == 0.0 {
+            return self.bilinear(0, s, t);
+        }
+        if minor_length * MAX_ANISOTROPY < major_length {
+            let scale = major_length / (minor_length * MAX_ANISOTROPY);
+            minor = (minor.0 * scale, minor.1 * scale);
+        }
+        let level = self.level(length2(minor).sqrt());
+        let (i, f) = (level.floor() as usize, level.fract());
+        let lower = self.ewa_level(i, s, t, major, minor);
+        if f == 0.0 {
+            return lower;
+        }
+        (1.0 - f) * lower + f * self.ewa_level(i + 1, s, t, major, minor)
+    }
+
+    fn ewa_level(
+        &self, level: usize, s: f32, t: f32, d0: (f32, f32), d1: (f32, f32),
+    ) -> Vec3 {
+        if level >= self.levels.len() - 1 {
+            return self.texel(self.levels.len() - 1, 0, 0);
+        }
+        let image = &self.levels[level];
+        let (w, h) = (image.width as f32, image.height as f32);
+        let (s, t) = (s * w - 0.5, t * h - 0.5);
+        let (d0, d1) = ((d0.0 * w, d0.1 * h), (d1.0 * w, d1.1 * h));
+        // The implicit ellipse a x² + b x y + c y² < 1, grown by a texel
+        // so that it covers at least one.
+        let a = d0.1 * d0.1 + d1.1 * d1.1 + 1.0;
+        let b = -2.0 * (d0.0 * d0.1 + d1.0 * d1.1);
+        let c = d0.0 * d0.0 + d1.0 * d1.0 + 1.0;
+        let inv_f = 1.0 / (a * c - 0.25 * b * b);
+        let (a, b, c) = (a * inv_f, b * inv_f, c * inv_f);
+        let det = 4.0 * a * c - b * b;
+        let (du, dv) = (2.0 * (c / det).sqrt(), 2.0 * (a / det).sqrt());
+        let (x0, x1) = ((s - du).ceil() as i64, (s + du).floor() as i64);
+        let (y0, y1) = ((t - dv).ceil() as i64, (t + dv).floor() as i64);
+        let mut sum = Vec3::new(0.0, 0.0, 0.0);
+        let mut weights = 0.0;
+        for y in y0..=y1 {
+            let dy = y as f32 - t;
+            for x in x0..=x1 {
+                let dx = x as f32 - s;
+                let r2 = a * dx * dx + b * dx * dy + c * dy * dy;
+                if r2 < 1.0 {
+                    let weight = (-2.0 * r2).exp() - (-2.0f32).exp();
+                    sum += weight * self.texel(level, x, y);
+                    weights += weight;
+                }
+            }
+        }
+        if weights > 0.0 {
+            sum / weights
+        } else {
+            self.bilinear(level, s, t)
+        }
+    }
+}
+
+/// The linear value of an sRGB encoded one.
+pub fn srgb_to_linear(c: f32) -> f32 {
+    if c <= 0.04045 {
+        c / 12.92
+    } else {
+        ((c + 0.055) / 1.055).powf(2.4)
+    }
+}
+
+/// An image mapped onto (u, v), with (0, 0) at the bottom left corner of
+/// the image and (1, 1) at the top right.
+#[derive(Debug)]
+pub struct ImageTexture {
+    mipmap: MipMap,
+    pub filter: FilterMode,
+    /// Multiplies (u, v) before the lookup; 2 shows the image twice.
+    pub scale: (f32, f32),
+    /// Added to (u, v) after `scale`.
+    pub offset: (f32, f32),
+}
+
+impl ImageTexture {
+    /// A texture of `image`, which has to hold linear values.
+    pub fn new(image: Image, wrap: WrapMode, filter: FilterMode) -> Self {
+        ImageTexture {
+            mipmap: MipMap::new(image, wrap),
+            filter,
+            scale: (1.0, 1.0),
+            offset: (0.0, 0.0),
+        }
+    }
+
+    /// Reads the image at `path` and decodes it from sRGB if `srgb` is
+    /// true. When it is not given, PNGs and JPEGs are taken to be sRGB and
+    /// the floating point formats to be linear already.
+    pub fn read(
+        path: &Path, srgb: Option<bool>, wrap: WrapMode, filter: FilterMode,
+    ) -> Result<Self, ImageError> {
+        let mut image = Image::read(path)?;
+        if image.width == 0 || image.height == 0 {
+            return Err(ImageError::Decode {
+                path: path.to_path_buf(),
+                message: "empty image".to_string(),
+            });
+        }
+        let float = matches!(
+            ImageFormat::from_path(path),
+            Some(ImageFormat::Pfm) | Some(ImageFormat::Hdr)
+        );
+        if srgb.unwrap_or(!float) {
+            for c in &mut image.pixels {
+                *c = Vec3::new(
+                    srgb_to_linear(c.x()),
+                    srgb_to_linear(c.y()),
+                    srgb_to_linear(c.z()),
+                );
+            }
+        }
+        Ok(ImageTexture::new(image, wrap, filter))
+    }
+
+    fn st(&self, u: f32, v: f32) -> (f32, f32) {
+        (
+            self.scale.0 * u + self.offset.0,
+            1.0 - (self.scale.1 * v + self.offset.1),
+        )
+    }
+}
+
+impl Texture for ImageTexture {
+    fn value(&self, u: f32, v: f32, _: Point3) -> Vec3 {
+        let (s, t) = self.st(u, v);
+        self.mipmap.bilinear(0, s, t)
+    }
+
+    fn filtered(&self, rec: &HitRecord) -> Vec3 {
+        let (s, t) = self.st(rec.u, rec.v);
+        // t runs against v.
+        let dst0 = (self.scale.0 * rec.dudx, -self.scale.1 * rec.dvdx);
+        let dst1 = (self.scale.0 * rec.dudy, -self.scale.1 * rec.dvdy);
+        match self.filter {
+            FilterMode::Bilinear => self.mipmap.bilinear(0, s, t),
+            FilterMode::Trilinear => {
+                let width = 2.0
+                    * dst0
+                        .0
+                        .abs()
+                        .max(dst0.1.abs())
+                        .max(dst1.0.abs().max(dst1.1.abs()));
+                self.mipmap.trilinear(s, t, width)
+            }
+            FilterMode::Ewa => self.mipmap.ewa(s, t, dst0, dst1),
+        }
+    }
+}
+
+#[cfg(test)]
+pub mod tests {
+    use super::*;
+    use ::std::default::Default;
+    use ::std::iter::Iterator;
+    use ::std::option::Option::None;
+    use ::std::{assert, assert_eq, panic};
+    use ::std::{env, fs};
+
+    /// Stripes of `width` columns alternating between white and black,
+    /// `n` by `n`.
+    fn stripes(n: usize, width: usize) -> Image {
+        let mut image = Image::new(n, n);
+        for y in 0..n {
+            for x in (0..n).filter(|x| (x / width).is_multiple_of(2)) {
+                image.set_pixel(x, y, Vec3::new(1.0, 1.0, 1.0));
+            }
+        }
+        image
+    }
+
+    fn close(a: Vec3, b: f32) -> bool {
+        (a - Vec3::new(b, b, b)).length() < 1e-4
+    }
+
+    #[test]
+    fn test_wrap_modes() {
+        let wrap = |mode: WrapMode| {
+            (-3..6).map(|i| mode.texel(i, 3)).collect::<Vec<_>>()
+        };
+        assert_eq!(wrap(WrapMode::Repeat), [0, 1, 2, 0, 1, 2, 0, 1, 2]);
+        assert_eq!(wrap(WrapMode::Clamp), [0, 0, 0, 0, 1, 2, 2, 2, 2]);
+        assert_eq!(wrap(WrapMode::Mirror), [2, 1, 0, 0, 1, 2, 2, 1, 0]);
+    }
+
+    #[test]
+    fn test_bilinear() {
+        let mut image = Image::new(2, 1);
+        image.set_pixel(1, 0, Vec3::new(1.0, 1.0, 1.0));
+        let clamp = MipMap::new(image.clone(), WrapMode::Clamp);
+        assert!(close(clamp.bilinear(0, 0.25, 0.5), 0.0));
+        assert!(close(clamp.bilinear(0, 0.5, 0.5), 0.5));
+        assert!(close(clamp.bilinear(0, 0.625, 0.5), 0.75));
+        assert!(close(clamp.bilinear(0, 1.5, 0.5), 1.0));
+        // Past the right edge, repeating blends back towards black.
+        let repeat = MipMap::new(image, WrapMode::Repeat);
+        assert!(close(repeat.bilinear(0, 1.0, 0.5), 0.5));
+        assert!(close(repeat.bilinear(0, -0.25, 0.5), 1.0));
+    }
+
+    #[test]
+    fn test_pyramid() {
+        let mipmap = MipMap::new(stripes(8, 1), WrapMode::Repeat);
+        let sizes = mipmap
+            .levels
+            .iter()
+            .map(|l| (l.width, l.height))
+            .collect::<Vec<_>>();
+        assert_eq!(sizes, [(8, 8), (4, 4), (2, 2), (1, 1)]);
+        assert!(close(mipmap.levels[1].pixel(3, 2), 0.5));
+        // Odd sizes still end in one texel.
+        let odd = MipMap::new(Image::new(5, 3), WrapMode::Clamp);
+        assert_eq!(odd.levels.len(), 4);
+
+        // A point lookup keeps the stripes, a footprint of a few texels
+        // averages them.
+        assert!(close(mipmap.trilinear(1.0 / 16.0, 0.5, 0.0), 1.0));
+        assert!(close(mipmap.trilinear(1.0 / 16.0, 0.5, 0.5), 0.5));
+        assert!(close(mipmap.trilinear(0.3, 0.2, 4.0), 0.5));
+    }
+
+    #[test]
+    fn test_ewa() {
+        let mipmap = MipMap::new(stripes(64, 8), WrapMode::Repeat);
+        // The middle of the first white stripe.
+        let s = 4.0 / 64.0;
+        // Along the stripes the footprint stays within the white one;
+        // across them it takes in the black ones too.
+        let along = mipmap.ewa(s, 0.5, (0.0, 0.25), (0.001, 0.0));
+        assert!(along.x() > 0.99, "{:?}", along);
+        let across = mipmap.ewa(s, 0.5, (0.25, 0.0), (0.0, 0.001));
+        assert!(across.x() > 0.25 && across.x() < 0.75, "{:?}", across);
+        // Trilinear blurs both alike.
+        assert!(close(mipmap.trilinear(s, 0.5, 2.0 * 0.25), 0.5));
+        // Without a footprint it is bilinear.
+        assert!(close(mipmap.ewa(s, 0.5, (0.0, 0.0), (0.0, 0.0)), 1.0));
+    }
+
+    #[test]
+    fn test_image_texture() {
+        let mut texture =
+            ImageTexture::new(stripes(4, 1), WrapMode::Repeat, FilterMode::Ewa);
+        // v runs up the image, so v = 1 is its top row.
+        assert!(close(texture.value(0.125, 1.0, Point3::default()), 1.0));
+        let mut rec = HitRecord {
+            u: 0.125,
+            v: 0.5,
+            ..HitRecord::default()
+        };
+        assert!(close(texture.filtered(&rec), 1.0));
+        rec.dudx = 0.5;
+        rec.dvdy = 0.5;
+        assert!(close(texture.filtered(&rec), 0.5));
+        texture.scale = (2.0, 1.0);
+        rec.u = 0.125 / 2.0 + 0.5;
+        assert!(close(texture.value(rec.u, rec.v, rec.p), 1.0));
+
+        // An image without texels cannot be looked up.
+        let path = env::temp_dir().join("raytracer_test_empty.hdr");
+        fs::write(&path, b"#?RADIANCE\n\n-Y 1 +X 0\n\0\0\0\0").unwrap();
+        let empty =
+            ImageTexture::read(&path, None, WrapMode::Repeat, FilterMode::Ewa);
+        let _ = fs::remove_file(&path);
+        match empty {
+            Err(e) => assert!(e.to_string().contains("empty image"), "{}", e),
+            Ok(_) => panic!("expected an empty image error"),
+        }
+
+        assert_eq!(srgb_to_linear(0.0), 0.0);
+        assert!((srgb_to_linear(1.0) - 1.0).abs() < 1e-6);
+        assert!((srgb_to_linear(0.5) - 0.214).abs() < 1e-3);
+    }
+}
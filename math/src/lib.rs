@@ -2,6 +2,7 @@
 #![cfg_attr(not(debug_assertions), allow(dead_code, unused_macros))]
 
 mod simd;
+mod transform;
 
 use ::std::cmp::PartialEq;
 use ::std::convert::From;
@@ -10,6 +11,7 @@ use ::std::fmt::Debug;
 use ::std::ops::{Add, AddAssign, Div, DivAssign, Index, Mul, Neg, Sub};
 
 use simd::F32x4;
+pub use transform::{Matrix4, Transform};
 
 #[derive(Default, Copy, Clone, PartialEq, Debug)]
 pub struct Vec3(F32x4);
@@ -47,8 +49,8 @@ impl Vec3 {
     #[inline(always)]
     pub fn cross(&self, other: &Vec3) -> Vec3 {
         // TODO: can one shuffle be replaced by using YZXy and yZXY?
-        const YZX: u8 = F32x4::shuffle_control(1, 2, 0, 0);
-        const ZXY: u8 = F32x4::shuffle_control(2, 0, 1, 0);
+        const YZX: i32 = F32x4::shuffle_control(1, 2, 0, 0);
+        const ZXY: i32 = F32x4::shuffle_control(2, 0, 1, 0);
         let self_yzx = self.0.shuffle::<YZX>();
         let other_zxy = other.0.shuffle::<ZXY>();
         let self_zxy = self.0.shuffle::<ZXY>();
@@ -250,7 +252,7 @@ impl Sub<Vec3> for Point3 {
 #[cfg(test)]
 pub mod tests {
     use super::*;
-    use ::std::assert_eq;
+    use ::std::{assert_eq, assert_ne};
 
     #[test]
     fn test_vec3_new() {
@@ -261,6 +263,14 @@ pub mod tests {
         assert_eq!(v.0[3], 0.0);
     }
 
+    #[test]
+    fn test_vec3_eq() {
+        let v = Vec3::new(1.0, 2.0, 3.0);
+        assert_eq!(v, Vec3::new(1.0, 2.0, 3.0));
+        assert_ne!(v, Vec3::new(1.0, 2.0, 4.0));
+        assert_ne!(v, Vec3::new(4.0, 5.0, 6.0));
+    }
+
     #[test]
     fn test_vec3_add() {
         let v1 = Vec3::new(1.0, 0.0, 0.0);
@@ -161,15 +161,15 @@ impl F32x4 {
 
     #[inline(always)]
     #[must_use]
-    pub const fn shuffle_control(a: u8, b: u8, c: u8, d: u8) -> u8 {
-        (a & 0b11) | ((b & 0b11) << 2) | ((c & 0b11) << 4) | ((d & 0b11) << 6)
+    pub const fn shuffle_control(a: u8, b: u8, c: u8, d: u8) -> i32 {
+        ((a & 0b11) | ((b & 0b11) << 2) | ((c & 0b11) << 4) | ((d & 0b11) << 6)) as i32
     }
 
     #[inline(always)]
     #[must_use]
-    pub fn shuffle<const CTRL: u8>(self) -> F32x4 {
+    pub fn shuffle<const CTRL: i32>(self) -> F32x4 {
         F32x4 {
-            r: unsafe { _mm_shuffle_ps(self.r, self.r, CTRL as i32) },
+            r: unsafe { _mm_shuffle_ps(self.r, self.r, CTRL) },
         }
     }
 }
@@ -334,21 +334,22 @@ impl PartialEq for F32x4 {
     #[must_use]
     fn eq(&self, other: &F32x4) -> bool {
         // TODO: 0.0 != -0.0
-        unsafe { _mm_movemask_ps(_mm_cmpeq_ps(self.r, other.r)) == 0 }
+        // The mask has one bit for each lane that compares equal.
+        unsafe { _mm_movemask_ps(_mm_cmpeq_ps(self.r, other.r)) == 0b1111 }
     }
 }
 
 #[cfg(test)]
 pub mod tests {
     use super::*;
-    use ::std::assert_eq;
+    use ::std::{assert, assert_eq};
 
     #[test]
     fn test_shuffle() {
-        const REVERSE: u8 = F32x4::shuffle_control(3, 2, 1, 0);
-        const MIX: u8 = F32x4::shuffle_control(2, 3, 0, 1);
-        const FIRST: u8 = F32x4::shuffle_control(0, 0, 0, 0);
-        const LAST: u8 = F32x4::shuffle_control(3, 3, 3, 3);
+        const REVERSE: i32 = F32x4::shuffle_control(3, 2, 1, 0);
+        const MIX: i32 = F32x4::shuffle_control(2, 3, 0, 1);
+        const FIRST: i32 = F32x4::shuffle_control(0, 0, 0, 0);
+        const LAST: i32 = F32x4::shuffle_control(3, 3, 3, 3);
 
         let a = F32x4::set(1.0, 2.0, 3.0, 4.0);
         let rev = a.shuffle::<REVERSE>();
@@ -360,4 +361,14 @@ pub mod tests {
         let last = a.shuffle::<LAST>();
         assert_eq!(last.as_array(), [4.0, 4.0, 4.0, 4.0]);
     }
+
+    #[test]
+    fn test_eq() {
+        let a = F32x4::set(1.0, 2.0, 3.0, 4.0);
+        assert!(a == F32x4::set(1.0, 2.0, 3.0, 4.0));
+        // Every lane has to match, not just some or none of them.
+        assert!(a != F32x4::set(1.0, 2.0, 3.0, 5.0));
+        assert!(a != F32x4::set(5.0, 6.0, 7.0, 8.0));
+        assert!(a != F32x4::set1(f32::NAN));
+    }
 }
@@ -0,0 +1,396 @@
+use ::std::cmp::PartialEq;
+use ::std::fmt::Debug;
+use ::std::iter::Iterator;
+use ::std::ops::Mul;
+use ::std::option::Option::{self, None, Some};
+
+use crate::simd::F32x4;
+use crate::{Point3, Vec3};
+
+/// A 4x4 matrix, stored as four SIMD columns.
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub struct Matrix4 {
+    cols: [F32x4; 4],
+}
+
+impl Matrix4 {
+    /// A matrix from its rows.
+    #[inline(always)]
+    pub fn new(rows: [[f32; 4]; 4]) -> Self {
+        let col = |j: usize| {
+            F32x4::set(rows[0][j], rows[1][j], rows[2][j], rows[3][j])
+        };
+        Matrix4 {
+            cols: [col(0), col(1), col(2), col(3)],
+        }
+    }
+
+    /// A matrix from 16 numbers in column-major order.
+    pub fn from_column_major(m: &[f32; 16]) -> Self {
+        let col = |j: usize| {
+            F32x4::set(m[4 * j], m[4 * j + 1], m[4 * j + 2], m[4 * j + 3])
+        };
+        Matrix4 {
+            cols: [col(0), col(1), col(2), col(3)],
+        }
+    }
+
+    #[inline(always)]
+    pub fn identity() -> Self {
+        Matrix4::new([
+            [1.0, 0.0, 0.0, 0.0],
+            [0.0, 1.0, 0.0, 0.0],
+            [0.0, 0.0, 1.0, 0.0],
+            [0.0, 0.0, 0.0, 1.0],
+        ])
+    }
+
+    #[inline(always)]
+    pub fn get(&self, row: usize, col: usize) -> f32 {
+        self.cols[col][row]
+    }
+
+    pub fn rows(&self) -> [[f32; 4]; 4] {
+        let mut rows = [[0.0; 4]; 4];
+        for (j, col) in self.cols.iter().enumerate() {
+            for (i, row) in rows.iter_mut().enumerate() {
+                row[j] = col[i];
+            }
+        }
+        rows
+    }
+
+    pub fn transpose(&self) -> Matrix4 {
+        let mut cols = self.cols;
+        for (j, col) in cols.iter_mut().enumerate() {
+            *col = F32x4::set(
+                self.cols[0][j],
+                self.cols[1][j],
+                self.cols[2][j],
+                self.cols[3][j],
+            );
+        }
+        Matrix4 { cols }
+    }
+
+    /// The inverse, or `None` if the matrix is singular.
+    pub fn inverse(&self) -> Option<Matrix4> {
+        // Gauss-Jordan with partial pivoting.
+        let mut a = self.rows();
+        let mut inv = Matrix4::identity().rows();
+        for c in 0..4 {
+            let mut pivot = c;
+            for i in c + 1..4 {
+                if a[i][c].abs() > a[pivot][c].abs() {
+                    pivot = i;
+                }
+            }
+            if a[pivot][c].abs() < 1e-12 {
+                return None;
+            }
+            a.swap(c, pivot);
+            inv.swap(c, pivot);
+            let p = 1.0 / a[c][c];
+            for j in 0..4 {
+                a[c][j] *= p;
+                inv[c][j] *= p;
+            }
+            for i in 0..4 {
+                if i != c {
+                    let f = a[i][c];
+                    for j in 0..4 {
+                        a[i][j] -= f * a[c][j];
+                        inv[i][j] -= f * inv[c][j];
+                    }
+                }
+            }
+        }
+        Some(Matrix4::new(inv))
+    }
+
+    /// The determinant of the upper left 3x3 block, the linear part of an
+    /// affine transform.
+    pub fn determinant3(&self) -> f32 {
+        let m = |i: usize, j: usize| self.get(i, j);
+        m(0, 0) * (m(1, 1) * m(2, 2) - m(1, 2) * m(2, 1))
+            - m(0, 1) * (m(1, 0) * m(2, 2) - m(1, 2) * m(2, 0))
+            + m(0, 2) * (m(1, 0) * m(2, 1) - m(1, 1) * m(2, 0))
+    }
+
+    #[inline(always)]
+    fn linear(&self, v: F32x4) -> F32x4 {
+        self.cols[0] * v[0] + self.cols[1] * v[1] + self.cols[2] * v[2]
+    }
+
+    /// Transforms `p` as a point, dividing by w if the matrix is
+    /// projective.
+    #[inline(always)]
+    pub fn point(&self, p: Point3) -> Point3 {
+        let mut r = self.linear(p.0) + self.cols[3];
+        let w = r[3];
+        if w != 1.0 {
+            r /= w;
+        }
+        r[3] = 0.0;
+        Point3(r)
+    }
+
+    /// Transforms `v` as a direction, ignoring the translation.
+    #[inline(always)]
+    pub fn vector(&self, v: Vec3) -> Vec3 {
+        let mut r = self.linear(v.0);
+        r[3] = 0.0;
+        Vec3(r)
+    }
+}
+
+impl Mul for Matrix4 {
+    type Output = Matrix4;
+
+    #[inline(always)]
+    fn mul(self, other: Matrix4) -> Matrix4 {
+        let col = |j: usize| {
+            let c = other.cols[j];
+            self.linear(c) + self.cols[3] * c[3]
+        };
+        Matrix4 {
+            cols: [col(0), col(1), col(2), col(3)],
+        }
+    }
+}
+
+/// An invertible transform with its inverse kept alongside, so points,
+/// vectors and normals can be mapped either way without inverting again.
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub struct Transform {
+    m: Matrix4,
+    m_inv: Matrix4,
+}
+
+impl Transform {
+    /// The transform for `m`, or `None` if `m` is singular.
+    pub fn new(m: Matrix4) -> Option<Self> {
+        let m_inv = m.inverse()?;
+        Some(Transform { m, m_inv })
+    }
+
+    pub fn identity() -> Self {
+        Transform {
+            m: Matrix4::identity(),
+            m_inv: Matrix4::identity(),
+        }
+    }
+
+    pub fn translate(d: Vec3) -> Self {
+        let m = |d: Vec3| {
+            Matrix4::new([
+                [1.0, 0.0, 0.0, d.x()],
+                [0.0, 1.0, 0.0, d.y()],
+                [0.0, 0.0, 1.0, d.z()],
+                [0.0, 0.0, 0.0, 1.0],
+            ])
+        };
+        Transform {
+            m: m(d),
+            m_inv: m(-d),
+        }
+    }
+
+    /// Scales by `x`, `y` and `z` along the axes. Zero factors make the
+    /// inverse infinite.
+    pub fn scale(x: f32, y: f32, z: f32) -> Self {
+        let m = |x: f32, y: f32, z: f32| {
+            Matrix4::new([
+                [x, 0.0, 0.0, 0.0],
+                [0.0, y, 0.0, 0.0],
+                [0.0, 0.0, z, 0.0],
+                [0.0, 0.0, 0.0, 1.0],
+            ])
+        };
+        Transform {
+            m: m(x, y, z),
+            m_inv: m(1.0 / x, 1.0 / y, 1.0 / z),
+        }
+    }
+
+    /// Rotates by `degrees` counterclockwise around `axis`, looking down
+    /// the axis.
+    pub fn rotate(degrees: f32, axis: Vec3) -> Self {
+        let a = axis.unit();
+        let (sin, cos) = degrees.to_radians().sin_cos();
+        let m = Matrix4::new([
+            [
+                a.x() * a.x() + (1.0 - a.x() * a.x()) * cos,
+                a.x() * a.y() * (1.0 - cos) - a.z() * sin,
+                a.x() * a.z() * (1.0 - cos) + a.y() * sin,
+                0.0,
+            ],
+            [
+                a.x() * a.y() * (1.0 - cos) + a.z() * sin,
+                a.y() * a.y() + (1.0 - a.y() * a.y()) * cos,
+                a.y() * a.z() * (1.0 - cos) - a.x() * sin,
+                0.0,
+            ],
+            [
+                a.x() * a.z() * (1.0 - cos) - a.y() * sin,
+                a.y() * a.z() * (1.0 - cos) + a.x() * sin,
+                a.z() * a.z() + (1.0 - a.z() * a.z()) * cos,
+                0.0,
+            ],
+            [0.0, 0.0, 0.0, 1.0],
+        ]);
+        Transform {
+            m,
+            m_inv: m.transpose(),
+        }
+    }
+
+    /// The world to camera transform of a camera at `eye` looking at `at`,
+    /// with +z forward and `up` roughly +y. `None` if `up` is parallel to
+    /// the viewing direction.
+    pub fn look_at(eye: Point3, at: Point3, up: Vec3) -> Option<Self> {
+        let dir = (at - eye).unit();
+        let right = up.unit().cross(&dir);
+        if right.length() == 0.0 {
+            return None;
+        }
+        let right = right.unit();
+        let new_up = dir.cross(&right);
+        let camera_to_world = Matrix4::new([
+            [right.x(), new_up.x(), dir.x(), eye.x()],
+            [right.y(), new_up.y(), dir.y(), eye.y()],
+            [right.z(), new_up.z(), dir.z(), eye.z()],
+            [0.0, 0.0, 0.0, 1.0],
+        ]);
+        Transform::new(camera_to_world).map(|t| t.inverse())
+    }
+
+    #[inline(always)]
+    pub fn matrix(&self) -> &Matrix4 {
+        &self.m
+    }
+
+    #[inline(always)]
+    pub fn inverse_matrix(&self) -> &Matrix4 {
+        &self.m_inv
+    }
+
+    #[inline(always)]
+    pub fn inverse(&self) -> Transform {
+        Transform {
+            m: self.m_inv,
+            m_inv: self.m,
+        }
+    }
+
+    /// Whether the transform turns a right-handed basis left-handed, which
+    /// flips the winding of triangles.
+    pub fn swaps_handedness(&self) -> bool {
+        self.m.determinant3() < 0.0
+    }
+
+    #[inline(always)]
+    pub fn point(&self, p: Point3) -> Point3 {
+        self.m.point(p)
+    }
+
+    #[inline(always)]
+    pub fn vector(&self, v: Vec3) -> Vec3 {
+        self.m.vector(v)
+    }
+
+    /// Transforms a surface normal with the inverse transpose, which keeps
+    /// it perpendicular to transformed tangents. The result is not
+    /// normalized.
+    #[inline(always)]
+    pub fn normal(&self, n: Vec3) -> Vec3 {
+        let c = &self.m_inv.cols;
+        let dot = |col: F32x4| Vec3(col).dot(n);
+        Vec3::new(dot(c[0]), dot(c[1]), dot(c[2]))
+    }
+}
+
+/// `a * b` applies `b` first.
+impl Mul for Transform {
+    type Output = Transform;
+
+    #[inline(always)]
+    fn mul(self, other: Transform) -> Transform {
+        Transform {
+            m: self.m * other.m,
+            m_inv: other.m_inv * self.m_inv,
+        }
+    }
+}
+
+#[cfg(test)]
+pub mod tests {
+    use super::*;
+    use ::std::assert;
+    use ::std::default::Default;
+
+    fn close(a: Vec3, b: Vec3) -> bool {
+        (a - b).length() < 1e-5
+    }
+
+    #[test]
+    fn test_matrix4_mul_inverse() {
+        let m = Matrix4::new([
+            [2.0, 0.0, 1.0, 3.0],
+            [0.0, 1.0, 0.0, -1.0],
+            [1.0, 0.0, 3.0, 0.5],
+            [0.0, 0.0, 0.0, 1.0],
+        ]);
+        let inv = m.inverse().unwrap();
+        let id = Matrix4::identity().rows();
+        let product = (m * inv).rows();
+        for i in 0..4 {
+            for j in 0..4 {
+                assert!((product[i][j] - id[i][j]).abs() < 1e-6);
+            }
+        }
+        assert!(m.transpose().get(0, 2) == 1.0);
+        assert!(m.transpose().get(3, 0) == 3.0);
+        let singular = Matrix4::new([[1.0, 2.0, 3.0, 4.0]; 4]);
+        assert!(singular.inverse().is_none());
+    }
+
+    #[test]
+    fn test_transform() {
+        let t = Transform::translate(Vec3::new(1.0, 2.0, 3.0))
+            * Transform::rotate(90.0, Vec3::new(0.0, 0.0, 1.0))
+            * Transform::scale(2.0, 3.0, 4.0);
+        let p = t.point(Point3::new(1.0, 1.0, 1.0));
+        assert!(close(p - Point3::new(-2.0, 4.0, 7.0), Vec3::default()));
+        let q = t.inverse().point(p);
+        assert!(close(q - Point3::new(1.0, 1.0, 1.0), Vec3::default()));
+        assert!(close(
+            t.vector(Vec3::new(1.0, 0.0, 0.0)),
+            Vec3::new(0.0, 2.0, 0.0)
+        ));
+        assert!(!t.swaps_handedness());
+        assert!(Transform::scale(-1.0, 1.0, 1.0).swaps_handedness());
+
+        // Normals stay perpendicular to transformed tangents.
+        let s = Transform::scale(1.0, 4.0, 1.0);
+        let tangent = s.vector(Vec3::new(1.0, -1.0, 0.0));
+        let normal = s.normal(Vec3::new(1.0, 1.0, 0.0));
+        assert!(tangent.dot(normal).abs() < 1e-6);
+    }
+
+    #[test]
+    fn test_look_at() {
+        let eye = Point3::new(1.0, 2.0, 3.0);
+        let t = Transform::look_at(
+            eye,
+            Point3::new(1.0, 2.0, 10.0),
+            Vec3::new(0.0, 1.0, 0.0),
+        )
+        .unwrap();
+        assert!(close(t.point(eye) - Point3::default(), Vec3::default()));
+        let forward = t.vector(Vec3::new(0.0, 0.0, 1.0));
+        assert!(close(forward, Vec3::new(0.0, 0.0, 1.0)));
+        let up = Vec3::new(0.0, 0.0, 1.0);
+        assert!(Transform::look_at(eye, eye + up, up).is_none());
+    }
+}